@@ -0,0 +1,14 @@
+//! Feeds arbitrary text to the assembler, which must report malformed
+//! source as an error instead of panicking
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use chip8vm::assembler;
+
+fuzz_target!(|source: &str| {
+    if let Err(e) = assembler::assemble(source) {
+        // Formatting underlines spans of the line, which must not panic either
+        let _ = e.to_string();
+    }
+});
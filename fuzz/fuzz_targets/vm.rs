@@ -0,0 +1,30 @@
+//! Runs arbitrary ROMs on a headless VM, which must stop with an error
+//! instead of panicking on anything a ROM can do
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use chip8vm::assembler::PROGRAM_START;
+use chip8vm::interpreter::{RAM_SIZE, VM};
+
+const N_CYCLES: u64 = 10_000;
+const CYCLES_PER_TIMER_TICK: u64 = 8;
+
+fuzz_target!(|rom: &[u8]| {
+    let rom = &rom[..rom.len().min(RAM_SIZE - PROGRAM_START as usize)];
+    let mut vm = VM::new();
+    vm.load_program(rom);
+    for cycle in 0..N_CYCLES {
+        if vm.step().is_err() {
+            break;
+        }
+        if cycle % CYCLES_PER_TIMER_TICK == 0 {
+            vm.tick_timers();
+        }
+        // Answer every Fx0A, or the rest of the ROM would never run
+        if vm.is_waiting_for_key() {
+            vm.set_key(cycle as u8, true);
+            vm.set_key(cycle as u8, false);
+        }
+    }
+});
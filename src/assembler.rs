@@ -63,6 +63,12 @@
 //! - DRW Vx, Vy, nibble: draw a sprite with a height of nibble pixels at a position (Vx, Vy)
 //! - SKP Vx: skip next instruction if key with the value of VX is pressed
 //! - SKNP Vx: skip next instruction if key with the value of VX is not pressed
+//!
+//! And the debug traps, `SYS` instructions doing nothing unless the
+//! interpreter runs with `--debug-traps` (see [crate::isa::DebugTrap]):
+//! - BRK: pause the program, in the debugger if there is one
+//! - PRINT: print the V registers and I
+//! - PRINT \[I\]: print the 16 bytes of memory at I
 //! 
 //! The assembler supports the following directives:
 //! - .BYTE byte, .DB byte: store a single byte
@@ -70,22 +76,42 @@
 //! - .TEXT string, .ASCII string: store a string
 //! - .FILL n, byte, .DB byte: fill the memory with a value
 //! - .SPACE n: reserve a number of bytes
+//! - .RORG addr: assemble the following code as if it was located at `addr`,
+//!   without moving it (for code that copies itself elsewhere before running)
 //! - .INCLUDE path: include a file
+//! - .REGION name, start, end: name the memory from `start` (an address or
+//!   a label) up to `end` in the debug info, like `.REGION score, 0x3F0,
+//!   0x3F2`, for the debugger to show addresses by name (like labels,
+//!   regions of included files are left out)
 //! - .WARN message: print a warning
 //! - .ERROR message: prints an error
+//!
+//! Constants (`.EQU`) and conditional assembly (`.IF`, `.ELSE`, `.ENDIF`)
+//! are handled beforehand by the [preprocessor], which also generates the
+//! constants of `.INCLUDE "machine.asm"` for the target platform.
 
+pub mod build;
 pub mod codegen_utils;
+pub mod debug_info;
 pub mod directives;
 pub mod instructions;
+pub mod music;
+pub mod pack;
+pub mod preprocessor;
 pub mod statement; 
+pub mod symbols;
+pub mod tilemap;
 
 use regex::Regex;
 use colored::Colorize;
 use std::{fmt, fs};
 use std::collections::HashMap;
+use debug_info::{DebugInfo, Region, SourceLine};
 use statement::{Statement, TokenSpan};
 use instructions::*;
 use directives::*;
+use crate::interpreter::RAM_SIZE;
+use crate::isa::Platform;
 
 /// The address of an instruction in the bytecode
 pub type OpcodeAddress = u16;
@@ -93,68 +119,153 @@ pub type OpcodeAddress = u16;
 /// A symbol table is a mapping of labels to their addresses in the bytecode
 pub type SymbolTable = HashMap<String, OpcodeAddress>;
 
+/// The address programs are loaded at. Label addresses in a [SymbolTable]
+/// are relative to it
+pub const PROGRAM_START: u16 = 0x200;
+
+/// The largest program that fits in RAM
+const MAX_PROGRAM_SIZE: u16 = RAM_SIZE as u16 - PROGRAM_START;
+
 const BYTES_PER_INSTRUCTION: u16 = 2;
 
+/// Arguments are separated by commas or whitespace, and strings may
+/// contain both
+const TOKEN_PATTERN: &str = r#""[^"]*"|[^,\s]+"#;
+
 /// Assemble a file into a vector of bytes
 pub fn assemble_from_file(path: &str) -> Result<Vec<u8>, Error> {
+    assemble_from_file_with_symbols(path).map(|(bytecode, _)| bytecode)
+}
+
+/// Assemble a file into a vector of bytes, also returning its symbol table
+pub fn assemble_from_file_with_symbols(path: &str) -> Result<(Vec<u8>, SymbolTable), Error> {
+    assemble_from_file_for_target(path, Platform::Chip8)
+}
+
+/// Assemble a file for a target platform, see [assemble_for_target]
+pub fn assemble_from_file_for_target(path: &str, target: Platform) -> Result<(Vec<u8>, SymbolTable), Error> {
     let source = fs::read_to_string(path).map_err(|_| Error::ReadError {
         path: path.to_string(),
     })?;
-    assemble(&source)
+    assemble_for_target(&source, target)
+}
+
+/// Assemble a file for a target platform, also returning the debug info
+/// mapping the bytecode back to the lines of the file and of the files it
+/// includes, see [debug_info]
+pub fn assemble_from_file_with_debug_info(path: &str, target: Platform) -> Result<(Vec<u8>, DebugInfo), Error> {
+    let source = fs::read_to_string(path).map_err(|_| Error::ReadError {
+        path: path.to_string(),
+    })?;
+    let preprocessed = preprocessor::preprocess(&source, target)?;
+    let (symbol_table, unresolved) = first_pass(&preprocessed, target)?;
+    let chunks = second_pass(&symbol_table, &unresolved, target)?;
+
+    let mut lines = Vec::new();
+    let mut regions = Vec::new();
+    let mut address = PROGRAM_START;
+    for (statement, bytes) in unresolved.iter().zip(&chunks) {
+        let instruction = statement.instruction().to_uppercase();
+        if instruction == ".INCLUDE" {
+            let (_, included) = assemble_from_file_with_debug_info(&statement.parse_string(0)?, target)?;
+            lines.extend(included.lines.into_iter().map(|line| SourceLine {
+                addresses: line.addresses.start - PROGRAM_START + address..line.addresses.end - PROGRAM_START + address,
+                ..line
+            }));
+        } else if instruction == ".REGION" {
+            regions.push(Region {
+                addresses: region_addresses(statement, &symbol_table)?,
+                name: statement.parse_string(0)?,
+            });
+        } else if !bytes.is_empty() {
+            lines.push(SourceLine {
+                addresses: address..address + bytes.len() as u16,
+                path: path.to_string(),
+                line: statement.line_number(),
+            });
+        }
+        address += bytes.len() as u16;
+        if instruction == ".RORG" {
+            address = PROGRAM_START + rorg_address(statement)?;
+        }
+    }
+    Ok((chunks.concat(), DebugInfo { symbols: symbol_table, regions, lines }))
 }
 
 /// Assemble source code string into a vector of bytes
 pub fn assemble(source: &str) -> Result<Vec<u8>, Error> {
-    let preprocessed = preprocess(source);
-    let (symbol_table, unresolved) = first_pass(&preprocessed)?;
-    second_pass(&symbol_table, &unresolved)
+    assemble_with_symbols(source).map(|(bytecode, _)| bytecode)
+}
+
+/// Assemble source code string into a vector of bytes, also returning its
+/// symbol table
+pub fn assemble_with_symbols(source: &str) -> Result<(Vec<u8>, SymbolTable), Error> {
+    assemble_for_target(source, Platform::Chip8)
 }
 
-fn preprocess(source: &str) -> String {
-    source
-        .lines()
-        .map(|line| line.splitn(2, ';').next().unwrap_or("").trim())
-        .filter(|line| !line.is_empty())
-        .collect::<Vec<_>>()
-        .join("\n")
+/// Assemble source code string for a target platform, which only changes
+/// the constants of `.INCLUDE "machine.asm"` (see [preprocessor]). Files
+/// included with `.INCLUDE` are assembled for the same target
+pub fn assemble_for_target(source: &str, target: Platform) -> Result<(Vec<u8>, SymbolTable), Error> {
+    let preprocessed = preprocessor::preprocess(source, target)?;
+    let (symbol_table, unresolved) = first_pass(&preprocessed, target)?;
+    let bytecode = second_pass(&symbol_table, &unresolved, target)?.concat();
+    Ok((bytecode, symbol_table))
+}
+
+/// Split a line into a statement, or `None` if it holds nothing
+fn lex<'a>(re: &Regex, line: &'a str, line_number: usize) -> Option<Statement<'a>> {
+    let mut lexemes = Vec::new();
+    let mut spans = Vec::new();
+    for mat in re.find_iter(line) {
+        lexemes.push(mat.as_str());
+        spans.push(TokenSpan::new(mat.start(), mat.end()));
+    }
+    if lexemes.is_empty() {
+        return None;
+    }
+    Some(Statement::new(lexemes[0], spans[0], lexemes[1..].to_vec(), spans[1..].to_vec(), line_number, line))
 }
 
-fn first_pass(source: &str) -> Result<(SymbolTable, Vec<Statement>), Error> {
+fn first_pass(source: &str, target: Platform) -> Result<(SymbolTable, Vec<Statement<'_>>), Error> {
     let mut labels = HashMap::new();
     let mut unresolved = Vec::new();
     let mut address: OpcodeAddress = 0;
 
+    let re = Regex::new(TOKEN_PATTERN).unwrap();
+
     for (line_index, line) in source.lines().enumerate() {
+        // Lines emptied by the preprocessor keep the line numbers right
+        if line.is_empty() {
+            continue;
+        }
         if line.ends_with(':') {
             let label = line.trim_end_matches(':');
             labels.insert(label.to_string(), address);
         } else {
-            let re = Regex::new(r#""[^"]*"|[^,\s]+"#).unwrap();
-            let mut lexemes = Vec::new();
-            let mut spans = Vec::new();
-            for mat in re.find_iter(line) {
-                lexemes.push(mat.as_str());
-                spans.push(TokenSpan::new(mat.start(), mat.end()));
-            }
-
-            let statement = Statement::new(
-                lexemes[0],
-                spans[0],
-                lexemes[1..].to_vec(),
-                spans[1..].to_vec(),
-                line_index + 1,
-                line
-            );
+            let statement = lex(&re, line, line_index + 1).ok_or_else(|| Error::MissingInstruction {
+                line_number: line_index + 1,
+                line: line.to_string()
+            })?;
 
             if line.starts_with(".") {
                 // Here we need to know the output size of the directive to not mess
                 // up the offsets. Essentially, we do double work here, but it would
                 // be a useless hassle to try to avoid it!
-                let n_bytes = parse_statement(&statement, &labels)?.len();
-                address += n_bytes as u16;
+                let n_bytes = parse_statement(&statement, &labels, target)?.len();
+                address = address.saturating_add(n_bytes.try_into().unwrap_or(u16::MAX));
             } else {
                 address += BYTES_PER_INSTRUCTION;
             }
+            if address > MAX_PROGRAM_SIZE {
+                return Err(Error::ProgramTooLarge {
+                    line_number: statement.line_number(),
+                    line: statement.line()
+                });
+            }
+            if statement.instruction().eq_ignore_ascii_case(".RORG") {
+                address = rorg_address(&statement)?;
+            }
 
             unresolved.push(statement);
         }
@@ -163,21 +274,24 @@ fn first_pass(source: &str) -> Result<(SymbolTable, Vec<Statement>), Error> {
     Ok((labels, unresolved))
 }
 
+/// Assemble every statement, returning the bytes of each
 fn second_pass(
     symbol_table: &SymbolTable, 
-    unresolved: &Vec<Statement>
-) -> Result<Vec<u8>, Error> {
+    unresolved: &Vec<Statement>,
+    target: Platform
+) -> Result<Vec<Vec<u8>>, Error> {
     let mut bytecode = Vec::new();
     for statement in unresolved {
-        let bytes = parse_statement(&statement, &symbol_table)?;
+        let bytes = parse_statement(statement, symbol_table, target)?;
         bytecode.push(bytes);
     }
-    Ok(bytecode.into_iter().flatten().collect())
+    Ok(bytecode)
 }
 
 fn parse_statement(
     statement: &Statement, 
-    symbol_table: &SymbolTable
+    symbol_table: &SymbolTable,
+    target: Platform
 ) -> Result<Vec<u8>, Error> {
     let opcode = match statement.instruction().to_uppercase().as_str() {
         // INSTRUCTIONS
@@ -201,14 +315,18 @@ fn parse_statement(
         "DRW"  =>  drw(statement),
         "SKP"  =>  skp(statement),
         "SKNP" => sknp(statement),
+        "BRK"  =>  brk(statement),
+        "PRINT" => print(statement),
         // ASSEMBLER DIRECTIVES
-        // TODO: macros and conditionals?
+        // TODO: macros?
         ".BYTE" | ".DB"    =>     byte(statement),
         ".WORD" | ".DW"    =>     word(statement),
         ".TEXT" | ".ASCII" =>     text(statement),
         ".FILL"            =>     fill(statement),
         ".SPACE"           =>    space(statement),
-        ".INCLUDE"         => _include(statement),
+        ".RORG"            =>     rorg(statement),
+        ".INCLUDE"         => _include(statement, target),
+        ".REGION"          =>   region(statement),
         ".WARN"            =>     warn(statement),
         ".ERROR"           =>   _error(statement),
         _ => Err(Error::UnknownInstruction {
@@ -273,6 +391,33 @@ pub enum Error {
         line_number: usize,
         line: String
     },
+    /// A line holds no instruction, only separators
+    MissingInstruction {
+        line_number: usize,
+        line: String
+    },
+    /// The program does not fit in RAM
+    ProgramTooLarge {
+        line_number: usize,
+        line: String
+    },
+    /// A constant was defined with `.EQU` twice
+    DuplicateConstant {
+        name: String,
+        line_number: usize,
+        line: String
+    },
+    /// An `.ELSE` or `.ENDIF` directive without a matching `.IF`
+    UnmatchedConditional {
+        directive: String,
+        line_number: usize,
+        line: String
+    },
+    /// An `.IF` directive without a matching `.ENDIF`
+    UnterminatedConditional {
+        line_number: usize,
+        line: String
+    },
     /// An internal error. If you see this, this is probably an internal bug
     /// in the assembler itself. Submit a GitHub issue!
     InvalidArgumentIndex {
@@ -332,6 +477,29 @@ impl fmt::Display for Error {
                 ),
                 Some(line), Some(line_number), vec![argument_span]
             ),
+            Error::MissingInstruction { line_number, line } => (
+                format!("expected an instruction at line {}", line_number),
+                Some(line), Some(line_number), vec![]
+            ),
+            Error::ProgramTooLarge { line_number, line } => (
+                format!(
+                    "the program grows past the end of RAM at line {} (at most {} bytes fit)",
+                    line_number, MAX_PROGRAM_SIZE
+                ),
+                Some(line), Some(line_number), vec![]
+            ),
+            Error::DuplicateConstant { name, line_number, line } => (
+                format!("constant \"{}\" is defined again at line {}", name, line_number),
+                Some(line), Some(line_number), vec![]
+            ),
+            Error::UnmatchedConditional { directive, line_number, line } => (
+                format!("{} without a matching .IF at line {}", directive, line_number),
+                Some(line), Some(line_number), vec![]
+            ),
+            Error::UnterminatedConditional { line_number, line } => (
+                format!(".IF at line {} is never closed by .ENDIF", line_number),
+                Some(line), Some(line_number), vec![]
+            ),
             Error::InvalidArgumentIndex { requested_index, n_arguments } => (
                 format!(
                     "invalid argument index {} requested; number of arguments: {}. If you see this, this \
@@ -344,8 +512,8 @@ impl fmt::Display for Error {
         writeln!(f, "{}", message)?;
         if let (Some(line), Some(line_number)) = (line, line_number) {
             write!(f, "{}\t{}", line_number, line)?;
-            if underlined_spans.len() != 0 {
-                writeln!(f, "")?;
+            if !underlined_spans.is_empty() {
+                writeln!(f)?;
                 write!(f, "\t{}", underline_spans(line, underlined_spans).green())?;
             }
         }
@@ -0,0 +1,308 @@
+//! Building several ROMs described by a build file
+//!
+//! A build file is a small subset of TOML listing the targets to assemble:
+//!
+//! ```toml
+//! # The game and its test ROM share include files
+//! [[target]]
+//! name = "game"
+//! input = "src/game.asm"
+//! output = "build/game.ch8"
+//! symbols = "build/game.sym"  # optional
+//! platform = "superchip"      # optional, see assembler --target
+//!
+//! [[target]]
+//! name = "tests"
+//! input = "src/tests.asm"
+//! output = "build/tests.ch8"
+//! ```
+//!
+//! Paths are relative to the directory of the build file, which is also
+//! where `.INCLUDE` paths are resolved from. A target is only rebuilt when
+//! the hash of its source, its includes (found recursively) or its settings
+//! changed since the last build, or when its output is missing. Hashes are
+//! kept in [CACHE_FILE_NAME] next to the build file.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::assembler;
+use crate::assembler::preprocessor::MACHINE_INCLUDE;
+use crate::assembler::symbols::write_symbol_table;
+use crate::isa::Platform;
+
+/// Name of the file the hashes of the last build are stored in
+pub const CACHE_FILE_NAME: &str = ".chip8-build-cache";
+
+/// A ROM to assemble
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    /// Name of the target, used in messages and in the cache
+    pub name: String,
+    /// The main source file
+    pub input: PathBuf,
+    /// The ROM to write
+    pub output: PathBuf,
+    /// The symbol file to write, if any
+    pub symbols: Option<PathBuf>,
+    /// The platform the target is assembled for
+    pub platform: Platform,
+}
+
+/// What happened to a target during a build
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The target was assembled
+    Built,
+    /// Nothing the target depends on changed
+    UpToDate,
+}
+
+/// Parse the contents of a build file
+pub fn parse_build_file(source: &str) -> Result<Vec<Target>, BuildError> {
+    let mut tables: Vec<(usize, HashMap<String, String>)> = Vec::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let parse_error = |message: &str| BuildError::Parse {
+            line_number,
+            message: message.to_string(),
+        };
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line == "[[target]]" {
+            tables.push((line_number, HashMap::new()));
+            continue;
+        }
+        let (key, value) = line.split_once('=')
+            .ok_or_else(|| parse_error("expected `[[target]]` or `key = \"value\"`"))?;
+        let (_, table) = tables.last_mut()
+            .ok_or_else(|| parse_error("keys must be inside a [[target]] table"))?;
+        let value = value.trim()
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .ok_or_else(|| parse_error("values must be double-quoted strings"))?;
+        let key = key.trim();
+        if !["name", "input", "output", "symbols", "platform"].contains(&key) {
+            return Err(parse_error(&format!("unknown key \"{}\"", key)));
+        }
+        if table.insert(key.to_string(), value.to_string()).is_some() {
+            return Err(parse_error(&format!("duplicate key \"{}\"", key)));
+        }
+    }
+
+    let mut targets: Vec<Target> = Vec::new();
+    for (line_number, mut table) in tables {
+        let platform = match table.remove("platform") {
+            Some(name) => Platform::from_name(&name).ok_or_else(|| BuildError::Parse {
+                line_number,
+                message: format!("unknown platform \"{}\"", name),
+            })?,
+            None => Platform::Chip8,
+        };
+        let mut required = |key: &str| table.remove(key).ok_or_else(|| BuildError::Parse {
+            line_number,
+            message: format!("target is missing the \"{}\" key", key),
+        });
+        let target = Target {
+            name: required("name")?,
+            input: required("input")?.into(),
+            output: required("output")?.into(),
+            symbols: table.remove("symbols").map(PathBuf::from),
+            platform,
+        };
+        if targets.iter().any(|other| other.name == target.name) {
+            return Err(BuildError::Parse {
+                line_number,
+                message: format!("duplicate target name \"{}\"", target.name),
+            });
+        }
+        targets.push(target);
+    }
+    Ok(targets)
+}
+
+// Quotes are not escaped in the supported subset, so a `#` outside of an
+// even number of quotes starts a comment
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Find the source files a source file depends on, including itself, by
+/// following `.INCLUDE` directives recursively (except the generated
+/// `machine.asm`)
+pub fn dependencies(path: &Path) -> Result<Vec<PathBuf>, BuildError> {
+    let mut found = Vec::new();
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        if found.contains(&path) {
+            continue;
+        }
+        let source = fs::read_to_string(&path).map_err(|_| BuildError::Read { path: path.clone() })?;
+        for line in source.lines() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            let Some((directive, argument)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+            let argument = argument.trim().trim_matches('"');
+            if directive.eq_ignore_ascii_case(".INCLUDE") && argument != MACHINE_INCLUDE {
+                pending.push(PathBuf::from(argument));
+            }
+        }
+        found.push(path);
+    }
+    Ok(found)
+}
+
+/// Hash everything a target depends on: its settings and the contents of
+/// its source files
+pub fn target_hash(target: &Target) -> Result<u64, BuildError> {
+    let mut hash = Fnv1a::new();
+    hash.write(format!("{:?}", target).as_bytes());
+    for path in dependencies(&target.input)? {
+        let contents = fs::read(&path).map_err(|_| BuildError::Read { path: path.clone() })?;
+        hash.write(path.to_string_lossy().as_bytes());
+        hash.write(&contents);
+    }
+    Ok(hash.finish())
+}
+
+/// Assemble every target whose hash changed since the last build. `cache`
+/// maps target names to the hashes of their last successful build and is
+/// updated in place. Paths are resolved from the current directory
+pub fn build(
+    targets: &[Target],
+    cache: &mut HashMap<String, u64>,
+) -> Vec<(String, Result<Outcome, BuildError>)> {
+    targets
+        .iter()
+        .map(|target| (target.name.clone(), build_target(target, cache)))
+        .collect()
+}
+
+fn build_target(target: &Target, cache: &mut HashMap<String, u64>) -> Result<Outcome, BuildError> {
+    let hash = target_hash(target)?;
+    let outputs_exist = target.output.exists()
+        && target.symbols.as_ref().is_none_or(|symbols| symbols.exists());
+    if outputs_exist && cache.get(&target.name) == Some(&hash) {
+        return Ok(Outcome::UpToDate);
+    }
+    cache.remove(&target.name);
+
+    let input = target.input.to_string_lossy();
+    let (bytecode, symbol_table) = assembler::assemble_from_file_for_target(&input, target.platform)
+        .map_err(|error| BuildError::Assemble { error })?;
+
+    write_file(&target.output, &bytecode)?;
+    if let Some(symbols) = &target.symbols {
+        let mut contents = Vec::new();
+        write_symbol_table(&symbol_table, &mut contents)
+            .map_err(|_| BuildError::Write { path: symbols.clone() })?;
+        write_file(symbols, &contents)?;
+    }
+
+    cache.insert(target.name.clone(), hash);
+    Ok(Outcome::Built)
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<(), BuildError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).map_err(|_| BuildError::Write { path: path.to_path_buf() })?;
+    }
+    fs::write(path, contents).map_err(|_| BuildError::Write { path: path.to_path_buf() })
+}
+
+/// Parse a cache file, one `name hash` pair per line. Malformed lines are
+/// ignored, which only causes their targets to be rebuilt
+pub fn parse_cache(source: &str) -> HashMap<String, u64> {
+    source
+        .lines()
+        .filter_map(|line| {
+            let (name, hash) = line.rsplit_once(' ')?;
+            Some((name.to_string(), u64::from_str_radix(hash, 16).ok()?))
+        })
+        .collect()
+}
+
+/// Format a cache for [parse_cache]
+pub fn format_cache(cache: &HashMap<String, u64>) -> String {
+    let mut entries: Vec<_> = cache.iter().collect();
+    entries.sort();
+    entries
+        .into_iter()
+        .map(|(name, hash)| format!("{} {:016x}\n", name, hash))
+        .collect()
+}
+
+/// 64-bit FNV-1a. Unlike [std::hash::DefaultHasher], its output is stable
+/// across Rust versions, so caches stay valid after a toolchain update
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Fnv1a {
+        Fnv1a(0xcbf29ce484222325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+        // Separate consecutive writes, so ("ab", "c") and ("a", "bc") differ
+        self.0 ^= 0xFF;
+        self.0 = self.0.wrapping_mul(0x100000001b3);
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// An error that can occur while building targets
+#[derive(Debug)]
+pub enum BuildError {
+    /// The build file is malformed
+    Parse {
+        line_number: usize,
+        message: String
+    },
+    /// A file could not be read
+    Read {
+        path: PathBuf
+    },
+    /// A file could not be written
+    Write {
+        path: PathBuf
+    },
+    /// A target failed to assemble
+    Assemble {
+        error: assembler::Error
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Parse { line_number, message } => {
+                write!(f, "build file line {}: {}", line_number, message)
+            }
+            BuildError::Read { path } => write!(f, "failed to read file {}", path.display()),
+            BuildError::Write { path } => write!(f, "failed to write file {}", path.display()),
+            BuildError::Assemble { error } => write!(f, "{}", error),
+        }
+    }
+}
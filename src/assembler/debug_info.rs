@@ -0,0 +1,143 @@
+//! Mapping bytecode back to the lines of assembly it came from
+//!
+//! With `--debug-info`, the assembler writes the labels of the program,
+//! the regions of memory declared with `.REGION` and the source line of
+//! every statement that emits bytes, each with the addresses of its bytes,
+//! one per line:
+//!
+//! ```text
+//! label 0x0216 loop
+//! region 0x0300..0x0360 sprites
+//! line 0x0216..0x0218 game.asm:12
+//! ```
+//!
+//! Lines of included files name the included file. Addresses are absolute,
+//! and statements after `.RORG` have the addresses they are assembled for.
+//! The debugger shows addresses in regions by name, like `sprites+16`.
+//! Empty lines and lines starting with `;` are ignored.
+
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use crate::assembler::{SymbolTable, PROGRAM_START};
+use crate::interpreter::parse_number;
+
+/// The bytes a statement emitted, and where it is in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub addresses: Range<u16>,
+    pub path: String,
+    /// The line number, starting at 1
+    pub line: usize,
+}
+
+/// A named range of memory, like the sprites or the score of a game
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub addresses: Range<u16>,
+    pub name: String,
+}
+
+/// The labels of a program, its regions and the source lines of its bytes
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugInfo {
+    pub symbols: SymbolTable,
+    pub regions: Vec<Region>,
+    /// In the order of the bytecode
+    pub lines: Vec<SourceLine>,
+}
+
+impl DebugInfo {
+    /// Find the source line of the byte at `address`
+    pub fn line_at(&self, address: u16) -> Option<&SourceLine> {
+        self.lines.iter().find(|line| line.addresses.contains(&address))
+    }
+
+    /// Name the byte at `address` after the smallest region containing it,
+    /// as `name` or `name+offset`
+    pub fn region_of(&self, address: u16) -> Option<String> {
+        let region = self.regions.iter()
+            .filter(|region| region.addresses.contains(&address))
+            .min_by_key(|region| region.addresses.len())?;
+        Some(match address - region.addresses.start {
+            0 => region.name.clone(),
+            offset => format!("{}+{}", region.name, offset),
+        })
+    }
+
+    /// Find the first line emitting bytes at or after `line` of a file,
+    /// where a breakpoint on `line` goes. Files are matched by the end of
+    /// their path, so `game.asm` matches `src/game.asm`
+    pub fn line_from(&self, path: &str, line: usize) -> Option<&SourceLine> {
+        self.lines.iter()
+            .filter(|source_line| source_line.line >= line && same_file(&source_line.path, path))
+            .min_by_key(|source_line| (source_line.line, source_line.addresses.start))
+    }
+}
+
+/// Check whether two paths name the same file, one being a suffix of the
+/// other on a component boundary
+fn same_file(a: &str, b: &str) -> bool {
+    let (a, b) = (a.replace('\\', "/"), b.replace('\\', "/"));
+    let (short, long) = if a.len() <= b.len() { (&a, &b) } else { (&b, &a) };
+    long.ends_with(short.as_str()) && (long.len() == short.len() || long[..long.len() - short.len()].ends_with('/'))
+}
+
+/// Write debug info in the format described in the module docs
+pub fn write_debug_info(debug_info: &DebugInfo, mut writer: impl Write) -> io::Result<()> {
+    let mut symbols: Vec<_> = debug_info.symbols.iter().collect();
+    symbols.sort_by_key(|&(label, address)| (*address, label.clone()));
+    for (label, address) in symbols {
+        writeln!(writer, "label 0x{:04X} {}", address + PROGRAM_START, label)?;
+    }
+    for region in &debug_info.regions {
+        writeln!(writer, "region 0x{:04X}..0x{:04X} {}", region.addresses.start, region.addresses.end, region.name)?;
+    }
+    for line in &debug_info.lines {
+        writeln!(writer, "line 0x{:04X}..0x{:04X} {}:{}", line.addresses.start, line.addresses.end, line.path, line.line)?;
+    }
+    Ok(())
+}
+
+/// Read debug info written by [write_debug_info]
+pub fn read_debug_info(reader: impl BufRead) -> io::Result<DebugInfo> {
+    let mut debug_info = DebugInfo::default();
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        let invalid = || io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid debug info at line {}: \"{}\"", line_index + 1, line)
+        );
+        let (kind, rest) = line.split_once(' ').ok_or_else(invalid)?;
+        let (address, value) = rest.trim().split_once(' ').ok_or_else(invalid)?;
+        match kind {
+            "label" => {
+                let address = parse_number(address)
+                    .and_then(|address| address.checked_sub(PROGRAM_START))
+                    .ok_or_else(invalid)?;
+                debug_info.symbols.insert(value.trim().to_string(), address);
+            }
+            "region" => {
+                let (start, end) = address.split_once("..").ok_or_else(invalid)?;
+                debug_info.regions.push(Region {
+                    addresses: parse_number(start).ok_or_else(invalid)?..parse_number(end).ok_or_else(invalid)?,
+                    name: value.trim().to_string(),
+                });
+            }
+            "line" => {
+                let (start, end) = address.split_once("..").ok_or_else(invalid)?;
+                let (path, number) = value.trim().rsplit_once(':').ok_or_else(invalid)?;
+                debug_info.lines.push(SourceLine {
+                    addresses: parse_number(start).ok_or_else(invalid)?..parse_number(end).ok_or_else(invalid)?,
+                    path: path.to_string(),
+                    line: number.parse().map_err(|_| invalid())?,
+                });
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(debug_info)
+}
@@ -4,6 +4,7 @@ use super::statement::Statement;
 use crate::assembler;
 use crate::split_u16;
 use crate::logging::warning;
+use crate::isa::Platform;
 
 pub fn byte(
     statement: &Statement,
@@ -41,11 +42,54 @@ pub fn space(
     Ok(vec![0x00; statement.parse_number(0, 16)? as usize])
 }
 
+/// Address the statements after a `.RORG addr` directive are assembled for,
+/// relative to [assembler::PROGRAM_START] like a [assembler::SymbolTable]
+pub fn rorg_address(
+    statement: &Statement,
+) -> Result<assembler::OpcodeAddress, assembler::Error> {
+    statement.assert_n_arguments(1)?;
+    statement.parse_number(0, 12)?
+        .checked_sub(assembler::PROGRAM_START)
+        .ok_or_else(|| statement.invalid_argument(0))
+}
+
+pub fn rorg(
+    statement: &Statement,
+) -> Result<Vec<u8>, assembler::Error> {
+    rorg_address(statement)?;
+    Ok(vec![])
+}
+
+/// Addresses of the region a `.REGION name, start, end` directive
+/// declares, which may be labels defined anywhere
+pub fn region_addresses(
+    statement: &Statement,
+    symbol_table: &assembler::SymbolTable,
+) -> Result<std::ops::Range<u16>, assembler::Error> {
+    statement.assert_n_arguments(3)?;
+    let start = statement.parse_addr_or_label(1, symbol_table)?;
+    let end = statement.parse_addr_or_label(2, symbol_table)?;
+    if end <= start {
+        return Err(statement.invalid_argument(2));
+    }
+    Ok(start..end)
+}
+
+/// Only checked for its arguments here, as labels after it are not known
+/// in the first pass, see [region_addresses]
+pub fn region(
+    statement: &Statement,
+) -> Result<Vec<u8>, assembler::Error> {
+    statement.assert_n_arguments(3)?;
+    Ok(vec![])
+}
+
 pub fn _include(
     statement: &Statement,
+    target: Platform,
 ) -> Result<Vec<u8>, assembler::Error> {
     let path = statement.parse_string(0)?;
-    assembler::assemble_from_file(&path).map_err(|e| assembler::Error::IncludeError {
+    assembler::assemble_from_file_for_target(&path, target).map(|(bytecode, _)| bytecode).map_err(|e| assembler::Error::IncludeError {
         path,
         error: Box::new(e),
         line_number: statement.line_number(),
@@ -0,0 +1,229 @@
+//! Compiling simple tunes into a note table and a routine playing it
+//!
+//! A song file lists notes and rests, one per line, with their duration as
+//! a fraction of a whole note:
+//!
+//! ```text
+//! ; Comments start with a semicolon
+//! tempo = 120     ; quarter notes per minute (default 120)
+//! C4 1/4
+//! E4 1/8
+//! rest 1/8
+//! G#4 1/2         ; notes are A to G, optionally sharp (#) or flat (b)
+//! beep 1          ; a note without a pitch
+//! ```
+//!
+//! The CHIP-8 buzzer has a single pitch, so notes only differ in duration
+//! and pitches are kept for platforms that can play them. Durations are
+//! rounded to 60 Hz timer ticks, and every note is cut one tick short so
+//! repeated notes can be told apart. [Song::to_assembly] generates assembly
+//! with the following labels, prefixed with a name:
+//! - `name_start`: a subroutine (re)starting the song
+//! - `name_update`: a subroutine to call regularly, for example once per
+//!   frame, starting the next note once the current one is over; it uses
+//!   the delay timer, clobbers V0..V2 and I, and stays silent after the
+//!   last note
+//! - `name_song`: the notes, as (sound timer, delay timer) pairs ending with
+//!   a zero delay
+//! - `name_position`: the offset of the next note in `name_song`
+//!
+//! The generated code uses labels, so it must be part of the same source
+//! file as the code calling it.
+
+use std::fmt;
+use std::fmt::Write;
+
+const DEFAULT_TEMPO: u32 = 120;
+const TICKS_PER_SECOND: u32 = 60;
+/// Notes are indexed with a single register, two bytes per note plus the
+/// end marker
+const MAX_NOTES: usize = 127;
+
+/// A note or a rest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note {
+    /// MIDI note number (60 is C4), or `None` for `beep` and rests
+    pub pitch: Option<u8>,
+    /// Whether the note is heard at all
+    pub rest: bool,
+    /// Duration in 60 Hz timer ticks, at least 1
+    pub ticks: u8,
+}
+
+/// A parsed song
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Song {
+    pub notes: Vec<Note>,
+}
+
+/// Parse the contents of a song file
+pub fn parse_song(source: &str) -> Result<Song, SongError> {
+    let mut tempo = DEFAULT_TEMPO;
+    let mut notes = Vec::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let parse_error = |message: String| SongError::Parse { line_number, message };
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some((key, value)) = line.split_once('=') {
+            if key.trim() != "tempo" {
+                return Err(parse_error(format!("unknown setting \"{}\"", key.trim())));
+            }
+            tempo = value.trim().parse().ok()
+                .filter(|tempo| *tempo > 0)
+                .ok_or_else(|| parse_error("the tempo must be a positive integer".to_string()))?;
+            continue;
+        }
+
+        let [name, duration] = line.split_whitespace().collect::<Vec<_>>()[..] else {
+            return Err(parse_error("expected `note duration`".to_string()));
+        };
+        let (pitch, rest) = match name {
+            "rest" => (None, true),
+            "beep" => (None, false),
+            _ => (Some(parse_pitch(name).ok_or_else(|| parse_error(format!("invalid note {}", name)))?), false),
+        };
+        let ticks = parse_duration(duration, tempo)
+            .ok_or_else(|| parse_error(format!("invalid duration {}", duration)))?;
+        if ticks == 0 || ticks > u8::MAX as u32 {
+            return Err(parse_error(format!(
+                "the duration is {} timer ticks long, but must be 1 to 255 ticks", ticks
+            )));
+        }
+        if notes.len() == MAX_NOTES {
+            return Err(parse_error(format!("at most {} notes are supported", MAX_NOTES)));
+        }
+        notes.push(Note { pitch, rest, ticks: ticks as u8 });
+    }
+
+    if notes.is_empty() {
+        return Err(SongError::Empty);
+    }
+    Ok(Song { notes })
+}
+
+/// Parse a note name such as `C4`, `F#3` or `Bb5` into a MIDI note number
+fn parse_pitch(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    let semitone = match chars.next()? {
+        'C' => 0, 'D' => 2, 'E' => 4, 'F' => 5, 'G' => 7, 'A' => 9, 'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = if let Some(octave) = rest.strip_prefix('#') {
+        (1, octave)
+    } else if let Some(octave) = rest.strip_prefix('b') {
+        (-1, octave)
+    } else {
+        (0, rest)
+    };
+    let octave: i32 = octave.parse().ok().filter(|octave| (0..=9).contains(octave))?;
+    u8::try_from((octave + 1) * 12 + semitone + accidental).ok()
+}
+
+/// Parse a duration (`1`, `1/4`, `3/8`, ...) in whole notes into timer ticks
+fn parse_duration(duration: &str, tempo: u32) -> Option<u32> {
+    let (numerator, denominator) = match duration.split_once('/') {
+        Some((numerator, denominator)) => (numerator.parse::<u32>().ok()?, denominator.parse::<u32>().ok()?),
+        None => (duration.parse::<u32>().ok()?, 1),
+    };
+    if denominator == 0 {
+        return None;
+    }
+    // A whole note lasts 4 quarter notes of 60 / tempo seconds
+    let ticks_per_whole = 4 * 60 * TICKS_PER_SECOND;
+    let scaled = ticks_per_whole.checked_mul(numerator)?;
+    let divisor = tempo.checked_mul(denominator)?;
+    Some((scaled + divisor / 2) / divisor)
+}
+
+impl Song {
+    /// The notes as (sound timer, delay timer) pairs, ending with `(0, 0)`
+    pub fn table(&self) -> Vec<(u8, u8)> {
+        self.notes
+            .iter()
+            .map(|note| {
+                let sound = if note.rest { 0 } else { note.ticks.saturating_sub(1).max(1) };
+                (sound, note.ticks)
+            })
+            .chain([(0, 0)])
+            .collect()
+    }
+
+    /// Generate the assembly of the song and the routines playing it.
+    /// `name` prefixes every label
+    pub fn to_assembly(&self, name: &str) -> String {
+        let mut source = format!("\
+; Generated from a song: {n_notes} notes
+
+; Play the song from the beginning. Clobbers V0 and I
+{name}_start:
+    LD V0, 0
+    LD I, {name}_position
+    LD [I], V0
+    RET
+
+; Start the next note once the current one is over. Call it regularly
+; (for example once per frame). Uses DT, clobbers V0..V2 and I
+{name}_update:
+    LD V0, DT
+    SE V0, 0
+    RET
+    LD I, {name}_position
+    LD V0, [I]
+    LD V2, V0
+    LD I, {name}_song
+    ADD I, V2
+    LD V1, [I]
+    SNE V1, 0
+    RET
+    LD ST, V0
+    LD DT, V1
+    ADD V2, 2
+    LD V0, V2
+    LD I, {name}_position
+    LD [I], V0
+    RET
+
+{name}_position:
+    .BYTE 0
+
+{name}_song:
+",
+            n_notes = self.notes.len(),
+        );
+        for (sound, delay) in self.table() {
+            writeln!(source, "    .BYTE {}\n    .BYTE {}", sound, delay).unwrap();
+        }
+        source
+    }
+}
+
+/// An error that can occur while parsing a song
+#[derive(Debug)]
+pub enum SongError {
+    /// The file is malformed
+    Parse {
+        line_number: usize,
+        message: String
+    },
+    /// The song has no notes
+    Empty,
+}
+
+impl std::error::Error for SongError {}
+
+impl fmt::Display for SongError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SongError::Parse { line_number, message } => {
+                write!(f, "song line {}: {}", line_number, message)
+            }
+            SongError::Empty => write!(f, "the song has no notes"),
+        }
+    }
+}
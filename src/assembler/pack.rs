@@ -0,0 +1,278 @@
+//! Packing ROMs into smaller, self-extracting ones (experimental)
+//!
+//! The ROM is run-length encoded and prepended with a stub, written in
+//! CHIP-8 assembly, that restores it before jumping to it. A packed ROM is
+//! laid out as follows:
+//!
+//! 1. a copier, at [PROGRAM_START], that moves the rest of the ROM above the
+//!    memory the unpacked ROM will occupy;
+//! 2. the decompressor, assembled with `.RORG` for the address it is copied
+//!    to, which unpacks the ROM to [PROGRAM_START], clears the registers and
+//!    `I`, and jumps to it;
+//! 3. the compressed ROM.
+//!
+//! The stub only uses instructions that behave the same on every platform
+//! and does not depend on quirks. It takes about 30 instructions per
+//! unpacked byte, so it is meant for interpreters running hundreds of
+//! instructions per frame. The memory above the unpacked ROM is left
+//! holding the stub instead of zeros.
+//!
+//! The compressed stream is a sequence of packets, each starting with a
+//! control byte `c`:
+//! - `0x00`: end of the stream;
+//! - `0x01..=0x7F`: `c` literal bytes follow;
+//! - `0x80..=0xFF`: the next byte is repeated `c - 0x7E` (2 to 129) times.
+
+use std::fmt;
+use crate::assembler::{self, PROGRAM_START};
+
+/// The first address after the end of RAM
+const RAM_END: usize = 0x1000;
+
+const MAX_LITERAL: usize = 0x7F;
+const MAX_RUN: usize = 0xFF - 0x7E;
+
+/// The copier moves this many bytes at once
+const CHUNK_SIZE: usize = 8;
+
+/// Run-length encode data into the stream format described in the module
+/// documentation
+pub fn rle_compress(data: &[u8]) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    let mut literals: Vec<u8> = Vec::new();
+
+    let mut i = 0;
+    while i < data.len() {
+        let run = data[i..]
+            .iter()
+            .take(MAX_RUN)
+            .take_while(|&&byte| byte == data[i])
+            .count();
+        // Shorter runs are stored cheaper as literals
+        if run >= 3 {
+            flush_literals(&mut compressed, &mut literals);
+            compressed.push((run + 0x7E) as u8);
+            compressed.push(data[i]);
+            i += run;
+        } else {
+            literals.push(data[i]);
+            if literals.len() == MAX_LITERAL {
+                flush_literals(&mut compressed, &mut literals);
+            }
+            i += 1;
+        }
+    }
+    flush_literals(&mut compressed, &mut literals);
+    compressed.push(0x00);
+    compressed
+}
+
+fn flush_literals(compressed: &mut Vec<u8>, literals: &mut Vec<u8>) {
+    if !literals.is_empty() {
+        compressed.push(literals.len() as u8);
+        compressed.append(literals);
+    }
+}
+
+/// Decode a stream made by [rle_compress]. Returns `None` if the stream is
+/// truncated
+pub fn rle_decompress(compressed: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut bytes = compressed.iter().copied();
+    loop {
+        match bytes.next()? {
+            0x00 => return Some(data),
+            control @ 0x01..=0x7F => {
+                for _ in 0..control {
+                    data.push(bytes.next()?);
+                }
+            }
+            control => {
+                let byte = bytes.next()?;
+                data.extend(std::iter::repeat_n(byte, (control - 0x7E) as usize));
+            }
+        }
+    }
+}
+
+/// Pack a ROM into a self-extracting one
+pub fn pack(rom: &[u8]) -> Result<Vec<u8>, PackError> {
+    let compressed = rle_compress(rom);
+    let rom_end = PROGRAM_START as usize + rom.len();
+
+    // The lengths of both parts of the stub do not depend on the addresses
+    // they are generated with, so assemble them once to measure them
+    let copier_len = copier(0, 0, 1).len();
+    let decompressor_len = decompressor(0x200, 0).len();
+    let payload_len = decompressor_len + compressed.len();
+    let payload_start = PROGRAM_START as usize + copier_len;
+
+    // The payload is copied above the unpacked ROM, and also above where it
+    // is loaded, so the copy never overlaps its source
+    let destination = rom_end.max(payload_start + payload_len);
+    let n_chunks = payload_len.div_ceil(CHUNK_SIZE);
+    let needed = destination + n_chunks * CHUNK_SIZE;
+    if needed > RAM_END || n_chunks > u8::MAX as usize {
+        return Err(PackError::TooLarge {
+            needed,
+            available: RAM_END,
+        });
+    }
+
+    let mut packed = copier(payload_start, destination, n_chunks as u8);
+    packed.extend(decompressor(destination, destination + decompressor_len));
+    packed.extend(compressed);
+    Ok(packed)
+}
+
+fn copier(source: usize, destination: usize, n_chunks: u8) -> Vec<u8> {
+    assemble_stub(&format!("
+            LD V8, {source_high}
+            LD V9, {source_low}
+            LD VA, {destination_high}
+            LD VB, {destination_low}
+            LD VC, {CHUNK_SIZE}
+            LD VD, {n_chunks}
+        copy:
+            ; Point the two LD I instructions below at the current chunk
+            LD V0, 0xA0
+            OR V0, V8
+            LD V1, V9
+            LD I, load
+            LD [I], V1
+            LD V0, 0xA0
+            OR V0, VA
+            LD V1, VB
+            LD I, store
+            LD [I], V1
+        load:
+            LD I, 0
+            LD V7, [I]
+        store:
+            LD I, 0
+            LD [I], V7
+            ADD V9, VC
+            ADD V8, VF
+            ADD VB, VC
+            ADD VA, VF
+            ADD VD, 0xFF
+            SE VD, 0
+            JP copy
+            JP {destination}
+        ",
+        source_high = source >> 8,
+        source_low = source & 0xFF,
+        destination_high = destination >> 8,
+        destination_low = destination & 0xFF,
+    ))
+}
+
+fn decompressor(origin: usize, stream: usize) -> Vec<u8> {
+    assemble_stub(&format!("
+            .RORG {origin}
+            ; V2:V3 points into the stream, V4:V5 into the unpacked ROM
+            LD V2, {stream_high}
+            LD V3, {stream_low}
+            LD V4, {start_high}
+            LD V5, {start_low}
+            LD V8, 1
+        packet:
+            CALL read
+            SNE V0, 0
+            JP done
+            LD V6, V0
+            LD V9, 0x80
+            AND V9, V6
+            SE V9, 0
+            JP run
+        literal:
+            CALL read
+            LD V7, V0
+            CALL write
+            ADD V6, 0xFF
+            SE V6, 0
+            JP literal
+            JP packet
+        run:
+            LD V9, 0x7E
+            SUB V6, V9
+            CALL read
+            LD V7, V0
+        repeat:
+            CALL write
+            ADD V6, 0xFF
+            SE V6, 0
+            JP repeat
+            JP packet
+
+        ; V0 = next byte of the stream
+        read:
+            LD V0, 0xA0
+            OR V0, V2
+            LD V1, V3
+            LD I, read_pointer
+            LD [I], V1
+        read_pointer:
+            LD I, 0
+            LD V0, [I]
+            ADD V3, V8
+            ADD V2, VF
+            RET
+
+        ; Append V7 to the unpacked ROM
+        write:
+            LD V0, 0xA0
+            OR V0, V4
+            LD V1, V5
+            LD I, write_pointer
+            LD [I], V1
+        write_pointer:
+            LD I, 0
+            LD V0, V7
+            LD [I], V0
+            ADD V5, V8
+            ADD V4, VF
+            RET
+
+        done:
+            LD I, zeros
+            LD VF, [I]
+            LD I, 0
+            JP {PROGRAM_START}
+        zeros:
+            .SPACE 16
+        ",
+        stream_high = stream >> 8,
+        stream_low = stream & 0xFF,
+        start_high = PROGRAM_START >> 8,
+        start_low = PROGRAM_START & 0xFF,
+    ))
+}
+
+fn assemble_stub(source: &str) -> Vec<u8> {
+    assembler::assemble(source).expect("the unpacking stub is valid assembly")
+}
+
+/// An error that can occur while packing a ROM
+#[derive(Debug)]
+pub enum PackError {
+    /// The unpacked ROM and the stub do not fit in RAM together
+    TooLarge {
+        needed: usize,
+        available: usize
+    }
+}
+
+impl std::error::Error for PackError {}
+
+impl fmt::Display for PackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PackError::TooLarge { needed, available } => write!(
+                f,
+                "the unpacked ROM and the unpacking stub need {} bytes of RAM, but only {} are available",
+                needed, available
+            ),
+        }
+    }
+}
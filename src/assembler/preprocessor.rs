@@ -0,0 +1,168 @@
+//! Removing comments, defining constants and assembling conditionally
+//!
+//! Before the two passes, the source goes through a preprocessor handling
+//! these directives, line by line:
+//! - `.EQU name, value`: define a constant. Arguments equal to `name` on
+//!   the following lines are replaced by `value`
+//! - `.IF value`, `.ELSE`, `.ENDIF`: assemble the lines up to `.ELSE` (or
+//!   `.ENDIF`) only if the value is not zero, and the lines after `.ELSE`
+//!   only if it is. Conditionals can be nested
+//! - `.INCLUDE "machine.asm"`: define the constants describing the target
+//!   platform, see [machine_constants]. No file is read
+//!
+//! The lines it handles or skips are left empty, so that errors still point
+//! at the right line of the source.
+
+use std::collections::HashMap;
+use regex::Regex;
+use crate::assembler::{lex, Error, TOKEN_PATTERN};
+use crate::isa::Platform;
+
+/// The name of the generated include defining [machine_constants]
+pub const MACHINE_INCLUDE: &str = "machine.asm";
+
+/// The constants defined by `.INCLUDE "machine.asm"` when assembling for
+/// `target`: `MACHINE_CHIP8`, `MACHINE_SCHIP` and `MACHINE_XOCHIP` (1 for
+/// the target, 0 for the others), and the size of the display in pixels,
+/// `DISPLAY_W` and `DISPLAY_H` (in high resolution mode where there is one)
+pub fn machine_constants(target: Platform) -> Vec<(&'static str, u16)> {
+    let (width, height) = match target {
+        Platform::Chip8 => (64, 32),
+        Platform::SuperChip | Platform::XoChip => (128, 64),
+    };
+    vec![
+        ("MACHINE_CHIP8", (target == Platform::Chip8) as u16),
+        ("MACHINE_SCHIP", (target == Platform::SuperChip) as u16),
+        ("MACHINE_XOCHIP", (target == Platform::XoChip) as u16),
+        ("DISPLAY_W", width),
+        ("DISPLAY_H", height),
+    ]
+}
+
+/// An `.IF` whose `.ENDIF` was not reached yet
+struct Conditional {
+    condition: bool,
+    in_else: bool,
+    line_number: usize,
+    line: String,
+}
+
+impl Conditional {
+    fn assembling(&self) -> bool {
+        self.condition != self.in_else
+    }
+}
+
+/// Strip the comments of the source and handle the directives listed in
+/// the module docs. The result has as many lines as the source
+pub fn preprocess(source: &str, target: Platform) -> Result<String, Error> {
+    let re = Regex::new(TOKEN_PATTERN).unwrap();
+    let mut constants: HashMap<String, u16> = HashMap::new();
+    let mut conditionals: Vec<Conditional> = Vec::new();
+    let mut output = Vec::new();
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        let assembling = conditionals.iter().all(Conditional::assembling);
+        let directive = re.find(line).map(|mat| mat.as_str().to_uppercase()).unwrap_or_default();
+        // The name defined by `.EQU` is not replaced, so redefinitions are
+        // reported as such
+        let n_kept = if directive == ".EQU" { 2 } else { 1 };
+        let line = substitute(&re, line, &constants, n_kept);
+        // Blank lines stay blank, and lines of separators are reported by
+        // the first pass
+        let Some(statement) = lex(&re, &line, line_index + 1) else {
+            output.push(line);
+            continue;
+        };
+
+        match directive.as_str() {
+            ".IF" => {
+                let condition = if assembling {
+                    statement.assert_n_arguments(1)?;
+                    statement.parse_number(0, 16)? != 0
+                } else {
+                    false
+                };
+                conditionals.push(Conditional {
+                    condition,
+                    in_else: false,
+                    line_number: statement.line_number(),
+                    line: statement.line(),
+                });
+            }
+            ".ELSE" | ".ENDIF" => {
+                statement.assert_n_arguments(0)?;
+                let unmatched = || Error::UnmatchedConditional {
+                    directive: directive.clone(),
+                    line_number: statement.line_number(),
+                    line: statement.line(),
+                };
+                if directive == ".ENDIF" {
+                    conditionals.pop().ok_or_else(unmatched)?;
+                } else {
+                    match conditionals.last_mut() {
+                        Some(conditional) if !conditional.in_else => conditional.in_else = true,
+                        _ => return Err(unmatched()),
+                    }
+                }
+            }
+            _ if !assembling => {}
+            ".EQU" => {
+                statement.assert_n_arguments(2)?;
+                let name = statement.argument(0)?;
+                if !is_identifier(name) {
+                    return Err(statement.invalid_argument(0));
+                }
+                if constants.contains_key(name) {
+                    return Err(Error::DuplicateConstant {
+                        name: name.to_string(),
+                        line_number: statement.line_number(),
+                        line: statement.line(),
+                    });
+                }
+                constants.insert(name.to_string(), statement.parse_number(1, 16)?);
+            }
+            ".INCLUDE" if statement.n_arguments() == 1 && statement.parse_string(0)? == MACHINE_INCLUDE => {
+                for (name, value) in machine_constants(target) {
+                    constants.insert(name.to_string(), value);
+                }
+            }
+            _ => {
+                output.push(line);
+                continue;
+            }
+        }
+        output.push(String::new());
+    }
+
+    if let Some(conditional) = conditionals.pop() {
+        return Err(Error::UnterminatedConditional {
+            line_number: conditional.line_number,
+            line: conditional.line,
+        });
+    }
+    Ok(output.join("\n"))
+}
+
+/// Replace the tokens of a line that are constants by their values, except
+/// for the first `n_kept` ones
+fn substitute(re: &Regex, line: &str, constants: &HashMap<String, u16>, n_kept: usize) -> String {
+    let mut substituted = String::new();
+    let mut end = 0;
+    for (index, mat) in re.find_iter(line).enumerate() {
+        substituted.push_str(&line[end..mat.start()]);
+        match constants.get(mat.as_str()) {
+            Some(value) if index >= n_kept => substituted.push_str(&value.to_string()),
+            _ => substituted.push_str(mat.as_str()),
+        }
+        end = mat.end();
+    }
+    substituted.push_str(&line[end..]);
+    substituted
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
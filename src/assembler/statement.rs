@@ -1,7 +1,8 @@
 //! [Statement] struct and its utilities
 
 use crate::*;
-use crate::assembler::{OpcodeAddress, SymbolTable};
+use crate::assembler::{OpcodeAddress, SymbolTable, PROGRAM_START};
+use crate::isa::Register;
 
 /// A span of text in the source code. Used to neatly underline errors
 #[derive(Debug, Clone, Copy)]
@@ -100,10 +101,10 @@ impl<'a> Statement<'a> {
         &self, argument_index: usize, max_n_bits: usize
     ) -> Result<u16, assembler::Error> {
         let lexeme = self.argument(argument_index)?;
-        let num = if lexeme.starts_with("0x") {
-            u16::from_str_radix(&lexeme[2..], 16)
-        } else if lexeme.starts_with("0b") {
-            u16::from_str_radix(&lexeme[2..], 2)
+        let num = if let Some(hex) = lexeme.strip_prefix("0x") {
+            u16::from_str_radix(hex, 16)
+        } else if let Some(binary) = lexeme.strip_prefix("0b") {
+            u16::from_str_radix(binary, 2)
         } else {
             lexeme.parse::<u16>()
         };
@@ -126,14 +127,19 @@ impl<'a> Statement<'a> {
         }
     }
 
+    /// Parse a byte from the argument at the given index
+    pub fn parse_byte(&self, argument_index: usize) -> Result<u8, assembler::Error> {
+        Ok(self.parse_number(argument_index, 8)? as u8)
+    }
+
     /// Parse a register from the argument at the given index
-    pub fn parse_register(&self, argument_index: usize) -> Result<u16, assembler::Error> {
+    pub fn parse_register(&self, argument_index: usize) -> Result<Register, assembler::Error> {
         let lexeme = self.argument(argument_index)?;
         let error = self.invalid_argument(argument_index);
         if lexeme.len() == 2 && lexeme.starts_with('V') {
             let register_char = lexeme.chars().nth(1).unwrap();
             let register = register_char.to_digit(16)
-                .ok_or_else(|| error)? as u16;
+                .ok_or(error)? as Register;
             Ok(register)
         } else {
             Err(error)
@@ -141,7 +147,7 @@ impl<'a> Statement<'a> {
     }
 
     /// Parse only two registers from the arguments and nothing else
-    pub fn parse_only_two_registers(&self) -> Result<(u16, u16), assembler::Error> {
+    pub fn parse_only_two_registers(&self) -> Result<(Register, Register), assembler::Error> {
         self.assert_n_arguments(2)?;
         let x = self.parse_register(0)?;
         let y = self.parse_register(1)?;
@@ -158,7 +164,7 @@ impl<'a> Statement<'a> {
         symbol_table
             .get(lexeme)
             .copied()
-            .map(|x| x + 0x200)  // offset for ROM
+            .map(|x| x + PROGRAM_START)
             .ok_or_else(|| self.invalid_argument(argument_index))
     }
 
@@ -0,0 +1,45 @@
+//! Reading and writing symbol tables
+//!
+//! A symbol file lists one label per line as an absolute address followed
+//! by the label name, sorted by address:
+//!
+//! ```text
+//! 0x0200 start
+//! 0x0216 loop
+//! ```
+
+use std::io::{self, BufRead, Write};
+use crate::assembler::{SymbolTable, PROGRAM_START};
+
+/// Write a symbol table in the symbol file format
+pub fn write_symbol_table(symbol_table: &SymbolTable, mut writer: impl Write) -> io::Result<()> {
+    let mut symbols: Vec<_> = symbol_table.iter().collect();
+    symbols.sort_by_key(|&(label, address)| (*address, label.clone()));
+    for (label, address) in symbols {
+        writeln!(writer, "0x{:04X} {}", address + PROGRAM_START, label)?;
+    }
+    Ok(())
+}
+
+/// Read a symbol table written by [write_symbol_table]
+pub fn read_symbol_table(reader: impl BufRead) -> io::Result<SymbolTable> {
+    let mut symbol_table = SymbolTable::new();
+    for (line_index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = || io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid symbol at line {}: \"{}\"", line_index + 1, line)
+        );
+        let (address, label) = line.split_once(' ').ok_or_else(invalid)?;
+        let address = address.strip_prefix("0x")
+            .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+            .and_then(|address| address.checked_sub(PROGRAM_START))
+            .ok_or_else(invalid)?;
+        symbol_table.insert(label.trim().to_string(), address);
+    }
+    Ok(symbol_table)
+}
@@ -0,0 +1,276 @@
+//! Compiling text tile maps into level data and a routine drawing it
+//!
+//! A tile map file defines the sprites of the tiles, each assigned to a
+//! character, followed by a grid of these characters:
+//!
+//! ```text
+//! ; Comments start with a semicolon
+//! height = 4              ; height of every tile, 1 to 15 (default 8)
+//! tile # = 0xFF 0x99 0x99 0xFF
+//! tile o = 0x00 0x66 0x66 0x00
+//! map
+//! ########
+//! #..o...#
+//! ########
+//! ```
+//!
+//! Tiles are 8 pixels wide, `.` and spaces are empty cells and every row of
+//! the grid must have the same width (trailing spaces are ignored, so empty
+//! cells at the end of a row must be `.`). [TileMap::to_assembly] generates
+//! assembly with the following labels, prefixed with a name:
+//! - `name_draw`: a subroutine drawing the map with its top-left corner at
+//!   (0, 0); it clobbers V0..V7 and I
+//! - `name_map`: the grid, two cells per byte (high nibble first), each the
+//!   index of a tile starting at 1, or 0 for an empty cell
+//! - `name_tiles`: the sprites of the tiles
+//!
+//! The generated code uses labels, so it must be part of the same source
+//! file as the code calling it.
+
+use std::fmt;
+use std::fmt::Write;
+
+const DEFAULT_HEIGHT: u8 = 8;
+const TILE_WIDTH: usize = 8;
+/// Tile indices are nibbles, and 0 is the empty cell
+const MAX_TILES: usize = 15;
+/// The map is indexed with a single register
+const MAX_MAP_BYTES: usize = 256;
+
+/// A parsed tile map
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileMap {
+    /// Height of every tile in pixels
+    pub height: u8,
+    /// The characters and sprites of the tiles, in index order
+    pub tiles: Vec<(char, Vec<u8>)>,
+    /// The tile index of every cell, row by row, 0 being empty
+    pub rows: Vec<Vec<u8>>,
+}
+
+/// Parse the contents of a tile map file
+pub fn parse_tilemap(source: &str) -> Result<TileMap, TileMapError> {
+    let mut height = DEFAULT_HEIGHT;
+    let mut tiles: Vec<(char, Vec<u8>)> = Vec::new();
+    let mut rows: Vec<Vec<u8>> = Vec::new();
+    let mut in_map = false;
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let parse_error = |message: String| TileMapError::Parse { line_number, message };
+
+        if in_map {
+            // Spaces are cells, so only trailing whitespace is ignored
+            let row = line.split(';').next().unwrap_or("").trim_end();
+            if row.is_empty() {
+                continue;
+            }
+            let cells = row
+                .chars()
+                .map(|c| match c {
+                    '.' | ' ' => Ok(0),
+                    _ => tiles.iter()
+                        .position(|(tile, _)| *tile == c)
+                        .map(|index| index as u8 + 1)
+                        .ok_or_else(|| parse_error(format!("undefined tile '{}'", c))),
+                })
+                .collect::<Result<Vec<u8>, _>>()?;
+            if rows.first().is_some_and(|first| first.len() != cells.len()) {
+                return Err(parse_error("all rows must have the same width".to_string()));
+            }
+            rows.push(cells);
+            continue;
+        }
+
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line == "map" {
+            in_map = true;
+            continue;
+        }
+        let (key, value) = line.split_once('=')
+            .ok_or_else(|| parse_error("expected `height = n`, `tile c = bytes` or `map`".to_string()))?;
+        let value = value.trim();
+        match key.split_whitespace().collect::<Vec<_>>()[..] {
+            ["height"] => {
+                height = parse_number(value)
+                    .filter(|height| (1..=15).contains(height))
+                    .ok_or_else(|| parse_error("the height must be between 1 and 15".to_string()))?;
+            }
+            ["tile", character] => {
+                let mut chars = character.chars();
+                let (Some(c), None) = (chars.next(), chars.next()) else {
+                    return Err(parse_error("a tile must be a single character".to_string()));
+                };
+                if c == '.' || tiles.iter().any(|(tile, _)| *tile == c) {
+                    return Err(parse_error(format!("tile '{}' is already defined", c)));
+                }
+                if tiles.len() == MAX_TILES {
+                    return Err(parse_error(format!("at most {} tiles can be defined", MAX_TILES)));
+                }
+                let sprite = value
+                    .split_whitespace()
+                    .map(|byte| parse_number(byte).ok_or_else(|| parse_error(format!("invalid byte {}", byte))))
+                    .collect::<Result<Vec<u8>, _>>()?;
+                tiles.push((c, sprite));
+            }
+            _ => return Err(parse_error(format!("unknown setting \"{}\"", key.trim()))),
+        }
+    }
+
+    if rows.is_empty() {
+        return Err(TileMapError::EmptyMap);
+    }
+    if let Some((c, sprite)) = tiles.iter().find(|(_, sprite)| sprite.len() != height as usize) {
+        return Err(TileMapError::SpriteHeight { tile: *c, n_bytes: sprite.len(), height });
+    }
+    let map = TileMap { height, tiles, rows };
+    if map.packed_map().len() > MAX_MAP_BYTES {
+        return Err(TileMapError::TooLarge { n_bytes: map.packed_map().len() });
+    }
+    Ok(map)
+}
+
+fn parse_number(lexeme: &str) -> Option<u8> {
+    match lexeme.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => match lexeme.strip_prefix("0b") {
+            Some(binary) => u8::from_str_radix(binary, 2).ok(),
+            None => lexeme.parse().ok(),
+        },
+    }
+}
+
+impl TileMap {
+    /// The grid, two cells per byte. Rows of an odd width are padded with
+    /// an empty cell
+    pub fn packed_map(&self) -> Vec<u8> {
+        self.rows
+            .iter()
+            .flat_map(|row| row.chunks(2).map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0)))
+            .collect()
+    }
+
+    /// Generate the assembly of the map, its tiles and the routine drawing
+    /// it. `name` prefixes every label
+    pub fn to_assembly(&self, name: &str) -> String {
+        let bytes_per_row = self.rows[0].len().div_ceil(2);
+        let mut source = format!("\
+; Generated from a tile map: {width}x{rows} tiles of 8x{height} pixels
+
+; Draw the map at (0, 0). Clobbers V0..V7 and I
+{name}_draw:
+    LD V2, 0
+    LD V3, 0
+    LD V6, {rows}
+{name}_draw_row:
+    LD V1, 0
+    LD V7, {bytes_per_row}
+{name}_draw_byte:
+    LD I, {name}_map
+    ADD I, V3
+    LD V0, [I]
+    ADD V3, 1
+    LD V4, V0
+    SHR V4, V4
+    SHR V4, V4
+    SHR V4, V4
+    SHR V4, V4
+    CALL {name}_draw_tile
+    ADD V1, {TILE_WIDTH}
+    LD V4, 0x0F
+    AND V4, V0
+    CALL {name}_draw_tile
+    ADD V1, {TILE_WIDTH}
+    ADD V7, 0xFF
+    SE V7, 0
+    JP {name}_draw_byte
+    ADD V2, {height}
+    ADD V6, 0xFF
+    SE V6, 0
+    JP {name}_draw_row
+    RET
+
+; Draw tile V4 at (V1, V2)
+{name}_draw_tile:
+    SNE V4, 0
+    RET
+    LD I, {name}_tiles
+    LD V5, {height}
+{name}_find_tile:
+    ADD V4, 0xFF
+    SNE V4, 0
+    JP {name}_found_tile
+    ADD I, V5
+    JP {name}_find_tile
+{name}_found_tile:
+    DRW V1, V2, {height}
+    RET
+
+{name}_map:
+",
+            width = self.rows[0].len(),
+            rows = self.rows.len(),
+            height = self.height,
+        );
+        for row in self.packed_map().chunks(bytes_per_row) {
+            writeln!(source, "    {}", format_bytes(row)).unwrap();
+        }
+        writeln!(source, "\n{}_tiles:", name).unwrap();
+        for (c, sprite) in &self.tiles {
+            writeln!(source, "    ; '{}'\n    {}", c, format_bytes(sprite)).unwrap();
+        }
+        source
+    }
+}
+
+fn format_bytes(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!(".byte 0x{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join("\n    ")
+}
+
+/// An error that can occur while parsing a tile map
+#[derive(Debug)]
+pub enum TileMapError {
+    /// The file is malformed
+    Parse {
+        line_number: usize,
+        message: String
+    },
+    /// The map has no rows
+    EmptyMap,
+    /// A sprite does not have as many bytes as the tiles are high
+    SpriteHeight {
+        tile: char,
+        n_bytes: usize,
+        height: u8
+    },
+    /// The packed map does not fit in 256 bytes
+    TooLarge {
+        n_bytes: usize
+    }
+}
+
+impl std::error::Error for TileMapError {}
+
+impl fmt::Display for TileMapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TileMapError::Parse { line_number, message } => {
+                write!(f, "tile map line {}: {}", line_number, message)
+            }
+            TileMapError::EmptyMap => write!(f, "the tile map has no `map` section or no rows"),
+            TileMapError::SpriteHeight { tile, n_bytes, height } => write!(
+                f, "tile '{}' has {} bytes, but tiles are {} pixels high", tile, n_bytes, height
+            ),
+            TileMapError::TooLarge { n_bytes } => write!(
+                f, "the map takes {} bytes, but at most {} are supported", n_bytes, MAX_MAP_BYTES
+            ),
+        }
+    }
+}
@@ -1,26 +1,233 @@
 use std::{env, fs};
+use std::path::Path;
 use chip8vm::logging::error;
 use chip8vm::assembler;
+use chip8vm::assembler::build::{self, Outcome, CACHE_FILE_NAME};
+use chip8vm::assembler::music::parse_song;
+use chip8vm::assembler::pack::pack;
+use chip8vm::assembler::tilemap::parse_tilemap;
+use chip8vm::assembler::debug_info::write_debug_info;
+use chip8vm::assembler::symbols::write_symbol_table;
+use chip8vm::isa::Platform;
+
+const OPTIONS_HELP: &str = "Options:
+    --target <chip8|superchip|xochip>
+        The platform the program is for, which sets the constants of
+        .INCLUDE \"machine.asm\" (default chip8)
+    --symbols <file.sym>
+        Write the addresses of all labels to <file.sym>
+    --debug-info <file.dbg>
+        Write the labels and the source line of every address to <file.dbg>,
+        for interpreter --debug-info and chip8 dap
+    --build <build.toml>
+        Assemble every target of a build file whose sources changed since
+        the last build
+    --pack <input.ch8> <output.ch8>
+        Compress a ROM into a self-extracting one (experimental)
+    --tilemap <level.map> <output.asm>
+        Compile a tile map into level data and a routine drawing it
+    --music <song.txt> <output.asm>
+        Compile a song into a note table and a routine playing it";
+
+/// Command-line options of the assembler
+struct Options {
+    input_path: String,
+    output_path: String,
+    symbols_path: Option<String>,
+    debug_info_path: Option<String>,
+    target: Platform,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut paths = Vec::new();
+    let mut symbols_path = None;
+    let mut debug_info_path = None;
+    let mut target = Platform::Chip8;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--symbols" => {
+                let path = args.next().ok_or("--symbols requires an argument")?;
+                symbols_path = Some(path.clone());
+            }
+            "--debug-info" => {
+                let path = args.next().ok_or("--debug-info requires an argument")?;
+                debug_info_path = Some(path.clone());
+            }
+            "--target" => {
+                let name = args.next().ok_or("--target requires an argument")?;
+                target = Platform::from_name(name).ok_or_else(|| format!("unknown target {}", name))?;
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    match <[String; 2]>::try_from(paths) {
+        Ok([input_path, output_path]) => Ok(Options { input_path, output_path, symbols_path, debug_info_path, target }),
+        Err(_) => Err("expected an input and an output file".to_string()),
+    }
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        eprintln!("Usage: {} <input.asm> <output.ch8>", args[0]);
-        std::process::exit(1);
+    if args.len() == 3 && args[1] == "--build" {
+        run_build(Path::new(&args[2]));
+        return;
+    }
+    if args.len() == 4 && args[1] == "--pack" {
+        run_pack(&args[2], &args[3]);
+        return;
+    }
+    if args.len() == 4 && args[1] == "--tilemap" {
+        run_tilemap(Path::new(&args[2]), &args[3]);
+        return;
+    }
+    if args.len() == 4 && args[1] == "--music" {
+        run_music(Path::new(&args[2]), &args[3]);
+        return;
     }
 
-    let input_path = &args[1];
-    let output_path = &args[2];
+    let options = parse_args(&args[1..]).unwrap_or_else(|e| {
+        error(e);
+        eprintln!(
+            "Usage: {} [options] <input.asm> <output.ch8>\n       {} --build <build.toml>\n       {} --pack <input.ch8> <output.ch8>\n       {} --tilemap <level.map> <output.asm>\n       {} --music <song.txt> <output.asm>\n\n{}",
+            args[0], args[0], args[0], args[0], args[0], OPTIONS_HELP
+        );
+        std::process::exit(1);
+    });
 
-    let bytecode = assembler::assemble_from_file(&input_path)
+    let (bytecode, debug_info) = assembler::assemble_from_file_with_debug_info(&options.input_path, options.target)
         .unwrap_or_else(|e| {
             error(e.to_string());
             std::process::exit(2);
         });
 
-    fs::write(output_path, bytecode)
+    fs::write(&options.output_path, bytecode)
         .unwrap_or_else(|e| {
-            error(format!("failed to write to output file: {}", e.to_string()));
+            error(format!("failed to write to output file: {}", e));
             std::process::exit(3);
         });
+
+    if let Some(symbols_path) = options.symbols_path {
+        fs::File::create(&symbols_path)
+            .and_then(|file| write_symbol_table(&debug_info.symbols, file))
+            .unwrap_or_else(|e| {
+                error(format!("failed to write symbol file: {}", e));
+                std::process::exit(3);
+            });
+    }
+
+    if let Some(debug_info_path) = options.debug_info_path {
+        fs::File::create(&debug_info_path)
+            .and_then(|file| write_debug_info(&debug_info, file))
+            .unwrap_or_else(|e| {
+                error(format!("failed to write debug info: {}", e));
+                std::process::exit(3);
+            });
+    }
+}
+
+fn run_build(build_file: &Path) {
+    let source = fs::read_to_string(build_file).unwrap_or_else(|e| {
+        error(format!("failed to read {}: {}", build_file.display(), e));
+        std::process::exit(1);
+    });
+    let targets = build::parse_build_file(&source).unwrap_or_else(|e| {
+        error(e.to_string());
+        std::process::exit(1);
+    });
+
+    // Paths in the build file are relative to its directory
+    if let Some(dir) = build_file.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        env::set_current_dir(dir).unwrap_or_else(|e| {
+            error(format!("failed to enter {}: {}", dir.display(), e));
+            std::process::exit(1);
+        });
+    }
+
+    let mut cache = build::parse_cache(&fs::read_to_string(CACHE_FILE_NAME).unwrap_or_default());
+    let results = build::build(&targets, &mut cache);
+    if let Err(e) = fs::write(CACHE_FILE_NAME, build::format_cache(&cache)) {
+        error(format!("failed to write {}: {}", CACHE_FILE_NAME, e));
+    }
+
+    let mut n_failed = 0;
+    for (name, result) in results {
+        match result {
+            Ok(Outcome::Built) => println!("{}: built", name),
+            Ok(Outcome::UpToDate) => println!("{}: up to date", name),
+            Err(e) => {
+                error(format!("target {}: {}", name, e));
+                n_failed += 1;
+            }
+        }
+    }
+    if n_failed > 0 {
+        std::process::exit(2);
+    }
+}
+
+fn run_pack(input_path: &str, output_path: &str) {
+    let rom = fs::read(input_path).unwrap_or_else(|e| {
+        error(format!("failed to read {}: {}", input_path, e));
+        std::process::exit(1);
+    });
+    let packed = pack(&rom).unwrap_or_else(|e| {
+        error(e.to_string());
+        std::process::exit(2);
+    });
+    fs::write(output_path, &packed).unwrap_or_else(|e| {
+        error(format!("failed to write to output file: {}", e));
+        std::process::exit(3);
+    });
+
+    println!("{} -> {} bytes", rom.len(), packed.len());
+    if packed.len() >= rom.len() {
+        println!("the ROM does not compress, the packed ROM is not smaller");
+    }
+}
+
+fn run_tilemap(input_path: &Path, output_path: &str) {
+    let source = fs::read_to_string(input_path).unwrap_or_else(|e| {
+        error(format!("failed to read {}: {}", input_path.display(), e));
+        std::process::exit(1);
+    });
+    let tilemap = parse_tilemap(&source).unwrap_or_else(|e| {
+        error(e.to_string());
+        std::process::exit(2);
+    });
+    write_generated(output_path, &tilemap.to_assembly(&label_prefix(input_path)));
+}
+
+fn run_music(input_path: &Path, output_path: &str) {
+    let source = fs::read_to_string(input_path).unwrap_or_else(|e| {
+        error(format!("failed to read {}: {}", input_path.display(), e));
+        std::process::exit(1);
+    });
+    let song = parse_song(&source).unwrap_or_else(|e| {
+        error(e.to_string());
+        std::process::exit(2);
+    });
+    write_generated(output_path, &song.to_assembly(&label_prefix(input_path)));
+}
+
+/// Labels of generated code are prefixed with the name of the file it was
+/// generated from, so code generated from several files can be combined
+fn label_prefix(input_path: &Path) -> String {
+    input_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+fn write_generated(output_path: &str, source: &str) {
+    fs::write(output_path, source).unwrap_or_else(|e| {
+        error(format!("failed to write to output file: {}", e));
+        std::process::exit(3);
+    });
 }
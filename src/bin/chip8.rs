@@ -0,0 +1,1040 @@
+use std::{env, fs, thread};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+use chip8vm::database::Database;
+use chip8vm::assembler::symbols::read_symbol_table;
+use chip8vm::debugger::{dap, location, Debugger};
+use chip8vm::disassembler::{analyze, Analysis};
+use chip8vm::frontend::palette::{palette_by_name, DEFAULT_PALETTE};
+use chip8vm::frontend::scaler::{scaler_by_name, Scaler};
+use chip8vm::isa::Platform;
+use chip8vm::json::{self, Value};
+use chip8vm::logging::{error, file_warning};
+use chip8vm::interpreter::{check_rom, display_hash, display_to_text, parse_address, VM};
+use chip8vm::interpreter::coredump::Core;
+use chip8vm::interpreter::diff::{format_changes, format_diff, format_displays};
+use chip8vm::interpreter::lockstep::{self, Comparison, Frames};
+use chip8vm::interpreter::replay::Replay;
+use chip8vm::interpreter::state::State;
+use chip8vm::interpreter::trace::{diff_canonical, CanonicalDiff, Trace, TraceFormat};
+use chip8vm::png::{self, Image};
+
+const COMMANDS_HELP: &str = "Commands:
+    compare [--cycles <n>] [--seed <n>] [--input <replay>] [--ignore-ram] <a.ch8> <b.ch8>
+        Run two ROMs in lockstep for <n> instructions (default 100000), with
+        both random number generators seeded with <n> (default 0, or the
+        seed of the replay), and show the states where they first differ.
+        --input presses the keys recorded with interpreter --record-input,
+        at the speed they were recorded at, and --ignore-ram only compares
+        the registers, timers, stack and display, since the RAM of two
+        builds of a program differs from the start
+    run [--cycles <n>] [--seed <n>] [--input <replay>] [--expect-display-hash <hash>] [--trace-file <file>]
+        [--run-until <address>] [--break-at <address>]... [--dump-state <file>] [--max-cycles <n>] <rom.ch8>
+        Run a ROM without a window for <n> instructions (default 100000),
+        like compare, and print the hash of the final display, failing if
+        it is not <hash> (see display_hash in src/interpreter.rs).
+        --trace-file writes every instruction to <file> in the canonical
+        trace format (see src/interpreter/trace.rs). --run-until and
+        --break-at stop before the instruction at an address instead,
+        --run-until failing if it is not reached, and --dump-state writes
+        the final state as JSON to <file>, or to the standard output if
+        <file> is -. --max-cycles runs until the ROM halts (jumps to
+        itself) instead, exiting with status 3 if it did not after <n>
+        instructions
+    frames [--frames <n>] [--every <n>] [--seed <n>] [--input <replay>] [--scale <n>] [--scaler <name>]
+        [--palette <name>] [--sheet <columns>] <rom.ch8> <output>
+        Run a ROM without a window for <n> frames (default 600, 10 seconds),
+        like run, and save the display at the end of every <n>th frame
+        (every frame by default) into the directory <output>, as
+        frame-00001.png... numbered from the first frame, or with --sheet,
+        as a single sprite sheet <output> with <columns> frames per row.
+        Frames are upscaled like the window of the interpreter (4 times
+        with nearest by default), in the colors of --palette
+    trace-diff <mine.log> <reference.log>
+        Compare two traces in the canonical format, for example one of
+        another emulator, and show the first instruction where they differ
+    diff-state [--symbols <file.sym>] <a.state> <b.state>
+        Show the registers, stack slots and RAM bytes that changed from one
+        state to the other, with the labels of the addresses, and how many
+        pixels of the display. States are in the binary format or the JSON
+        of interpreter --dump-state-on-exit (see src/interpreter/state.rs).
+        Exits with status 2 if they differ, like trace-diff
+    inspect-core [--ram] <crash.c8core>
+        Show the core file the interpreter wrote when a program stopped with
+        an error: the error, the registers, the stack, the last instructions
+        executed with the registers they changed and the display, and with
+        --ram, a hexdump of the RAM (see src/interpreter/coredump.rs)
+    dap
+        Serve the Debug Adapter Protocol on the standard input and output,
+        for editors to debug programs (see src/debugger/dap.rs)
+    verify-replay [--expected-state <state.json>] <rom.ch8> <replay>
+        Play a replay recorded with interpreter --record-input back without
+        a window and check that the final state has the values of
+        <state.json> (keys left out and null array items are not checked),
+        or print the final state as JSON to create <state.json>
+    compare-screens [--as-chip8] <a.png> <b.png>
+        Compare two screenshots pixel by pixel, or with --as-chip8, reduce
+        them to 64x32 CHIP-8 pixels first and highlight the ones that differ
+    usage <rom.ch8 | dir>...
+        Report which opcodes the code reachable in each ROM uses, and list
+        the ROMs needing SUPER-CHIP or XO-CHIP, which cannot be run yet
+    import-database <programs.json> <database.txt>
+        Convert the programs.json file of the CHIP-8 database
+        (https://github.com/chip-8/chip-8-database) into a ROM database
+    test [--watch]
+        Run the integration test suites (from the repository root), built
+        without SDL, and with --watch, run them again whenever a source file
+        or fixture changes";
+
+const DEFAULT_COMPARE_CYCLES: u64 = 100_000;
+const DEFAULT_EXPORTED_FRAMES: u64 = 600;
+/// The width of the lines between the frames of a sprite sheet, in pixels
+const SHEET_GAP: usize = 2;
+const SHEET_GAP_COLOR: [u8; 4] = [0x40, 0x40, 0x40, 0xFF];
+/// The exit status of `run --max-cycles` when the ROM did not halt in time
+const EXIT_MAX_CYCLES: i32 = 3;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let result = match args.get(1).map(String::as_str) {
+        Some("compare") => parse_compare_args(&args[2..]).map(compare),
+        Some("run") => parse_run_args(&args[2..]).map(run),
+        Some("frames") => parse_frames_args(&args[2..]).map(frames),
+        Some("trace-diff") => <[String; 2]>::try_from(args[2..].to_vec())
+            .map_err(|_| "trace-diff expects two traces".to_string())
+            .map(trace_diff),
+        Some("diff-state") => parse_diff_state_args(&args[2..]).map(diff_state),
+        Some("inspect-core") => parse_inspect_core_args(&args[2..]).map(inspect_core),
+        Some("dap") if args.len() == 2 => {
+            dap::serve(io::stdin(), io::stdout().lock()).map_err(|e| format!("failed to serve: {}", e))
+        }
+        Some("dap") => Err("dap expects no arguments".to_string()),
+        Some("verify-replay") => parse_verify_replay_args(&args[2..]).map(verify_replay),
+        Some("compare-screens") => parse_compare_screens_args(&args[2..]).map(compare_screens),
+        Some("usage") => parse_usage_args(&args[2..]).map(usage),
+        Some("import-database") => <[String; 2]>::try_from(args[2..].to_vec())
+            .map_err(|_| "import-database expects an input and an output file".to_string())
+            .map(import_database),
+        Some("test") => parse_test_args(&args[2..]).map(test),
+        Some(command) => Err(format!("unknown command {}", command)),
+        None => Err("expected a command".to_string()),
+    };
+    if let Err(e) = result {
+        error(e);
+        eprintln!("Usage: {} <command> [options]\n\n{}", args[0], COMMANDS_HELP);
+        std::process::exit(1);
+    }
+}
+
+/// Options of the `compare` command
+struct CompareOptions {
+    rom_paths: [String; 2],
+    cycles: u64,
+    seed: Option<u64>,
+    input_path: Option<String>,
+    ignore_ram: bool,
+}
+
+fn parse_compare_args(args: &[String]) -> Result<CompareOptions, String> {
+    let mut paths = Vec::new();
+    let mut cycles = DEFAULT_COMPARE_CYCLES;
+    let mut seed = None;
+    let mut input_path = None;
+    let mut ignore_ram = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cycles" | "--seed" => {
+                let value = args.next().ok_or(format!("{} requires an argument", arg))?;
+                let value = value.parse().map_err(|_| format!("invalid number {}", value))?;
+                if arg == "--cycles" { cycles = value } else { seed = Some(value) }
+            }
+            "--input" => input_path = Some(args.next().ok_or("--input requires an argument")?.clone()),
+            "--ignore-ram" => ignore_ram = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    let rom_paths = <[String; 2]>::try_from(paths)
+        .map_err(|_| "compare expects two ROMs".to_string())?;
+    Ok(CompareOptions { rom_paths, cycles, seed, input_path, ignore_ram })
+}
+
+fn load_vm(path: &str, seed: u64) -> VM {
+    let rom = fs::read(path).unwrap_or_else(|e| {
+        error(format!("failed to read {}: {}", path, e));
+        std::process::exit(1);
+    });
+    check_rom(&rom).unwrap_or_else(|e| {
+        error(format!("{}: {}", path, e));
+        std::process::exit(1);
+    });
+    if rom.len() % 2 == 1 {
+        file_warning("odd size, the ROM may be truncated (padded with a zero byte)".to_string(), path);
+    }
+    let mut vm = VM::new();
+    vm.seed_rng(seed);
+    vm.load_program(&rom);
+    vm
+}
+
+fn load_replay(path: &str) -> Replay {
+    File::open(path).and_then(|file| Replay::read_from(BufReader::new(file))).unwrap_or_else(|e| {
+        error(format!("failed to read {}: {}", path, e));
+        std::process::exit(1);
+    })
+}
+
+fn compare(options: CompareOptions) {
+    let [path_a, path_b] = &options.rom_paths;
+    let replay = options.input_path.as_deref().map(load_replay);
+    let seed = options.seed.or(replay.as_ref().map(|replay| replay.seed)).unwrap_or(0);
+    let mut vms = [load_vm(path_a, seed), load_vm(path_b, seed)];
+    let mut frames = Frames::new(replay, &mut vms);
+
+    match lockstep::compare(&mut vms, &mut frames, options.cycles, options.ignore_ram) {
+        Comparison::Identical => println!("No difference in {} instructions", options.cycles),
+        Comparison::Stopped { cycles, error } => {
+            println!("Both ROMs stopped identically after {} instructions: {}", cycles, error);
+        }
+        Comparison::Diverged(divergence) => {
+            println!("States differ after instruction {} (frame {})", divergence.cycles, divergence.frame);
+            for ((name, path), error) in ["A", "B"].iter().zip(&options.rom_paths).zip(&divergence.errors) {
+                match error {
+                    None => println!("{}: {}", name, path),
+                    Some(e) => println!("{}: {} (stopped: {})", name, path, e),
+                }
+            }
+            println!();
+            let [state_a, state_b] = &divergence.states;
+            print!("{}", format_diff(state_a, state_b, "A", "B"));
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Options of the `run` command
+struct RunOptions {
+    rom_path: String,
+    cycles: u64,
+    seed: Option<u64>,
+    input_path: Option<String>,
+    expected_hash: Option<String>,
+    trace_path: Option<String>,
+    /// The address of `--run-until`, which must be reached
+    run_until: Option<u16>,
+    break_at: Vec<u16>,
+    state_dump_path: Option<String>,
+    /// Run until the ROM halts, at most this many instructions, instead of
+    /// `cycles`
+    max_cycles: Option<u64>,
+}
+
+fn parse_run_args(args: &[String]) -> Result<RunOptions, String> {
+    let mut paths = Vec::new();
+    let mut cycles = DEFAULT_COMPARE_CYCLES;
+    let mut seed = None;
+    let mut input_path = None;
+    let mut expected_hash = None;
+    let mut trace_path = None;
+    let mut run_until = None;
+    let mut break_at = Vec::new();
+    let mut state_dump_path = None;
+    let mut max_cycles = None;
+    let mut cycles_given = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cycles" | "--seed" => {
+                let value = args.next().ok_or(format!("{} requires an argument", arg))?;
+                let value = value.parse().map_err(|_| format!("invalid number {}", value))?;
+                if arg == "--cycles" { cycles = value } else { seed = Some(value) }
+                cycles_given |= arg == "--cycles";
+            }
+            "--max-cycles" => {
+                let value = args.next().ok_or("--max-cycles requires an argument")?;
+                max_cycles = Some(value.parse().map_err(|_| format!("invalid number {}", value))?);
+            }
+            "--input" => input_path = Some(args.next().ok_or("--input requires an argument")?.clone()),
+            "--expect-display-hash" => {
+                let hash = args.next().ok_or("--expect-display-hash requires an argument")?;
+                if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(format!("invalid display hash {} (expected 40 hexadecimal digits)", hash));
+                }
+                expected_hash = Some(hash.to_ascii_lowercase());
+            }
+            "--trace-file" => trace_path = Some(args.next().ok_or("--trace-file requires an argument")?.clone()),
+            "--run-until" | "--break-at" => {
+                let value = args.next().ok_or(format!("{} requires an argument", arg))?;
+                let address = parse_address(value).ok_or(format!("invalid address {}", value))?;
+                if arg == "--run-until" { run_until = Some(address) } else { break_at.push(address) }
+            }
+            "--dump-state" => state_dump_path = Some(args.next().ok_or("--dump-state requires an argument")?.clone()),
+            // Nothing is ever shown, but CI scripts may say so
+            "--headless" => {}
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    let [rom_path] = <[String; 1]>::try_from(paths)
+        .map_err(|_| "run expects one ROM".to_string())?;
+    if cycles_given && max_cycles.is_some() {
+        return Err("--cycles and --max-cycles cannot be combined".to_string());
+    }
+    Ok(RunOptions {
+        rom_path, cycles, seed, input_path, expected_hash, trace_path, run_until, break_at, state_dump_path, max_cycles,
+    })
+}
+
+/// Check whether the next instruction jumps to itself, which is how
+/// programs end
+fn is_halted(vm: &VM) -> bool {
+    let pc = vm.pc() as usize;
+    vm.ram().get(pc..pc + 2).is_some_and(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]) == 0x1000 | pc as u16)
+}
+
+fn run(options: RunOptions) {
+    let replay = options.input_path.as_deref().map(load_replay);
+    let seed = options.seed.or(replay.as_ref().map(|replay| replay.seed)).unwrap_or(0);
+    let mut vms = [load_vm(&options.rom_path, seed)];
+    if let Some(path) = &options.trace_path {
+        let file = File::create(path).unwrap_or_else(|e| {
+            error(format!("failed to create {}: {}", path, e));
+            std::process::exit(1);
+        });
+        let mut trace = Trace::new(Box::new(file), None);
+        trace.set_format(TraceFormat::Canonical);
+        vms[0].set_trace(trace);
+    }
+    let mut frames = Frames::new(replay, &mut vms);
+    let dump_state = |vm: &VM| {
+        if let Some(path) = &options.state_dump_path {
+            let json = vm.state().to_json().to_pretty_string() + "\n";
+            if path == "-" {
+                print!("{}", json);
+            } else if let Err(e) = fs::write(path, json) {
+                error(format!("failed to write {}: {}", path, e));
+            }
+        }
+    };
+
+    let limit = options.max_cycles.unwrap_or(options.cycles);
+    let mut cycles = 0;
+    let mut stopped = false;
+    while cycles < limit {
+        let pc = vms[0].pc();
+        if options.run_until == Some(pc) || options.break_at.contains(&pc) {
+            println!("Stopped after {} instructions (frame {}) at {}", cycles, frames.frame + 1, location(&vms[0]));
+            stopped = true;
+            break;
+        }
+        if options.max_cycles.is_some() && is_halted(&vms[0]) {
+            println!("The ROM halted after {} instructions at {}", cycles, location(&vms[0]));
+            stopped = true;
+            break;
+        }
+        if let Err(e) = vms[0].step() {
+            println!("The ROM stopped after {} instructions: {}", cycles, e);
+            dump_state(&vms[0]);
+            std::process::exit(2);
+        }
+        cycles += 1;
+        frames.update(cycles, &mut vms);
+    }
+
+    let hash = display_hash(vms[0].display());
+    println!("Display hash after {} instructions: {}", cycles, hash);
+    dump_state(&vms[0]);
+    if let Some(max_cycles) = options.max_cycles && !stopped {
+        println!("The ROM did not halt in {} instructions", max_cycles);
+        std::process::exit(EXIT_MAX_CYCLES);
+    }
+    if let Some(address) = options.run_until && vms[0].pc() != address {
+        println!("0x{:03X} was not reached in {} instructions", address, limit);
+        std::process::exit(2);
+    }
+    if let Some(expected) = options.expected_hash && hash != expected {
+        println!("Expected {}, the display is:\n{}", expected, display_to_text(vms[0].display()));
+        std::process::exit(2);
+    }
+}
+
+/// Options of the `frames` command
+struct FramesOptions {
+    rom_path: String,
+    output_path: PathBuf,
+    frames: u64,
+    every: u64,
+    seed: Option<u64>,
+    input_path: Option<String>,
+    scaler: Box<dyn Scaler>,
+    colors: ([u8; 3], [u8; 3]),
+    /// The number of frames per row of the sprite sheet, if writing one
+    sheet_columns: Option<usize>,
+}
+
+fn parse_frames_args(args: &[String]) -> Result<FramesOptions, String> {
+    let mut paths = Vec::new();
+    let mut frames = DEFAULT_EXPORTED_FRAMES;
+    let mut every = 1;
+    let mut seed = None;
+    let mut input_path = None;
+    let mut scale = 4;
+    let mut scaler = "nearest".to_string();
+    let mut colors = DEFAULT_PALETTE;
+    let mut sheet_columns = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" | "--every" | "--seed" | "--scale" | "--sheet" => {
+                let value = args.next().ok_or(format!("{} requires an argument", arg))?;
+                let number: u64 = value.parse().map_err(|_| format!("invalid number {}", value))?;
+                if number == 0 && arg != "--seed" {
+                    return Err(format!("{} must be at least 1", arg));
+                }
+                match arg.as_str() {
+                    "--frames" => frames = number,
+                    "--every" => every = number,
+                    "--seed" => seed = Some(number),
+                    "--scale" => scale = number as usize,
+                    _ => sheet_columns = Some(number as usize),
+                }
+            }
+            "--input" => input_path = Some(args.next().ok_or("--input requires an argument")?.clone()),
+            "--scaler" => scaler = args.next().ok_or("--scaler requires an argument")?.clone(),
+            "--palette" => colors = palette_by_name(args.next().ok_or("--palette requires an argument")?)?,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    let [rom_path, output_path] = <[String; 2]>::try_from(paths)
+        .map_err(|_| "frames expects a ROM and an output".to_string())?;
+    Ok(FramesOptions {
+        rom_path,
+        output_path: PathBuf::from(output_path),
+        frames,
+        every,
+        seed,
+        input_path,
+        scaler: scaler_by_name(&scaler, scale)?,
+        colors,
+        sheet_columns,
+    })
+}
+
+/// Run a ROM for a number of frames and save some of the displays, as PNG
+/// files or a sprite sheet
+fn frames(options: FramesOptions) {
+    let replay = options.input_path.as_deref().map(load_replay);
+    let seed = options.seed.or(replay.as_ref().map(|replay| replay.seed)).unwrap_or(0);
+    let mut vms = [load_vm(&options.rom_path, seed)];
+    if options.sheet_columns.is_none() && let Err(e) = fs::create_dir_all(&options.output_path) {
+        error(format!("failed to create {}: {}", options.output_path.display(), e));
+        std::process::exit(1);
+    }
+    let write = |path: &Path, image: &Image| {
+        if let Err(e) = fs::write(path, png::encode(image)) {
+            error(format!("failed to write {}: {}", path.display(), e));
+            std::process::exit(1);
+        }
+    };
+
+    let mut frames = Frames::new(replay, &mut vms);
+    let mut sheet = Vec::new();
+    let mut saved = 0;
+    let mut cycles = 0;
+    let mut stopped = None;
+    while frames.frame < options.frames {
+        if let Err(e) = vms[0].step() {
+            stopped = Some(e);
+            break;
+        }
+        cycles += 1;
+        let frame = frames.frame;
+        frames.update(cycles, &mut vms);
+        if frames.frame == frame || !frames.frame.is_multiple_of(options.every) {
+            continue;
+        }
+        let (background, foreground) = options.colors;
+        let image = options.scaler.render(vms[0].display(), background, foreground);
+        saved += 1;
+        if options.sheet_columns.is_some() {
+            sheet.push(image);
+        } else {
+            write(&options.output_path.join(format!("frame-{:05}.png", frames.frame)), &image);
+        }
+    }
+
+    if let Some(columns) = options.sheet_columns {
+        write(&options.output_path, &sprite_sheet(&sheet, columns));
+    }
+    println!("Saved {} frames to {}", saved, options.output_path.display());
+    if let Some(e) = stopped {
+        println!("The ROM stopped after {} instructions (frame {}): {}", cycles, frames.frame + 1, e);
+        std::process::exit(2);
+    }
+}
+
+/// Lay images of the same size out in rows of `columns`, separated by
+/// lines of [SHEET_GAP_COLOR]
+fn sprite_sheet(images: &[Image], columns: usize) -> Image {
+    let (cell_width, cell_height) = images.first().map_or((0, 0), |image| (image.width, image.height));
+    let columns = columns.min(images.len());
+    let rows = images.len().div_ceil(columns.max(1));
+    let width = (columns * (cell_width + SHEET_GAP)).saturating_sub(SHEET_GAP);
+    let height = (rows * (cell_height + SHEET_GAP)).saturating_sub(SHEET_GAP);
+    let mut pixels = vec![SHEET_GAP_COLOR; width * height];
+    for (i, image) in images.iter().enumerate() {
+        let (left, top) = (i % columns * (cell_width + SHEET_GAP), i / columns * (cell_height + SHEET_GAP));
+        for (y, row) in image.pixels.chunks(cell_width).enumerate() {
+            let start = (top + y) * width + left;
+            pixels[start..start + cell_width].copy_from_slice(row);
+        }
+    }
+    Image { width, height, pixels }
+}
+
+/// Compare two traces in the canonical format line by line, and show the
+/// first instruction where they differ
+fn trace_diff(paths: [String; 2]) {
+    let traces = paths.each_ref().map(|path| {
+        let file = File::open(path).unwrap_or_else(|e| {
+            error(format!("failed to read {}: {}", path, e));
+            std::process::exit(1);
+        });
+        BufReader::new(file).lines().map(move |line| line.unwrap_or_else(|e| {
+            error(format!("failed to read {}: {}", path, e));
+            std::process::exit(1);
+        }))
+    });
+    let diff = diff_canonical(traces).unwrap_or_else(|e| {
+        error(format!("{} line {}: not a canonical trace line: \"{}\"", paths[e.trace], e.line_number, e.line));
+        std::process::exit(1);
+    });
+    match diff {
+        CanonicalDiff::Identical { instructions } => println!("No difference in {} instructions", instructions),
+        CanonicalDiff::Differ { instruction, fields, lines, previous } => {
+            println!("The traces differ at instruction {} ({}):", instruction, fields.join(", "));
+            if let Some(previous) = previous {
+                println!("  before: {}", previous);
+            }
+            println!("  {}: {}", paths[0], lines[0]);
+            println!("  {}: {}", paths[1], lines[1]);
+            std::process::exit(2);
+        }
+        CanonicalDiff::Ends { shorter, instructions } => {
+            println!("{} ends after {} instructions, {} goes on", paths[shorter], instructions, paths[1 - shorter]);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Options of the `diff-state` command
+struct DiffStateOptions {
+    state_paths: [String; 2],
+    symbols_path: Option<String>,
+}
+
+fn parse_diff_state_args(args: &[String]) -> Result<DiffStateOptions, String> {
+    let mut paths = Vec::new();
+    let mut symbols_path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--symbols" => symbols_path = Some(args.next().ok_or("--symbols requires an argument")?.clone()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg.clone()),
+        }
+    }
+    let state_paths = <[String; 2]>::try_from(paths).map_err(|_| "diff-state expects two states".to_string())?;
+    Ok(DiffStateOptions { state_paths, symbols_path })
+}
+
+/// Read a state in the binary format, or in JSON if it starts with `{`
+fn load_state(path: &str) -> State {
+    let bytes = fs::read(path).unwrap_or_else(|e| {
+        error(format!("failed to read {}: {}", path, e));
+        std::process::exit(1);
+    });
+    let state = if bytes.trim_ascii_start().starts_with(b"{") {
+        String::from_utf8(bytes).map_err(|_| "invalid UTF-8".to_string())
+            .and_then(|text| json::parse(&text).map_err(|e| e.to_string()))
+            .and_then(|json| State::from_json(&json))
+    } else {
+        State::read_from(&bytes[..]).map_err(|e| e.to_string())
+    };
+    state.unwrap_or_else(|e| {
+        error(format!("{}: {}", path, e));
+        std::process::exit(1);
+    })
+}
+
+fn diff_state(options: DiffStateOptions) {
+    let [a, b] = options.state_paths.each_ref().map(|path| load_state(path));
+    let mut debugger = Debugger::new();
+    if let Some(path) = &options.symbols_path {
+        match File::open(path).and_then(|file| read_symbol_table(BufReader::new(file))) {
+            Ok(symbols) => debugger.set_symbols(&symbols),
+            Err(e) => {
+                error(format!("failed to read {}: {}", path, e));
+                std::process::exit(1);
+            }
+        }
+    }
+    print!("{}", format_changes(&a, &b, |address| debugger.label_of(address)));
+    if a != b {
+        std::process::exit(2);
+    }
+}
+
+/// Options of the `inspect-core` command
+struct InspectCoreOptions {
+    path: String,
+    ram: bool,
+}
+
+fn parse_inspect_core_args(args: &[String]) -> Result<InspectCoreOptions, String> {
+    let mut path = None;
+    let mut ram = false;
+    for arg in args {
+        match arg.as_str() {
+            "--ram" => ram = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err("inspect-core expects one core file".to_string()),
+        }
+    }
+    Ok(InspectCoreOptions { path: path.ok_or("inspect-core expects a core file")?, ram })
+}
+
+fn inspect_core(options: InspectCoreOptions) {
+    let core = File::open(&options.path)
+        .and_then(|file| Core::read_from(BufReader::new(file)))
+        .unwrap_or_else(|e| {
+            error(format!("failed to read {}: {}", options.path, e));
+            std::process::exit(1);
+        });
+    print!("{}", core.report(options.ram));
+}
+
+/// Options of the `verify-replay` command
+struct VerifyReplayOptions {
+    rom_path: String,
+    replay_path: String,
+    expected_state_path: Option<String>,
+}
+
+fn parse_verify_replay_args(args: &[String]) -> Result<VerifyReplayOptions, String> {
+    let mut paths = Vec::new();
+    let mut expected_state_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--expected-state" => expected_state_path = Some(args.next().ok_or("--expected-state requires an argument")?.clone()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    let [rom_path, replay_path] = <[String; 2]>::try_from(paths)
+        .map_err(|_| "verify-replay expects a ROM and a replay".to_string())?;
+    Ok(VerifyReplayOptions { rom_path, replay_path, expected_state_path })
+}
+
+fn verify_replay(options: VerifyReplayOptions) {
+    let expected = options.expected_state_path.as_ref().map(|path| {
+        let source = fs::read_to_string(path).unwrap_or_else(|e| {
+            error(format!("failed to read {}: {}", path, e));
+            std::process::exit(1);
+        });
+        json::parse(&source).unwrap_or_else(|e| {
+            error(format!("{}: {}", path, e));
+            std::process::exit(1);
+        })
+    });
+    let replay = load_replay(&options.replay_path);
+    let mut vm = load_vm(&options.rom_path, replay.seed);
+    replay.set_up(&mut vm);
+    replay.apply(0, &mut vm);
+    if let Err(e) = vm.run_frames(replay.frames as u64, |vm| replay.apply(vm.frames() as u32, vm)) {
+        println!("The ROM stopped at frame {} of {}: {}", vm.frames(), replay.frames, e);
+        std::process::exit(2);
+    }
+
+    let state = vm.state().to_json();
+    let Some(expected) = expected else {
+        println!("{}", state.to_pretty_string());
+        return;
+    };
+    let mut differences = Vec::new();
+    json_differences(&expected, &state, "state", &mut differences);
+    if differences.is_empty() {
+        println!("The final state matches after {} frames", replay.frames);
+        return;
+    }
+    println!("The final state differs after {} frames:", replay.frames);
+    for difference in differences {
+        println!("  {}", difference);
+    }
+    std::process::exit(2);
+}
+
+/// List where `actual` differs from `expected`, ignoring the members of
+/// objects that `expected` leaves out and its `null` array items
+fn json_differences(expected: &Value, actual: &Value, path: &str, differences: &mut Vec<String>) {
+    match (expected, actual) {
+        (Value::Object(members), Value::Object(_)) => {
+            for (key, value) in members {
+                match actual.get(key) {
+                    Some(actual) => json_differences(value, actual, &format!("{}.{}", path, key), differences),
+                    None => differences.push(format!("{}.{}: unknown key", path, key)),
+                }
+            }
+        }
+        (Value::Array(expected_items), Value::Array(actual_items)) => {
+            if expected_items.len() != actual_items.len() {
+                differences.push(format!("{}: expected {} items, got {}", path, expected_items.len(), actual_items.len()));
+                return;
+            }
+            for (i, (expected, actual)) in expected_items.iter().zip(actual_items).enumerate() {
+                if *expected == Value::Null {
+                    continue;
+                }
+                json_differences(expected, actual, &format!("{}[{}]", path, i), differences);
+            }
+        }
+        _ if expected != actual => differences.push(format!("{}: expected {}, got {}", path, expected, actual)),
+        _ => {}
+    }
+}
+
+/// Options of the `compare-screens` command
+struct CompareScreensOptions {
+    image_paths: [String; 2],
+    as_chip8: bool,
+}
+
+fn parse_compare_screens_args(args: &[String]) -> Result<CompareScreensOptions, String> {
+    let mut paths = Vec::new();
+    let mut as_chip8 = false;
+    for arg in args {
+        match arg.as_str() {
+            "--as-chip8" => as_chip8 = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => paths.push(arg.clone()),
+        }
+    }
+
+    let image_paths = <[String; 2]>::try_from(paths)
+        .map_err(|_| "compare-screens expects two images".to_string())?;
+    Ok(CompareScreensOptions { image_paths, as_chip8 })
+}
+
+fn load_image(path: &str) -> Image {
+    let data = fs::read(path).unwrap_or_else(|e| {
+        error(format!("failed to read {}: {}", path, e));
+        std::process::exit(1);
+    });
+    png::decode(&data).unwrap_or_else(|e| {
+        error(format!("{}: {}", path, e));
+        std::process::exit(1);
+    })
+}
+
+fn compare_screens(options: CompareScreensOptions) {
+    let [path_a, path_b] = &options.image_paths;
+    let (a, b) = (load_image(path_a), load_image(path_b));
+
+    if options.as_chip8 {
+        let (display_a, display_b) = (a.to_display(), b.to_display());
+        let n_different = display_a.iter().flatten()
+            .zip(display_b.iter().flatten())
+            .filter(|(x, y)| x != y)
+            .count();
+        if n_different == 0 {
+            println!("The screens are identical at the CHIP-8 pixel level");
+            return;
+        }
+        println!("{} CHIP-8 pixels differ\nA: {}\nB: {}\n", n_different, path_a, path_b);
+        print!("{}", format_displays(&display_a, &display_b, "A", "B"));
+        std::process::exit(2);
+    }
+
+    if (a.width, a.height) != (b.width, b.height) {
+        println!(
+            "The screens have different sizes: {}x{} and {}x{} (use --as-chip8 to compare them anyway)",
+            a.width, a.height, b.width, b.height
+        );
+        std::process::exit(2);
+    }
+    let different: Vec<(usize, usize)> = (0..a.height)
+        .flat_map(|y| (0..a.width).map(move |x| (x, y)))
+        .filter(|&(x, y)| a.pixel(x, y) != b.pixel(x, y))
+        .collect();
+    if different.is_empty() {
+        println!("The screens are identical");
+        return;
+    }
+    let min_x = different.iter().map(|&(x, _)| x).min().unwrap();
+    let max_x = different.iter().map(|&(x, _)| x).max().unwrap();
+    let min_y = different.iter().map(|&(_, y)| y).min().unwrap();
+    let max_y = different.iter().map(|&(_, y)| y).max().unwrap();
+    println!(
+        "{} pixels differ, between ({}, {}) and ({}, {})",
+        different.len(), min_x, min_y, max_x, max_y
+    );
+    std::process::exit(2);
+}
+
+/// Options of the `usage` command
+struct UsageOptions {
+    paths: Vec<String>,
+}
+
+fn parse_usage_args(args: &[String]) -> Result<UsageOptions, String> {
+    if let Some(arg) = args.iter().find(|arg| arg.starts_with("--")) {
+        return Err(format!("unknown option {}", arg));
+    }
+    if args.is_empty() {
+        return Err("usage expects ROMs or directories".to_string());
+    }
+    Ok(UsageOptions { paths: args.to_vec() })
+}
+
+fn usage(options: UsageOptions) {
+    let mut roms = Vec::new();
+    for path in options.paths.iter().map(PathBuf::from) {
+        if path.is_dir() {
+            find_roms(&path, &mut roms).unwrap_or_else(|e| {
+                error(format!("failed to read directory {}: {}", path.display(), e));
+                std::process::exit(1);
+            });
+        } else {
+            roms.push(path);
+        }
+    }
+    roms.sort();
+
+    let mut analyses: Vec<(PathBuf, Analysis)> = Vec::new();
+    for rom in roms {
+        match fs::read(&rom) {
+            Ok(bytecode) => analyses.push((rom, analyze(&bytecode))),
+            Err(e) => error(format!("failed to read {}: {}", rom.display(), e)),
+        }
+    }
+    if analyses.is_empty() {
+        error("no ROMs to analyze".to_string());
+        std::process::exit(1);
+    }
+
+    // Number of ROMs using every pattern, and its total number of uses
+    let mut totals: BTreeMap<(Platform, &str), (usize, usize)> = BTreeMap::new();
+    for (_, analysis) in &analyses {
+        for (&key, &count) in &analysis.opcode_counts {
+            let (n_roms, n_uses) = totals.entry(key).or_default();
+            *n_roms += 1;
+            *n_uses += count;
+        }
+    }
+    let mut totals: Vec<_> = totals.into_iter().collect();
+    totals.sort_by_key(|&((platform, pattern), (n_roms, _))| (platform, Reverse(n_roms), pattern));
+
+    println!("Opcodes reachable in {} ROMs:", analyses.len());
+    println!("{:<8} {:<10} {:>6} {:>8}", "OPCODE", "PLATFORM", "ROMS", "USES");
+    for ((platform, pattern), (n_roms, n_uses)) in totals {
+        println!("{:<8} {:<10} {:>6} {:>8}", pattern, platform.to_string(), n_roms, n_uses);
+    }
+
+    let unsupported: Vec<_> = analyses
+        .iter()
+        .filter(|(_, analysis)| analysis.platform != Platform::Chip8 || analysis.unknown_opcodes > 0)
+        .collect();
+    if unsupported.is_empty() {
+        println!("\nEvery ROM only uses CHIP-8 opcodes");
+        return;
+    }
+    println!("\nROMs that cannot be run yet:");
+    for (path, analysis) in unsupported {
+        let extensions: Vec<&str> = analysis.opcode_counts
+            .keys()
+            .filter(|(platform, _)| *platform != Platform::Chip8)
+            .map(|(_, pattern)| *pattern)
+            .collect();
+        let mut reasons = Vec::new();
+        if !extensions.is_empty() {
+            reasons.push(format!("{} ({})", analysis.platform, extensions.join(" ")));
+        }
+        if analysis.unknown_opcodes > 0 {
+            reasons.push(format!("{} unknown opcodes", analysis.unknown_opcodes));
+        }
+        println!("{}: {}", path.display(), reasons.join(", "));
+    }
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_roms(&path, roms)?;
+        } else if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("ch8")) {
+            roms.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn import_database([input_path, output_path]: [String; 2]) {
+    let source = fs::read_to_string(&input_path).unwrap_or_else(|e| {
+        error(format!("failed to read {}: {}", input_path, e));
+        std::process::exit(1);
+    });
+    let database = Database::import(&source).unwrap_or_else(|e| {
+        error(format!("{}: {}", input_path, e));
+        std::process::exit(2);
+    });
+    let header = format!("; Imported from {}\n\n", input_path);
+    fs::write(&output_path, header + &database.to_string()).unwrap_or_else(|e| {
+        error(format!("failed to write {}: {}", output_path, e));
+        std::process::exit(3);
+    });
+    println!("Imported {} ROMs", database.len());
+}
+
+/// Files and directories whose changes trigger a new run of the tests
+const WATCHED_PATHS: &[&str] = &["Cargo.toml", "src", "tests", "roms"];
+const WATCH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Options of the `test` command
+struct TestOptions {
+    watch: bool,
+}
+
+fn parse_test_args(args: &[String]) -> Result<TestOptions, String> {
+    match args {
+        [] => Ok(TestOptions { watch: false }),
+        [arg] if arg == "--watch" => Ok(TestOptions { watch: true }),
+        _ => Err("test only accepts --watch".to_string()),
+    }
+}
+
+fn test(options: TestOptions) {
+    if !Path::new("Cargo.toml").exists() || !Path::new("tests").is_dir() {
+        error("test must be run from the root of the repository".to_string());
+        std::process::exit(1);
+    }
+
+    let mut previous: Option<BTreeMap<String, bool>> = None;
+    loop {
+        let started = newest_modification(WATCHED_PATHS);
+        let results = run_tests();
+        if let Some(results) = &results {
+            print_test_results(results, previous.as_ref());
+        }
+        if !options.watch {
+            let passed = results.is_some_and(|results| results.values().all(|&passed| passed));
+            std::process::exit(if passed { 0 } else { 2 });
+        }
+        if results.is_some() {
+            previous = results;
+        }
+
+        println!("Waiting for changes...");
+        while newest_modification(WATCHED_PATHS) == started {
+            thread::sleep(WATCH_INTERVAL);
+        }
+    }
+}
+
+/// Run every test target in `tests/` and return whether each test passed,
+/// keyed by `target::test`, or `None` if the tests failed to build
+fn run_tests() -> Option<BTreeMap<String, bool>> {
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    // Build everything first, so compilation errors are shown once. The
+    // tests are headless, so they are built without SDL, which may not be
+    // installed
+    let built = Command::new(&cargo).args(["test", "--no-default-features", "--tests", "--no-run", "--quiet"]).status();
+    if !built.is_ok_and(|status| status.success()) {
+        error("the tests failed to build".to_string());
+        return None;
+    }
+
+    let mut targets: Vec<String> = fs::read_dir("tests").ok()?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            if path.extension()? != "rs" {
+                return None;
+            }
+            Some(path.file_stem()?.to_string_lossy().into_owned())
+        })
+        .collect();
+    targets.sort();
+
+    let mut results = BTreeMap::new();
+    for target in targets {
+        let output = Command::new(&cargo)
+            .args(["test", "--no-default-features", "--quiet", "--test", &target, "--", "--format=pretty"])
+            .output().ok()?;
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let Some((name, outcome)) = line.strip_prefix("test ").and_then(|line| line.split_once(" ... ")) else {
+                continue;
+            };
+            if outcome != "ignored" {
+                results.insert(format!("{}::{}", target, name), outcome == "ok");
+            }
+        }
+    }
+    Some(results)
+}
+
+/// Print a summary of a run, and the tests that started or stopped passing
+/// since the previous one. New tests are neither
+fn print_test_results(results: &BTreeMap<String, bool>, previous: Option<&BTreeMap<String, bool>>) {
+    let n_passed = results.values().filter(|&&passed| passed).count();
+    let n_failed = results.len() - n_passed;
+    let changed: Vec<(&String, bool)> = results
+        .iter()
+        .filter(|&(name, passed)| previous.and_then(|previous| previous.get(name)).is_some_and(|was| was != passed))
+        .map(|(name, &passed)| (name, passed))
+        .collect();
+    let n_fixed = changed.iter().filter(|(_, passed)| *passed).count();
+
+    let summary = format!("{} passed, {} failed", n_passed, n_failed);
+    if previous.is_some() {
+        println!("{} (+{} fixed, -{} broken)", summary, n_fixed, changed.len() - n_fixed);
+    } else {
+        println!("{}", summary);
+    }
+    for (name, passed) in &changed {
+        println!("  {} {}", if *passed { "fixed " } else { "broken" }, name);
+    }
+    for (name, _) in results.iter().filter(|&(name, &passed)| !passed && !changed.iter().any(|(changed, _)| *changed == name)) {
+        println!("  failed {}", name);
+    }
+}
+
+/// The latest modification time of the files under the given paths
+fn newest_modification(paths: &[&str]) -> Option<SystemTime> {
+    fn newest(path: &Path) -> Option<SystemTime> {
+        let metadata = fs::metadata(path).ok()?;
+        let mut time = metadata.modified().ok();
+        if metadata.is_dir() {
+            for entry in fs::read_dir(path).ok()?.flatten() {
+                time = time.max(newest(&entry.path()));
+            }
+        }
+        time
+    }
+    paths.iter().filter_map(|path| newest(Path::new(path))).max()
+}
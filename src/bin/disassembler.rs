@@ -1,104 +1,126 @@
 use std::{env, fs};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 use chip8vm::logging::error;
+use chip8vm::disassembler::{analyze, disassemble, Analysis};
+
+const OPTIONS_HELP: &str = "Options:
+    --batch <dir> -o <out_dir>
+        Disassemble every .ch8 file under <dir> into <out_dir>, keeping the
+        directory structure, and write a summary to <out_dir>/index.txt";
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        eprintln!("Usage: {} <input.ch8> <output.asm>", args[0]);
-        return;
+    match args.get(1).map(String::as_str) {
+        Some("--batch") if args.len() == 5 && args[3] == "-o" => {
+            batch(Path::new(&args[2]), Path::new(&args[4]));
+        }
+        _ if args.len() == 3 => disassemble_file(&args[1], &args[2]),
+        _ => {
+            eprintln!(
+                "Usage: {} <input.ch8> <output.asm>\n       {} --batch <dir> -o <out_dir>\n\n{}",
+                args[0], args[0], OPTIONS_HELP
+            );
+        }
     }
+}
 
-    let input_path = &args[1];
-    let output_path = &args[2];
-
+fn disassemble_file(input_path: &str, output_path: &str) {
     let bytecode = fs::read(input_path)
         .unwrap_or_else(|e| {
-            error(format!("failed to read input file: {}", e.to_string()));
+            error(format!("failed to read input file: {}", e));
             std::process::exit(1);
         });
-    let asm = disassemble(bytecode);
+    let asm = disassemble(&bytecode);
 
     fs::write(output_path, asm)
         .unwrap_or_else(|e| {
-            error(format!("failed to write to output file: {}", e.to_string()));
+            error(format!("failed to write to output file: {}", e));
             std::process::exit(2);
         });
 }
 
-fn disassemble(bytecode: Vec<u8>) -> String {
-    let mut result = String::new();
-    let mut i = 0;
+fn batch(input_dir: &Path, output_dir: &Path) {
+    let mut roms = Vec::new();
+    find_roms(input_dir, &mut roms).unwrap_or_else(|e| {
+        error(format!("failed to read directory {}: {}", input_dir.display(), e));
+        std::process::exit(1);
+    });
+    roms.sort();
 
-    while i < bytecode.len() {
-        if i + 1 >= bytecode.len() {
-            result.push_str(&format!(".byte 0x{:02X}\n", bytecode[i]));
-            break;
+    // Every worker takes the next unprocessed ROM until there are none left
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+    let n_workers = thread::available_parallelism().map_or(1, |n| n.get());
+    thread::scope(|scope| {
+        for _ in 0..n_workers {
+            scope.spawn(|| {
+                while let Some(rom) = roms.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let relative = rom.strip_prefix(input_dir).unwrap_or(rom);
+                    let output = output_dir.join(relative).with_extension("asm");
+                    let result = disassemble_rom(rom, &output);
+                    results.lock().unwrap().push((relative.to_path_buf(), result));
+                }
+            });
         }
+    });
 
-        let high = bytecode[i] as u16;
-        let low = bytecode[i + 1] as u16;
-        let opcode = (high << 8) | low;
-
-        let line = decode_instruction(opcode);
-        result.push_str(&line);
-        result.push('\n');
+    let mut results = results.into_inner().unwrap();
+    results.sort_by(|a, b| a.0.cmp(&b.0));
 
-        i += 2;
+    let mut index = String::new();
+    writeln!(index, "{:<40} {:>6} {:<10} {:>7}", "ROM", "SIZE", "PLATFORM", "UNKNOWN").unwrap();
+    let mut n_failed = 0;
+    for (path, result) in &results {
+        match result {
+            Ok(analysis) => writeln!(
+                index, "{:<40} {:>6} {:<10} {:>7}",
+                path.display(), analysis.size, analysis.platform.to_string(), analysis.unknown_opcodes
+            ).unwrap(),
+            Err(e) => {
+                error(format!("{}: {}", path.display(), e));
+                n_failed += 1;
+            }
+        }
     }
 
-    result
-}
+    let index_path = output_dir.join("index.txt");
+    fs::create_dir_all(output_dir)
+        .and_then(|_| fs::write(&index_path, index))
+        .unwrap_or_else(|e| {
+            error(format!("failed to write {}: {}", index_path.display(), e));
+            std::process::exit(2);
+        });
 
-fn decode_instruction(opcode: u16) -> String {
-    let nibbles = (
-        (opcode & 0xF000) >> 12,
-        (opcode & 0x0F00) >> 8,
-        (opcode & 0x00F0) >> 4,
-        (opcode & 0x000F)
-    );
+    println!("Disassembled {} of {} ROMs into {}", results.len() - n_failed, results.len(), output_dir.display());
+    if n_failed > 0 {
+        std::process::exit(2);
+    }
+}
 
-    let nnn = opcode & 0x0FFF;
-    let kk = (opcode & 0x00FF) as u8;
-    let x = ((opcode & 0x0F00) >> 8) as u8;
-    let y = ((opcode & 0x00F0) >> 4) as u8;
-    let n = (opcode & 0x000F) as u8;
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_roms(&path, roms)?;
+        } else if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("ch8")) {
+            roms.push(path);
+        }
+    }
+    Ok(())
+}
 
-    match nibbles {
-        (0x0, 0x0, 0xE, 0x0) => "CLS".to_string(),
-        (0x0, 0x0, 0xE, 0xE) => "RET".to_string(),
-        (0x0, _, _, _)       => format!("SYS 0x{:03X}",         nnn),
-        (0x1, _, _, _)       => format!("JP 0x{:03X}",          nnn),
-        (0x2, _, _, _)       => format!("CALL 0x{:03X}",        nnn),
-        (0x3, _, _, _)       => format!("SE V{:X}, 0x{:02X}",   x, kk),
-        (0x4, _, _, _)       => format!("SNE V{:X}, 0x{:02X}",  x, kk),
-        (0x5, _, _, 0x0)     => format!("SE V{:X}, V{:X}",      x, y),
-        (0x6, _, _, _)       => format!("LD V{:X}, 0x{:02X}",   x, kk),
-        (0x7, _, _, _)       => format!("ADD V{:X}, 0x{:02X}",  x, kk),
-        (0x8, _, _, 0x0)     => format!("LD V{:X}, V{:X}",      x, y),
-        (0x8, _, _, 0x1)     => format!("OR V{:X}, V{:X}",      x, y),
-        (0x8, _, _, 0x2)     => format!("AND V{:X}, V{:X}",     x, y),
-        (0x8, _, _, 0x3)     => format!("XOR V{:X}, V{:X}",     x, y),
-        (0x8, _, _, 0x4)     => format!("ADD V{:X}, V{:X}",     x, y),
-        (0x8, _, _, 0x5)     => format!("SUB V{:X}, V{:X}",     x, y),
-        (0x8, _, _, 0x6)     => format!("SHR V{:X}",            x),
-        (0x8, _, _, 0x7)     => format!("SUBN V{:X}, V{:X}",    x, y),
-        (0x8, _, _, 0xE)     => format!("SHL V{:X}",            x),
-        (0x9, _, _, 0x0)     => format!("SNE V{:X}, V{:X}",     x, y),
-        (0xA, _, _, _)       => format!("LD I, 0x{:03X}",       nnn),
-        (0xB, _, _, _)       => format!("JP V0, 0x{:03X}",      nnn),
-        (0xC, _, _, _)       => format!("RND V{:X}, 0x{:02X}",  x, kk),
-        (0xD, _, _, _)       => format!("DRW V{:X}, V{:X}, {}", x, y, n),
-        (0xE, _, 0x9, 0xE)   => format!("SKP V{:X}",            x),
-        (0xE, _, 0xA, 0x1)   => format!("SKNP V{:X}",           x),
-        (0xF, _, 0x0, 0x7)   => format!("LD V{:X}, DT",         x),
-        (0xF, _, 0x0, 0xA)   => format!("LD V{:X}, K",          x),
-        (0xF, _, 0x1, 0x5)   => format!("LD DT, V{:X}",         x),
-        (0xF, _, 0x1, 0x8)   => format!("LD ST, V{:X}",         x),
-        (0xF, _, 0x1, 0xE)   => format!("ADD I, V{:X}",         x),
-        (0xF, _, 0x2, 0x9)   => format!("LD F, V{:X}",          x),
-        (0xF, _, 0x3, 0x3)   => format!("LD B, V{:X}",          x),
-        (0xF, _, 0x5, 0x5)   => format!("LD [I], V{:X}",        x),
-        (0xF, _, 0x6, 0x5)   => format!("LD V{:X}, [I]",        x),
-        _ => format!(".word 0x{:04X}", opcode),
+fn disassemble_rom(input: &Path, output: &Path) -> Result<Analysis, String> {
+    let bytecode = fs::read(input)
+        .map_err(|e| format!("failed to read: {}", e))?;
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("failed to create {}: {}", parent.display(), e))?;
     }
+    fs::write(output, disassemble(&bytecode))
+        .map_err(|e| format!("failed to write {}: {}", output.display(), e))?;
+    Ok(analyze(&bytecode))
 }
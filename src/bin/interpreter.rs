@@ -1,22 +1,1251 @@
-use std::fs::File;
-use std::io::Read;
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::net::TcpListener;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use sdl2::keyboard::Scancode;
 use sdl2::pixels::PixelFormatEnum;
-use chip8vm::interpreter::{VM, DISPLAY_WIDTH, DISPLAY_HEIGHT};
+use sdl2::surface::Surface;
+use std::time::{SystemTime, UNIX_EPOCH};
+use chip8vm::database::{config_dir, enabled_quirks, user_database_path, parse_color, parse_quirks, Database, Entry};
+use chip8vm::assembler::debug_info::read_debug_info;
+use chip8vm::assembler::symbols::read_symbol_table;
+use chip8vm::debugger::{Debugger, HISTORY_LENGTH};
+use chip8vm::disassembler::analyze;
+use chip8vm::hash::{crc32, sha1, to_hex};
+use chip8vm::frontend::scaler::scaler_by_name;
+use chip8vm::frontend::crt::CrtEffects;
+use chip8vm::frontend::font::glyph;
+use chip8vm::frontend::mirror::Mirror;
+use chip8vm::frontend::browser::RomBrowser;
+use chip8vm::frontend::palette::{palette_by_name, DEFAULT_PALETTE};
+use chip8vm::frontend::recorder::Recorder;
+use chip8vm::frontend::waveform::{waveform_by_name, Waveform, DEFAULT_DUTY_CYCLE};
+use chip8vm::frontend::sdl::{
+    Frontend, KeyMatching, Keymap, BEEP_FREQUENCY_RANGE, DEFAULT_AUDIO_BUFFER, DEFAULT_BEEP_FREQUENCY, DEFAULT_TITLE, SPEED_RANGE
+};
+use chip8vm::interpreter::{check_rom, display_to_text, parse_number, Quirks, VM, RomProtection, DISPLAY_WIDTH, DISPLAY_HEIGHT, RAM_SIZE};
+use chip8vm::interpreter::coredump::{Core, RECENT_LENGTH};
+use chip8vm::interpreter::memory_dump::MemoryDump;
+use chip8vm::interpreter::replay::Replay;
+use chip8vm::interpreter::trace::{Trace, TraceFilter, TraceFormat};
+use chip8vm::interpreter::watchpoint::Watchpoint;
+use chip8vm::isa::Platform;
+use chip8vm::logging::{error, file_warning, timestamp};
+use chip8vm::png::{self, Image};
+use chip8vm::settings::{settings_path, GameSettings, Settings};
+use chip8vm::wav::{self, Sound};
 
-pub const WINDOW_WIDTH: usize = 640;
-pub const WINDOW_HEIGHT: usize = 320;
+/// The size of the window in CHIP-8 pixels, unless set
+const DEFAULT_WINDOW_SCALE: usize = 10;
+const DEFAULT_SCALE: usize = 4;
+/// How many instructions `--trace` prints per frame at most
+const TRACE_LINES_PER_FRAME: u32 = 100;
+
+const OPTIONS_HELP: &str = "Without a ROM, a list of the ROMs in the --rom-dir directory is shown to
+choose one from.
+
+Options:
+    --rom-dir <dir>
+        The directory whose ROMs are listed when no ROM is given (default:
+        the current directory)
+    --dump-on-write <start>..<end>=<file>
+        Append a timestamped snapshot of RAM[start..end] to <file>
+        every time it changes (may be repeated)
+    --break-on-change <register>[=<value>]
+        Pause when an instruction changes V0..VF, I, DT or ST, or changes it
+        to <value>, printing where (may be repeated)
+    --break-on-illegal
+        Pause when an opcode that is not a CHIP-8 instruction is executed,
+        printing where, instead of doing nothing silently
+    --debug-traps
+        Pause at the BRK debug traps of the assembler, and print the
+        registers or memory at the PRINT ones (SYS 0xFF0 to 0xFF2, which
+        otherwise do nothing)
+    --watch
+        Start the ROM again whenever the file changes, like with F1. Its
+        modification time is checked every 250 ms
+    --compare <rom.ch8>
+        Run another build of the ROM side by side, in lockstep and with
+        the same keys, outlining the pixels that differ
+    --compare-quirks <quirks>
+        Run the ROM (or the --compare one) side by side with these quirks,
+        a platform name or quirk names as in database files
+    --protect-rom <warn|strict>
+        Report writes into the loaded ROM image (warn), or stop the
+        program on the first one (strict)
+    --protect-code <warn|strict>
+        Report writes into bytes already executed as code, like a store
+        through a bad I trampling the program (warn), or stop the program
+        on the first one (strict)
+    --database <file>
+        Look the ROM up in <file> too, whose entries replace the ones of the
+        bundled database and of ~/.config/chip8vm/database.txt
+    --no-database
+        Do not apply the quirks, speed and colors of known ROMs
+    --speed <n>
+        Execute <n> instructions per second (100..5000, default 500). The
+        speed can also be changed with + and - while running
+    --scaler <nearest|epx|hqx-lite>
+        How to upscale the display (default nearest)
+    --scale <n>
+        Upscale the display <n> times before fitting it to the window
+        (default 4)
+    --volume <percent>
+        The volume of the buzzer, from 0 to 100 (default 50). [ and ] turn
+        it down and up while running
+    --mute
+        Start with the buzzer silenced. M mutes and unmutes it while
+        running
+    --waveform square|triangle|sine|noise
+        The tone of the buzzer (default square)
+    --duty-cycle <percent>
+        How much of every period square waves are high, from 1 to 99
+        (default 50)
+    --beep-frequency <Hz>
+        The frequency of the tone of the buzzer, from 50 to 4000 (default
+        440). Shift+[ and Shift+] change it by a semitone while running
+    --audio-buffer <samples>
+        The size of audio buffers, a power of two (default 512). Smaller
+        buffers make the buzzer react faster but may crackle. The
+        obtained size is printed
+    --beep-sound <file.wav>
+        Loop a sound while the buzzer sounds instead of a waveform
+    --keep-running-unfocused
+        Do not pause the program while the window is not focused
+    --no-vsync
+        Present the display without waiting for the monitor to refresh,
+        which may tear
+    --filter <nearest|linear>
+        How the upscaled display is stretched to the window: with sharp
+        edges (default), or smoothed
+    --integer-scale
+        Scale the display by whole numbers only, keeping pixels square and
+        the same size, with bars around it
+    --palette <classic|gameboy|amber|green|paper>
+        The colors of the display (default classic, white on black)
+    --fg <#RRGGBB>, --bg <#RRGGBB>
+        The color of lit and unlit pixels, replacing the one of the palette
+    --bars <#RRGGBB>
+        The color around the display when the window does not have its
+        aspect ratio (default: the background color)
+    --fullscreen
+        Start in fullscreen (F11 switches back and forth)
+    --window-scale <n>
+        Open the window <n> screen pixels per CHIP-8 pixel (default 10)
+    --title <template>
+        The title of the window, {rom} being replaced by the name of the
+        ROM and {status} by its status (default \"chip8vm — {rom}{status}\")
+    --icon <file.png>
+        The icon of the window (default a CHIP-8 digit 8)
+    --borderless
+        Open the window without borders and title bar
+    --record-video <file>
+        Record the display and buzzer to <file>, a .y4m video with a .wav
+        file next to it, or any format ffmpeg (which must be installed)
+        writes, like .mp4
+    --record-input <file>
+        Record the keys pressed to <file>, to play them back with --replay
+        or chip8 compare --input (the speed cannot be changed meanwhile)
+    --replay <file>
+        Play back the keys recorded with --record-input, with the random
+        numbers and speed of the recording, ignoring the keyboard until it
+        ends. The program runs identically every time, and when reloaded
+        with F1, to step through it with --debug
+    --dump-display-on-exit <file>
+        Write the display as text (█ for lit pixels) to <file>, or to the
+        standard output if <file> is -, when the program stops
+    --dump-state-on-exit <file>
+        Write the registers, timers, stack, display and a hexdump of the RAM
+        as JSON to <file>, or to the standard output if <file> is -, when
+        the program stops, for bug reports or to compare emulators
+    --coredump
+        Write a core file, crash-<timestamp>.c8core, when the program is
+        quit or interrupted too, not only when it stops with an error
+    --screenshot-dir <dir>
+        Save screenshots taken with F12 into <dir> (default: the current
+        directory)
+    --screenshot-scale <n>
+        Upscale screenshots <n> times (default: like the window)
+    --debug
+        Read debugger commands (step, next, finish, continue,
+        reverse-step...) from the terminal, type help for the list
+    --break-at <location>
+        Pause at a location, an address, a label or a file:line of the
+        source, or before instructions like Fx18, and read debugger
+        commands like --debug (may be repeated)
+    --run-until <location>
+        Like --break-at, but only pause the first time
+    --debug-script <file>
+        Pause before the first instruction and carry out the debugger
+        commands of <file>, one per line (lines starting with ; are
+        comments), whenever the program is paused, so that commands after
+        continue wait for it to stop, then read commands like --debug
+    --trace
+        Print every instruction executed and the registers it changed, at
+        most 100 per frame
+    --trace-file <file>
+        Write every instruction executed to <file> instead, without limit
+    --trace-format <text|binary|canonical>
+        Write the trace as text (default), as binary records, or in the
+        format chip8 trace-diff compares, see src/interpreter/trace.rs
+    --trace-pc <start>..<end>
+        Only trace the instructions at these addresses (may be repeated)
+    --trace-opcodes <patterns>
+        Only trace these opcodes, patterns like Dxyn,2nnn or first digits
+        like F, separated by commas
+    --profile <file>
+        Count the instructions executed and the time they took, by opcode
+        and by address, and write the report, with the numbers of frames,
+        sprites drawn, collisions and sounds, to <file> when the program
+        stops, as JSON if <file> ends with .json, or to the standard output
+        if <file> is -
+    --heatmap <file>
+        Count how many times the instruction at every address was executed
+        and write them to <file> when the program stops, as an image of the
+        RAM if <file> ends with .png, or as CSV
+    --coverage <file>
+        Record which bytes of the program were executed and write a report
+        with the regions never executed and the disassembly of the program,
+        marking the instructions never executed, to <file> when the program
+        stops, or to the standard output if <file> is -
+    --session <file>
+        Save the breakpoints, watchpoints and expressions displayed by the
+        debugger to <file> when the program stops, and restore them from it
+        the next time (default: sessions/<SHA-1 of the ROM>.txt in the
+        config directory)
+    --symbols <file.sym>
+        Show the labels of a symbol file written by assembler --symbols in
+        the debugger
+    --debug-info <file.dbg>
+        Show the labels and source lines of a file written by assembler
+        --debug-info in the debugger, and set breakpoints on lines
+    --debug-overlay
+        Show the achieved frames and instructions per second, the timers
+        and the registers over the display (F10 shows and hides them)
+    --grid
+        Draw lines between the pixels of the display (F4 shows and hides
+        them)
+    --crt <all|scanlines,curvature,vignette>
+        Imitate a CRT monitor with some effects (F3 turns them off and on)
+    --phosphor <frames>
+        Fade pixels out over <frames> frames (3 to 6 look good) like on a
+        CRT, which hides the flicker of XOR drawing
+    --blend-frames
+        Present at the refresh rate of the monitor, fading between the last
+        two frames to hide the flicker of XOR drawing (adds a frame of lag)
+    --mirror <file>
+        Keep a copy of the display and keypad in <file> for capture software,
+        for example /dev/shm/chip8vm (see src/frontend/mirror.rs)
+    --control <address>
+        Take JSON requests to pause, step, read and write memory, take
+        screenshots and press keys on <address>, like 127.0.0.1:6502, over
+        TCP or WebSocket (see src/frontend/control.rs). Anyone who can
+        connect controls the emulator, so keep it on 127.0.0.1
+    --key <k>=<keys>
+        Press CHIP-8 key <k> (0..F) with the keyboard keys named in <keys>,
+        separated by commas, for example 8=Up,Keypad 8 (may be repeated)
+    --keys-by <position|layout>
+        Match keyboard keys by their position (default), or by what they
+        type with the current layout, for AZERTY or QWERTZ keyboards
+    --latch-keys
+        Keep a key tapped shortly before LD Vx, K until the end of the
+        frame, so that quick taps are not missed
+    --forget-settings
+        Start with the usual speed, colors, muting and keys instead of the
+        ones remembered for the ROM
+    --print-keymap
+        Print which keyboard keys press which CHIP-8 keys, and exit
+    --info
+        Print the checksums of the ROM and what the database knows about it,
+        and exit
+
+Options are also read from ~/.config/chip8vm/config.txt, one per line and
+without the leading dashes: `scaler = epx`, `no-database`, and `key 8 = Up`
+for --key.";
+
+/// Command-line options of the interpreter
+struct Options {
+    /// The ROM to run, empty to choose one from `rom_dir`
+    rom_path: String,
+    rom_dir: Option<PathBuf>,
+    dumps: Vec<(Range<usize>, String)>,
+    watchpoints: Vec<Watchpoint>,
+    break_on_illegal: bool,
+    debug_traps: bool,
+    rom_protection: RomProtection,
+    code_protection: RomProtection,
+    watch: bool,
+    /// The ROM and quirks of the twin machine, see `--compare`
+    compare_path: Option<String>,
+    compare_quirks: Option<Quirks>,
+    database_path: Option<String>,
+    use_database: bool,
+    info: bool,
+    speed: Option<u32>,
+    scaler: String,
+    linear_filtering: bool,
+    vsync: bool,
+    pause_when_unfocused: bool,
+    volume: Option<u32>,
+    muted: bool,
+    waveform: Waveform,
+    duty_cycle: f32,
+    beep_sound_path: Option<PathBuf>,
+    audio_buffer: Option<u16>,
+    beep_frequency: f32,
+    scale: usize,
+    integer_scaling: bool,
+    palette: Option<([u8; 3], [u8; 3])>,
+    foreground: Option<[u8; 3]>,
+    background: Option<[u8; 3]>,
+    bar_color: Option<[u8; 3]>,
+    fullscreen: bool,
+    window_scale: usize,
+    title: String,
+    icon_path: Option<PathBuf>,
+    borderless: bool,
+    video_path: Option<PathBuf>,
+    input_recording_path: Option<PathBuf>,
+    replay_path: Option<PathBuf>,
+    display_dump_path: Option<String>,
+    state_dump_path: Option<String>,
+    coredump: bool,
+    screenshot_dir: Option<PathBuf>,
+    screenshot_scale: Option<usize>,
+    debugger: bool,
+    /// The locations of `--break-at`, and of `--run-until` which are
+    /// temporary
+    breakpoints: Vec<(String, bool)>,
+    symbols_path: Option<PathBuf>,
+    debug_info_path: Option<PathBuf>,
+    session_path: Option<PathBuf>,
+    debug_script_path: Option<PathBuf>,
+    trace: bool,
+    trace_path: Option<PathBuf>,
+    trace_format: TraceFormat,
+    trace_filter: TraceFilter,
+    profile_path: Option<String>,
+    heatmap_path: Option<String>,
+    coverage_path: Option<String>,
+    debug_overlay: bool,
+    grid: bool,
+    crt: CrtEffects,
+    phosphor: u32,
+    blend_frames: bool,
+    mirror_path: Option<String>,
+    control_address: Option<String>,
+    keymap: Keymap,
+    key_matching: KeyMatching,
+    latch_keys: bool,
+    forget_settings: bool,
+    print_keymap: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut rom_path = None;
+    let mut rom_dir = None;
+    let mut dumps = Vec::new();
+    let mut watchpoints = Vec::new();
+    let mut break_on_illegal = false;
+    let mut debug_traps = false;
+    let mut rom_protection = RomProtection::Off;
+    let mut code_protection = RomProtection::Off;
+    let mut watch = false;
+    let mut compare_path = None;
+    let mut compare_quirks = None;
+    let mut database_path = None;
+    let mut use_database = true;
+    let mut info = false;
+    let mut speed = None;
+    let mut scaler = "nearest".to_string();
+    let mut linear_filtering = false;
+    let mut vsync = true;
+    let mut pause_when_unfocused = true;
+    let mut volume = None;
+    let mut muted = false;
+    let mut waveform = Waveform::default();
+    let mut duty_cycle = DEFAULT_DUTY_CYCLE;
+    let mut beep_sound_path = None;
+    let mut audio_buffer = None;
+    let mut beep_frequency = DEFAULT_BEEP_FREQUENCY;
+    let mut scale = DEFAULT_SCALE;
+    let mut integer_scaling = false;
+    let mut palette = None;
+    let mut foreground = None;
+    let mut background = None;
+    let mut bar_color = None;
+    let mut fullscreen = false;
+    let mut window_scale = DEFAULT_WINDOW_SCALE;
+    let mut title = DEFAULT_TITLE.to_string();
+    let mut icon_path = None;
+    let mut borderless = false;
+    let mut video_path = None;
+    let mut input_recording_path = None;
+    let mut replay_path = None;
+    let mut display_dump_path = None;
+    let mut state_dump_path = None;
+    let mut coredump = false;
+    let mut screenshot_dir = None;
+    let mut screenshot_scale = None;
+    let mut debugger = false;
+    let mut breakpoints = Vec::new();
+    let mut symbols_path = None;
+    let mut debug_info_path = None;
+    let mut session_path = None;
+    let mut debug_script_path = None;
+    let mut trace = false;
+    let mut trace_path = None;
+    let mut trace_format = TraceFormat::Text;
+    let mut trace_filter = TraceFilter::default();
+    let mut profile_path = None;
+    let mut heatmap_path = None;
+    let mut coverage_path = None;
+    let mut debug_overlay = false;
+    let mut grid = false;
+    let mut crt = CrtEffects::default();
+    let mut phosphor = 0;
+    let mut blend_frames = false;
+    let mut mirror_path = None;
+    let mut control_address = None;
+    let mut keymap = Keymap::default();
+    let mut key_matching = KeyMatching::Position;
+    let mut latch_keys = false;
+    let mut forget_settings = false;
+    let mut print_keymap = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dump-on-write" => {
+                let spec = args.next()
+                    .ok_or("--dump-on-write requires an argument")?;
+                dumps.push(parse_dump_spec(spec)?);
+            }
+            "--break-on-change" => {
+                let spec = args.next().ok_or("--break-on-change requires an argument")?;
+                watchpoints.push(spec.parse().map_err(|e| format!("invalid --break-on-change argument: {}", e))?);
+            }
+            "--break-on-illegal" => break_on_illegal = true,
+            "--debug-traps" => debug_traps = true,
+            "--protect-rom" => {
+                rom_protection = match args.next().map(String::as_str) {
+                    Some("warn") => RomProtection::Warn,
+                    Some("strict") => RomProtection::Strict,
+                    _ => return Err("--protect-rom requires \"warn\" or \"strict\"".to_string()),
+                };
+            }
+            "--protect-code" => {
+                code_protection = match args.next().map(String::as_str) {
+                    Some("warn") => RomProtection::Warn,
+                    Some("strict") => RomProtection::Strict,
+                    _ => return Err("--protect-code requires \"warn\" or \"strict\"".to_string()),
+                };
+            }
+            "--rom-dir" => rom_dir = Some(PathBuf::from(args.next().ok_or("--rom-dir requires an argument")?)),
+            "--watch" => watch = true,
+            "--compare" => compare_path = Some(args.next().ok_or("--compare requires an argument")?.clone()),
+            "--compare-quirks" => {
+                let value = args.next().ok_or("--compare-quirks requires an argument")?;
+                compare_quirks = Some(match Platform::from_name(value) {
+                    Some(platform) => Quirks::of_platform(platform),
+                    None => parse_quirks(value)?,
+                });
+            }
+            "--database" => {
+                database_path = Some(args.next().ok_or("--database requires an argument")?.clone());
+            }
+            "--no-database" => use_database = false,
+            "--info" => info = true,
+            "--key" => {
+                let spec = args.next().ok_or("--key requires an argument")?;
+                let (chip8_key, scancodes) = parse_key_spec(spec)?;
+                keymap.set(chip8_key, scancodes);
+            }
+            "--keys-by" => {
+                key_matching = match args.next().map(String::as_str) {
+                    Some("position") => KeyMatching::Position,
+                    Some("layout") => KeyMatching::Layout,
+                    _ => return Err("--keys-by requires \"position\" or \"layout\"".to_string()),
+                };
+            }
+            "--latch-keys" => latch_keys = true,
+            "--forget-settings" => forget_settings = true,
+            "--print-keymap" => print_keymap = true,
+            "--speed" => {
+                let value = args.next().ok_or("--speed requires an argument")?;
+                let ips = value.parse().map_err(|_| format!("invalid speed {}", value))?;
+                if !SPEED_RANGE.contains(&ips) {
+                    return Err(format!("speed {} is out of range (100..5000)", ips));
+                }
+                speed = Some(ips);
+            }
+            "--scaler" => scaler = args.next().ok_or("--scaler requires an argument")?.clone(),
+            "--no-vsync" => vsync = false,
+            "--keep-running-unfocused" => pause_when_unfocused = false,
+            "--volume" => {
+                let value = args.next().ok_or("--volume requires an argument")?;
+                let percent = value.parse().map_err(|_| format!("invalid volume {}", value))?;
+                if percent > 100 {
+                    return Err(format!("volume {} is out of range (0..100)", percent));
+                }
+                volume = Some(percent);
+            }
+            "--mute" => muted = true,
+            "--waveform" => waveform = waveform_by_name(args.next().ok_or("--waveform requires an argument")?)?,
+            "--duty-cycle" => {
+                let value = args.next().ok_or("--duty-cycle requires an argument")?;
+                let percent: u32 = value.parse().map_err(|_| format!("invalid duty cycle {}", value))?;
+                if !(1..=99).contains(&percent) {
+                    return Err(format!("duty cycle {} is out of range (1..99)", percent));
+                }
+                duty_cycle = percent as f32 / 100.0;
+            }
+            "--beep-frequency" => {
+                let value = args.next().ok_or("--beep-frequency requires an argument")?;
+                beep_frequency = value.parse().map_err(|_| format!("invalid frequency {}", value))?;
+                if !BEEP_FREQUENCY_RANGE.contains(&beep_frequency) {
+                    return Err(format!(
+                        "frequency {} is out of range ({}..{})",
+                        beep_frequency, BEEP_FREQUENCY_RANGE.start(), BEEP_FREQUENCY_RANGE.end()
+                    ));
+                }
+            }
+            "--audio-buffer" => {
+                let value = args.next().ok_or("--audio-buffer requires an argument")?;
+                let samples: u16 = value.parse().map_err(|_| format!("invalid audio buffer size {}", value))?;
+                if !samples.is_power_of_two() || samples < 64 {
+                    return Err(format!("audio buffer size {} is not a power of two of at least 64", samples));
+                }
+                audio_buffer = Some(samples);
+            }
+            "--beep-sound" => {
+                beep_sound_path = Some(PathBuf::from(args.next().ok_or("--beep-sound requires an argument")?));
+            }
+            "--filter" => {
+                linear_filtering = match args.next().map(String::as_str) {
+                    Some("nearest") => false,
+                    Some("linear") => true,
+                    _ => return Err("--filter requires \"nearest\" or \"linear\"".to_string()),
+                };
+            }
+            "--scale" => {
+                let value = args.next().ok_or("--scale requires an argument")?;
+                scale = value.parse().map_err(|_| format!("invalid scale {}", value))?;
+            }
+            "--integer-scale" => integer_scaling = true,
+            "--palette" => palette = Some(palette_by_name(args.next().ok_or("--palette requires an argument")?)?),
+            "--fg" | "--bg" => {
+                let value = args.next().ok_or_else(|| format!("{} requires an argument", arg))?;
+                let color = parse_color(value).ok_or_else(|| format!("invalid color {} (expected #RRGGBB)", value))?;
+                if arg == "--fg" {
+                    foreground = Some(color);
+                } else {
+                    background = Some(color);
+                }
+            }
+            "--bars" => {
+                let value = args.next().ok_or("--bars requires an argument")?;
+                bar_color = Some(parse_color(value).ok_or_else(|| format!("invalid color {} (expected #RRGGBB)", value))?);
+            }
+            "--fullscreen" => fullscreen = true,
+            "--window-scale" => {
+                let value = args.next().ok_or("--window-scale requires an argument")?;
+                window_scale = value.parse().ok().filter(|&scale| scale > 0)
+                    .ok_or_else(|| format!("invalid window scale {}", value))?;
+            }
+            "--title" => title = args.next().ok_or("--title requires an argument")?.clone(),
+            "--icon" => icon_path = Some(PathBuf::from(args.next().ok_or("--icon requires an argument")?)),
+            "--borderless" => borderless = true,
+            "--record-video" => {
+                video_path = Some(PathBuf::from(args.next().ok_or("--record-video requires an argument")?));
+            }
+            "--record-input" => {
+                input_recording_path = Some(PathBuf::from(args.next().ok_or("--record-input requires an argument")?));
+            }
+            "--replay" => {
+                replay_path = Some(PathBuf::from(args.next().ok_or("--replay requires an argument")?));
+            }
+            "--dump-display-on-exit" => {
+                display_dump_path = Some(args.next().ok_or("--dump-display-on-exit requires an argument")?.clone());
+            }
+            "--dump-state-on-exit" => {
+                state_dump_path = Some(args.next().ok_or("--dump-state-on-exit requires an argument")?.clone());
+            }
+            "--coredump" => coredump = true,
+            "--screenshot-dir" => {
+                screenshot_dir = Some(PathBuf::from(args.next().ok_or("--screenshot-dir requires an argument")?));
+            }
+            "--screenshot-scale" => {
+                let value = args.next().ok_or("--screenshot-scale requires an argument")?;
+                screenshot_scale = Some(value.parse().map_err(|_| format!("invalid scale {}", value))?);
+            }
+            "--debug" => debugger = true,
+            "--break-at" | "--run-until" => {
+                let location = args.next().ok_or(format!("{} requires an argument", arg))?;
+                breakpoints.push((location.clone(), arg == "--run-until"));
+                debugger = true;
+            }
+            "--debug-script" => {
+                debug_script_path = Some(PathBuf::from(args.next().ok_or("--debug-script requires an argument")?));
+                debugger = true;
+            }
+            "--trace" => trace = true,
+            "--trace-file" => trace_path = Some(PathBuf::from(args.next().ok_or("--trace-file requires an argument")?)),
+            "--trace-format" => {
+                trace_format = match args.next().map(String::as_str) {
+                    Some("text") => TraceFormat::Text,
+                    Some("binary") => TraceFormat::Binary,
+                    Some("canonical") => TraceFormat::Canonical,
+                    _ => return Err("--trace-format requires \"text\", \"binary\" or \"canonical\"".to_string()),
+                };
+            }
+            "--trace-pc" => {
+                let spec = args.next().ok_or("--trace-pc requires an argument")?;
+                trace_filter.addresses.push(parse_address_range(spec).ok_or_else(|| {
+                    format!("invalid --trace-pc argument \"{}\"", spec)
+                })?);
+            }
+            "--trace-opcodes" => {
+                let patterns = args.next().ok_or("--trace-opcodes requires an argument")?;
+                trace_filter.opcodes.extend(patterns.split(',').map(|pattern| pattern.trim().to_string()));
+            }
+            "--profile" => profile_path = Some(args.next().ok_or("--profile requires an argument")?.clone()),
+            "--heatmap" => heatmap_path = Some(args.next().ok_or("--heatmap requires an argument")?.clone()),
+            "--coverage" => coverage_path = Some(args.next().ok_or("--coverage requires an argument")?.clone()),
+            "--symbols" => symbols_path = Some(PathBuf::from(args.next().ok_or("--symbols requires an argument")?)),
+            "--debug-info" => {
+                debug_info_path = Some(PathBuf::from(args.next().ok_or("--debug-info requires an argument")?));
+            }
+            "--session" => session_path = Some(PathBuf::from(args.next().ok_or("--session requires an argument")?)),
+            "--debug-overlay" => debug_overlay = true,
+            "--grid" => grid = true,
+            "--crt" => crt = CrtEffects::parse(args.next().ok_or("--crt requires an argument")?)?,
+            "--phosphor" => {
+                let value = args.next().ok_or("--phosphor requires an argument")?;
+                phosphor = value.parse().map_err(|_| format!("invalid number of frames {}", value))?;
+            }
+            "--blend-frames" => blend_frames = true,
+            "--mirror" => mirror_path = Some(args.next().ok_or("--mirror requires an argument")?.clone()),
+            "--control" => control_address = Some(args.next().ok_or("--control requires an argument")?.clone()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ if rom_path.is_none() => rom_path = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument {}", arg)),
+        }
+    }
+
+    if phosphor > 0 && blend_frames {
+        return Err("--phosphor and --blend-frames cannot be combined".to_string());
+    }
+    if input_recording_path.is_some() && replay_path.is_some() {
+        return Err("--record-input and --replay cannot be combined".to_string());
+    }
+    if rom_path.is_none() && info {
+        return Err("no ROM file given".to_string());
+    }
+    Ok(Options {
+        rom_path: rom_path.unwrap_or_default(),
+        rom_dir,
+        dumps,
+        watchpoints,
+        break_on_illegal,
+        debug_traps,
+        rom_protection,
+        code_protection,
+        watch,
+        compare_path,
+        compare_quirks,
+        database_path,
+        use_database,
+        info,
+        speed,
+        scaler,
+        linear_filtering,
+        vsync,
+        pause_when_unfocused,
+        volume,
+        muted,
+        waveform,
+        duty_cycle,
+        beep_sound_path,
+        audio_buffer,
+        beep_frequency,
+        scale,
+        integer_scaling,
+        palette,
+        foreground,
+        background,
+        bar_color,
+        fullscreen,
+        window_scale,
+        title,
+        icon_path,
+        borderless,
+        video_path,
+        input_recording_path,
+        replay_path,
+        display_dump_path,
+        state_dump_path,
+        coredump,
+        screenshot_dir,
+        screenshot_scale,
+        debugger,
+        breakpoints,
+        symbols_path,
+        debug_info_path,
+        session_path,
+        debug_script_path,
+        trace,
+        trace_path,
+        trace_format,
+        trace_filter,
+        profile_path,
+        heatmap_path,
+        coverage_path,
+        debug_overlay,
+        grid,
+        crt,
+        phosphor,
+        blend_frames,
+        mirror_path,
+        control_address,
+        keymap,
+        key_matching,
+        latch_keys,
+        forget_settings,
+        print_keymap,
+    })
+}
+
+/// Parse `<k>=<keys>`
+fn parse_key_spec(spec: &str) -> Result<(u8, Vec<Scancode>), String> {
+    let (chip8_key, names) = spec.split_once('=')
+        .ok_or_else(|| format!("invalid --key argument \"{}\"", spec))?;
+    let chip8_key = u8::from_str_radix(chip8_key.trim(), 16).ok()
+        .filter(|key| *key < 16)
+        .ok_or_else(|| format!("invalid CHIP-8 key \"{}\" (expected 0..F)", chip8_key))?;
+    let scancodes = names.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| Scancode::from_name(name).ok_or_else(|| format!("unknown key \"{}\"", name)))
+        .collect::<Result<_, _>>()?;
+    Ok((chip8_key, scancodes))
+}
+
+/// Convert the lines of a config file into command-line arguments:
+/// `name = value` becomes `--name value`, `name` becomes `--name`, and
+/// `key k = keys` becomes `--key k=keys`
+fn config_args(source: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    for (line_index, line) in source.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        match line.split_once('=') {
+            Some((name, value)) => match name.trim().strip_prefix("key ") {
+                Some(chip8_key) => args.extend(["--key".to_string(), format!("{}={}", chip8_key.trim(), value.trim())]),
+                None => args.extend([format!("--{}", name.trim()), value.trim().to_string()]),
+            },
+            None if !line.contains(char::is_whitespace) => args.push(format!("--{}", line)),
+            None => return Err(format!("line {}: expected `name = value` or `name`", line_index + 1)),
+        }
+    }
+    Ok(args)
+}
+
+/// Read the options of the config file, if there is one
+fn load_config() -> Vec<String> {
+    let Some(path) = config_dir().map(|dir| dir.join("config.txt")).filter(|path| path.exists()) else {
+        return Vec::new();
+    };
+    fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|source| config_args(&source))
+        .unwrap_or_else(|e| {
+            error(format!("failed to load {}: {}", path.display(), e));
+            std::process::exit(1);
+        })
+}
+
+/// Read the settings remembered for ROMs, if there are some
+fn load_settings() -> Settings {
+    let Some(path) = settings_path().filter(|path| path.exists()) else {
+        return Settings::default();
+    };
+    fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|source| Settings::parse(&source).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| {
+            error(format!("failed to load {}: {}", path.display(), e));
+            std::process::exit(1);
+        })
+}
+
+/// Remember the settings of a ROM, keeping the ones of other ROMs that may
+/// have been saved meanwhile
+fn save_game_settings(rom: &[u8], game: GameSettings) {
+    let Some(path) = settings_path() else {
+        return;
+    };
+    let mut settings = load_settings();
+    settings.set(rom, game);
+    let result = path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|()| {
+        let header = "; Settings remembered for ROMs, see src/settings.rs\n\n";
+        fs::write(&path, header.to_string() + &settings.to_string())
+    });
+    if let Err(e) = result {
+        error(format!("failed to write {}: {}", path.display(), e));
+    }
+}
+
+/// Apply the speed, colors, muting and keys that options set, leaving the
+/// others as they are
+fn apply_options(frontend: &mut Frontend, options: &Options) {
+    if let Some(speed) = options.speed {
+        frontend.set_speed(speed);
+    }
+    if options.palette.is_some() || options.foreground.is_some() || options.background.is_some() {
+        let (background, foreground) = options.palette.unwrap_or(frontend.colors());
+        frontend.set_colors(options.background.unwrap_or(background), options.foreground.unwrap_or(foreground));
+    }
+    if options.muted {
+        frontend.set_muted(true);
+    }
+    let mut keymap = frontend.keymap().clone();
+    for chip8_key in 0..16 {
+        if options.keymap.keys(chip8_key) != Keymap::default().keys(chip8_key) {
+            keymap.set(chip8_key, options.keymap.keys(chip8_key).to_vec());
+        }
+    }
+    frontend.set_keymap(keymap);
+}
+
+/// The settings a ROM gets without the ones remembered for it, which only
+/// the differences from are remembered
+struct UsualSettings {
+    speed: u32,
+    colors: ([u8; 3], [u8; 3]),
+    muted: bool,
+    keymap: Keymap,
+}
+
+impl UsualSettings {
+    fn of(frontend: &Frontend) -> UsualSettings {
+        UsualSettings {
+            speed: frontend.speed(),
+            colors: frontend.colors(),
+            muted: frontend.muted(),
+            keymap: frontend.keymap().clone(),
+        }
+    }
+
+    /// The settings of the frontend that differ from these
+    fn differences(&self, frontend: &Frontend) -> GameSettings {
+        let keys = (0..16)
+            .filter(|&chip8_key| frontend.keymap().keys(chip8_key) != self.keymap.keys(chip8_key))
+            .map(|chip8_key| {
+                let names = frontend.keymap().keys(chip8_key).iter().map(|scancode| scancode.name().to_string());
+                (chip8_key, names.collect())
+            })
+            .collect();
+        GameSettings {
+            speed: Some(frontend.speed()).filter(|&speed| speed != self.speed),
+            colors: Some(frontend.colors()).filter(|&colors| colors != self.colors),
+            muted: Some(frontend.muted()).filter(|&muted| muted != self.muted),
+            keys,
+        }
+    }
+}
+
+/// Apply the settings remembered for a ROM
+fn apply_game_settings(frontend: &mut Frontend, game: &GameSettings) {
+    if let Some(speed) = game.speed {
+        frontend.set_speed(speed);
+    }
+    if let Some((background, foreground)) = game.colors {
+        frontend.set_colors(background, foreground);
+    }
+    if let Some(muted) = game.muted {
+        frontend.set_muted(muted);
+    }
+    let mut keymap = frontend.keymap().clone();
+    for (&chip8_key, names) in &game.keys {
+        let scancodes = names.iter().filter_map(|name| {
+            let scancode = Scancode::from_name(name);
+            if scancode.is_none() {
+                error(format!("unknown key \"{}\" in the remembered settings", name));
+            }
+            scancode
+        });
+        keymap.set(chip8_key, scancodes.collect());
+    }
+    frontend.set_keymap(keymap);
+}
+
+/// Load the bundled database, extended with the user's and the one given
+/// with `--database`
+fn load_database(path: Option<&str>) -> Database {
+    let mut database = Database::bundled();
+    let user_path = user_database_path().filter(|path| path.exists());
+    let paths = user_path.iter().map(|path| path.display().to_string()).chain(path.map(str::to_string));
+    for path in paths {
+        let extension = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|source| Database::parse(&source).map_err(|e| e.to_string()))
+            .unwrap_or_else(|e| {
+                error(format!("failed to load {}: {}", path, e));
+                std::process::exit(1);
+            });
+        database.extend(extension);
+    }
+    database
+}
+
+/// Print the metadata of a ROM for `--info`
+fn print_info(path: &str, rom: &[u8], entry: Option<&Entry>) {
+    println!("File:      {}", path);
+    println!("Size:      {} bytes", rom.len());
+    println!("SHA-1:     {}", to_hex(&sha1(rom)));
+    println!("CRC-32:    {:08x}", crc32(rom));
+    match check_rom(rom) {
+        Ok(()) if rom.len() % 2 == 1 => println!("Loadable:  yes, but the odd size suggests it is truncated"),
+        Ok(()) => println!("Loadable:  yes"),
+        Err(e) => println!("Loadable:  no, {}", e),
+    }
+    println!("Opcodes:   {} or later", analyze(rom).platform);
+
+    let Some(entry) = entry else {
+        println!("Database:  unknown ROM");
+        return;
+    };
+    println!("Title:     {}", entry.title.as_deref().unwrap_or("unknown"));
+    println!("Authors:   {}", entry.authors.as_deref().unwrap_or("unknown"));
+    println!("Platform:  {}", entry.platform);
+    let quirks = enabled_quirks(&entry.quirks);
+    println!("Quirks:    {}", if quirks.is_empty() { "none".to_string() } else { quirks.join(" ") });
+    match entry.tickrate {
+        Some(tickrate) => println!("Tickrate:  {} instructions per frame", tickrate),
+        None => println!("Tickrate:  default"),
+    }
+}
+
+/// A seed for the random number generator, different every time
+fn time_seed() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// Let the user choose a ROM in the `--rom-dir` directory with a
+/// [RomBrowser], and quit if they close it
+fn choose_rom(options: &Options, database: &Database) -> String {
+    let dir = options.rom_dir.clone().unwrap_or_else(|| PathBuf::from("."));
+    let mut browser = RomBrowser::new(&dir, database).unwrap_or_else(|e| {
+        error(format!("failed to read directory {}: {}", dir.display(), e));
+        std::process::exit(1);
+    });
+    if browser.is_empty() {
+        error(format!("no ROM given, and no ROMs in {} (see --rom-dir)", dir.display()));
+        std::process::exit(1);
+    }
+    let (background, foreground) = options.palette.unwrap_or(DEFAULT_PALETTE);
+    browser.set_colors(options.background.unwrap_or(background), options.foreground.unwrap_or(foreground));
+
+    // The window is closed once a ROM is chosen, before the one running it
+    // is opened
+    let sdl_context = sdl2::init().expect("Failed to initialize SDL2");
+    let video_subsystem = sdl_context.video().expect("Failed to initialize video subsystem");
+    let (width, height) = (DISPLAY_WIDTH * options.window_scale, DISPLAY_HEIGHT * options.window_scale);
+    let window = video_subsystem.window("chip8vm — ROMs", width as u32, height as u32)
+        .position_centered()
+        .resizable()
+        .build()
+        .expect("Failed to create window");
+    let mut canvas = window.into_canvas().present_vsync().build().expect("Failed to create canvas");
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    match browser.choose(&mut canvas, &mut event_pump) {
+        Some(path) => path.display().to_string(),
+        None => std::process::exit(0),
+    }
+}
+
+/// Read a ROM and create the twin machine of `--compare` running it, with
+/// the `--compare-quirks` if given
+fn create_twin(options: &Options, database: &Database, seed: u64) -> Result<VM, String> {
+    let path = options.compare_path.as_ref().unwrap_or(&options.rom_path);
+    let rom = fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+    check_rom(&rom).map_err(|e| format!("{}: {}", path, e))?;
+    let mut twin = create_vm(&rom, options, database.get(&rom), seed)?;
+    if let Some(quirks) = options.compare_quirks {
+        twin.set_quirks(quirks);
+    }
+    Ok(twin)
+}
+
+/// Read and decode a PNG file
+fn load_png(path: &Path) -> Result<Image, String> {
+    let data = fs::read(path).map_err(|e| format!("failed to read: {}", e))?;
+    png::decode(&data).map_err(|e| e.to_string())
+}
+
+/// The icon of the window unless one is given: the digit 8 of the CHIP-8
+/// font, lit on a black square
+fn default_icon() -> Image {
+    const SCALE: usize = 4;
+    let size = 8 * SCALE;
+    let glyph = glyph('8');
+    let pixels = (0..size * size).map(|i| {
+        // The 4x5 glyph in the middle of an 8x8 grid
+        let (x, y) = ((i % size / SCALE) as isize - 2, (i / size / SCALE) as isize - 1);
+        let lit = (0..4).contains(&x) && (0..5).contains(&y) && glyph[y as usize] & (0x80 >> x) != 0;
+        if lit { [0xFF, 0xFF, 0xFF, 0xFF] } else { [0x00, 0x00, 0x00, 0xFF] }
+    }).collect();
+    Image { width: size, height: size, pixels }
+}
+
+/// Read and decode a WAV file
+fn load_sound(path: &Path) -> Result<Sound, String> {
+    let data = fs::read(path).map_err(|e| format!("failed to read: {}", e))?;
+    wav::decode(&data).map_err(|e| e.to_string())
+}
+
+/// Create a virtual machine running a ROM, set up as the options and the
+/// database entry of the ROM say, the random number generator starting
+/// from `seed`
+fn create_vm(rom: &[u8], options: &Options, entry: Option<&Entry>, seed: u64) -> Result<VM, String> {
+    let mut vm = VM::new();
+    vm.seed_rng(seed);
+    vm.load_program(rom);
+    vm.set_rom_protection(options.rom_protection);
+    vm.set_code_protection(options.code_protection);
+    vm.set_key_latching(options.latch_keys);
+    if options.debugger {
+        vm.set_history_length(HISTORY_LENGTH);
+    }
+    if let Some(entry) = entry {
+        vm.set_quirks(entry.quirks);
+    }
+    for &watchpoint in &options.watchpoints {
+        vm.add_watchpoint(watchpoint);
+    }
+    vm.set_break_on_illegal(options.break_on_illegal);
+    vm.set_debug_traps(options.debug_traps);
+    for (range, path) in &options.dumps {
+        let dump = MemoryDump::new(range.clone(), path).map_err(|e| format!("failed to create {}: {}", path, e))?;
+        vm.add_memory_dump(dump);
+    }
+    Ok(vm)
+}
+
+/// Trace, profile, count and record the coverage of the instructions of
+/// the machine (not of its twin) if asked to, and keep the last ones for
+/// a core file
+fn set_up_tracing(vm: &mut VM, options: &Options) -> Result<(), String> {
+    vm.enable_recent(RECENT_LENGTH);
+    if options.profile_path.is_some() {
+        vm.enable_profiling();
+    }
+    if options.heatmap_path.is_some() {
+        vm.enable_heatmap();
+    }
+    if options.coverage_path.is_some() {
+        vm.enable_coverage();
+    }
+    let mut trace = match &options.trace_path {
+        Some(path) => {
+            let file = File::create(path).map_err(|e| format!("failed to create {}: {}", path.display(), e))?;
+            Trace::new(Box::new(file), None)
+        }
+        None if options.trace => Trace::new(Box::new(io::stdout()), Some(TRACE_LINES_PER_FRAME)),
+        None => return Ok(()),
+    };
+    trace.set_format(options.trace_format);
+    trace.set_filter(options.trace_filter.clone());
+    vm.set_trace(trace);
+    Ok(())
+}
+
+/// Parse `<start>..<end>=<file>`
+fn parse_dump_spec(spec: &str) -> Result<(Range<usize>, String), String> {
+    let invalid = || format!("invalid --dump-on-write argument \"{}\"", spec);
+    let (range, path) = spec.split_once('=').ok_or_else(invalid)?;
+    let (start, end) = range.split_once("..").ok_or_else(invalid)?;
+    let start = parse_number(start).ok_or_else(invalid)? as usize;
+    let end = parse_number(end).ok_or_else(invalid)? as usize;
+    if start >= end || end > RAM_SIZE || path.is_empty() {
+        return Err(invalid());
+    }
+    Ok((start..end, path.to_string()))
+}
+
+/// Parse `<start>..<end>`, a range of addresses
+fn parse_address_range(spec: &str) -> Option<Range<u16>> {
+    let (start, end) = spec.split_once("..")?;
+    let (start, end) = (parse_number(start)?, parse_number(end)?);
+    (start < end && end as usize <= RAM_SIZE).then_some(start..end)
+}
+
+/// Write what `--dump-display-on-exit`, `--dump-state-on-exit`,
+/// `--profile` or `--coverage` dumps to `path`, or to the standard output if it is `-`.
+/// Returns whether it was written, the other dumps being written anyway
+fn write_dump(path: &str, text: String) -> bool {
+    if path == "-" {
+        print!("{}", text);
+    } else if let Err(e) = fs::write(path, text) {
+        error(format!("failed to write {}: {}", path, e));
+        return false;
+    }
+    true
+}
+
+/// Create the debugger, with the symbols, debug info and breakpoints of
+/// the options
+fn create_debugger(options: &Options) -> Debugger {
+    let mut debugger = Debugger::new();
+    if let Some(path) = &options.symbols_path {
+        let symbols = File::open(path).and_then(|file| read_symbol_table(BufReader::new(file)));
+        match symbols {
+            Ok(symbols) => debugger.set_symbols(&symbols),
+            Err(e) => {
+                error(format!("failed to read {}: {}", path.display(), e));
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &options.debug_info_path {
+        let debug_info = File::open(path).and_then(|file| read_debug_info(BufReader::new(file)));
+        match debug_info {
+            Ok(debug_info) => debugger.set_debug_info(debug_info),
+            Err(e) => {
+                error(format!("failed to read {}: {}", path.display(), e));
+                std::process::exit(1);
+            }
+        }
+    }
+    for (location, temporary) in &options.breakpoints {
+        if let Err(e) = debugger.add_breakpoint(location, *temporary) {
+            let option = if *temporary { "--run-until" } else { "--break-at" };
+            error(format!("invalid {} argument: {}", option, e));
+            std::process::exit(1);
+        }
+    }
+    debugger
+}
+
+/// The session of the debugger for a ROM, kept by its SHA-1 in
+/// `sessions` in the config directory
+fn default_session_path(rom: &[u8]) -> Option<PathBuf> {
+    Some(config_dir()?.join("sessions").join(format!("{}.txt", to_hex(&sha1(rom)))))
+}
+
+/// Carry out the commands of a session saved by [save_session]
+fn restore_session(debugger: &mut Debugger, vm: &mut VM, path: &Path) {
+    match fs::read_to_string(path) {
+        Ok(session) => {
+            println!("Restoring the debugger session of {}", path.display());
+            for e in debugger.restore(vm, &session) {
+                file_warning(e, &path.display().to_string());
+            }
+        }
+        Err(e) => error(format!("failed to read {}: {}", path.display(), e)),
+    }
+}
+
+/// Save the breakpoints, watchpoints and expressions displayed, unless
+/// there are none and none were saved before
+fn save_session(session: &str, path: &Path) {
+    if session.is_empty() && !path.exists() {
+        return;
+    }
+    let result = path.parent().map_or(Ok(()), fs::create_dir_all).and_then(|()| {
+        let header = "; Debugger session, restored the next time the ROM is debugged, see src/debugger.rs\n\n";
+        fs::write(path, header.to_string() + session)
+    });
+    if let Err(e) = result {
+        error(format!("failed to write {}: {}", path.display(), e));
+    }
+}
+
+/// Write a core file of `vm` into the current directory, saying why, and
+/// print where
+fn write_core(vm: &VM, options: &Options, reason: String) {
+    let time = SystemTime::now();
+    let core = Core {
+        time: time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs()),
+        reason,
+        rom_path: options.rom_path.clone(),
+        state: vm.state(),
+        recent: vm.recent().map_or(Vec::new(), |recent| recent.iter().copied().collect()),
+    };
+    let path = format!("crash-{}.c8core", timestamp(time));
+    let mut bytes = Vec::new();
+    core.write_to(&mut bytes).expect("writing to a vector cannot fail");
+    match fs::write(&path, bytes) {
+        Ok(()) => println!("Wrote {}, see chip8 inspect-core", path),
+        Err(e) => error(format!("failed to write {}: {}", path, e)),
+    }
+}
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: {} <rom.ch8>", args[0]);
+    let config_args = load_config();
+    let all_args = [config_args.as_slice(), &args[1..]].concat();
+    let mut options = parse_args(&all_args).unwrap_or_else(|e| {
+        error(e);
+        eprintln!("Usage: {} [options] [rom.ch8]\n\n{}", args[0], OPTIONS_HELP);
         std::process::exit(1);
+    });
+    if options.print_keymap {
+        print!("{}", options.keymap);
+        return;
+    }
+    // Parsing fewer arguments cannot fail once all of them were parsed
+    let config_options = parse_args(&[config_args.as_slice(), std::slice::from_ref(&options.rom_path)].concat()).unwrap();
+    let command_line_options = parse_args(&args[1..]).unwrap();
+
+    let database = if options.use_database { load_database(options.database_path.as_deref()) } else { Database::default() };
+    if options.rom_path.is_empty() {
+        options.rom_path = choose_rom(&options, &database);
     }
 
-    let mut file = File::open(&args[1]).expect("Failed to open ROM file");
+    let mut file = File::open(&options.rom_path).expect("Failed to open ROM file");
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer).expect("Failed to read ROM file");
 
+    let entry = database.get(&buffer);
+    if options.info {
+        print_info(&options.rom_path, &buffer, entry);
+        return;
+    }
+
+    check_rom(&buffer).unwrap_or_else(|e| {
+        error(format!("{}: {}", options.rom_path, e));
+        std::process::exit(1);
+    });
+    if buffer.len() % 2 == 1 {
+        file_warning("odd size, the ROM may be truncated (padded with a zero byte)".to_string(), &options.rom_path);
+    }
+
+    // Both machines of a comparison draw the same random numbers, and the
+    // frontend reloading them borrows it
+    let seed = Cell::new(time_seed());
+
+    let scaler = scaler_by_name(&options.scaler, options.scale).unwrap_or_else(|e| {
+        error(e);
+        std::process::exit(1);
+    });
+    let screenshot_scaler = options.screenshot_scale.map(|scale| {
+        scaler_by_name(&options.scaler, scale).unwrap_or_else(|e| {
+            error(format!("screenshots: {}", e));
+            std::process::exit(1);
+        })
+    });
+
+    let beep_sound = options.beep_sound_path.as_ref().map(|path| load_sound(path).unwrap_or_else(|e| {
+        error(format!("{}: {}", path.display(), e));
+        std::process::exit(1);
+    }));
+
     let sdl_context = sdl2::init().expect("Failed to initialize SDL2");
     let video_subsystem = sdl_context
         .video()
@@ -25,34 +1254,271 @@ fn main() {
         .audio()
         .expect("Failed to initialize audio subsystem");
 
-    let window = video_subsystem
-        .window("CHIP-8 Emulator", WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32)
-        .position_centered()
-        .build()
-        .expect("Failed to create window");
-    let canvas = window
-        .into_canvas()
-        .accelerated()
-        .present_vsync()
-        .build()
-        .expect("Failed to create canvas");
+    let name = entry.and_then(Entry::name).unwrap_or_else(|| {
+        Path::new(&options.rom_path).file_name().map_or(options.rom_path.clone(), |name| name.to_string_lossy().into_owned())
+    });
+    let icon = match &options.icon_path {
+        Some(path) => load_png(path).unwrap_or_else(|e| {
+            error(format!("{}: {}", path.display(), e));
+            std::process::exit(1);
+        }),
+        None => default_icon(),
+    };
+    // The twin machine of a comparison is shown to the right
+    let comparing = options.compare_path.is_some() || options.compare_quirks.is_some();
+    let displays = if comparing { 2 } else { 1 };
+    let (width, height) = (displays * DISPLAY_WIDTH * options.window_scale, DISPLAY_HEIGHT * options.window_scale);
+    let mut window_builder = video_subsystem.window("chip8vm", width as u32, height as u32);
+    window_builder.position_centered().resizable();
+    if options.borderless {
+        window_builder.borderless();
+    }
+    let mut window = window_builder.build().expect("Failed to create window");
+    let mut icon_pixels: Vec<u8> = icon.pixels.concat();
+    match Surface::from_data(&mut icon_pixels, icon.width as u32, icon.height as u32, icon.width as u32 * 4, PixelFormatEnum::RGBA32) {
+        Ok(surface) => window.set_icon(surface),
+        Err(e) => error(format!("failed to set the icon of the window: {}", e)),
+    }
+    let mut canvas_builder = window.into_canvas().accelerated();
+    if options.vsync {
+        canvas_builder = canvas_builder.present_vsync();
+    }
+    let canvas = canvas_builder.build().expect("Failed to create canvas");
 
+    // Textures are filtered as the hint said when they were created
+    sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", if options.linear_filtering { "linear" } else { "nearest" });
     let texture_creator = canvas.texture_creator();
     let texture = texture_creator
         .create_texture_target(
-            PixelFormatEnum::RGB332,
-            DISPLAY_WIDTH as u32,
-            DISPLAY_HEIGHT as u32
+            PixelFormatEnum::RGBA32,
+            (displays * DISPLAY_WIDTH * scaler.factor()) as u32,
+            (DISPLAY_HEIGHT * scaler.factor()) as u32
         )
         .expect("Failed to create texture");
 
     let event_pump = sdl_context.event_pump().unwrap();
 
-    let mut vm = VM::new(canvas, texture, event_pump, audio_subsystem);
-    vm.load_program(&buffer);
+    let recorder = options.video_path.as_ref().map(|path| {
+        let mut recorder = Recorder::new(path, DISPLAY_WIDTH * scaler.factor(), DISPLAY_HEIGHT * scaler.factor())
+            .unwrap_or_else(|e| {
+                error(format!("failed to record to {}: {}", path.display(), e));
+                std::process::exit(1);
+            });
+        match &beep_sound {
+            Some(sound) => recorder.set_beep_sound(sound),
+            None => recorder.set_waveform(options.waveform, options.duty_cycle),
+        }
+        recorder
+    });
+
+    let audio_buffer = options.audio_buffer.unwrap_or(DEFAULT_AUDIO_BUFFER);
+    let mut frontend = Frontend::new(canvas, texture, scaler, event_pump, audio_subsystem, audio_buffer);
+    if options.audio_buffer.is_some() {
+        let spec = frontend.audio_spec();
+        println!(
+            "Audio: {} Hz, buffers of {} samples ({:.1} ms)",
+            spec.freq, spec.samples, spec.samples as f64 * 1000.0 / spec.freq as f64
+        );
+    }
+    if let Some(recorder) = recorder {
+        frontend.set_recorder(recorder);
+    }
+    frontend.set_key_matching(options.key_matching);
+    frontend.set_frame_blending(options.blend_frames);
+    frontend.set_phosphor(options.phosphor);
+    frontend.set_crt_effects(options.crt);
+    frontend.set_grid(options.grid);
+    frontend.set_debug_overlay(options.debug_overlay);
+    if let Some(dir) = &options.screenshot_dir {
+        frontend.set_screenshot_dir(dir.clone());
+    }
+    if let Some(scaler) = screenshot_scaler {
+        frontend.set_screenshot_scaler(scaler);
+    }
+    frontend.set_integer_scaling(options.integer_scaling);
+    frontend.set_pause_when_unfocused(options.pause_when_unfocused);
+    frontend.set_title(&options.title, &name);
+    if let Some(volume) = options.volume {
+        frontend.set_volume(volume);
+    }
+    match &beep_sound {
+        Some(sound) => frontend.set_beep_sound(sound),
+        None => frontend.set_waveform(options.waveform, options.duty_cycle),
+    }
+    // After the waveform, and the recorder, whose tones it changes
+    frontend.set_beep_frequency(options.beep_frequency);
+    if let Some(color) = options.bar_color {
+        frontend.set_bar_color(color);
+    }
+    if options.fullscreen && let Err(e) = frontend.set_fullscreen(true) {
+        error(format!("failed to switch to fullscreen: {}", e));
+    }
+    if let Some(path) = &options.mirror_path {
+        match Mirror::new(path) {
+            Ok(mirror) => frontend.set_mirror(mirror),
+            Err(e) => {
+                error(format!("failed to create {}: {}", path, e));
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(address) = &options.control_address {
+        match TcpListener::bind(address) {
+            Ok(listener) => {
+                println!("Control server listening on {}", address);
+                frontend.set_control_server(listener);
+            }
+            Err(e) => {
+                error(format!("failed to listen on {}: {}", address, e));
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(path) = &options.input_recording_path {
+        frontend.set_input_recording(path.clone(), seed.get());
+    }
+    if let Some(path) = &options.replay_path {
+        let replay = File::open(path).and_then(|file| Replay::read_from(BufReader::new(file))).unwrap_or_else(|e| {
+            error(format!("failed to read {}: {}", path.display(), e));
+            std::process::exit(1);
+        });
+        seed.set(replay.seed);
+        frontend.set_input_playback(replay);
+    }
+
+    let mut vm = create_vm(&buffer, &options, entry, seed.get()).unwrap_or_else(|e| {
+        error(e);
+        std::process::exit(1);
+    });
+    if let Err(e) = set_up_tracing(&mut vm, &options) {
+        error(e);
+        std::process::exit(1);
+    }
+    let session_path = options.session_path.clone().or_else(|| default_session_path(&buffer));
+    if options.debugger {
+        let mut debugger = create_debugger(&options);
+        if let Some(path) = session_path.as_ref().filter(|path| path.exists()) {
+            restore_session(&mut debugger, &mut vm, path);
+        }
+        frontend.set_debugger(debugger);
+        if let Some(path) = &options.debug_script_path {
+            let script = fs::read_to_string(path).unwrap_or_else(|e| {
+                error(format!("failed to read {}: {}", path.display(), e));
+                std::process::exit(1);
+            });
+            let commands = script.lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with(';'))
+                .map(str::to_string)
+                .collect();
+            frontend.set_debug_script(commands);
+        }
+    }
+    if comparing {
+        let twin = create_twin(&options, &database, seed.get()).unwrap_or_else(|e| {
+            error(e);
+            std::process::exit(1);
+        });
+        println!(
+            "Comparing with {}{} on the right",
+            options.compare_path.as_deref().unwrap_or(&options.rom_path),
+            options.compare_quirks.map_or(String::new(), |quirks| format!(" (quirks: {})", enabled_quirks(&quirks).join(" ")))
+        );
+        frontend.set_twin(twin, || create_twin(&options, &database, seed.get()));
+    }
+    if let Some(entry) = entry {
+        println!("Recognized {}", entry.name().as_deref().unwrap_or("the ROM"));
+        if entry.platform != Platform::Chip8 {
+            println!("It was written for {}, which is not supported, so it may not run correctly", entry.platform);
+        }
+        if let Some(tickrate) = entry.tickrate {
+            frontend.set_tickrate(tickrate);
+        }
+        if let Some((background, foreground)) = entry.colors {
+            frontend.set_colors(background, foreground);
+        }
+    }
+    // Settings apply in order: the database, the config file, the settings
+    // remembered for the ROM, and the command line
+    apply_options(&mut frontend, &config_options);
+    let usual_settings = UsualSettings::of(&frontend);
+    let remembered = load_settings().get(&buffer).cloned().unwrap_or_default();
+    if !options.forget_settings {
+        apply_game_settings(&mut frontend, &remembered);
+    }
+    apply_options(&mut frontend, &command_line_options);
+    frontend.set_reload(|| {
+        let rom = fs::read(&options.rom_path).map_err(|e| format!("failed to read {}: {}", options.rom_path, e))?;
+        check_rom(&rom).map_err(|e| format!("{}: {}", options.rom_path, e))?;
+        // The recorded input plays back with the seed it was recorded with
+        if options.input_recording_path.is_none() && options.replay_path.is_none() {
+            seed.set(time_seed());
+        }
+        let mut vm = create_vm(&rom, &options, database.get(&rom), seed.get())?;
+        set_up_tracing(&mut vm, &options)?;
+        println!("Reloaded {} bytes", rom.len());
+        Ok(vm)
+    });
+    if options.watch {
+        frontend.set_watched_file(PathBuf::from(&options.rom_path));
+    }
 
     println!("Loaded {} bytes into RAM (address 0x200)", buffer.len());
     println!("Starting VM...");
 
-    vm.mainloop();
+    let result = frontend.run(&mut vm);
+    let game_settings = usual_settings.differences(&frontend);
+    if game_settings != remembered {
+        save_game_settings(&buffer, game_settings);
+    }
+    if let Some((path, Err(e))) = frontend.finish_recording() {
+        error(format!("failed to finish {}: {}", path.display(), e));
+    }
+    if let Some((path, Err(e))) = frontend.finish_input_recording() {
+        error(format!("failed to write {}: {}", path.display(), e));
+    }
+    let mut dumps_written = true;
+    if let Some(path) = &options.display_dump_path {
+        dumps_written &= write_dump(path, display_to_text(vm.display()));
+    }
+    if let Some(path) = &options.state_dump_path {
+        dumps_written &= write_dump(path, vm.state().to_json().to_pretty_string() + "\n");
+    }
+    if let (Some(path), Some(profile)) = (&options.profile_path, vm.profile()) {
+        if path.ends_with(".json") {
+            dumps_written &= write_dump(path, profile.to_json(&vm.counters()).to_pretty_string() + "\n");
+        } else {
+            dumps_written &= write_dump(path, profile.report(&vm.counters()));
+        }
+    }
+    if let (Some(path), Some(coverage)) = (&options.coverage_path, vm.coverage()) {
+        dumps_written &= write_dump(path, coverage.report(vm.ram(), vm.rom()));
+    }
+    if let (Some(path), Some(heatmap)) = (&options.heatmap_path, vm.heatmap()) {
+        let result = if path.ends_with(".png") {
+            fs::write(path, png::encode(&heatmap.to_image()))
+        } else {
+            fs::write(path, heatmap.to_csv())
+        };
+        if let Err(e) = result {
+            error(format!("failed to write {}: {}", path, e));
+            dumps_written = false;
+        }
+    }
+    if let (Some(debugger), Some(path)) = (frontend.debugger(), &session_path) {
+        save_session(&debugger.session(&vm), path);
+    }
+    match &result {
+        Err(e) => write_core(&vm, &options, e.to_string()),
+        Ok(()) if options.coredump => write_core(&vm, &options, "quit".to_string()),
+        Ok(()) => {}
+    }
+    if let Err(e) = result {
+        error(e.to_string());
+        std::process::exit(2);
+    }
+    if !dumps_written {
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,380 @@
+//! Settings for known ROMs, keyed by their SHA-1
+//!
+//! Many ROMs only run correctly with some [Quirks], or at a particular
+//! speed. A database file records them, one section per ROM:
+//!
+//! ```text
+//! ; Comments start with a semicolon
+//! [1ba58656810b67fd131eb9af3e3987863bf26c90]   ; SHA-1 of the ROM
+//! title = IBM Logo
+//! authors = Anonymous         ; shown after the title
+//! platform = chip8            ; chip8, superchip or xochip (default chip8)
+//! quirks = logic vblank       ; enabled quirks (default: the platform's)
+//! tickrate = 15               ; instructions per 60 Hz frame
+//! colors = #000000 #FFFFFF    ; background and foreground
+//! ```
+//!
+//! Quirks are named after the fields of [Quirks], as in the
+//! [CHIP-8 database](https://github.com/chip-8/chip-8-database):
+//! `shift`, `memoryIncrementByX`, `memoryLeaveIUnchanged`, `wrap`, `jump`,
+//! `vblank` and `logic`, and `keyRelease`, which it does not have. Its `programs.json` can be converted with
+//! [Database::import]. The database in `roms/database.txt` is compiled in
+//! (see [Database::bundled]), and entries of other databases replace the ones
+//! for the same ROM.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+use std::path::PathBuf;
+use crate::hash::{sha1, to_hex};
+use crate::interpreter::Quirks;
+use crate::isa::Platform;
+use crate::json;
+
+const BUNDLED: &str = include_str!("../roms/database.txt");
+
+const QUIRK_NAMES: [&str; 8] = [
+    "shift", "memoryIncrementByX", "memoryLeaveIUnchanged", "wrap", "jump", "vblank", "logic", "keyRelease",
+];
+
+/// The settings of a ROM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub title: Option<String>,
+    pub authors: Option<String>,
+    /// The platform the ROM was written for
+    pub platform: Platform,
+    pub quirks: Quirks,
+    /// Number of instructions to execute per 60 Hz frame
+    pub tickrate: Option<u32>,
+    /// Background and foreground colors, as RGB
+    pub colors: Option<([u8; 3], [u8; 3])>,
+}
+
+impl Entry {
+    fn new(platform: Platform) -> Entry {
+        Entry {
+            title: None,
+            authors: None,
+            platform,
+            quirks: Quirks::of_platform(platform),
+            tickrate: None,
+            colors: None,
+        }
+    }
+
+    /// The title followed by the authors between parentheses, if known
+    pub fn name(&self) -> Option<String> {
+        let title = self.title.as_ref()?;
+        Some(match &self.authors {
+            Some(authors) => format!("{} ({})", title, authors),
+            None => title.clone(),
+        })
+    }
+}
+
+/// ROM settings, keyed by the SHA-1 of the ROM in lowercase hexadecimal
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Database {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Database {
+    /// The database shipped with the emulator
+    pub fn bundled() -> Database {
+        Database::parse(BUNDLED).expect("The bundled ROM database is invalid")
+    }
+
+    /// Parse the contents of a database file
+    pub fn parse(source: &str) -> Result<Database, DatabaseError> {
+        let mut entries = BTreeMap::new();
+        // The current section, and whether it sets the quirks explicitly
+        let mut current: Option<(String, Entry, bool)> = None;
+
+        for (line_index, line) in source.lines().enumerate() {
+            let line_number = line_index + 1;
+            let parse_error = |message: String| DatabaseError::Parse { line_number, message };
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(hash) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                let hash = hash.trim().to_ascii_lowercase();
+                if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(parse_error(format!("\"{}\" is not a SHA-1", hash)));
+                }
+                if let Some((hash, entry, _)) = current.replace((hash, Entry::new(Platform::Chip8), false)) {
+                    entries.insert(hash, entry);
+                }
+                continue;
+            }
+
+            let Some((_, entry, explicit_quirks)) = &mut current else {
+                return Err(parse_error("expected a [sha1] section".to_string()));
+            };
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(parse_error("expected `key = value`".to_string()));
+            };
+            let value = value.trim();
+            match key.trim() {
+                "title" => entry.title = Some(value.to_string()),
+                "authors" => entry.authors = Some(value.to_string()),
+                "platform" => {
+                    entry.platform = Platform::from_name(value)
+                        .ok_or_else(|| parse_error(format!("unknown platform {}", value)))?;
+                    if !*explicit_quirks {
+                        entry.quirks = Quirks::of_platform(entry.platform);
+                    }
+                }
+                "quirks" => {
+                    entry.quirks = parse_quirks(value).map_err(parse_error)?;
+                    *explicit_quirks = true;
+                }
+                "tickrate" => {
+                    entry.tickrate = Some(value.parse().ok().filter(|tickrate| *tickrate > 0)
+                        .ok_or_else(|| parse_error("the tickrate must be a positive integer".to_string()))?);
+                }
+                "colors" => {
+                    let colors: Option<Vec<[u8; 3]>> = value.split_whitespace().map(parse_color).collect();
+                    let Some(&[background, foreground]) = colors.as_deref() else {
+                        return Err(parse_error("expected two colors such as #000000 #FFFFFF".to_string()));
+                    };
+                    entry.colors = Some((background, foreground));
+                }
+                key => return Err(parse_error(format!("unknown setting \"{}\"", key))),
+            }
+        }
+
+        if let Some((hash, entry, _)) = current {
+            entries.insert(hash, entry);
+        }
+        Ok(Database { entries })
+    }
+
+    /// Convert the `programs.json` file of the
+    /// [CHIP-8 database](https://github.com/chip-8/chip-8-database). ROMs for
+    /// platforms other than CHIP-8, SUPER-CHIP and XO-CHIP are left out
+    pub fn import(programs_json: &str) -> Result<Database, DatabaseError> {
+        let invalid = |message: &str| DatabaseError::Import { message: message.to_string() };
+        let programs = json::parse(programs_json).map_err(DatabaseError::Json)?;
+        let programs = programs.as_array().ok_or_else(|| invalid("expected an array of programs"))?;
+
+        let mut entries = BTreeMap::new();
+        for program in programs {
+            let title = program.get("title").and_then(json::Value::as_str);
+            let authors: Vec<&str> = program.get("authors")
+                .and_then(json::Value::as_array)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(json::Value::as_str)
+                .collect();
+            let roms = program.get("roms").and_then(json::Value::as_object).unwrap_or_default();
+            for (hash, rom) in roms {
+                let Some((platform_id, mut entry)) = rom.get("platforms")
+                    .and_then(json::Value::as_array)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(json::Value::as_str)
+                    .find_map(|id| community_platform(id).map(|entry| (id, entry)))
+                else {
+                    continue;
+                };
+
+                let quirk_overrides = rom.get("quirkyPlatforms")
+                    .and_then(|platforms| platforms.get(platform_id))
+                    .and_then(json::Value::as_object)
+                    .unwrap_or_default();
+                for (name, enabled) in quirk_overrides {
+                    if let (Some(quirk), Some(enabled)) = (quirk_mut(&mut entry.quirks, name), enabled.as_bool()) {
+                        *quirk = enabled;
+                    }
+                }
+
+                entry.title = title.map(str::to_string);
+                entry.authors = (!authors.is_empty()).then(|| authors.join(", "));
+                entry.tickrate = rom.get("tickrate")
+                    .and_then(json::Value::as_f64)
+                    .filter(|tickrate| *tickrate >= 1.0)
+                    .map(|tickrate| tickrate as u32);
+                let pixels: Vec<[u8; 3]> = rom.get("colors")
+                    .and_then(|colors| colors.get("pixels"))
+                    .and_then(json::Value::as_array)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|color| parse_color(color.as_str()?))
+                    .collect();
+                if let [background, foreground, ..] = pixels[..] {
+                    entry.colors = Some((background, foreground));
+                }
+                entries.insert(hash.to_ascii_lowercase(), entry);
+            }
+        }
+        Ok(Database { entries })
+    }
+
+    /// Add the entries of another database, replacing the ones for the same
+    /// ROMs
+    pub fn extend(&mut self, other: Database) {
+        self.entries.extend(other.entries);
+    }
+
+    /// Get the settings of a ROM
+    pub fn get(&self, rom: &[u8]) -> Option<&Entry> {
+        self.entries.get(&to_hex(&sha1(rom)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl fmt::Display for Database {
+    /// Write the database in the format read by [Database::parse]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (hash, entry)) in self.entries.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "[{}]", hash)?;
+            // Semicolons would start a comment
+            if let Some(title) = &entry.title {
+                writeln!(f, "title = {}", title.replace(';', ","))?;
+            }
+            if let Some(authors) = &entry.authors {
+                writeln!(f, "authors = {}", authors.replace(';', ","))?;
+            }
+            writeln!(f, "platform = {}", entry.platform.name())?;
+            let mut quirks = String::new();
+            for name in enabled_quirks(&entry.quirks) {
+                write!(quirks, " {}", name)?;
+            }
+            writeln!(f, "quirks ={}", quirks)?;
+            if let Some(tickrate) = entry.tickrate {
+                writeln!(f, "tickrate = {}", tickrate)?;
+            }
+            if let Some((background, foreground)) = entry.colors {
+                writeln!(f, "colors = {} {}", format_color(background), format_color(foreground))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The directory of the user's settings: `$XDG_CONFIG_HOME/chip8vm`, or
+/// `~/.config/chip8vm`
+pub fn config_dir() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config.join("chip8vm"))
+}
+
+/// The path of the database with the user's own settings, `database.txt`
+/// in [config_dir]
+pub fn user_database_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("database.txt"))
+}
+
+/// The names of the enabled quirks, as written in database files
+pub fn enabled_quirks(quirks: &Quirks) -> Vec<&'static str> {
+    QUIRK_NAMES
+        .into_iter()
+        .filter(|name| *quirk_mut(&mut quirks.clone(), name).unwrap())
+        .collect()
+}
+
+/// Parse the names of enabled quirks, as written in database files,
+/// separated by spaces or commas. The others are disabled
+pub fn parse_quirks(names: &str) -> Result<Quirks, String> {
+    let mut quirks = no_quirks();
+    for name in names.split([' ', '\t', ',']).filter(|name| !name.is_empty()) {
+        *quirk_mut(&mut quirks, name).ok_or_else(|| format!("unknown quirk {}", name))? = true;
+    }
+    Ok(quirks)
+}
+
+/// The platform and usual quirks of a platform identifier of the CHIP-8
+/// database
+fn community_platform(id: &str) -> Option<Entry> {
+    let quirks = |names: &[&str]| {
+        let mut quirks = no_quirks();
+        for name in names {
+            *quirk_mut(&mut quirks, name).unwrap() = true;
+        }
+        quirks
+    };
+    let (platform, quirks) = match id {
+        "originalChip8" | "hybridVIP" => (Platform::Chip8, quirks(&["vblank", "logic", "keyRelease"])),
+        "modernChip8" => (Platform::Chip8, quirks(&[])),
+        "chip48" | "superchip1" => (Platform::SuperChip, quirks(&["shift", "memoryIncrementByX", "jump", "keyRelease"])),
+        "superchip" => (Platform::SuperChip, quirks(&["shift", "memoryLeaveIUnchanged", "jump", "keyRelease"])),
+        "xochip" => (Platform::XoChip, quirks(&["wrap", "keyRelease"])),
+        _ => return None,
+    };
+    Some(Entry { quirks, ..Entry::new(platform) })
+}
+
+fn no_quirks() -> Quirks {
+    Quirks { logic: false, key_release: false, ..Quirks::default() }
+}
+
+fn quirk_mut<'a>(quirks: &'a mut Quirks, name: &str) -> Option<&'a mut bool> {
+    match name {
+        "shift" => Some(&mut quirks.shift),
+        "memoryIncrementByX" => Some(&mut quirks.memory_increment_by_x),
+        "memoryLeaveIUnchanged" => Some(&mut quirks.memory_leave_i_unchanged),
+        "wrap" => Some(&mut quirks.wrap),
+        "jump" => Some(&mut quirks.jump),
+        "vblank" => Some(&mut quirks.vblank),
+        "logic" => Some(&mut quirks.logic),
+        "keyRelease" => Some(&mut quirks.key_release),
+        _ => None,
+    }
+}
+
+/// Parse a `#RRGGBB` color
+pub fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#').filter(|hex| hex.len() == 6)?;
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+/// Format a color as `#RRGGBB`, as read by [parse_color]
+pub fn format_color([r, g, b]: [u8; 3]) -> String {
+    format!("#{:02X}{:02X}{:02X}", r, g, b)
+}
+
+/// An error that can occur while reading a database
+#[derive(Debug)]
+pub enum DatabaseError {
+    /// The database file is malformed
+    Parse {
+        line_number: usize,
+        message: String
+    },
+    /// The file to import is not valid JSON
+    Json(json::Error),
+    /// The file to import is not in the expected format
+    Import {
+        message: String
+    },
+}
+
+impl std::error::Error for DatabaseError {}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Parse { line_number, message } => {
+                write!(f, "database line {}: {}", line_number, message)
+            }
+            DatabaseError::Json(e) => write!(f, "{}", e),
+            DatabaseError::Import { message } => write!(f, "{}", message),
+        }
+    }
+}
@@ -0,0 +1,671 @@
+//! A debugger driven by commands typed in the terminal
+//!
+//! With `--debug`, the interpreter reads commands from the standard input,
+//! one per line, and its [runner](crate::frontend::runner) carries them out
+//! through a [Debugger]. Stepping needs the program to be paused, with `P`,
+//! `pause` or a watchpoint:
+//! - `step` (`s`): execute one instruction, entering subroutines
+//! - `next` (`n`): execute one instruction, running a subroutine `CALL`
+//!   enters until it returns
+//! - `finish` (`f`): run until the current subroutine returns
+//! - `continue` (`c`): resume the program
+//! - `reverse-step` (`rs`): undo the last instruction
+//! - `reverse-continue` (`rc`): undo instructions until the last one that
+//!   hit a watchpoint, or back to a breakpoint
+//! - `pause` (`p`): pause it
+//! - `backtrace` (`bt`): list the subroutines being run, innermost first,
+//!   with the `CALL` instructions that entered them
+//! - `break <location>` (`b`): stop when the program reaches a location, a
+//!   `file:line` of the source, a label or an address, or an instruction
+//!   of a class (see [OpcodeMask]), like `Fx18` or `Dxyn`. Without a
+//!   location, list the breakpoints
+//! - `tbreak <location>`: like `break`, but only stop once
+//! - `delete [<n>]`: delete breakpoint `n`, or all of them
+//! - `display <expression>`: print an [expression](expression) of
+//!   registers and memory, like `V0+V1` or `[I]`, every time the program
+//!   stops. Without an expression, print them all
+//! - `undisplay [<n>]`: stop printing expression `n`, or any
+//! - `watch <register>[=<value>]`: pause when an instruction changes a
+//!   register, or changes it to a value, like `--break-on-change`. Without
+//!   a register, list the watchpoints
+//! - `unwatch [<register>]`: delete the watchpoints on a register, or all
+//! - `regs`: print the registers, timers and stack pointer
+//! - `x/<n> <address>`: print `n` bytes of RAM (16 by default) in
+//!   hexadecimal
+//! - `set <register> <value>`: change V0..VF, I, DT or ST
+//! - `poke <address> <value>`: change a byte of RAM
+//! - `help`: list the commands
+//!
+//! Numbers are decimal, or hexadecimal with `0x`. An empty line repeats
+//! the last command. Subroutines are told apart by
+//! the depth of the stack of the machine, so that `next` steps over
+//! recursive calls too.
+//!
+//! The last [HISTORY_LENGTH] instructions can be undone (see
+//! [history](crate::interpreter::history)). Running forward again executes
+//! them anew, with the keys pressed then.
+//!
+//! Given the symbol table of the program (see [Debugger::set_symbols]),
+//! addresses are shown along with the label they follow. Given its
+//! [debug info](crate::assembler::debug_info) (see
+//! [Debugger::set_debug_info]), they are shown along with their source
+//! line too, and the program stops showing the line of assembly it is at.
+//!
+//! The breakpoints, watchpoints and expressions displayed make a session
+//! (see [Debugger::session]), the commands creating them, which the
+//! interpreter saves when it exits and runs again the next time the ROM
+//! is debugged:
+//!
+//! ```text
+//! break game.asm:12
+//! break Fx18
+//! watch V3=0x05
+//! display [I]
+//! ```
+
+pub mod dap;
+pub mod expression;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use crate::assembler::debug_info::DebugInfo;
+use crate::assembler::{SymbolTable, PROGRAM_START};
+use expression::Expression;
+use crate::interpreter::trace::format_line;
+use crate::interpreter::watchpoint::{Register, Watchpoint, WatchpointHit};
+use crate::interpreter::{parse_address, parse_number, Error, VM, RAM_SIZE};
+use crate::isa::{Instruction, OpcodeMask};
+
+/// How many of the last instructions executed can be undone
+pub const HISTORY_LENGTH: usize = 10_000;
+/// How many bytes `x` prints per line, and by default
+const HEXDUMP_ROW_SIZE: usize = 16;
+
+const HELP: &str = "\
+step, s      execute one instruction, entering subroutines
+next, n      execute one instruction, running subroutines until they return
+finish, f    run until the current subroutine returns
+continue, c  resume the program
+reverse-step, rs
+             undo the last instruction
+reverse-continue, rc
+             undo instructions until the last one that hit a watchpoint,
+             or back to a breakpoint
+pause, p     pause it
+backtrace, bt
+             list the subroutines being run and where they were called
+break, b [<file:line> | <label> | <address> | <opcode pattern>]
+             stop at a location or before instructions like Fx18 or
+             0xF018/0xF0FF, or list the breakpoints
+tbreak <location>
+             stop at a location once
+delete [<n>] delete breakpoint <n>, or all of them
+display [<expression>]
+             print an expression like V0+V1 or [I+1] at every stop, or
+             print them all
+undisplay [<n>]
+             stop printing expression <n>, or any
+watch [<register>[=<value>]]
+             pause when an instruction changes a register, or changes it
+             to <value>, or list the watchpoints
+unwatch [<register>]
+             delete the watchpoints on a register, or all of them
+regs         print the registers
+x/<n> <address>
+             print <n> bytes of RAM (default 16)
+set <register> <value>
+             change V0..VF, I, DT or ST
+poke <address> <value>
+             change a byte of RAM
+help         list the commands
+Numbers are decimal, or hexadecimal with 0x. An empty line repeats the last
+command.";
+
+/// What the runner should do to carry out a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Nothing, the command printed what it had to
+    None,
+    /// Execute one instruction, printing it
+    Step,
+    /// Run until [Debugger::should_stop] says so, or the program is paused
+    /// otherwise
+    Run,
+    Pause,
+    /// Undo the last instruction, printing where the program is
+    StepBack,
+    /// Undo instructions until the last one that hit a watchpoint
+    RunBackwards,
+    /// Show the machine, which the command changed
+    Changed,
+}
+
+/// Where a breakpoint stops the program
+#[derive(Debug, Clone)]
+enum Target {
+    Address(u16),
+    /// Before the instructions of a class, with the pattern it was given
+    /// as
+    Opcode(OpcodeMask, String),
+}
+
+#[derive(Debug, Clone)]
+struct Breakpoint {
+    target: Target,
+    /// As given, to save it in the session
+    location: String,
+    /// Deleted once hit, see `tbreak`
+    temporary: bool,
+}
+
+/// What the debugger remembers between commands
+#[derive(Debug, Default)]
+pub struct Debugger {
+    last_command: String,
+    /// How deep the stack is once the subroutine run by `next` or `finish`
+    /// returned
+    stop_depth: Option<usize>,
+    /// The labels of the program by address
+    labels: BTreeMap<u16, String>,
+    debug_info: Option<DebugInfo>,
+    /// The lines of the source files of the debug info, by path
+    sources: HashMap<String, Vec<String>>,
+    /// The breakpoints, numbered from 1, `None` once deleted
+    breakpoints: Vec<Option<Breakpoint>>,
+    /// The expressions printed at every stop, as typed, numbered from 1,
+    /// `None` once deleted
+    displays: Vec<Option<(Expression, String)>>,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger::default()
+    }
+
+    /// Show addresses along with the labels of a symbol table
+    pub fn set_symbols(&mut self, symbol_table: &SymbolTable) {
+        self.labels = symbol_table
+            .iter()
+            .map(|(label, &address)| (address + PROGRAM_START, label.clone()))
+            .collect();
+    }
+
+    /// Show addresses along with the labels and source lines of debug
+    /// info, reading the source files it names
+    pub fn set_debug_info(&mut self, debug_info: DebugInfo) {
+        self.set_symbols(&debug_info.symbols);
+        for line in &debug_info.lines {
+            if !self.sources.contains_key(&line.path) && let Ok(source) = fs::read_to_string(&line.path) {
+                self.sources.insert(line.path.clone(), source.lines().map(str::to_string).collect());
+            }
+        }
+        self.debug_info = Some(debug_info);
+    }
+
+    /// Carry out a command, telling the runner what to do. Errors are
+    /// messages for the user
+    pub fn execute(&mut self, line: &str, vm: &mut VM, paused: bool) -> Result<Action, String> {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => line.to_string(),
+        };
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&command, args)) = words.split_first() else {
+            return Ok(Action::None);
+        };
+        self.last_command = line.clone();
+
+        // `x/16` is `x` with a count
+        let (command, count) = match command.split_once('/') {
+            Some((command, count)) => (command, Some(count)),
+            None => (command, None),
+        };
+        match command {
+            "help" | "h" => {
+                println!("{}", HELP);
+                Ok(Action::None)
+            }
+            "backtrace" | "bt" => {
+                self.print_backtrace(vm);
+                Ok(Action::None)
+            }
+            "break" | "b" if args.is_empty() => {
+                self.print_breakpoints(vm);
+                Ok(Action::None)
+            }
+            "break" | "b" | "tbreak" => {
+                let n = self.add_breakpoint(&args.join(" "), command == "tbreak")?;
+                let kind = if command == "tbreak" { "Temporary breakpoint" } else { "Breakpoint" };
+                println!("{} {} {}", kind, n, self.describe_breakpoint(vm, n));
+                Ok(Action::None)
+            }
+            "delete" => {
+                match args {
+                    [] => self.breakpoints.clear(),
+                    [n] => {
+                        let breakpoint = n.parse::<usize>().ok()
+                            .and_then(|n| n.checked_sub(1))
+                            .and_then(|i| self.breakpoints.get_mut(i))
+                            .filter(|breakpoint| breakpoint.is_some())
+                            .ok_or_else(|| format!("no breakpoint {}", n))?;
+                        *breakpoint = None;
+                    }
+                    _ => return Err("usage: delete [<n>]".to_string()),
+                }
+                Ok(Action::None)
+            }
+            "display" if args.is_empty() => {
+                for line in self.displays(vm) {
+                    println!("{}", line);
+                }
+                Ok(Action::None)
+            }
+            "display" => {
+                let text = args.join(" ");
+                let expression: Expression = text.parse()?;
+                self.displays.push(Some((expression, text)));
+                println!("{}", self.format_display(vm, self.displays.len()));
+                Ok(Action::None)
+            }
+            "undisplay" => {
+                match args {
+                    [] => self.displays.clear(),
+                    [n] => {
+                        let display = n.parse::<usize>().ok()
+                            .and_then(|n| n.checked_sub(1))
+                            .and_then(|i| self.displays.get_mut(i))
+                            .filter(|display| display.is_some())
+                            .ok_or_else(|| format!("no expression {}", n))?;
+                        *display = None;
+                    }
+                    _ => return Err("usage: undisplay [<n>]".to_string()),
+                }
+                Ok(Action::None)
+            }
+            "watch" if args.is_empty() => {
+                if vm.watchpoints().is_empty() {
+                    println!("No watchpoints");
+                }
+                for watchpoint in vm.watchpoints() {
+                    println!("{}", watchpoint);
+                }
+                Ok(Action::None)
+            }
+            "watch" => {
+                let watchpoint: Watchpoint = args.join("").parse()?;
+                println!("Watchpoint on {}", watchpoint);
+                vm.add_watchpoint(watchpoint);
+                Ok(Action::None)
+            }
+            "unwatch" => {
+                match args {
+                    [] => vm.remove_watchpoints(None),
+                    [register] => vm.remove_watchpoints(Some(register.parse()?)),
+                    _ => return Err("usage: unwatch [<register>]".to_string()),
+                }
+                Ok(Action::None)
+            }
+            "regs" => {
+                print_registers(vm);
+                Ok(Action::None)
+            }
+            "x" => {
+                let count = match count {
+                    Some(count) => parse_number(count).ok_or_else(|| format!("invalid count \"{}\"", count))? as usize,
+                    None => HEXDUMP_ROW_SIZE,
+                };
+                let [address] = args else {
+                    return Err("usage: x/<n> <address>".to_string());
+                };
+                let address = parse_address(address).ok_or_else(|| format!("invalid address \"{}\"", address))?;
+                self.print_hexdump(vm, address, count);
+                Ok(Action::None)
+            }
+            "set" => {
+                let [register, value] = args else {
+                    return Err("usage: set <register> <value>".to_string());
+                };
+                let register: Register = register.parse()?;
+                let value = parse_number(value)
+                    .filter(|&value| value <= register.max_value())
+                    .ok_or_else(|| format!("invalid value \"{}\" for {}", value, register))?;
+                vm.set_register(register, value);
+                Ok(Action::Changed)
+            }
+            "poke" => {
+                let [address, value] = args else {
+                    return Err("usage: poke <address> <value>".to_string());
+                };
+                let address = parse_address(address).ok_or_else(|| format!("invalid address \"{}\"", address))?;
+                let value = parse_number(value)
+                    .and_then(|value| u8::try_from(value).ok())
+                    .ok_or_else(|| format!("invalid byte \"{}\"", value))?;
+                vm.poke(address, value);
+                Ok(Action::Changed)
+            }
+            "pause" | "p" if paused => Ok(Action::None),
+            "pause" | "p" => Ok(Action::Pause),
+            "step" | "s" | "next" | "n" | "finish" | "f" | "continue" | "c" | "reverse-step" | "rs"
+                | "reverse-continue" | "rc" if !paused => {
+                Err("the program is running, pause it first (pause or P)".to_string())
+            }
+            "step" | "s" => Ok(Action::Step),
+            "next" | "n" if is_call(vm) => {
+                self.stop_depth = Some(vm.stack().len());
+                Ok(Action::Run)
+            }
+            "next" | "n" => Ok(Action::Step),
+            "finish" | "f" => {
+                let depth = vm.stack().len().checked_sub(1).ok_or("not in a subroutine")?;
+                self.stop_depth = Some(depth);
+                Ok(Action::Run)
+            }
+            "continue" | "c" => {
+                self.stop_depth = None;
+                Ok(Action::Run)
+            }
+            "reverse-step" | "rs" | "reverse-continue" | "rc" if vm.history_length() == 0 => {
+                Err("no instruction to undo".to_string())
+            }
+            "reverse-step" | "rs" => Ok(Action::StepBack),
+            "reverse-continue" | "rc" => Ok(Action::RunBackwards),
+            _ => Err(format!("unknown command \"{}\", type help for the list", command)),
+        }
+    }
+
+    /// Get the index of the first breakpoint the next instruction is at, if
+    /// any
+    pub fn breakpoint_at(&self, vm: &VM) -> Option<usize> {
+        let opcode = opcode_at(vm, vm.pc());
+        self.breakpoints.iter().position(|breakpoint| match breakpoint.as_ref().map(|breakpoint| &breakpoint.target) {
+            Some(Target::Address(address)) => *address == vm.pc(),
+            Some(Target::Opcode(opcode_mask, _)) => opcode.is_some_and(|opcode| opcode_mask.matches(opcode)),
+            None => false,
+        })
+    }
+
+    /// Check, after an instruction executed, whether the command the
+    /// program runs for is done, or the program reached a breakpoint
+    pub fn should_stop(&mut self, vm: &VM) -> bool {
+        if let Some(i) = self.breakpoint_at(vm) {
+            if self.breakpoints[i].as_ref().is_some_and(|breakpoint| breakpoint.temporary) {
+                println!("Temporary breakpoint {}", i + 1);
+                self.breakpoints[i] = None;
+            } else {
+                println!("Breakpoint {}", i + 1);
+            }
+            self.stop_depth = None;
+            return true;
+        }
+        match self.stop_depth {
+            Some(depth) if vm.stack().len() <= depth => {
+                self.stop_depth = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The expressions of `display` with their values, a line each, to
+    /// print when the program stops
+    pub fn displays(&self, vm: &VM) -> Vec<String> {
+        (1..=self.displays.len())
+            .filter(|&n| self.displays[n - 1].is_some())
+            .map(|n| self.format_display(vm, n))
+            .collect()
+    }
+
+    /// The expression `n` and its value, in decimal and hexadecimal, like
+    /// `1: V0+V1 = 12 (0xC)`
+    fn format_display(&self, vm: &VM, n: usize) -> String {
+        let Some(Some((expression, text))) = self.displays.get(n - 1) else {
+            return String::new();
+        };
+        match expression.evaluate(vm) {
+            Ok(value) if value >= 0 => format!("{}: {} = {} (0x{:X})", n, text, value, value),
+            Ok(value) => format!("{}: {} = {}", n, text, value),
+            Err(message) => format!("{}: {} = <{}>", n, text, message),
+        }
+    }
+
+    /// Stop the program at a `file:line` of the source, a label, an address
+    /// or before instructions like `Fx18` (see [OpcodeMask]), and only the
+    /// first time if `temporary`. Returns the number of the breakpoint
+    pub fn add_breakpoint(&mut self, location: &str, temporary: bool) -> Result<usize, String> {
+        let target = match self.resolve(location) {
+            Ok(address) => Target::Address(address),
+            Err(message) => Target::Opcode(location.parse().map_err(|_| message)?, location.to_string()),
+        };
+        self.breakpoints.push(Some(Breakpoint { target, location: location.to_string(), temporary }));
+        Ok(self.breakpoints.len())
+    }
+
+    /// The commands creating the breakpoints (but the temporary ones), the
+    /// watchpoints of `vm` and the expressions displayed, one per line,
+    /// each once
+    pub fn session(&self, vm: &VM) -> String {
+        let breakpoints = self.breakpoints.iter().flatten()
+            .filter(|breakpoint| !breakpoint.temporary)
+            .map(|breakpoint| format!("break {}", breakpoint.location));
+        let watchpoints = vm.watchpoints().iter().map(|watchpoint| format!("watch {}", watchpoint));
+        let displays = self.displays.iter().flatten().map(|(_, text)| format!("display {}", text));
+        let mut commands: Vec<String> = Vec::new();
+        for command in breakpoints.chain(watchpoints).chain(displays) {
+            if !commands.contains(&command) {
+                commands.push(command);
+            }
+        }
+        commands.iter().map(|command| command.clone() + "\n").collect()
+    }
+
+    /// Carry out the commands of a session, one per line, skipping empty
+    /// lines and comments starting with `;`. Returns the errors, each with
+    /// its line number, as a command failing does not stop the others
+    pub fn restore(&mut self, vm: &mut VM, session: &str) -> Vec<String> {
+        let last_command = self.last_command.clone();
+        let mut errors = Vec::new();
+        for (index, line) in session.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            if let Err(e) = self.execute(line, vm, true) {
+                errors.push(format!("line {}: {}", index + 1, e));
+            }
+        }
+        self.last_command = last_command;
+        errors
+    }
+
+    /// Forget the command the program runs for, when it was paused
+    /// otherwise
+    pub fn cancel(&mut self) {
+        self.stop_depth = None;
+    }
+
+    /// The next instruction, with its label, see [location], followed by
+    /// its line of source on a line of its own
+    pub fn location(&self, vm: &VM) -> String {
+        match self.source_text(vm.pc()) {
+            Some(text) => format!("{}\n    {}", self.describe(vm, vm.pc()), text),
+            None => self.describe(vm, vm.pc()),
+        }
+    }
+
+    /// The line of source of the instruction at `address`, as
+    /// `game.asm:12  ADD V0, 1`, if the debug info has it
+    pub fn source_line(&self, address: u16) -> Option<String> {
+        let line = self.debug_info.as_ref()?.line_at(address)?;
+        Some(format!("{}:{}  {}", line.path, line.line, self.source_text(address).unwrap_or("")))
+    }
+
+    /// The text of the line of source of the instruction at `address`, if
+    /// the source file could be read
+    fn source_text(&self, address: u16) -> Option<&str> {
+        let line = self.debug_info.as_ref()?.line_at(address)?;
+        self.sources.get(&line.path)?.get(line.line.checked_sub(1)?).map(|text| text.trim())
+    }
+
+    /// Find the address of a `file:line` of the source, a label or an
+    /// address
+    fn resolve(&self, location: &str) -> Result<u16, String> {
+        if let Some((path, line)) = location.rsplit_once(':') && let Ok(line) = line.parse() {
+            let debug_info = self.debug_info.as_ref().ok_or("no debug info, load it with --debug-info")?;
+            return debug_info.line_from(path, line)
+                .map(|source_line| source_line.addresses.start)
+                .ok_or_else(|| format!("no code at or after {}", location));
+        }
+        match self.labels.iter().find(|(_, label)| label.as_str() == location) {
+            Some((&address, _)) => Ok(address),
+            None => parse_address(location).ok_or_else(|| format!("unknown label or invalid address \"{}\"", location)),
+        }
+    }
+
+    fn print_breakpoints(&self, vm: &VM) {
+        if self.breakpoints.iter().all(Option::is_none) {
+            println!("No breakpoints");
+        }
+        for (i, breakpoint) in self.breakpoints.iter().enumerate() {
+            if let Some(breakpoint) = breakpoint {
+                let temporary = if breakpoint.temporary { "  (temporary)" } else { "" };
+                println!("{}  {}{}", i + 1, self.describe_breakpoint(vm, i + 1), temporary);
+            }
+        }
+    }
+
+    /// Where breakpoint `n` stops, as `at <instruction>` or
+    /// `on <pattern> instructions`
+    fn describe_breakpoint(&self, vm: &VM, n: usize) -> String {
+        match self.breakpoints.get(n - 1).and_then(Option::as_ref).map(|breakpoint| &breakpoint.target) {
+            Some(Target::Address(address)) => format!("at {}", self.describe(vm, *address)),
+            Some(Target::Opcode(_, pattern)) => format!("on {} instructions", pattern),
+            None => String::new(),
+        }
+    }
+
+    /// Print the next instruction, then the `CALL` instructions of the
+    /// subroutines being run from the innermost
+    fn print_backtrace(&self, vm: &VM) {
+        println!("#0  {}", self.describe(vm, vm.pc()));
+        for (frame, &return_address) in vm.stack().iter().rev().enumerate() {
+            println!("#{}  {}", frame + 1, self.describe(vm, return_address.wrapping_sub(2)));
+        }
+    }
+
+    /// The address, opcode and disassembly of an instruction, followed by
+    /// the label the instruction follows and its source file and line
+    fn describe(&self, vm: &VM, address: u16) -> String {
+        let mut description = describe_instruction(vm, address);
+        if let Some(label) = self.label_of(address) {
+            description = format!("{:<28}<{}>", description, label);
+        }
+        if let Some(line) = self.debug_info.as_ref().and_then(|debug_info| debug_info.line_at(address)) {
+            description = format!("{}  {}:{}", description, line.path, line.line);
+        }
+        description
+    }
+
+    /// The region of the debug info an address is in, as `name` or
+    /// `name+offset`
+    pub fn region_of(&self, address: u16) -> Option<String> {
+        self.debug_info.as_ref()?.region_of(address)
+    }
+
+    /// Describe a watchpoint hit, with the region `I` points into if it
+    /// changed
+    pub fn describe_hit(&self, hit: &WatchpointHit) -> String {
+        match self.region_of(hit.new).filter(|_| hit.register == Register::I) {
+            Some(region) => format!("{}  <{}>", hit, region),
+            None => hit.to_string(),
+        }
+    }
+
+    /// Print `count` bytes of RAM from `address`, a row of
+    /// [HEXDUMP_ROW_SIZE] per line, followed by the regions of the row
+    fn print_hexdump(&self, vm: &VM, address: u16, count: usize) {
+        let start = address as usize;
+        let end = (start + count).min(RAM_SIZE);
+        for row_start in (start..end).step_by(HEXDUMP_ROW_SIZE) {
+            let row_end = (row_start + HEXDUMP_ROW_SIZE).min(end);
+            let bytes: Vec<String> = vm.ram()[row_start..row_end].iter().map(|byte| format!("{:02X}", byte)).collect();
+            // The region of the first byte, then those starting in the row
+            let mut regions: Vec<String> = self.region_of(row_start as u16).into_iter().collect();
+            if let Some(debug_info) = &self.debug_info {
+                regions.extend(debug_info.regions.iter()
+                    .filter(|region| (row_start + 1..row_end).contains(&(region.addresses.start as usize)))
+                    .map(|region| region.name.clone()));
+            }
+            if regions.is_empty() {
+                println!("{:04X}: {}", row_start, bytes.join(" "));
+            } else {
+                println!("{:04X}: {:<width$}  <{}>", row_start, bytes.join(" "), regions.join(", "), width = HEXDUMP_ROW_SIZE * 3 - 1);
+            }
+        }
+    }
+
+    /// The closest label at or before an address, as `label` or
+    /// `label+offset`
+    pub fn label_of(&self, address: u16) -> Option<String> {
+        let (&label_address, label) = self.labels.range(..=address).next_back()?;
+        Some(match address - label_address {
+            0 => label.clone(),
+            offset => format!("{}+{}", label, offset),
+        })
+    }
+}
+
+/// Print the V registers, I, the timers, PC and SP
+fn print_registers(vm: &VM) {
+    for row in 0..2 {
+        let registers: Vec<String> = (0..8)
+            .map(|x| Register::V(row * 8 + x))
+            .map(|register| format!("{}={:02X}", register, vm.register(register)))
+            .collect();
+        println!("{}", registers.join("  "));
+    }
+    println!(
+        "I=0x{:03X}  DT={:02X}  ST={:02X}  PC=0x{:03X}  SP={}",
+        vm.register(Register::I), vm.register(Register::DelayTimer), vm.register(Register::SoundTimer),
+        vm.pc(), vm.stack().len()
+    );
+}
+
+/// Check whether the next instruction is a `CALL`
+fn is_call(vm: &VM) -> bool {
+    opcode_at(vm, vm.pc()).and_then(Instruction::decode).is_some_and(|instruction| matches!(instruction, Instruction::Call(_)))
+}
+
+fn opcode_at(vm: &VM, address: u16) -> Option<u16> {
+    let address = address as usize;
+    vm.ram().get(address..address + 2).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// The address, opcode and disassembly of an instruction
+pub fn describe_instruction(vm: &VM, address: u16) -> String {
+    match opcode_at(vm, address) {
+        Some(opcode) => format_line(address, opcode, ""),
+        None => format!("0x{:04X}", address),
+    }
+}
+
+/// The address, opcode and disassembly of the next instruction
+pub fn location(vm: &VM) -> String {
+    describe_instruction(vm, vm.pc())
+}
+
+/// Execute one instruction, printing it as a line of
+/// [trace](crate::interpreter::trace)
+pub fn step_and_print(vm: &mut VM) -> Result<(), Error> {
+    let (pc, cycles, registers) = (vm.pc(), vm.cycles(), vm.registers());
+    let opcode = opcode_at(vm, pc);
+    vm.step()?;
+    match opcode {
+        _ if vm.cycles() == cycles => {
+            let reason = if vm.is_waiting_for_key() { "a key press" } else { "the next frame" };
+            println!("0x{:04X}  waiting for {}", pc, reason);
+        }
+        Some(opcode) => println!("{}", format_line(pc, opcode, &registers.changes(&vm.registers()))),
+        None => {}
+    }
+    Ok(())
+}
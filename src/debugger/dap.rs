@@ -0,0 +1,741 @@
+//! A Debug Adapter Protocol server, to debug programs from editors
+//!
+//! `chip8 dap` speaks the [Debug Adapter Protocol] on its standard input
+//! and output, which is how VS Code and other editors talk to debuggers.
+//! The program runs without a window, at the usual speed, and the editor
+//! shows its registers, stack, display and memory.
+//!
+//! The `launch` request takes the path of the ROM as `program`, and
+//! optionally a symbol file written by `assembler --symbols` as `symbols`,
+//! the [debug info](crate::assembler::debug_info) written by `assembler
+//! --debug-info` as `debugInfo`, `stopOnEntry` and the `seed` of the
+//! random number generator. Attaching to a running interpreter is not
+//! supported.
+//!
+//! Breakpoints are set on lines of the source, given the debug info, on
+//! addresses (instruction breakpoints, from the disassembly view) or on
+//! labels, addresses like `0x2F0` and classes of instructions like `Fx18`
+//! (function breakpoints, see [OpcodeMask]). Stepping goes over
+//! subroutines with `next`, into them with `stepIn` and out with `stepOut`,
+//! and back through the [history](crate::interpreter::history) with
+//! `stepBack` and `reverseContinue`. The `illegal` exception breakpoint
+//! stops on opcodes that are not CHIP-8 instructions (see
+//! [VM::set_break_on_illegal]). The debug console and watches evaluate
+//! registers (`V0`, `I`, `PC`...), bytes of memory (`[0x300]`) and
+//! [expressions](super::expression) of them, and the debug console presses
+//! keys with `press <key>` and `release <key>`.
+//!
+//! [Debug Adapter Protocol]: https://microsoft.github.io/debug-adapter-protocol/
+//! [VM::set_break_on_illegal]: crate::interpreter::VM::set_break_on_illegal
+//! [OpcodeMask]: crate::isa::OpcodeMask
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::Path;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::assembler::debug_info::read_debug_info;
+use crate::assembler::symbols::read_symbol_table;
+use crate::disassembler::decode_instruction;
+use crate::interpreter::watchpoint::Register;
+use crate::interpreter::{check_rom, parse_address, parse_number, VM, RAM_SIZE, TIMER_FREQUENCY, VM_FREQUENCY};
+use crate::isa::OpcodeMask;
+use crate::base64;
+use crate::json::{self, Value};
+use super::expression::Expression;
+use super::{is_call, opcode_at, Debugger, HISTORY_LENGTH};
+
+/// The only thread of a program
+const THREAD_ID: u64 = 1;
+/// The variables references of the scopes
+const REGISTERS_REFERENCE: u64 = 1;
+const STACK_REFERENCE: u64 = 2;
+const DISPLAY_REFERENCE: u64 = 3;
+
+/// What the program runs until, besides breakpoints
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Run {
+    Continue,
+    /// An instruction executed after this many
+    Cycle(u64),
+    /// The stack is this deep, an instruction having executed
+    Depth(usize, u64),
+}
+
+/// A debugging session of a program
+pub struct Session<W: Write> {
+    output: W,
+    seq: u64,
+    /// Events to send after the response to the current request
+    events: Vec<Value>,
+    vm: Option<VM>,
+    debugger: Debugger,
+    breakpoints: Vec<u16>,
+    /// The classes of instructions of the function breakpoints
+    opcode_breakpoints: Vec<OpcodeMask>,
+    /// Instruction, function and source breakpoints (by path), kept apart
+    /// since the editor sets them separately
+    instruction_breakpoints: Vec<u16>,
+    source_breakpoints: HashMap<String, Vec<u16>>,
+    break_on_illegal: bool,
+    stop_on_entry: bool,
+    run: Option<Run>,
+    /// Whether the program was just resumed at a breakpoint, which does
+    /// not stop it again
+    resumed: bool,
+    frames: u64,
+}
+
+/// Serve a session on `input` and `output` until the editor disconnects
+pub fn serve(input: impl Read + Send + 'static, output: impl Write) -> io::Result<()> {
+    let (sender, receiver) = mpsc::channel();
+    // Requests are read on their own thread to arrive while the program
+    // runs, like `pause`
+    thread::spawn(move || {
+        let mut input = BufReader::new(input);
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let frame = Duration::from_secs(1) / TIMER_FREQUENCY;
+    let mut session = Session::new(output);
+    let mut next_frame = Instant::now();
+    loop {
+        let message = if session.is_running() {
+            match receiver.try_recv() {
+                Ok(message) => Some(message),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        } else {
+            match receiver.recv() {
+                Ok(message) => Some(message),
+                Err(_) => return Ok(()),
+            }
+        };
+        match message {
+            Some(message) => {
+                if !session.handle(&message)? {
+                    return Ok(());
+                }
+            }
+            None => {
+                session.run_frame()?;
+                next_frame = (next_frame + frame).max(Instant::now());
+                thread::sleep(next_frame.saturating_duration_since(Instant::now()));
+            }
+        }
+    }
+}
+
+impl<W: Write> Session<W> {
+    pub fn new(output: W) -> Session<W> {
+        Session {
+            output,
+            seq: 0,
+            events: Vec::new(),
+            vm: None,
+            debugger: Debugger::new(),
+            breakpoints: Vec::new(),
+            opcode_breakpoints: Vec::new(),
+            instruction_breakpoints: Vec::new(),
+            source_breakpoints: HashMap::new(),
+            break_on_illegal: false,
+            stop_on_entry: false,
+            run: None,
+            resumed: false,
+            frames: 0,
+        }
+    }
+
+    /// Check whether the program is running, rather than stopped or not
+    /// launched
+    pub fn is_running(&self) -> bool {
+        self.run.is_some()
+    }
+
+    /// Answer a request, returning whether the session goes on
+    pub fn handle(&mut self, message: &Value) -> io::Result<bool> {
+        if message.get("type").and_then(Value::as_str) != Some("request") {
+            return Ok(true);
+        }
+        let command = message.get("command").and_then(Value::as_str).unwrap_or("");
+        let arguments = message.get("arguments").unwrap_or(&Value::Null);
+        let result = self.request(command, arguments);
+        let mut response = vec![
+            ("type".to_string(), Value::String("response".to_string())),
+            ("request_seq".to_string(), message.get("seq").cloned().unwrap_or(Value::Null)),
+            ("success".to_string(), Value::Bool(result.is_ok())),
+            ("command".to_string(), Value::String(command.to_string())),
+        ];
+        match result {
+            Ok(body) => response.push(("body".to_string(), body)),
+            Err(message) => response.push(("message".to_string(), Value::String(message))),
+        }
+        self.send(response)?;
+        for event in std::mem::take(&mut self.events) {
+            self.send_event(event)?;
+        }
+        Ok(!matches!(command, "disconnect" | "terminate"))
+    }
+
+    /// Carry out a request, returning the body of the response or an
+    /// error message
+    fn request(&mut self, command: &str, arguments: &Value) -> Result<Value, String> {
+        match command {
+            "initialize" => {
+                self.event("initialized", Value::Object(Vec::new()));
+                Ok(object(vec![
+                    ("supportsConfigurationDoneRequest", Value::Bool(true)),
+                    ("supportsFunctionBreakpoints", Value::Bool(true)),
+                    ("supportsInstructionBreakpoints", Value::Bool(true)),
+                    ("supportsDisassembleRequest", Value::Bool(true)),
+                    ("supportsReadMemoryRequest", Value::Bool(true)),
+                    ("supportsWriteMemoryRequest", Value::Bool(true)),
+                    ("supportsSetVariable", Value::Bool(true)),
+                    ("supportsStepBack", Value::Bool(true)),
+                    ("supportsTerminateRequest", Value::Bool(true)),
+                    ("exceptionBreakpointFilters", Value::Array(vec![object(vec![
+                        ("filter", Value::String("illegal".to_string())),
+                        ("label", Value::String("Illegal opcodes".to_string())),
+                        ("description", Value::String("Stop on opcodes that are not CHIP-8 instructions".to_string())),
+                    ])])),
+                ]))
+            }
+            "launch" => {
+                self.launch(arguments)?;
+                Ok(Value::Null)
+            }
+            "attach" => Err("attaching is not supported, launch the program instead".to_string()),
+            "setBreakpoints" => {
+                let path = arguments.get("source").and_then(|source| source.get("path")).and_then(Value::as_str).unwrap_or("");
+                let lines = arguments.get("breakpoints").and_then(Value::as_array).unwrap_or(&[]);
+                let debug_info = self.debugger.debug_info.as_ref();
+                let source_lines: Vec<_> = lines.iter()
+                    .map(|line| {
+                        let line = line.get("line").and_then(Value::as_f64).unwrap_or(0.0) as usize;
+                        debug_info.and_then(|debug_info| debug_info.line_from(path, line))
+                    })
+                    .collect();
+                let breakpoints = source_lines.iter()
+                    .map(|source_line| match source_line {
+                        Some(source_line) => {
+                            let mut breakpoint = breakpoint(Some(source_line.addresses.start), "");
+                            if let Value::Object(members) = &mut breakpoint {
+                                members.push(("line".to_string(), number(source_line.line as u64)));
+                            }
+                            breakpoint
+                        }
+                        None if debug_info.is_none() => breakpoint(None, "no debug info, launch with debugInfo"),
+                        None => breakpoint(None, "no code at or after this line"),
+                    })
+                    .collect();
+                let addresses = source_lines.iter().flatten().map(|source_line| source_line.addresses.start).collect();
+                self.source_breakpoints.insert(path.to_string(), addresses);
+                Ok(object(vec![("breakpoints", Value::Array(breakpoints))]))
+            }
+            "setFunctionBreakpoints" => {
+                let names = arguments.get("breakpoints").and_then(Value::as_array).unwrap_or(&[]);
+                let names: Vec<&str> = names.iter()
+                    .map(|breakpoint| breakpoint.get("name").and_then(Value::as_str).unwrap_or(""))
+                    .collect();
+                let addresses: Vec<Option<u16>> = names.iter().map(|name| self.resolve(name)).collect();
+                let opcode_masks: Vec<Option<OpcodeMask>> = names.iter().zip(&addresses)
+                    .map(|(name, address)| address.is_none().then(|| name.trim().parse().ok()).flatten())
+                    .collect();
+                self.breakpoints = addresses.iter().flatten().copied().collect();
+                self.opcode_breakpoints = opcode_masks.iter().flatten().copied().collect();
+                let breakpoints = addresses.into_iter().zip(opcode_masks)
+                    .map(|(address, opcode_mask)| match opcode_mask {
+                        Some(_) => object(vec![("verified", Value::Bool(true))]),
+                        None => breakpoint(address, "unknown label, invalid address or opcode pattern"),
+                    })
+                    .collect();
+                Ok(object(vec![("breakpoints", Value::Array(breakpoints))]))
+            }
+            "setInstructionBreakpoints" => {
+                let references = arguments.get("breakpoints").and_then(Value::as_array).unwrap_or(&[]);
+                let addresses: Vec<Option<u16>> = references.iter()
+                    .map(|breakpoint| {
+                        let reference = breakpoint.get("instructionReference").and_then(Value::as_str).unwrap_or("");
+                        let offset = breakpoint.get("offset").and_then(Value::as_f64).unwrap_or(0.0) as i64;
+                        memory_address(reference, offset)
+                    })
+                    .collect();
+                self.instruction_breakpoints = addresses.iter().flatten().copied().collect();
+                let breakpoints = addresses.into_iter().map(|address| breakpoint(address, "invalid address")).collect();
+                Ok(object(vec![("breakpoints", Value::Array(breakpoints))]))
+            }
+            "setExceptionBreakpoints" => {
+                let filters = arguments.get("filters").and_then(Value::as_array).unwrap_or(&[]);
+                self.break_on_illegal = filters.iter().any(|filter| filter.as_str() == Some("illegal"));
+                if let Some(vm) = &mut self.vm {
+                    vm.set_break_on_illegal(self.break_on_illegal);
+                }
+                Ok(Value::Null)
+            }
+            "configurationDone" => {
+                self.vm()?;
+                if self.stop_on_entry {
+                    self.stop("entry", None);
+                } else {
+                    self.resume(Run::Continue);
+                }
+                Ok(Value::Null)
+            }
+            "threads" => Ok(object(vec![("threads", Value::Array(vec![object(vec![
+                ("id", number(THREAD_ID)),
+                ("name", Value::String("CHIP-8".to_string())),
+            ])]))])),
+            "stackTrace" => {
+                let vm = self.vm()?;
+                let addresses: Vec<u16> = std::iter::once(vm.pc())
+                    .chain(vm.stack().iter().rev().map(|&address| address.wrapping_sub(2)))
+                    .collect();
+                let frames: Vec<Value> = addresses.iter().enumerate()
+                    .map(|(id, &address)| {
+                        let source_line = self.debugger.debug_info.as_ref().and_then(|debug_info| debug_info.line_at(address));
+                        let mut frame = vec![
+                            ("id", number(id as u64)),
+                            ("name", Value::String(self.frame_name(address))),
+                            ("line", number(source_line.map_or(0, |source_line| source_line.line as u64))),
+                            ("column", number(source_line.map_or(0, |_| 1))),
+                            ("instructionPointerReference", Value::String(format!("0x{:04X}", address))),
+                        ];
+                        if let Some(source_line) = source_line {
+                            let path = fs::canonicalize(&source_line.path).unwrap_or_else(|_| source_line.path.clone().into());
+                            let name = Path::new(&source_line.path).file_name().map_or(String::new(), |name| name.to_string_lossy().to_string());
+                            frame.push(("source", object(vec![
+                                ("name", Value::String(name)),
+                                ("path", Value::String(path.display().to_string())),
+                            ])));
+                        }
+                        object(frame)
+                    })
+                    .collect();
+                let total = frames.len() as u64;
+                Ok(object(vec![("stackFrames", Value::Array(frames)), ("totalFrames", number(total))]))
+            }
+            "scopes" => {
+                let scope = |name: &str, reference: u64| object(vec![
+                    ("name", Value::String(name.to_string())),
+                    ("variablesReference", number(reference)),
+                    ("expensive", Value::Bool(false)),
+                ]);
+                Ok(object(vec![("scopes", Value::Array(vec![
+                    scope("Registers", REGISTERS_REFERENCE),
+                    scope("Stack", STACK_REFERENCE),
+                    scope("Display", DISPLAY_REFERENCE),
+                ]))]))
+            }
+            "variables" => {
+                let reference = arguments.get("variablesReference").and_then(Value::as_f64).unwrap_or(0.0) as u64;
+                Ok(object(vec![("variables", Value::Array(self.variables(reference)?))]))
+            }
+            "setVariable" => {
+                let name = arguments.get("name").and_then(Value::as_str).unwrap_or("");
+                let value = arguments.get("value").and_then(Value::as_str).unwrap_or("");
+                let register: Register = name.parse()?;
+                let value = parse_number(value.trim())
+                    .filter(|&value| value <= register.max_value())
+                    .ok_or_else(|| format!("invalid value \"{}\" for {}", value, register))?;
+                self.vm_mut()?.set_register(register, value);
+                Ok(object(vec![("value", Value::String(register.format_value(value)))]))
+            }
+            "continue" => {
+                self.vm()?;
+                self.resume(Run::Continue);
+                Ok(object(vec![("allThreadsContinued", Value::Bool(true))]))
+            }
+            "next" => {
+                let vm = self.vm()?;
+                let run = if is_call(vm) { Run::Depth(vm.stack().len(), vm.cycles()) } else { Run::Cycle(vm.cycles()) };
+                self.resume(run);
+                Ok(Value::Null)
+            }
+            "stepIn" => {
+                let cycles = self.vm()?.cycles();
+                self.resume(Run::Cycle(cycles));
+                Ok(Value::Null)
+            }
+            "stepOut" => {
+                let vm = self.vm()?;
+                let depth = vm.stack().len().checked_sub(1).ok_or("not in a subroutine")?;
+                let run = Run::Depth(depth, vm.cycles());
+                self.resume(run);
+                Ok(Value::Null)
+            }
+            "stepBack" => {
+                if !self.vm_mut()?.step_back() {
+                    return Err("no instruction to undo".to_string());
+                }
+                self.stop("step", None);
+                Ok(Value::Null)
+            }
+            "reverseContinue" => {
+                let breakpoints = self.all_breakpoints();
+                let opcode_breakpoints = self.opcode_breakpoints.clone();
+                let vm = self.vm_mut()?;
+                let (undone, hit) = vm.run_backwards(|vm| at_breakpoint(vm, &breakpoints, &opcode_breakpoints));
+                if undone == 0 {
+                    return Err("no instruction to undo".to_string());
+                }
+                match hit {
+                    Some(hit) => self.stop("data breakpoint", Some(hit.to_string())),
+                    None => self.stop("breakpoint", None),
+                }
+                Ok(Value::Null)
+            }
+            "pause" => {
+                if self.is_running() {
+                    self.stop("pause", None);
+                }
+                Ok(Value::Null)
+            }
+            "readMemory" => {
+                let reference = arguments.get("memoryReference").and_then(Value::as_str).unwrap_or("");
+                let offset = arguments.get("offset").and_then(Value::as_f64).unwrap_or(0.0) as i64;
+                let count = arguments.get("count").and_then(Value::as_f64).unwrap_or(0.0) as usize;
+                let address = memory_address(reference, offset).ok_or("invalid memory reference")?;
+                let ram = self.vm()?.ram();
+                let end = (address as usize + count).min(RAM_SIZE);
+                let bytes = &ram[address as usize..end];
+                Ok(object(vec![
+                    ("address", Value::String(format!("0x{:04X}", address))),
+                    ("unreadableBytes", number((count - bytes.len()) as u64)),
+                    ("data", Value::String(base64::encode(bytes))),
+                ]))
+            }
+            "writeMemory" => {
+                let reference = arguments.get("memoryReference").and_then(Value::as_str).unwrap_or("");
+                let offset = arguments.get("offset").and_then(Value::as_f64).unwrap_or(0.0) as i64;
+                let address = memory_address(reference, offset).ok_or("invalid memory reference")?;
+                let data = arguments.get("data").and_then(Value::as_str).and_then(base64::decode).ok_or("invalid data")?;
+                let vm = self.vm_mut()?;
+                let written = data.len().min(RAM_SIZE - address as usize);
+                for (i, &byte) in data.iter().take(written).enumerate() {
+                    vm.poke(address + i as u16, byte);
+                }
+                Ok(object(vec![("bytesWritten", number(written as u64))]))
+            }
+            "disassemble" => {
+                let reference = arguments.get("memoryReference").and_then(Value::as_str).unwrap_or("");
+                let offset = arguments.get("offset").and_then(Value::as_f64).unwrap_or(0.0) as i64;
+                let first = arguments.get("instructionOffset").and_then(Value::as_f64).unwrap_or(0.0) as i64;
+                let count = arguments.get("instructionCount").and_then(Value::as_f64).unwrap_or(0.0) as i64;
+                let start = memory_address(reference, offset).ok_or("invalid memory reference")? as i64;
+                let vm = self.vm()?;
+                let instructions = (first..first + count)
+                    .map(|i| {
+                        let address = start + 2 * i;
+                        let opcode = u16::try_from(address).ok().and_then(|address| opcode_at(vm, address));
+                        let mut fields = vec![("address", Value::String(format!("0x{:04X}", address.max(0))))];
+                        match opcode {
+                            Some(opcode) => {
+                                fields.push(("instructionBytes", Value::String(format!("{:02X} {:02X}", opcode >> 8, opcode & 0xFF))));
+                                fields.push(("instruction", Value::String(decode_instruction(opcode))));
+                                if let Some(label) = self.debugger.labels.get(&(address as u16)) {
+                                    fields.push(("symbol", Value::String(label.clone())));
+                                }
+                            }
+                            None => fields.push(("instruction", Value::String("??".to_string()))),
+                        }
+                        object(fields)
+                    })
+                    .collect();
+                Ok(object(vec![("instructions", Value::Array(instructions))]))
+            }
+            "evaluate" => {
+                let expression = arguments.get("expression").and_then(Value::as_str).unwrap_or("");
+                let result = self.evaluate(expression)?;
+                Ok(object(vec![("result", Value::String(result)), ("variablesReference", number(0))]))
+            }
+            "disconnect" | "terminate" => {
+                self.run = None;
+                Ok(Value::Null)
+            }
+            _ => Err(format!("unsupported request {}", command)),
+        }
+    }
+
+    /// Load the program of a `launch` request
+    fn launch(&mut self, arguments: &Value) -> Result<(), String> {
+        let path = arguments.get("program").and_then(Value::as_str).ok_or("launch requires a program")?;
+        let rom = fs::read(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+        check_rom(&rom).map_err(|e| format!("{}: {}", path, e))?;
+        if let Some(path) = arguments.get("symbols").and_then(Value::as_str) {
+            let symbols = File::open(path)
+                .and_then(|file| read_symbol_table(BufReader::new(file)))
+                .map_err(|e| format!("failed to read {}: {}", path, e))?;
+            self.debugger.set_symbols(&symbols);
+        }
+        if let Some(path) = arguments.get("debugInfo").and_then(Value::as_str) {
+            let debug_info = File::open(path)
+                .and_then(|file| read_debug_info(BufReader::new(file)))
+                .map_err(|e| format!("failed to read {}: {}", path, e))?;
+            self.debugger.set_debug_info(debug_info);
+        }
+        let mut vm = VM::new();
+        vm.seed_rng(arguments.get("seed").and_then(Value::as_f64).unwrap_or(0.0) as u64);
+        vm.load_program(&rom);
+        vm.set_history_length(HISTORY_LENGTH);
+        vm.set_break_on_illegal(self.break_on_illegal);
+        self.vm = Some(vm);
+        self.stop_on_entry = arguments.get("stopOnEntry").and_then(Value::as_bool).unwrap_or(false);
+        Ok(())
+    }
+
+    /// Run a frame of the program, stopping it at breakpoints, when the
+    /// step it runs for is done or on errors
+    pub fn run_frame(&mut self) -> io::Result<()> {
+        let breakpoints = self.all_breakpoints();
+        let (Some(vm), Some(run)) = (&mut self.vm, self.run) else {
+            return Ok(());
+        };
+        let rate = VM_FREQUENCY as u64;
+        let start = self.frames * rate / TIMER_FREQUENCY as u64;
+        let end = (self.frames + 1) * rate / TIMER_FREQUENCY as u64;
+        let mut stop = None;
+        for _ in start..end {
+            if !self.resumed && at_breakpoint(vm, &breakpoints, &self.opcode_breakpoints) {
+                stop = Some(("breakpoint", None));
+                break;
+            }
+            let cycles = vm.cycles();
+            if let Err(e) = vm.step() {
+                stop = Some(("exception", Some(e.to_string())));
+                break;
+            }
+            if vm.cycles() == cycles {
+                continue;
+            }
+            self.resumed = false;
+            if let Some(illegal_opcode) = vm.take_illegal_opcode() {
+                stop = Some(("exception", Some(illegal_opcode.to_string())));
+                break;
+            }
+            let done = match run {
+                Run::Continue => false,
+                Run::Cycle(cycles) => vm.cycles() > cycles,
+                Run::Depth(depth, cycles) => vm.cycles() > cycles && vm.stack().len() <= depth,
+            };
+            if done {
+                stop = Some(("step", None));
+                break;
+            }
+        }
+        match stop {
+            Some((reason, description)) => self.stop(reason, description),
+            None => {
+                vm.tick_timers();
+                self.frames += 1;
+            }
+        }
+        for event in std::mem::take(&mut self.events) {
+            self.send_event(event)?;
+        }
+        Ok(())
+    }
+
+    /// The addresses of all the breakpoints, see [at_breakpoint]
+    fn all_breakpoints(&self) -> Vec<u16> {
+        let mut breakpoints = [self.breakpoints.as_slice(), &self.instruction_breakpoints].concat();
+        breakpoints.extend(self.source_breakpoints.values().flatten());
+        breakpoints
+    }
+
+    /// Resume the program until `run` is done
+    fn resume(&mut self, run: Run) {
+        self.run = Some(run);
+        self.resumed = true;
+    }
+
+    /// Stop the program, telling the editor why
+    fn stop(&mut self, reason: &str, description: Option<String>) {
+        self.run = None;
+        let mut body = vec![
+            ("reason", Value::String(reason.to_string())),
+            ("threadId", number(THREAD_ID)),
+            ("allThreadsStopped", Value::Bool(true)),
+        ];
+        if let Some(description) = description {
+            body.push(("text", Value::String(description)));
+        }
+        self.event("stopped", object(body));
+    }
+
+    /// Find the address of a label, or parse an address
+    fn resolve(&self, name: &str) -> Option<u16> {
+        let name = name.trim();
+        self.debugger.labels.iter()
+            .find(|(_, label)| label.as_str() == name)
+            .map(|(&address, _)| address)
+            .or_else(|| parse_address(name))
+    }
+
+    /// The name of a stack frame: the label its instruction follows, or
+    /// its disassembly
+    fn frame_name(&self, address: u16) -> String {
+        match (self.debugger.label_of(address), self.vm.as_ref().and_then(|vm| opcode_at(vm, address))) {
+            (Some(label), _) => label,
+            (None, Some(opcode)) => format!("0x{:04X} {}", address, decode_instruction(opcode)),
+            (None, None) => format!("0x{:04X}", address),
+        }
+    }
+
+    fn variables(&self, reference: u64) -> Result<Vec<Value>, String> {
+        let vm = self.vm()?;
+        let variable = |name: String, value: String| object(vec![
+            ("name", Value::String(name)),
+            ("value", Value::String(value)),
+            ("variablesReference", number(0)),
+        ]);
+        Ok(match reference {
+            REGISTERS_REFERENCE => {
+                let registers = (0..16).map(Register::V).chain([Register::I, Register::DelayTimer, Register::SoundTimer]);
+                let mut variables: Vec<Value> = registers
+                    .map(|register| {
+                        let value = vm.register(register);
+                        match self.debugger.region_of(value).filter(|_| register == Register::I) {
+                            Some(region) => variable(register.to_string(), format!("{} <{}>", register.format_value(value), region)),
+                            None => variable(register.to_string(), register.format_value(value)),
+                        }
+                    })
+                    .collect();
+                variables.push(variable("PC".to_string(), format!("0x{:03X}", vm.pc())));
+                variables.push(variable("SP".to_string(), vm.stack().len().to_string()));
+                variables
+            }
+            STACK_REFERENCE => vm.stack().iter().enumerate().rev()
+                .map(|(i, &address)| variable(format!("#{}", i), self.frame_name(address)))
+                .collect(),
+            DISPLAY_REFERENCE => vm.display().iter().enumerate()
+                .map(|(y, row)| variable(y.to_string(), row.iter().map(|&pixel| if pixel { '#' } else { '.' }).collect()))
+                .collect(),
+            _ => Vec::new(),
+        })
+    }
+
+    /// Evaluate a register, a byte of memory like `[0x300]`, or press or
+    /// release a key
+    fn evaluate(&mut self, expression: &str) -> Result<String, String> {
+        let words: Vec<&str> = expression.split_whitespace().collect();
+        match words.as_slice() {
+            [action @ ("press" | "release"), key] => {
+                let key = parse_number(key).filter(|&key| key < 16).ok_or_else(|| format!("invalid key \"{}\"", key))?;
+                self.vm_mut()?.set_key(key as u8, *action == "press");
+                Ok(format!("key {:X} {}", key, if *action == "press" { "pressed" } else { "released" }))
+            }
+            ["PC" | "pc"] => Ok(format!("0x{:03X}", self.vm()?.pc())),
+            [word] if word.starts_with('[') && word.ends_with(']') => {
+                let address = parse_number(&word[1..word.len() - 1])
+                    .filter(|&address| (address as usize) < RAM_SIZE)
+                    .ok_or_else(|| format!("invalid address \"{}\"", word))?;
+                Ok(format!("0x{:02X}", self.vm()?.ram()[address as usize]))
+            }
+            [word] if word.parse::<Register>().is_ok() => {
+                let register: Register = word.parse()?;
+                Ok(register.format_value(self.vm()?.register(register)))
+            }
+            _ => {
+                let value = expression.parse::<Expression>()?.evaluate(self.vm()?)?;
+                Ok(if value >= 0 { format!("{} (0x{:X})", value, value) } else { value.to_string() })
+            }
+        }
+    }
+
+    fn vm(&self) -> Result<&VM, String> {
+        self.vm.as_ref().ok_or_else(|| "no program launched".to_string())
+    }
+
+    fn vm_mut(&mut self) -> Result<&mut VM, String> {
+        self.vm.as_mut().ok_or_else(|| "no program launched".to_string())
+    }
+
+    /// Queue an event to send after the response
+    fn event(&mut self, name: &str, body: Value) {
+        self.events.push(object(vec![("event", Value::String(name.to_string())), ("body", body)]));
+    }
+
+    fn send_event(&mut self, event: Value) -> io::Result<()> {
+        let mut message = vec![("type".to_string(), Value::String("event".to_string()))];
+        message.extend(event.as_object().unwrap_or(&[]).iter().cloned());
+        self.send(message)
+    }
+
+    /// Send a message, numbering it
+    fn send(&mut self, mut message: Vec<(String, Value)>) -> io::Result<()> {
+        self.seq += 1;
+        message.insert(0, ("seq".to_string(), number(self.seq)));
+        let body = Value::Object(message).to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.output.flush()
+    }
+}
+
+/// Read a message, a `Content-Length` header then a JSON body, or `None`
+/// at the end of the input. Messages that are not JSON are skipped
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    loop {
+        let mut length = None;
+        loop {
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') && name.trim().eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+        let Some(length) = length else {
+            continue;
+        };
+        let mut body = vec![0; length];
+        input.read_exact(&mut body)?;
+        if let Ok(message) = json::parse(&String::from_utf8_lossy(&body)) {
+            return Ok(Some(message));
+        }
+    }
+}
+
+fn object(members: Vec<(&str, Value)>) -> Value {
+    Value::Object(members.into_iter().map(|(key, value)| (key.to_string(), value)).collect())
+}
+
+fn number(n: u64) -> Value {
+    Value::Number(n as f64)
+}
+
+/// A breakpoint at `address`, or one that could not be set
+fn breakpoint(address: Option<u16>, message: &str) -> Value {
+    match address {
+        Some(address) => object(vec![
+            ("verified", Value::Bool(true)),
+            ("instructionReference", Value::String(format!("0x{:04X}", address))),
+        ]),
+        None => object(vec![("verified", Value::Bool(false)), ("message", Value::String(message.to_string()))]),
+    }
+}
+
+/// Check whether the next instruction is at one of the `addresses` or
+/// of one of the classes of `opcode_masks`
+fn at_breakpoint(vm: &VM, addresses: &[u16], opcode_masks: &[OpcodeMask]) -> bool {
+    addresses.contains(&vm.pc()) || opcode_at(vm, vm.pc()).is_some_and(|opcode| {
+        opcode_masks.iter().any(|opcode_mask| opcode_mask.matches(opcode))
+    })
+}
+
+/// Parse a memory or instruction reference, an address, plus an offset
+fn memory_address(reference: &str, offset: i64) -> Option<u16> {
+    let address = parse_number(reference.trim())? as i64 + offset;
+    (0..RAM_SIZE as i64).contains(&address).then_some(address as u16)
+}
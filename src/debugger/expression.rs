@@ -0,0 +1,180 @@
+//! Expressions of registers and memory, evaluated on a paused machine
+//!
+//! An [Expression] is made of numbers (decimal, or hexadecimal with `0x`),
+//! registers (`V0`..`VF`, `I`, `DT`, `ST`, `PC` and `SP`, the depth of the
+//! stack), bytes of RAM in brackets (`[I+1]`), parentheses and the
+//! operators of Rust, from the lowest precedence: `|`, `^`, `&`, `<<` and
+//! `>>`, `+` and `-`, `*`, `/` and `%`, and unary `-`:
+//!
+//! ```text
+//! V0+V1
+//! [I] << 8 | [I+1]
+//! (PC - 0x200) / 2
+//! ```
+//!
+//! Values are signed 64-bit integers, so that registers add up without
+//! wrapping around.
+
+use std::str::FromStr;
+use crate::interpreter::watchpoint::Register;
+use crate::interpreter::{VM, RAM_SIZE};
+
+/// The binary operators by precedence, lowest first
+const PRECEDENCE: [&[&str]; 5] = [&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"]];
+const MULTIPLICATIVE: [&str; 3] = ["*", "/", "%"];
+
+/// A parsed expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expression {
+    Number(i64),
+    Register(Register),
+    Pc,
+    Sp,
+    /// The byte of RAM at an address
+    Memory(Box<Expression>),
+    Negate(Box<Expression>),
+    Binary(&'static str, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    /// Evaluate the expression on `vm`. Errors are messages for the user
+    pub fn evaluate(&self, vm: &VM) -> Result<i64, String> {
+        Ok(match self {
+            Expression::Number(n) => *n,
+            Expression::Register(register) => vm.register(*register) as i64,
+            Expression::Pc => vm.pc() as i64,
+            Expression::Sp => vm.stack().len() as i64,
+            Expression::Memory(address) => {
+                let address = address.evaluate(vm)?;
+                usize::try_from(address).ok()
+                    .filter(|&address| address < RAM_SIZE)
+                    .map(|address| vm.ram()[address] as i64)
+                    .ok_or_else(|| format!("address 0x{:X} past the end of RAM", address))?
+            }
+            Expression::Negate(operand) => operand.evaluate(vm)?.wrapping_neg(),
+            Expression::Binary(operator, left, right) => {
+                let (left, right) = (left.evaluate(vm)?, right.evaluate(vm)?);
+                match *operator {
+                    "|" => left | right,
+                    "^" => left ^ right,
+                    "&" => left & right,
+                    "<<" => left.wrapping_shl(right as u32),
+                    ">>" => left.wrapping_shr(right as u32),
+                    "+" => left.wrapping_add(right),
+                    "-" => left.wrapping_sub(right),
+                    "*" => left.wrapping_mul(right),
+                    "/" => left.checked_div(right).ok_or("division by zero")?,
+                    _ => left.checked_rem(right).ok_or("division by zero")?,
+                }
+            }
+        })
+    }
+}
+
+impl FromStr for Expression {
+    type Err = String;
+
+    /// Parse an expression as described in the module docs
+    fn from_str(s: &str) -> Result<Expression, String> {
+        let mut parser = Parser { tokens: tokenize(s)?, position: 0 };
+        let expression = parser.binary(0)?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(expression),
+            Some(token) => Err(format!("unexpected \"{}\" in \"{}\"", token, s)),
+        }
+    }
+}
+
+/// Split an expression into numbers, names, operators and brackets
+fn tokenize(s: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_alphanumeric() || c == '_' {
+            let mut token = String::new();
+            while let Some(&c) = chars.peek().filter(|c| c.is_ascii_alphanumeric() || **c == '_') {
+                token.push(c);
+                chars.next();
+            }
+            tokens.push(token);
+        } else if c == '<' || c == '>' {
+            chars.next();
+            if chars.next() != Some(c) {
+                return Err(format!("unknown operator \"{}\", expected {}{}", c, c, c));
+            }
+            tokens.push(format!("{}{}", c, c));
+        } else if "|^&+-*/%()[]".contains(c) {
+            tokens.push(c.to_string());
+            chars.next();
+        } else {
+            return Err(format!("unexpected \"{}\" in \"{}\"", c, s));
+        }
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser over tokens
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+impl Parser {
+    /// Parse operators of [PRECEDENCE] from `level` up
+    fn binary(&mut self, level: usize) -> Result<Expression, String> {
+        let operators: &[&'static str] = match PRECEDENCE.get(level) {
+            Some(operators) => operators,
+            None if level == PRECEDENCE.len() => &MULTIPLICATIVE,
+            None => return self.unary(),
+        };
+        let mut left = self.binary(level + 1)?;
+        while let Some(&operator) = operators.iter().find(|&&operator| self.peek() == Some(operator)) {
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        let token = self.tokens.get(self.position).cloned().ok_or("incomplete expression")?;
+        self.position += 1;
+        match token.as_str() {
+            "-" => Ok(Expression::Negate(Box::new(self.unary()?))),
+            "(" => {
+                let expression = self.binary(0)?;
+                self.expect(")")?;
+                Ok(expression)
+            }
+            "[" => {
+                let address = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Expression::Memory(Box::new(address)))
+            }
+            _ if token.eq_ignore_ascii_case("PC") => Ok(Expression::Pc),
+            _ if token.eq_ignore_ascii_case("SP") => Ok(Expression::Sp),
+            _ if token.starts_with(|c: char| c.is_ascii_digit()) => {
+                let number = match token.strip_prefix("0x") {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => token.parse(),
+                };
+                number.map(Expression::Number).map_err(|_| format!("invalid number \"{}\"", token))
+            }
+            _ => token.parse().map(Expression::Register),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(String::as_str)
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.peek() != Some(token) {
+            return Err(format!("expected \"{}\"", token));
+        }
+        self.position += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+//! Converts CHIP-8 bytecode back into assembly
+//!
+//! The bytecode is decoded with a linear sweep: every pair of bytes is
+//! treated as an instruction, starting at the first byte. Pairs that are not
+//! valid instructions are emitted as `.word` directives, so the output can
+//! always be assembled back into the same bytecode. Sprites and other data
+//! mixed with code are decoded as (usually nonsensical) instructions.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use crate::assembler::PROGRAM_START;
+use crate::isa::{opcode_pattern, Instruction, Platform};
+
+/// Disassemble bytecode into assembly source accepted by the assembler
+pub fn disassemble(bytecode: &[u8]) -> String {
+    let mut result = String::new();
+    for chunk in bytecode.chunks(2) {
+        match *chunk {
+            [high, low] => {
+                let opcode = u16::from_be_bytes([high, low]);
+                result.push_str(&decode_instruction(opcode));
+                result.push('\n');
+            }
+            [byte] => writeln!(result, ".byte 0x{:02X}", byte).unwrap(),
+            _ => unreachable!(),
+        }
+    }
+    result
+}
+
+/// Disassemble a single opcode
+pub fn decode_instruction(opcode: u16) -> String {
+    match Instruction::decode(opcode) {
+        Some(instruction) => instruction.to_string(),
+        None => format!(".word 0x{:04X}", opcode),
+    }
+}
+
+/// A summary of the opcodes found in a ROM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Analysis {
+    /// Size of the ROM in bytes
+    pub size: usize,
+    /// The earliest platform that defines every reachable opcode
+    pub platform: Platform,
+    /// Number of reachable opcodes that exist on no platform
+    pub unknown_opcodes: usize,
+    /// Number of reachable opcodes of every pattern (see [opcode_pattern]),
+    /// keyed by the platform defining the pattern and the pattern itself
+    pub opcode_counts: BTreeMap<(Platform, &'static str), usize>,
+}
+
+/// Analyze the opcodes of a ROM that can be reached by following execution
+/// from its first instruction. Unlike the linear sweep of [disassemble], this
+/// skips most of the data mixed with the code
+pub fn analyze(bytecode: &[u8]) -> Analysis {
+    let mut platform = Platform::Chip8;
+    let mut unknown_opcodes = 0;
+    let mut opcode_counts = BTreeMap::new();
+    for offset in reachable_code(bytecode) {
+        let opcode = u16::from_be_bytes([bytecode[offset], bytecode[offset + 1]]);
+        match Platform::of_opcode(opcode).zip(opcode_pattern(opcode)) {
+            Some((opcode_platform, pattern)) => {
+                platform = platform.max(opcode_platform);
+                *opcode_counts.entry((opcode_platform, pattern)).or_insert(0) += 1;
+            }
+            None => unknown_opcodes += 1,
+        }
+    }
+    Analysis {
+        size: bytecode.len(),
+        platform,
+        unknown_opcodes,
+        opcode_counts,
+    }
+}
+
+/// Find the offsets of all instructions reachable from the first one,
+/// following jumps, calls and both outcomes of every skip. Indirect jumps
+/// (`JP V0, addr`) cannot be followed
+pub fn reachable_code(bytecode: &[u8]) -> Vec<usize> {
+    let mut visited = vec![false; bytecode.len()];
+    let mut pending = vec![0];
+    while let Some(offset) = pending.pop() {
+        if offset + 1 >= bytecode.len() || visited[offset] {
+            continue;
+        }
+        visited[offset] = true;
+
+        let opcode = u16::from_be_bytes([bytecode[offset], bytecode[offset + 1]]);
+        let next = offset + 2;
+        let to_offset = |address: u16| (address as usize).checked_sub(PROGRAM_START as usize);
+        match Instruction::decode(opcode) {
+            Some(Instruction::Ret | Instruction::JpV0(_)) => {}
+            Some(Instruction::Jp(address)) => pending.extend(to_offset(address)),
+            Some(Instruction::Call(address)) => {
+                pending.extend(to_offset(address));
+                pending.push(next);
+            }
+            Some(
+                Instruction::SeByte(..) | Instruction::SneByte(..) | Instruction::SeReg(..)
+                | Instruction::SneReg(..) | Instruction::Skp(_) | Instruction::Sknp(_)
+            ) => pending.extend([next, next + 2]),
+            // 00FD is the SUPER-CHIP "exit interpreter" instruction
+            None if opcode == 0x00FD => {}
+            _ => pending.push(next),
+        }
+    }
+    (0..bytecode.len()).filter(|&offset| visited[offset]).collect()
+}
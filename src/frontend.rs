@@ -0,0 +1,35 @@
+//! Frontends connecting the headless [VM](crate::interpreter::VM) to the
+//! outside world
+//!
+//! - `scaler`: upscaling the display into images, shared by all of them
+//! - `crt`: scanlines, curvature and vignette effects
+//! - `palette`: named color schemes for the display
+//! - `font`: a bitmap font for text drawn over the display
+//! - `mirror`: a copy of the display and keypad in a file, for capture
+//!   software
+//! - `recorder`: recording the display and buzzer to a video file
+//! - `waveform`: the tones the buzzer can play
+//! - `runner`: running the VM on its own thread, for the window frontends
+//! - `control`: a server taking JSON requests over TCP or WebSocket, for
+//!   other programs to drive the runner
+//! - `sdl`: a desktop window with audio, enabled by the `sdl` feature
+//! - `browser`: a list of ROMs to choose one from in a window, with the
+//!   `sdl` feature
+//! - `wasm`: a C ABI to drive the VM from JavaScript in a browser, enabled
+//!   by the `wasm` feature
+
+#[cfg(feature = "sdl")]
+pub mod browser;
+pub mod control;
+pub mod crt;
+pub mod font;
+pub mod mirror;
+pub mod palette;
+pub mod recorder;
+pub mod runner;
+pub mod scaler;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+pub mod waveform;
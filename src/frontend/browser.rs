@@ -0,0 +1,188 @@
+//! A list of the ROMs in a directory to choose one from, shown when the
+//! interpreter is started without a ROM
+//!
+//! The ROMs are the `.ch8` files under the directory, listed by their title
+//! in the [Database] if they are known and by their file name otherwise.
+//! Up and Down (or Page Up, Page Down, Home and End) move the selection,
+//! typing a letter or digit selects the next ROM starting with it, Enter
+//! loads the selected ROM and Escape quits.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use sdl2::EventPump;
+use sdl2::event::Event;
+use sdl2::keyboard::Scancode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use crate::database::Database;
+use super::font::{GLYPH_HEIGHT, GLYPH_WIDTH};
+use super::palette::DEFAULT_PALETTE;
+use super::sdl::draw_text;
+
+/// How long to wait for events before drawing the list again, which
+/// catches resizes SDL reports late
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+/// How many glyph pixels the window is tall, at least, which sets the
+/// size of the text
+const ROWS_PER_WINDOW: i32 = 120;
+
+/// The ROMs of a directory and the one selected
+pub struct RomBrowser {
+    /// The name shown and the path of every ROM, sorted by name
+    roms: Vec<(String, PathBuf)>,
+    selected: usize,
+    /// The first ROM shown, when they do not all fit
+    first_shown: usize,
+    colors: ([u8; 3], [u8; 3]),
+}
+
+impl RomBrowser {
+    /// List the ROMs under a directory, named after their entry in the
+    /// database if they have one
+    pub fn new(dir: &Path, database: &Database) -> io::Result<RomBrowser> {
+        let mut paths = Vec::new();
+        find_roms(dir, &mut paths)?;
+        let mut roms: Vec<(String, PathBuf)> = paths
+            .into_iter()
+            .map(|path| {
+                let entry = fs::read(&path).ok().and_then(|rom| database.get(&rom).and_then(|entry| entry.name()));
+                let name = entry.unwrap_or_else(|| {
+                    path.strip_prefix(dir).unwrap_or(&path).with_extension("").display().to_string()
+                });
+                (name, path)
+            })
+            .collect();
+        roms.sort_by_key(|(name, _)| name.to_lowercase());
+        Ok(RomBrowser { roms, selected: 0, first_shown: 0, colors: DEFAULT_PALETTE })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    /// Set the RGB colors of the background and the text
+    pub fn set_colors(&mut self, background: [u8; 3], foreground: [u8; 3]) {
+        self.colors = (background, foreground);
+    }
+
+    /// Show the list until a ROM is chosen and return its path, or `None`
+    /// if the window is closed
+    pub fn choose(&mut self, canvas: &mut Canvas<Window>, event_pump: &mut EventPump) -> Option<PathBuf> {
+        loop {
+            self.draw(canvas);
+            let Some(event) = event_pump.wait_event_timeout(REDRAW_INTERVAL.as_millis() as u32) else {
+                continue;
+            };
+            let page = self.rows_shown(canvas).max(1);
+            let last = self.roms.len().saturating_sub(1);
+            match event {
+                Event::Quit { .. } | Event::KeyDown { scancode: Some(Scancode::Escape), .. } => return None,
+                Event::KeyDown { scancode: Some(Scancode::Return | Scancode::KpEnter), .. } => {
+                    return self.roms.get(self.selected).map(|(_, path)| path.clone());
+                }
+                Event::KeyDown { scancode: Some(scancode), .. } => match scancode {
+                    Scancode::Up => self.selected = self.selected.saturating_sub(1),
+                    Scancode::Down => self.selected = (self.selected + 1).min(last),
+                    Scancode::PageUp => self.selected = self.selected.saturating_sub(page),
+                    Scancode::PageDown => self.selected = (self.selected + page).min(last),
+                    Scancode::Home => self.selected = 0,
+                    Scancode::End => self.selected = last,
+                    _ => {}
+                },
+                Event::TextInput { text, .. } => self.select_next_starting_with(&text),
+                _ => {}
+            }
+        }
+    }
+
+    /// Select the next ROM, after the selected one and wrapping around,
+    /// whose name starts with some text
+    fn select_next_starting_with(&mut self, text: &str) {
+        let text = text.to_lowercase();
+        let n = self.roms.len();
+        if let Some(offset) = (1..=n).find(|offset| {
+            self.roms[(self.selected + offset) % n].0.to_lowercase().starts_with(&text)
+        }) {
+            self.selected = (self.selected + offset) % n;
+        }
+    }
+
+    /// The size of the glyph pixels and the height of a line of text, for
+    /// the size of the window
+    fn metrics(canvas: &Canvas<Window>) -> (i32, i32) {
+        let (_, height) = canvas.output_size().unwrap_or((0, 0));
+        let pixel_size = (height as i32 / ROWS_PER_WINDOW).max(1);
+        (pixel_size, (GLYPH_HEIGHT as i32 + 3) * pixel_size)
+    }
+
+    /// The number of ROMs that fit in the window, below the header
+    fn rows_shown(&self, canvas: &Canvas<Window>) -> usize {
+        let (_, height) = canvas.output_size().unwrap_or((0, 0));
+        let (_, line_height) = RomBrowser::metrics(canvas);
+        ((height as i32 / line_height) - 3).max(1) as usize
+    }
+
+    fn draw(&mut self, canvas: &mut Canvas<Window>) {
+        let (background, foreground) = self.colors;
+        let (width, _) = canvas.output_size().unwrap_or((0, 0));
+        let (pixel_size, line_height) = RomBrowser::metrics(canvas);
+        let margin = line_height;
+        let max_chars = ((width as i32 - 2 * margin) / ((GLYPH_WIDTH as i32 + 1) * pixel_size)).max(1) as usize;
+
+        canvas.set_draw_color(Color::RGB(background[0], background[1], background[2]));
+        canvas.clear();
+        let header = if self.roms.is_empty() {
+            "No ROMs found".to_string()
+        } else {
+            format!("{} ROMs - Enter plays, Esc quits", self.roms.len())
+        };
+        draw_text(canvas, &truncate(&header, max_chars), margin, margin / 2, pixel_size, foreground);
+
+        let rows = self.rows_shown(canvas);
+        if self.selected < self.first_shown {
+            self.first_shown = self.selected;
+        } else if self.selected >= self.first_shown + rows {
+            self.first_shown = self.selected + 1 - rows;
+        }
+        for (row, (name, _)) in self.roms.iter().enumerate().skip(self.first_shown).take(rows) {
+            let y = margin * 2 + (row - self.first_shown) as i32 * line_height;
+            let name = truncate(name, max_chars);
+            let color = if row == self.selected {
+                // The selected ROM is highlighted with inverted colors
+                canvas.set_draw_color(Color::RGB(foreground[0], foreground[1], foreground[2]));
+                let highlight = Rect::new(margin / 2, y - pixel_size, width.saturating_sub(margin as u32), line_height as u32);
+                canvas.fill_rect(highlight).unwrap();
+                background
+            } else {
+                foreground
+            };
+            draw_text(canvas, &name, margin, y, pixel_size, color);
+        }
+        canvas.present();
+    }
+}
+
+/// Shorten a line of text to `max_chars` characters, ending it with `..`
+/// if it is longer
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    text.chars().take(max_chars.saturating_sub(2)).chain("..".chars()).collect()
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_roms(&path, roms)?;
+        } else if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("ch8")) {
+            roms.push(path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,482 @@
+//! A control server, for other programs to drive the running machine
+//!
+//! The server listens on a TCP port (see [serve]) for test harnesses,
+//! scripts and debugger interfaces. Every request is a JSON object on a
+//! line, answered by a JSON object on a line. A client opening the
+//! connection with a WebSocket handshake, like a browser, sends every
+//! request as a text message and gets the answer as a text message.
+//!
+//! Requests name a `command`, and an `id`, if given, is copied into the
+//! answer:
+//! - `{"command": "pause"}` and `{"command": "resume"}`
+//! - `{"command": "step", "count": 10}`: execute instructions while paused,
+//!   1 by default and up to [MAX_STEP_COUNT], stopping at breakpoints and
+//!   watchpoints like the debugger, answering with the number of `steps`
+//!   executed, the new `pc` and `cycles`
+//! - `{"command": "state"}`: the `state`, in the JSON of
+//!   [State::to_json](crate::interpreter::state::State::to_json), the
+//!   [counters](crate::interpreter::counters) and whether `paused`
+//! - `{"command": "read", "address": 768, "length": 16}`: the `bytes` of
+//!   RAM from the address
+//! - `{"command": "write", "address": 768, "bytes": [1, 2]}`: change bytes
+//!   of RAM
+//! - `{"command": "screenshot", "scale": 4}`: the display as a PNG image,
+//!   in base64 as `png`, white on black and upscaled 4 times by default
+//! - `{"command": "key", "key": 5, "pressed": true}`: press or release a
+//!   key, like the keyboard
+//! - `{"command": "counters"}`: the `counters` alone, and whether `paused`
+//! - `{"command": "subscribe", "interval": 1000}`: push metrics every
+//!   `interval` milliseconds (1000 by default, see
+//!   [SUBSCRIPTION_INTERVALS]) until `unsubscribe`, for dashboards not to
+//!   poll the whole state. Another `subscribe` changes the interval
+//!
+//! Metrics are pushed as objects with `"event": "metrics"`, the
+//! `instructions_per_second` and the average `frame_time` in milliseconds
+//! over the interval (`null` if no frame ended, while paused), and the
+//! `counters`. The buzzer is synthesized in the audio callback, without a
+//! queue of samples to run dry, so there are no audio underruns to count.
+//!
+//! Answers have `"ok": true` and the values asked for, or `"ok": false`
+//! and an `error`:
+//!
+//! ```text
+//! {"id": 1, "command": "read", "address": 512, "length": 2}
+//! {"id": 1, "ok": true, "bytes": [0, 224]}
+//! ```
+//!
+//! Lines and messages are limited to [MAX_MESSAGE_SIZE] bytes, and the
+//! connection is closed after a longer one, and after a WebSocket frame
+//! the client did not mask, as RFC 6455 requires. Anyone who can connect
+//! controls the machine, so the server should only listen on the loopback
+//! interface.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::base64;
+use crate::hash::sha1;
+use crate::interpreter::RAM_SIZE;
+use crate::json::{self, Value};
+use crate::logging::error;
+use super::runner::Command;
+
+/// The longest request line or WebSocket message, in bytes
+pub const MAX_MESSAGE_SIZE: usize = 1 << 20;
+/// The most instructions a `step` request executes, so that the runner
+/// does not stop answering for long
+pub const MAX_STEP_COUNT: u64 = 100_000;
+/// The intervals between metrics a subscription can ask for, in
+/// milliseconds
+pub const SUBSCRIPTION_INTERVALS: RangeInclusive<u64> = 100..=60_000;
+const DEFAULT_SUBSCRIPTION_INTERVAL: u64 = 1000;
+/// The largest `scale` of screenshots
+pub const MAX_SCREENSHOT_SCALE: usize = 16;
+/// The GUID the key of a WebSocket handshake is hashed with
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// A request to the runner, see the module docs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Pause,
+    Resume,
+    Step(u64),
+    State,
+    Read { address: u16, length: u16 },
+    Write { address: u16, bytes: Vec<u8> },
+    Screenshot { scale: usize },
+    Key { key: u8, pressed: bool },
+    Counters,
+}
+
+/// The members of a successful answer, or the error
+pub type Reply = Result<Vec<(String, Value)>, String>;
+
+impl Request {
+    /// Parse a request object, see the module docs
+    pub fn parse(request: &Value) -> Result<Request, String> {
+        let number = |key: &str, default: Option<u64>, max: u64| match request.get(key) {
+            Some(value) => value.as_f64()
+                .filter(|n| n.fract() == 0.0 && *n >= 0.0 && *n <= max as f64)
+                .map(|n| n as u64)
+                .ok_or_else(|| format!("{} must be a whole number up to {}", key, max)),
+            None => default.ok_or_else(|| format!("{} is missing", key)),
+        };
+        let command = request.get("command").and_then(Value::as_str).ok_or("command is missing")?;
+        match command {
+            "pause" => Ok(Request::Pause),
+            "resume" => Ok(Request::Resume),
+            "step" => Ok(Request::Step(number("count", Some(1), MAX_STEP_COUNT)?)),
+            "state" => Ok(Request::State),
+            "read" => {
+                let address = number("address", None, RAM_SIZE as u64 - 1)?;
+                let length = number("length", Some(1), RAM_SIZE as u64 - address)?;
+                Ok(Request::Read { address: address as u16, length: length as u16 })
+            }
+            "write" => {
+                let bytes = request.get("bytes").and_then(Value::as_array).ok_or("bytes is missing")?;
+                let bytes = bytes.iter()
+                    .map(|byte| byte.as_f64().filter(|n| n.fract() == 0.0 && (0.0..=255.0).contains(n)).map(|n| n as u8))
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or("bytes must be numbers from 0 to 255")?;
+                let address = number("address", None, RAM_SIZE as u64 - 1)?;
+                if address as usize + bytes.len() > RAM_SIZE {
+                    return Err(format!("the RAM ends at 0x{:03X}", RAM_SIZE));
+                }
+                Ok(Request::Write { address: address as u16, bytes })
+            }
+            "screenshot" => {
+                let scale = number("scale", Some(4), MAX_SCREENSHOT_SCALE as u64)? as usize;
+                if scale == 0 {
+                    return Err("scale must be at least 1".to_string());
+                }
+                Ok(Request::Screenshot { scale })
+            }
+            "key" => Ok(Request::Key {
+                key: number("key", None, 0xF)? as u8,
+                pressed: request.get("pressed").and_then(Value::as_bool).ok_or("pressed must be true or false")?,
+            }),
+            "counters" => Ok(Request::Counters),
+            _ => Err(format!("unknown command {}", command)),
+        }
+    }
+}
+
+/// Accept connections on `listener` and pass their requests to the runner
+/// receiving `commands`, until it goes away. Every connection is served on
+/// its own thread
+pub fn serve(listener: TcpListener, commands: Sender<Command>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                error(format!("failed to accept a control connection: {}", e));
+                continue;
+            }
+        };
+        let commands = commands.clone();
+        thread::spawn(move || {
+            // Clients may go away without closing WebSocket connections
+            if let Err(e) = serve_connection(stream, commands) && e.kind() != io::ErrorKind::UnexpectedEof {
+                error(format!("control connection closed: {}", e));
+            }
+        });
+    }
+}
+
+/// Answer the requests of a client, line by line or as WebSocket messages
+fn serve_connection(stream: TcpStream, commands: Sender<Command>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    read_line(&mut reader, &mut line)?;
+    let websocket = line.starts_with("GET ");
+    if websocket && !accept_websocket(&mut reader, &mut writer)? {
+        return Ok(());
+    }
+    let mut client = Client {
+        commands,
+        writer: Arc::new(Mutex::new(Writer { stream: writer, websocket })),
+        subscription: None,
+    };
+    if websocket {
+        return serve_websocket(reader, &mut client);
+    }
+
+    loop {
+        if line.is_empty() {
+            return Ok(());
+        }
+        if !line.trim().is_empty() && !client.handle(line.trim())? {
+            return Ok(());
+        }
+        line.clear();
+        read_line(&mut reader, &mut line)?;
+    }
+}
+
+/// Complete the WebSocket handshake whose request line was read, returning
+/// whether it succeeded
+fn accept_websocket(reader: &mut BufReader<TcpStream>, writer: &mut TcpStream) -> io::Result<bool> {
+    let mut key = None;
+    loop {
+        let mut header = String::new();
+        if read_line(reader, &mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') && name.trim().eq_ignore_ascii_case("Sec-WebSocket-Key") {
+            key = Some(value.trim().to_string());
+        }
+    }
+    let Some(key) = key else {
+        writer.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+        return Ok(false);
+    };
+    let accept = base64::encode(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()));
+    write!(
+        writer,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept
+    )?;
+    Ok(true)
+}
+
+/// Answer text messages until the client closes the connection, or close
+/// it after a protocol error, telling the client why
+fn serve_websocket(mut reader: BufReader<TcpStream>, client: &mut Client) -> io::Result<()> {
+    let result = answer_messages(&mut reader, client);
+    if let Err(e) = &result && e.kind() == io::ErrorKind::InvalidData {
+        // 1002 is the status of protocol errors
+        let mut close = 1002u16.to_be_bytes().to_vec();
+        close.extend(e.to_string().bytes().take(123));
+        client.writer().write_frame(0x8, &close)?;
+    }
+    result
+}
+
+fn answer_messages(reader: &mut BufReader<TcpStream>, client: &mut Client) -> io::Result<()> {
+    let mut message = Vec::new();
+    loop {
+        let (fin, opcode, payload) = read_frame(reader)?;
+        match opcode {
+            // A text message, or the continuation of one
+            0x0 | 0x1 => {
+                if message.len() + payload.len() > MAX_MESSAGE_SIZE {
+                    return Err(too_long());
+                }
+                message.extend_from_slice(&payload);
+                if !fin {
+                    continue;
+                }
+                let text = String::from_utf8_lossy(&message).into_owned();
+                message.clear();
+                if !client.handle(text.trim())? {
+                    return Ok(());
+                }
+            }
+            0x8 => return client.writer().write_frame(0x8, &payload),
+            0x9 => client.writer().write_frame(0xA, &payload)?,
+            _ => {}
+        }
+    }
+}
+
+/// Where the answers and events of a client go, shared with the thread of
+/// its subscription
+struct Writer {
+    stream: TcpStream,
+    websocket: bool,
+}
+
+impl Writer {
+    /// Send an object, on a line or as a text message
+    fn send(&mut self, value: &Value) -> io::Result<()> {
+        if self.websocket {
+            self.write_frame(0x1, value.to_string().as_bytes())
+        } else {
+            writeln!(self.stream, "{}", value)
+        }
+    }
+
+    /// Write an unmasked WebSocket frame, a whole message
+    fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            length @ 0..=125 => frame.push(length as u8),
+            length @ 126..=0xFFFF => {
+                frame.push(126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                frame.push(127);
+                frame.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+        self.stream.write_all(&frame)
+    }
+}
+
+/// Stops pushing metrics when dropped
+struct Subscription(Arc<AtomicBool>);
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Relaxed);
+    }
+}
+
+/// A connected client
+struct Client {
+    commands: Sender<Command>,
+    writer: Arc<Mutex<Writer>>,
+    subscription: Option<Subscription>,
+}
+
+impl Client {
+    fn writer(&self) -> MutexGuard<'_, Writer> {
+        self.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Carry a request out and send its answer, returning whether the
+    /// runner is still there
+    fn handle(&mut self, text: &str) -> io::Result<bool> {
+        let request = json::parse(text).map_err(|e| format!("invalid JSON: {}", e));
+        let id = request.as_ref().ok().and_then(|request| request.get("id")).cloned();
+        let reply = match request {
+            Ok(request) => match request.get("command").and_then(Value::as_str) {
+                Some("subscribe") => self.subscribe(&request),
+                Some("unsubscribe") => {
+                    self.subscription = None;
+                    Ok(Vec::new())
+                }
+                _ => match Request::parse(&request) {
+                    Ok(request) => match run(&self.commands, request) {
+                        Some(reply) => reply,
+                        None => return Ok(false),
+                    },
+                    Err(e) => Err(e),
+                },
+            },
+            Err(e) => Err(e),
+        };
+        let mut members: Vec<(String, Value)> = id.map(|id| ("id".to_string(), id)).into_iter().collect();
+        match reply {
+            Ok(values) => {
+                members.push(("ok".to_string(), Value::Bool(true)));
+                members.extend(values);
+            }
+            Err(e) => {
+                members.push(("ok".to_string(), Value::Bool(false)));
+                members.push(("error".to_string(), Value::String(e)));
+            }
+        }
+        self.writer().send(&Value::Object(members))?;
+        Ok(true)
+    }
+
+    /// Push metrics every `interval` milliseconds from now on, see the
+    /// module docs
+    fn subscribe(&mut self, request: &Value) -> Reply {
+        let interval = match request.get("interval") {
+            Some(value) => value.as_f64()
+                .filter(|n| n.fract() == 0.0 && SUBSCRIPTION_INTERVALS.contains(&(*n as u64)))
+                .ok_or_else(|| format!(
+                    "interval must be a whole number of milliseconds from {} to {}",
+                    SUBSCRIPTION_INTERVALS.start(), SUBSCRIPTION_INTERVALS.end()
+                ))? as u64,
+            None => DEFAULT_SUBSCRIPTION_INTERVAL,
+        };
+        self.subscription = None;
+        let active = Arc::new(AtomicBool::new(true));
+        let (commands, writer) = (self.commands.clone(), self.writer.clone());
+        let subscription = active.clone();
+        thread::spawn(move || push_metrics(&commands, &writer, &subscription, Duration::from_millis(interval)));
+        self.subscription = Some(Subscription(active));
+        Ok(vec![("interval".to_string(), Value::Number(interval as f64))])
+    }
+}
+
+/// Have the runner carry a request out, or `None` if it is gone
+fn run(commands: &Sender<Command>, request: Request) -> Option<Reply> {
+    let (reply_sender, reply) = mpsc::channel();
+    commands.send(Command::Control(request, reply_sender)).ok()?;
+    reply.recv().ok()
+}
+
+/// Send the metrics of the last `interval` to a client while `active`, see
+/// the module docs
+fn push_metrics(commands: &Sender<Command>, writer: &Mutex<Writer>, active: &AtomicBool, interval: Duration) {
+    let counters = || {
+        let members = run(commands, Request::Counters)?.ok()?;
+        members.into_iter().find(|(key, _)| key == "counters").map(|(_, counters)| counters)
+    };
+    let Some(mut previous) = counters() else {
+        return;
+    };
+    let mut since = Instant::now();
+    loop {
+        thread::sleep(interval);
+        if !active.load(Ordering::Relaxed) {
+            return;
+        }
+        let Some(current) = counters() else {
+            return;
+        };
+        let elapsed = since.elapsed().as_secs_f64();
+        // The counters start again when the program is reloaded
+        let count = |key: &str| {
+            let [current, previous] = [&current, &previous].map(|counters| counters.get(key).and_then(Value::as_f64).unwrap_or(0.0));
+            if current >= previous { current - previous } else { current }
+        };
+        let frames = count("frames");
+        let frame_time = if frames > 0.0 {
+            Value::Number((elapsed * 1000.0 / frames * 100.0).round() / 100.0)
+        } else {
+            Value::Null
+        };
+        let event = Value::Object(vec![
+            ("event".to_string(), Value::String("metrics".to_string())),
+            ("instructions_per_second".to_string(), Value::Number((count("instructions") / elapsed).round())),
+            ("frame_time".to_string(), frame_time),
+            ("counters".to_string(), current.clone()),
+        ]);
+        let sent = writer.lock().unwrap_or_else(PoisonError::into_inner).send(&event);
+        if sent.is_err() || !active.load(Ordering::Relaxed) {
+            return;
+        }
+        (previous, since) = (current, Instant::now());
+    }
+}
+
+/// Read a line like [BufRead::read_line], failing if it is longer than
+/// [MAX_MESSAGE_SIZE]
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> io::Result<usize> {
+    let length = reader.take(MAX_MESSAGE_SIZE as u64 + 1).read_line(line)?;
+    if length > MAX_MESSAGE_SIZE {
+        return Err(too_long());
+    }
+    Ok(length)
+}
+
+fn too_long() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("message longer than {} bytes", MAX_MESSAGE_SIZE))
+}
+
+/// Read a WebSocket frame from a client: whether it is the last of its
+/// message, its opcode and its unmasked payload. Clients must mask frames
+fn read_frame(reader: &mut impl Read) -> io::Result<(bool, u8, Vec<u8>)> {
+    let mut header = [0u8; 2];
+    reader.read_exact(&mut header)?;
+    let length = match header[1] & 0x7F {
+        126 => {
+            let mut length = [0u8; 2];
+            reader.read_exact(&mut length)?;
+            u16::from_be_bytes(length) as u64
+        }
+        127 => {
+            let mut length = [0u8; 8];
+            reader.read_exact(&mut length)?;
+            u64::from_be_bytes(length)
+        }
+        length => length as u64,
+    };
+    if length > MAX_MESSAGE_SIZE as u64 {
+        return Err(too_long());
+    }
+    if header[1] & 0x80 == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "unmasked frame from the client"));
+    }
+    let mut mask = [0u8; 4];
+    reader.read_exact(&mut mask)?;
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok((header[0] & 0x80 != 0, header[0] & 0x0F, payload))
+}
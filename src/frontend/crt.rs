@@ -0,0 +1,91 @@
+//! Imitating a CRT monitor over the scaled display
+//!
+//! The effects are applied to the images of the [Scaler](super::scaler::Scaler),
+//! so they are sharper at higher scales:
+//! - `scanlines`: every other row of the image is darkened
+//! - `curvature`: the image bulges like the glass of a tube, leaving black
+//!   corners
+//! - `vignette`: the edges of the image are darkened
+
+use crate::png::Image;
+
+/// The names accepted by [CrtEffects::parse]
+pub const CRT_EFFECT_NAMES: [&str; 3] = ["scanlines", "curvature", "vignette"];
+
+/// How bright the darkened rows are
+const SCANLINE_BRIGHTNESS: f32 = 0.7;
+/// How much the image bulges at its corners
+const CURVATURE: f32 = 0.06;
+/// How much darker the corners are than the center
+const VIGNETTE: f32 = 0.45;
+
+/// Which CRT effects are applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CrtEffects {
+    pub scanlines: bool,
+    pub curvature: bool,
+    pub vignette: bool,
+}
+
+impl CrtEffects {
+    /// Every effect
+    pub const ALL: CrtEffects = CrtEffects { scanlines: true, curvature: true, vignette: true };
+
+    /// Parse a comma-separated list of [CRT_EFFECT_NAMES], or `all`
+    pub fn parse(list: &str) -> Result<CrtEffects, String> {
+        if list == "all" {
+            return Ok(CrtEffects::ALL);
+        }
+        let mut effects = CrtEffects::default();
+        for name in list.split(',').map(str::trim) {
+            match name {
+                "scanlines" => effects.scanlines = true,
+                "curvature" => effects.curvature = true,
+                "vignette" => effects.vignette = true,
+                _ => return Err(format!(
+                    "unknown CRT effect {} (expected all or some of {})", name, CRT_EFFECT_NAMES.join(", ")
+                )),
+            }
+        }
+        Ok(effects)
+    }
+
+    /// Check whether no effect is applied
+    pub fn is_empty(&self) -> bool {
+        *self == CrtEffects::default()
+    }
+
+    /// Apply the effects to an image
+    pub fn apply(&self, image: &Image) -> Image {
+        let (width, height) = (image.width as f32, image.height as f32);
+        let mut pixels = Vec::with_capacity(image.pixels.len());
+        for y in 0..image.height {
+            for x in 0..image.width {
+                // From -1 to 1, the center of the image being at 0
+                let mut u = (x as f32 + 0.5) / width * 2.0 - 1.0;
+                let mut v = (y as f32 + 0.5) / height * 2.0 - 1.0;
+                if self.curvature {
+                    (u, v) = (u * (1.0 + CURVATURE * v * v), v * (1.0 + CURVATURE * u * u));
+                    if u.abs() > 1.0 || v.abs() > 1.0 {
+                        pixels.push([0x00, 0x00, 0x00, 0xFF]);
+                        continue;
+                    }
+                }
+                let source_x = (((u + 1.0) / 2.0 * width) as usize).min(image.width - 1);
+                let source_y = (((v + 1.0) / 2.0 * height) as usize).min(image.height - 1);
+                let [r, g, b, a] = image.pixel(source_x, source_y);
+
+                let mut brightness = 1.0;
+                if self.scanlines && y % 2 == 1 {
+                    brightness *= SCANLINE_BRIGHTNESS;
+                }
+                if self.vignette {
+                    brightness *= 1.0 - VIGNETTE * (u * u + v * v) / 2.0;
+                }
+                let darken = |channel: u8| (channel as f32 * brightness).round() as u8;
+                pixels.push([darken(r), darken(g), darken(b), a]);
+            }
+        }
+        Image { width: image.width, height: image.height, pixels }
+    }
+}
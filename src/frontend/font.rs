@@ -0,0 +1,66 @@
+//! A small bitmap font for text drawn over the display
+//!
+//! Glyphs are 4x5 pixels like the digits of the CHIP-8 font, which are
+//! used for `0`..`9` and `A`..`F` so that labels look like the programs'
+//! own text. Each row is a byte whose high nibble holds the pixels.
+
+use crate::interpreter::FONT;
+
+/// The width of a glyph in pixels
+pub const GLYPH_WIDTH: usize = 4;
+/// The height of a glyph in pixels
+pub const GLYPH_HEIGHT: usize = 5;
+
+/// Get the rows of the glyph of a character. Letters are uppercased, and
+/// characters missing from the font are drawn as `?`
+pub fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    let c = c.to_ascii_uppercase();
+    if let Some(digit) = c.to_digit(16) {
+        let start = digit as usize * GLYPH_HEIGHT;
+        return FONT[start..start + GLYPH_HEIGHT].try_into().unwrap();
+    }
+    match c {
+        'G' => [0x70, 0x80, 0xB0, 0x90, 0x70],
+        'H' => [0x90, 0x90, 0xF0, 0x90, 0x90],
+        'I' => [0xE0, 0x40, 0x40, 0x40, 0xE0],
+        'J' => [0x10, 0x10, 0x10, 0x90, 0x60],
+        'K' => [0x90, 0xA0, 0xC0, 0xA0, 0x90],
+        'L' => [0x80, 0x80, 0x80, 0x80, 0xF0],
+        'M' => [0x90, 0xF0, 0xF0, 0x90, 0x90],
+        'N' => [0x90, 0xD0, 0xB0, 0x90, 0x90],
+        'O' => [0x60, 0x90, 0x90, 0x90, 0x60],
+        'P' => [0xE0, 0x90, 0xE0, 0x80, 0x80],
+        'Q' => [0x60, 0x90, 0x90, 0xB0, 0x70],
+        'R' => [0xE0, 0x90, 0xE0, 0xA0, 0x90],
+        'S' => [0x70, 0x80, 0x60, 0x10, 0xE0],
+        'T' => [0xE0, 0x40, 0x40, 0x40, 0x40],
+        'U' => [0x90, 0x90, 0x90, 0x90, 0x60],
+        'V' => [0xA0, 0xA0, 0xA0, 0xA0, 0x40],
+        'W' => [0x90, 0x90, 0xF0, 0xF0, 0x90],
+        'X' => [0x90, 0x90, 0x60, 0x90, 0x90],
+        'Y' => [0xA0, 0xA0, 0x40, 0x40, 0x40],
+        'Z' => [0xF0, 0x10, 0x60, 0x80, 0xF0],
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x40],
+        ',' => [0x00, 0x00, 0x00, 0x40, 0x80],
+        ':' => [0x00, 0x40, 0x00, 0x40, 0x00],
+        '-' => [0x00, 0x00, 0xE0, 0x00, 0x00],
+        '+' => [0x00, 0x40, 0xE0, 0x40, 0x00],
+        '=' => [0x00, 0xE0, 0x00, 0xE0, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0xF0],
+        '/' => [0x10, 0x10, 0x20, 0x40, 0x80],
+        '%' => [0x90, 0x10, 0x20, 0x40, 0x90],
+        '(' => [0x20, 0x40, 0x40, 0x40, 0x20],
+        ')' => [0x40, 0x20, 0x20, 0x20, 0x40],
+        '!' => [0x40, 0x40, 0x40, 0x00, 0x40],
+        '<' => [0x20, 0x40, 0x80, 0x40, 0x20],
+        '>' => [0x80, 0x40, 0x20, 0x40, 0x80],
+        _ => [0xE0, 0x10, 0x60, 0x00, 0x40],
+    }
+}
+
+/// Get the width of a line of text in pixels, with one pixel between
+/// glyphs
+pub fn text_width(text: &str) -> usize {
+    (text.chars().count() * (GLYPH_WIDTH + 1)).saturating_sub(1)
+}
@@ -0,0 +1,87 @@
+//! A copy of the display and keypad in a file, for capture software
+//!
+//! The file is rewritten in place whenever the display, the keys or the
+//! buzzer change, so external programs (OBS plugins, visualizers...) can
+//! map it into memory and read the emulator output directly. On Linux, a
+//! file in `/dev/shm` is shared memory. The layout is (multi-byte values
+//! big-endian):
+//! - 4 bytes: magic `C8FB`
+//! - 1 byte: format version, currently 1
+//! - 1 byte each: display width and height in pixels
+//! - 1 byte: reserved, 0
+//! - 4 bytes: sequence number, odd while the file is being updated
+//! - 8 bytes: number of instructions executed
+//! - 2 bytes: pressed keys, bit `k` set if key `k` is pressed
+//! - 1 byte: 1 if the buzzer is sounding, 0 otherwise
+//! - 1 byte: reserved, 0
+//! - width x height bytes: display, one byte per pixel (0 or 1), row by row
+//!
+//! Readers should read the sequence number before and after copying the
+//! rest, and retry if it was odd or changed.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use crate::interpreter::{DISPLAY_HEIGHT, DISPLAY_WIDTH, VM};
+
+const MAGIC: &[u8; 4] = b"C8FB";
+const VERSION: u8 = 1;
+const SEQUENCE_OFFSET: u64 = 8;
+const HEADER_SIZE: usize = 24;
+/// The size of the file
+pub const MIRROR_SIZE: usize = HEADER_SIZE + DISPLAY_WIDTH * DISPLAY_HEIGHT;
+
+/// The file the display and keypad are mirrored to
+pub struct Mirror {
+    path: String,
+    file: File,
+    sequence: u32,
+    last: Option<Vec<u8>>,
+}
+
+impl Mirror {
+    /// Create the file at `path`, or reuse it so readers that already
+    /// mapped it keep working
+    pub fn new(path: &str) -> io::Result<Mirror> {
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        file.set_len(MIRROR_SIZE as u64)?;
+        Ok(Mirror { path: path.to_string(), file, sequence: 0, last: None })
+    }
+
+    /// Get the path of the file
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Rewrite the file if the output of the virtual machine changed since
+    /// the last update
+    pub fn update(&mut self, vm: &VM) -> io::Result<()> {
+        let keys = vm.keys()
+            .iter()
+            .enumerate()
+            .fold(0u16, |bits, (key, &pressed)| bits | ((pressed as u16) << key));
+        let mut contents = Vec::with_capacity(MIRROR_SIZE - SEQUENCE_OFFSET as usize);
+        contents.extend_from_slice(&vm.cycles().to_be_bytes());
+        contents.extend_from_slice(&keys.to_be_bytes());
+        contents.extend_from_slice(&[vm.sound_active() as u8, 0]);
+        contents.extend(vm.display().as_flattened().iter().map(|&pixel| pixel as u8));
+
+        // The instruction count alone does not make a change worth reporting
+        if self.last.as_ref().is_some_and(|last| last[8..] == contents[8..]) {
+            return Ok(());
+        }
+
+        self.sequence = self.sequence.wrapping_add(1);
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(MAGIC)?;
+        self.file.write_all(&[VERSION, DISPLAY_WIDTH as u8, DISPLAY_HEIGHT as u8, 0])?;
+        self.file.write_all(&self.sequence.to_be_bytes())?;
+        self.file.write_all(&contents)?;
+
+        self.sequence = self.sequence.wrapping_add(1);
+        self.file.seek(SeekFrom::Start(SEQUENCE_OFFSET))?;
+        self.file.write_all(&self.sequence.to_be_bytes())?;
+
+        self.last = Some(contents);
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+//! Named pairs of background and foreground colors
+//!
+//! White on black is the default, the others imitate the screens CHIP-8
+//! programs are fondly remembered on:
+//! - `classic`: white on black
+//! - `gameboy`: the greens of the original Game Boy
+//! - `amber`: an amber monochrome monitor
+//! - `green`: a green phosphor monochrome monitor
+//! - `paper`: dark ink on off-white, easier on the eyes in daylight
+
+/// The names accepted by [palette_by_name]
+pub const PALETTE_NAMES: [&str; 5] = ["classic", "gameboy", "amber", "green", "paper"];
+
+/// The background and foreground colors of the `classic` palette
+pub const DEFAULT_PALETTE: ([u8; 3], [u8; 3]) = ([0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF]);
+
+/// Get the background and foreground RGB colors of one of [PALETTE_NAMES]
+pub fn palette_by_name(name: &str) -> Result<([u8; 3], [u8; 3]), String> {
+    match name {
+        "classic" => Ok(DEFAULT_PALETTE),
+        "gameboy" => Ok(([0x0F, 0x38, 0x0F], [0x9B, 0xBC, 0x0F])),
+        "amber" => Ok(([0x1A, 0x0F, 0x00], [0xFF, 0xB0, 0x00])),
+        "green" => Ok(([0x00, 0x14, 0x00], [0x33, 0xFF, 0x33])),
+        "paper" => Ok(([0xF2, 0xEE, 0xE3], [0x1E, 0x1E, 0x28])),
+        _ => Err(format!("unknown palette {} (expected one of {})", name, PALETTE_NAMES.join(", "))),
+    }
+}
@@ -0,0 +1,227 @@
+//! Recording the display and buzzer to a video file
+//!
+//! Every 60 Hz frame is recorded, with the 1/60 s of buzzer it plays, so
+//! videos run at the speed of the virtual machine whatever happens to the
+//! window. Depending on the extension of the output:
+//! - `.y4m`: the frames are written to a YUV4MPEG2 file (uncompressed
+//!   4:4:4 video, which most video tools read) and the sound to a WAV file
+//!   next to it, with the same name
+//! - anything else: the frames are piped to `ffmpeg`, which must be
+//!   installed, into a temporary lossless video, and the sound written to
+//!   a temporary WAV file. When the recording finishes, `ffmpeg` combines
+//!   them into the output in the format of its extension (`.mp4`, `.mkv`,
+//!   `.webm`...)
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use crate::interpreter::{AudioPattern, TIMER_FREQUENCY};
+use crate::png::Image;
+use crate::wav::Sound;
+use super::waveform::{Oscillator, Waveform};
+
+const SAMPLE_RATE: u32 = 44100;
+const SAMPLES_PER_FRAME: u32 = SAMPLE_RATE / TIMER_FREQUENCY;
+const TONE_FREQUENCY: f32 = 440.0;
+const AMPLITUDE: i16 = 0x0C00;
+const WAV_HEADER_SIZE: u32 = 44;
+
+/// Where the frames go
+enum Video {
+    Y4m(BufWriter<File>),
+    Ffmpeg {
+        process: Child,
+        stdin: BufWriter<ChildStdin>,
+        /// The lossless video `ffmpeg` writes into
+        temporary: PathBuf,
+    },
+}
+
+/// A video being recorded
+pub struct Recorder {
+    path: PathBuf,
+    width: usize,
+    height: usize,
+    video: Video,
+    audio_path: PathBuf,
+    audio: BufWriter<File>,
+    n_samples: u32,
+    oscillator: Oscillator,
+}
+
+impl Recorder {
+    /// Start recording frames of `width` x `height` pixels to `path`
+    pub fn new(path: &Path, width: usize, height: usize) -> io::Result<Recorder> {
+        let is_y4m = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("y4m"));
+        let (video, audio_path) = if is_y4m {
+            let mut file = BufWriter::new(File::create(path)?);
+            // 4:4:4 chroma, not interlaced, square pixels
+            writeln!(file, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, TIMER_FREQUENCY)?;
+            (Video::Y4m(file), path.with_extension("wav"))
+        } else {
+            let temporary = temporary_path(path, "mkv");
+            let mut process = Command::new("ffmpeg")
+                .args(["-y", "-loglevel", "error", "-f", "rawvideo", "-pix_fmt", "rgb24"])
+                .args(["-video_size", &format!("{}x{}", width, height)])
+                .args(["-framerate", &TIMER_FREQUENCY.to_string(), "-i", "-", "-c:v", "ffv1"])
+                .arg(&temporary)
+                .stdin(Stdio::piped())
+                .spawn()
+                .map_err(|e| io::Error::new(e.kind(), format!("failed to start ffmpeg: {}", e)))?;
+            let stdin = BufWriter::new(process.stdin.take().unwrap());
+            (Video::Ffmpeg { process, stdin, temporary }, temporary_path(path, "wav"))
+        };
+
+        let mut audio = BufWriter::new(File::create(&audio_path)?);
+        // The sizes are filled in when the recording finishes
+        audio.write_all(&wav_header(0))?;
+        Ok(Recorder {
+            path: path.to_path_buf(),
+            width,
+            height,
+            video,
+            audio_path,
+            audio,
+            n_samples: 0,
+            oscillator: Oscillator::new(Waveform::default(), TONE_FREQUENCY, SAMPLE_RATE as f32),
+        })
+    }
+
+    /// Get the path of the output
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Set the waveform the buzzer is recorded with, and the part of every
+    /// period square waves are high, from 0 to 1
+    pub fn set_waveform(&mut self, waveform: Waveform, duty_cycle: f32) {
+        self.oscillator = Oscillator::new(waveform, TONE_FREQUENCY, SAMPLE_RATE as f32);
+        self.oscillator.set_duty_cycle(duty_cycle);
+    }
+
+    /// Set the frequency of the tone the buzzer is recorded with, 440 Hz
+    /// by default
+    pub fn set_beep_frequency(&mut self, frequency: f32) {
+        self.oscillator.set_frequency(frequency);
+    }
+
+    /// Record the buzzer looping a sound instead of a waveform
+    pub fn set_beep_sound(&mut self, sound: &Sound) {
+        self.oscillator = Oscillator::from_sound(sound, SAMPLE_RATE as f32);
+    }
+
+    /// Record the buzzer playing an XO-CHIP audio pattern, from the next
+    /// frame on, or stop
+    pub fn set_audio_pattern(&mut self, pattern: Option<AudioPattern>) {
+        self.oscillator.set_pattern(pattern);
+    }
+
+    /// Record a frame, and whether the buzzer sounded during it
+    pub fn add_frame(&mut self, image: &Image, sound: bool) -> io::Result<()> {
+        if image.width != self.width || image.height != self.height {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the size of the frames changed"));
+        }
+        match &mut self.video {
+            Video::Y4m(file) => {
+                file.write_all(b"FRAME\n")?;
+                let planes: [fn([u8; 4]) -> u8; 3] = [luma, blue_difference, red_difference];
+                for plane in planes {
+                    let bytes: Vec<u8> = image.pixels.iter().map(|&pixel| plane(pixel)).collect();
+                    file.write_all(&bytes)?;
+                }
+            }
+            Video::Ffmpeg { stdin, .. } => {
+                let bytes: Vec<u8> = image.pixels.iter().flat_map(|&[r, g, b, _]| [r, g, b]).collect();
+                stdin.write_all(&bytes)?;
+            }
+        }
+
+        if !sound {
+            // Like the window, sounds start over with the buzzer
+            self.oscillator.restart();
+        }
+        for _ in 0..SAMPLES_PER_FRAME {
+            let sample = if sound { (self.oscillator.next_sample() * AMPLITUDE as f32) as i16 } else { 0 };
+            self.audio.write_all(&sample.to_le_bytes())?;
+        }
+        self.n_samples += SAMPLES_PER_FRAME;
+        Ok(())
+    }
+
+    /// Finish writing the output
+    pub fn finish(self) -> io::Result<()> {
+        let mut audio = self.audio.into_inner().map_err(io::IntoInnerError::into_error)?;
+        audio.seek(SeekFrom::Start(0))?;
+        audio.write_all(&wav_header(self.n_samples * 2))?;
+        drop(audio);
+
+        let (mut process, stdin, temporary) = match self.video {
+            Video::Y4m(mut file) => return file.flush(),
+            Video::Ffmpeg { process, stdin, temporary } => (process, stdin, temporary),
+        };
+        // Closing the pipe ends the video
+        drop(stdin.into_inner().map_err(io::IntoInnerError::into_error)?);
+        let encoded = process.wait()?;
+
+        let muxed = if encoded.success() {
+            let mut command = Command::new("ffmpeg");
+            command.args(["-y", "-loglevel", "error", "-i"]).arg(&temporary).arg("-i").arg(&self.audio_path);
+            // Most players only play H.264 in these containers in 4:2:0
+            let extension = self.path.extension().map(|extension| extension.to_ascii_lowercase());
+            if extension.is_some_and(|extension| extension == "mp4" || extension == "mov") {
+                command.args(["-pix_fmt", "yuv420p"]);
+            }
+            Some(command.arg(&self.path).status()?)
+        } else {
+            None
+        };
+        let _ = fs::remove_file(&temporary);
+        let _ = fs::remove_file(&self.audio_path);
+        match muxed {
+            Some(status) if status.success() => Ok(()),
+            _ => Err(io::Error::other("ffmpeg failed")),
+        }
+    }
+}
+
+/// A path next to `path`, for the intermediate files
+fn temporary_path(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".part.{}", extension));
+    path.with_file_name(name)
+}
+
+/// The header of a 16-bit mono WAV file holding `data_size` bytes of
+/// samples
+fn wav_header(data_size: u32) -> Vec<u8> {
+    let mut header = Vec::with_capacity(WAV_HEADER_SIZE as usize);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(WAV_HEADER_SIZE - 8 + data_size).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, one channel
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&1u16.to_le_bytes());
+    header.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    header.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes());
+    header.extend_from_slice(&16u16.to_le_bytes());
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_size.to_le_bytes());
+    header
+}
+
+// BT.601 conversion to studio-range YCbCr, which Y4M files use
+
+fn luma([r, g, b, _]: [u8; 4]) -> u8 {
+    (16.0 + 0.257 * r as f32 + 0.504 * g as f32 + 0.098 * b as f32).round() as u8
+}
+
+fn blue_difference([r, g, b, _]: [u8; 4]) -> u8 {
+    (128.0 - 0.148 * r as f32 - 0.291 * g as f32 + 0.439 * b as f32).round() as u8
+}
+
+fn red_difference([r, g, b, _]: [u8; 4]) -> u8 {
+    (128.0 + 0.439 * r as f32 - 0.368 * g as f32 - 0.071 * b as f32).round() as u8
+}
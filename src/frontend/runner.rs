@@ -0,0 +1,803 @@
+//! Running the virtual machine on its own thread
+//!
+//! A [Runner] executes instructions and ticks the timers at the right pace,
+//! and talks to the frontend through channels: the frontend sends it
+//! [Command]s (keys, pausing, speed...) and receives [Update]s with
+//! [Snapshot]s of the machine. This keeps the window responsive whatever
+//! the machine does, running as fast as possible or waiting for a key.
+//!
+//! The channel of updates is bounded, so a runner running faster than the
+//! frontend can follow waits for it instead of piling updates up.
+//!
+//! A runner can also run a twin machine (see [Runner::set_twin]), another
+//! build of the program or the same one with other quirks, in lockstep:
+//! both execute the same number of instructions, get the same keys and
+//! are stepped together, and the first frame after which their displays
+//! differ is reported.
+//!
+//! While recording the input (see [Runner::set_input_recording]), every
+//! frame executes exactly the instructions [VM::run_frames] would, and keys
+//! only change at the start of frames, so that the recording plays back
+//! identically. Speed changes are ignored meanwhile.
+//!
+//! Playing a recording back (see [Runner::set_input_playback]), frames are
+//! executed the same way and the recorded keys change at the start of the
+//! same frames, the keyboard and speed changes being ignored until the
+//! last frame recorded. With the seed of the recording, the program goes
+//! through exactly the same states as it did, whenever it is paused and
+//! stepped, and every time it is replaced, so that a bug caught once can
+//! be debugged again and again.
+//!
+//! When the machine hits a watchpoint (see [VM::add_watchpoint]) or
+//! executes an illegal opcode (see [VM::set_break_on_illegal]), the runner
+//! prints it, pauses and sends [Update::PauseChanged]. So it does when a
+//! command of the [Debugger] (see [Runner::set_debugger]) is done, and at
+//! the `BRK` debug traps (see [VM::set_debug_traps]), while the `PRINT`
+//! ones are printed without pausing.
+//!
+//! A debugger script (see [Runner::set_debug_script]) pauses the machine
+//! before the first instruction, and its commands are carried out one by
+//! one whenever the machine is paused, so that those after `continue` wait
+//! for a breakpoint or a watchpoint, like they would be typed.
+//!
+//! The [control](super::control) server passes its requests as
+//! [Command::Control], answered with a [Reply] once carried out, so that
+//! they happen between two instructions like the commands of the window.
+
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, SyncSender, TryRecvError};
+use std::time::{Duration, Instant};
+use crate::debugger::{location, step_and_print, Action, Debugger};
+use crate::interpreter::counters::Counters;
+use crate::interpreter::replay::Replay;
+use crate::interpreter::state::State;
+use crate::interpreter::watchpoint::WatchpointHit;
+use crate::interpreter::{AudioPattern, Display, Error, TIMER_FREQUENCY, VM};
+use crate::isa::DebugTrap;
+use crate::json::Value;
+use crate::logging::error;
+use crate::{base64, png};
+use super::control::{Reply, Request};
+use super::mirror::Mirror;
+use super::palette::DEFAULT_PALETTE;
+use super::scaler::{Nearest, Scaler};
+
+/// How many updates can wait for the frontend
+pub const UPDATE_QUEUE_SIZE: usize = 16;
+/// How far behind schedule the virtual machine may fall before skipping
+/// ahead instead of catching up
+const MAX_LAG: Duration = Duration::from_millis(100);
+/// The shortest time between two [Update::Changed]
+const CHANGE_INTERVAL: Duration = Duration::from_millis(4);
+
+/// What the frontend needs to show the virtual machine
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub state: State,
+    pub counters: Counters,
+    pub keys: [bool; 16],
+    pub sound_active: bool,
+    pub audio_pattern: Option<AudioPattern>,
+    /// The display of the twin machine, if any
+    pub twin_display: Option<Display>,
+    /// The first frame after which the displays of the machine and its
+    /// twin differed, counting from 1
+    pub divergence: Option<u64>,
+}
+
+impl Snapshot {
+    /// Take a snapshot of a virtual machine, without a twin
+    pub fn of(vm: &VM) -> Snapshot {
+        Snapshot {
+            state: vm.state(),
+            counters: vm.counters(),
+            keys: *vm.keys(),
+            sound_active: vm.sound_active(),
+            audio_pattern: vm.audio_pattern(),
+            twin_display: None,
+            divergence: None,
+        }
+    }
+}
+
+/// A request from the frontend to the runner
+pub enum Command {
+    /// Press or release a CHIP-8 key
+    SetKey(u8, bool),
+    /// Stop executing instructions and ticking the timers, or resume
+    SetPaused(bool),
+    /// Run as fast as possible, the timers ticking every frame worth of
+    /// instructions, or at the normal speed again
+    SetFastForward(bool),
+    /// Execute this many instructions per second
+    SetSpeed(u32),
+    /// Run one frame while paused, printing the executed instructions
+    StepFrame,
+    /// Execute one instruction while paused, printing it
+    StepInstruction,
+    /// Run another virtual machine, typically the program loaded again, and
+    /// another twin if there is one
+    Replace(Box<VM>, Option<Box<VM>>),
+    /// Carry out a command typed in the debugger
+    Debug(String),
+    /// Carry out a request of the control server, and send its answer
+    Control(Request, Sender<Reply>),
+    /// Stop running
+    Quit,
+}
+
+/// A message from the runner to the frontend
+pub enum Update {
+    /// A 1/60 s frame ended, and the timers ticked
+    Frame(Box<Snapshot>),
+    /// The display or the keys changed during a frame, or the virtual
+    /// machine was stepped or replaced
+    Changed(Box<Snapshot>),
+    /// The runner paused by itself, at a watchpoint, an illegal opcode or
+    /// once a debugger command was done, or resumed for a debugger command
+    PauseChanged(bool, Box<Snapshot>),
+    /// The program caused an error, and the runner stopped
+    Stopped(Error),
+}
+
+/// Runs a virtual machine, see the module docs
+pub struct Runner<'a> {
+    vm: &'a mut VM,
+    twin: Option<&'a mut VM>,
+    /// The frames ended since the machines started, and the first one
+    /// after which their displays differed
+    frames: u64,
+    divergence: Option<u64>,
+    /// The input recorded so far, the instructions executed during the
+    /// current frame, and the keys to change at the start of the next one
+    recording: Option<Replay>,
+    frame_steps: u64,
+    playback: Option<Replay>,
+    pending_keys: Vec<(u8, bool)>,
+    commands: Receiver<Command>,
+    updates: SyncSender<Update>,
+    mirror: Option<Mirror>,
+    debugger: Option<Debugger>,
+    /// The commands of the debugger script not carried out yet
+    script: VecDeque<String>,
+    instructions_per_second: u32,
+    paused: bool,
+    fast_forward: bool,
+    quit: bool,
+    /// When the current frame started, and when the next instruction is
+    /// due. Both are deadlines, to make up for the time lost sleeping too
+    /// long
+    frame_start: Instant,
+    next_cycle: Instant,
+    last_change: Instant,
+}
+
+impl<'a> Runner<'a> {
+    /// Create a runner executing `instructions_per_second` instructions per
+    /// second
+    pub fn new(
+        vm: &'a mut VM,
+        commands: Receiver<Command>,
+        updates: SyncSender<Update>,
+        instructions_per_second: u32
+    ) -> Runner<'a> {
+        Runner {
+            vm,
+            twin: None,
+            frames: 0,
+            divergence: None,
+            recording: None,
+            frame_steps: 0,
+            playback: None,
+            pending_keys: Vec::new(),
+            commands,
+            updates,
+            mirror: None,
+            debugger: None,
+            script: VecDeque::new(),
+            instructions_per_second,
+            paused: false,
+            fast_forward: false,
+            quit: false,
+            frame_start: Instant::now(),
+            next_cycle: Instant::now(),
+            last_change: Instant::now(),
+        }
+    }
+
+    /// Copy the display and keypad into a file every frame
+    pub fn set_mirror(&mut self, mirror: Mirror) {
+        self.mirror = Some(mirror);
+    }
+
+    /// Carry out the commands sent with [Command::Debug]
+    pub fn set_debugger(&mut self, debugger: Debugger) {
+        self.debugger = Some(debugger);
+    }
+
+    /// Carry out debugger commands before the ones sent, see the module
+    /// docs
+    pub fn set_debug_script(&mut self, commands: Vec<String>) {
+        self.script = commands.into();
+    }
+
+    /// Run a twin machine in lockstep, see the module docs
+    pub fn set_twin(&mut self, twin: &'a mut VM) {
+        self.twin = Some(twin);
+    }
+
+    /// Record the keys pressed into `replay`, see the module docs. Its
+    /// speed is the one of the runner
+    pub fn set_input_recording(&mut self, mut replay: Replay) {
+        replay.instructions_per_second = self.instructions_per_second;
+        self.recording = Some(replay);
+    }
+
+    /// Press the keys of `replay` at the frames they were recorded at, see
+    /// the module docs. The machines must be seeded with its seed
+    pub fn set_input_playback(&mut self, replay: Replay) {
+        self.playback = Some(replay);
+    }
+
+    /// Run until [Command::Quit] is received, the frontend goes away or
+    /// the program causes an error, which is sent as [Update::Stopped].
+    /// Returns the recorded input, if it was recorded, and the debugger
+    pub fn run(mut self) -> (Option<Replay>, Option<Debugger>) {
+        if let Err(e) = self.run_until_stopped() {
+            let _ = self.updates.send(Update::Stopped(e));
+        }
+        (self.recording, self.debugger)
+    }
+
+    fn run_until_stopped(&mut self) -> Result<(), Error> {
+        let frame_duration = Duration::from_secs_f64(1.0 / TIMER_FREQUENCY as f64);
+        self.apply_playback();
+        self.send_changed();
+        if !self.script.is_empty() {
+            self.pause();
+        }
+
+        while !self.quit {
+            if self.paused && let Some(line) = self.script.pop_front() {
+                println!("> {}", line);
+                self.debug(&line)?;
+                continue;
+            }
+            if self.paused {
+                // Nothing happens until the next command
+                match self.commands.recv() {
+                    Ok(command) => self.handle(command)?,
+                    Err(_) => return Ok(()),
+                }
+                continue;
+            }
+            loop {
+                match self.commands.try_recv() {
+                    Ok(command) => self.handle(command)?,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                }
+            }
+            if self.paused || self.quit {
+                continue;
+            }
+
+            if self.fast_forward {
+                // While recording, ending the frame executes its instructions
+                if self.recording.is_none() {
+                    for _ in 0..self.instructions_per_second / TIMER_FREQUENCY {
+                        self.step()?;
+                        if self.paused {
+                            break;
+                        }
+                    }
+                }
+                if !self.paused {
+                    self.end_frame()?;
+                }
+                continue;
+            }
+
+            if self.frame_start.elapsed() >= frame_duration {
+                self.end_frame()?;
+                if self.paused {
+                    continue;
+                }
+                self.frame_start += frame_duration;
+                if self.frame_start.elapsed() > MAX_LAG {
+                    self.frame_start = Instant::now();
+                }
+            }
+
+            // The instructions that came due since the last iteration run
+            // at once, so that sleeping too long (the timers of some
+            // systems are coarse) only makes the next batch bigger
+            let now = Instant::now();
+            if now.saturating_duration_since(self.next_cycle) > MAX_LAG {
+                self.next_cycle = now;
+            }
+            let cycle_duration = Duration::from_secs_f64(1.0 / self.instructions_per_second as f64);
+            let keys = *self.vm.keys();
+            while self.next_cycle <= now && self.remaining_steps() != Some(0) && !self.paused {
+                self.step()?;
+                self.next_cycle += cycle_duration;
+            }
+            if self.paused {
+                continue;
+            }
+            if (self.display_changed() || *self.vm.keys() != keys) && self.last_change.elapsed() >= CHANGE_INTERVAL {
+                self.last_change = Instant::now();
+                self.send_changed();
+            }
+
+            // Until the next instruction or the end of the frame, unless a
+            // command comes first
+            let frame_end = self.frame_start + frame_duration;
+            let wake_up = if self.remaining_steps() == Some(0) { frame_end } else { self.next_cycle.min(frame_end) };
+            match self.commands.recv_timeout(wake_up.saturating_duration_since(Instant::now())) {
+                Ok(command) => self.handle(command)?,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, command: Command) -> Result<(), Error> {
+        match command {
+            Command::SetKey(..) if self.playing_back().is_some() => {}
+            Command::SetKey(key, pressed) if self.recording.is_some() => self.pending_keys.push((key, pressed)),
+            Command::SetKey(key, pressed) => {
+                self.set_key(key, pressed);
+                self.send_changed();
+            }
+            Command::SetPaused(paused) => {
+                self.paused = paused;
+                if let Some(debugger) = &mut self.debugger {
+                    debugger.cancel();
+                }
+                // The timers and instructions pick up from where they
+                // were, without catching up the time spent paused
+                self.restart_clock();
+            }
+            Command::SetFastForward(fast_forward) => {
+                self.fast_forward = fast_forward;
+                self.restart_clock();
+            }
+            Command::SetSpeed(_) if self.recording.is_some() || self.playing_back().is_some() => {}
+            Command::SetSpeed(instructions_per_second) => self.instructions_per_second = instructions_per_second,
+            Command::StepFrame if self.paused => {
+                let steps = self.remaining_steps().unwrap_or((self.instructions_per_second / TIMER_FREQUENCY) as u64);
+                for _ in 0..steps {
+                    self.step_and_print()?;
+                    if self.check_hits() {
+                        return Ok(());
+                    }
+                }
+                self.tick_timers();
+                println!("-- frame --");
+                self.print_displays();
+                self.send_changed();
+            }
+            Command::StepInstruction if self.paused => self.step_instruction()?,
+            Command::StepFrame | Command::StepInstruction => {}
+            Command::Replace(vm, twin) => {
+                *self.vm = *vm;
+                if let (Some(current), Some(twin)) = (&mut self.twin, twin) {
+                    **current = *twin;
+                }
+                self.frames = 0;
+                self.divergence = None;
+                self.frame_steps = 0;
+                self.pending_keys.clear();
+                if let Some(recording) = &mut self.recording {
+                    recording.frames = 0;
+                    recording.events.clear();
+                }
+                self.apply_playback();
+                if let Some(debugger) = &mut self.debugger {
+                    debugger.cancel();
+                }
+                self.restart_clock();
+                self.send_changed();
+            }
+            Command::Debug(line) => self.debug(&line)?,
+            Command::Control(request, reply) => match self.control(request) {
+                Ok(answer) => {
+                    let _ = reply.send(answer);
+                }
+                // The client is answered before the runner stops
+                Err(e) => {
+                    let _ = reply.send(Err(format!("the program stopped: {}", e)));
+                    return Err(e);
+                }
+            },
+            Command::Quit => self.quit = true,
+        }
+        Ok(())
+    }
+
+    /// Execute one instruction while paused, printing it
+    fn step_instruction(&mut self) -> Result<(), Error> {
+        // The instructions of the frame ran while recording
+        if self.remaining_steps() == Some(0) {
+            self.tick_timers();
+            println!("-- frame --");
+        }
+        self.step_and_print()?;
+        if let Some(debugger) = &self.debugger && let Some(source_line) = debugger.source_line(self.vm.pc()) {
+            println!("{}", source_line);
+        }
+        if !self.check_hits() {
+            self.print_displays();
+            self.send_changed();
+        }
+        Ok(())
+    }
+
+    /// Carry out a request of the control server, returning its answer,
+    /// or the error the program caused while stepped
+    fn control(&mut self, request: Request) -> Result<Reply, Error> {
+        let number = |n: u64| Value::Number(n as f64);
+        let reply = match request {
+            Request::Pause => {
+                if !self.paused {
+                    self.pause();
+                }
+                Ok(Vec::new())
+            }
+            Request::Resume => {
+                if self.paused {
+                    self.paused = false;
+                    self.restart_clock();
+                    let snapshot = self.snapshot();
+                    self.send(Update::PauseChanged(false, snapshot));
+                }
+                Ok(Vec::new())
+            }
+            Request::Step(_) if !self.paused => Err("the machine must be paused to be stepped".to_string()),
+            Request::Step(count) => {
+                let mut steps = 0;
+                while steps < count {
+                    // The instructions of the frame ran while recording
+                    if self.remaining_steps() == Some(0) {
+                        self.tick_timers();
+                    }
+                    self.execute()?;
+                    steps += 1;
+                    if self.should_stop() {
+                        break;
+                    }
+                }
+                self.send_changed();
+                Ok(vec![
+                    ("steps".to_string(), number(steps)),
+                    ("pc".to_string(), number(self.vm.pc() as u64)),
+                    ("cycles".to_string(), number(self.vm.cycles())),
+                ])
+            }
+            Request::State => Ok(vec![
+                ("paused".to_string(), Value::Bool(self.paused)),
+                ("state".to_string(), self.vm.state().to_json()),
+                ("counters".to_string(), self.vm.counters().to_json()),
+            ]),
+            Request::Counters => Ok(vec![
+                ("paused".to_string(), Value::Bool(self.paused)),
+                ("counters".to_string(), self.vm.counters().to_json()),
+            ]),
+            Request::Read { address, length } => {
+                let ram = self.vm.ram();
+                match ram.get(address as usize..address as usize + length as usize) {
+                    Some(bytes) => Ok(vec![
+                        ("bytes".to_string(), Value::Array(bytes.iter().map(|&byte| number(byte as u64)).collect())),
+                    ]),
+                    None => Err(format!("the RAM ends at 0x{:03X}", ram.len())),
+                }
+            }
+            Request::Write { address, bytes } if address as usize + bytes.len() > self.vm.ram().len() => {
+                Err(format!("the RAM ends at 0x{:03X}", self.vm.ram().len()))
+            }
+            Request::Write { address, bytes } => {
+                for (offset, byte) in bytes.into_iter().enumerate() {
+                    self.vm.poke(address + offset as u16, byte);
+                }
+                self.send_changed();
+                Ok(Vec::new())
+            }
+            Request::Screenshot { scale } => {
+                let (background, foreground) = DEFAULT_PALETTE;
+                let image = Nearest { factor: scale }.render(self.vm.display(), background, foreground);
+                Ok(vec![("png".to_string(), Value::String(base64::encode(&png::encode(&image))))])
+            }
+            Request::Key { key, pressed } => {
+                self.handle(Command::SetKey(key, pressed))?;
+                Ok(Vec::new())
+            }
+        };
+        Ok(reply)
+    }
+
+    /// Carry out a debugger command
+    fn debug(&mut self, line: &str) -> Result<(), Error> {
+        let Some(debugger) = &mut self.debugger else {
+            return Ok(());
+        };
+        match debugger.execute(line, self.vm, self.paused) {
+            Ok(Action::None) => {}
+            Ok(Action::Changed) => self.send_changed(),
+            Ok(Action::Step) => self.step_instruction()?,
+            Ok(Action::Run) => {
+                self.paused = false;
+                self.restart_clock();
+                let snapshot = self.snapshot();
+                self.send(Update::PauseChanged(false, snapshot));
+            }
+            Ok(Action::Pause) => {
+                println!("{}", self.location());
+                self.print_displays();
+                self.pause();
+            }
+            Ok(Action::StepBack | Action::RunBackwards) if self.recording.is_some() || self.playing_back().is_some() => {
+                error("instructions cannot be undone while recording or playing back the input".to_string());
+            }
+            Ok(Action::StepBack) => {
+                self.vm.step_back();
+                if let Some(twin) = &mut self.twin {
+                    twin.step_back();
+                }
+                println!("{}", self.location());
+                self.print_displays();
+                self.send_changed();
+            }
+            Ok(Action::RunBackwards) => {
+                let debugger = self.debugger.as_ref();
+                let (undone, hit) = self.vm.run_backwards(|vm| debugger.is_some_and(|debugger| debugger.breakpoint_at(vm).is_some()));
+                if let Some(twin) = &mut self.twin {
+                    for _ in 0..undone {
+                        twin.step_back();
+                    }
+                }
+                let breakpoint = self.debugger.as_ref().and_then(|debugger| debugger.breakpoint_at(self.vm));
+                match (hit, breakpoint) {
+                    (Some(hit), _) => println!("Watchpoint: {}", self.describe_hit(&hit)),
+                    (None, Some(i)) => println!("Breakpoint {}", i + 1),
+                    (None, None) => println!("Reached the oldest instruction remembered"),
+                }
+                println!("{}", self.location());
+                self.print_displays();
+                self.send_changed();
+            }
+            Err(message) => error(message),
+        }
+        Ok(())
+    }
+
+    /// The next instruction, with its label if the debugger knows it
+    fn location(&self) -> String {
+        match &self.debugger {
+            Some(debugger) => debugger.location(self.vm),
+            None => location(self.vm),
+        }
+    }
+
+    /// A watchpoint hit, with the region `I` points into if the debugger
+    /// knows it
+    fn describe_hit(&self, hit: &WatchpointHit) -> String {
+        match &self.debugger {
+            Some(debugger) => debugger.describe_hit(hit),
+            None => hit.to_string(),
+        }
+    }
+
+    /// Print the expressions of the debugger `display` command, when the
+    /// program stopped
+    fn print_displays(&self) {
+        if let Some(debugger) = &self.debugger {
+            for line in debugger.displays(self.vm) {
+                println!("{}", line);
+            }
+        }
+    }
+
+    /// Press or release a key of the machine and its twin
+    fn set_key(&mut self, key: u8, pressed: bool) {
+        self.vm.set_key(key, pressed);
+        if let Some(twin) = &mut self.twin {
+            twin.set_key(key, pressed);
+        }
+    }
+
+    /// The input played back, until the last frame recorded
+    fn playing_back(&self) -> Option<&Replay> {
+        self.playback.as_ref().filter(|replay| self.frames < replay.frames as u64)
+    }
+
+    /// Press and release the keys played back at the start of the current
+    /// frame
+    fn apply_playback(&mut self) {
+        let Some(replay) = &self.playback else {
+            return;
+        };
+        let frame = self.frames as u32;
+        replay.apply(frame, self.vm);
+        if let Some(twin) = &mut self.twin {
+            replay.apply(frame, twin);
+        }
+        if self.frames == replay.frames as u64 {
+            println!("The replay ended after {} frames, the keyboard takes over", replay.frames);
+        }
+    }
+
+    /// How many instructions are left to execute during the current frame
+    /// while recording or playing back, as many as [VM::run_frames]
+    /// executes
+    fn remaining_steps(&self) -> Option<u64> {
+        let rate = self.recording.as_ref().or(self.playing_back())?.instructions_per_second as u64;
+        let timer_frequency = TIMER_FREQUENCY as u64;
+        let steps = (self.frames + 1) * rate / timer_frequency - self.frames * rate / timer_frequency;
+        Some(steps.saturating_sub(self.frame_steps))
+    }
+
+    /// Execute one instruction on the machine and its twin, pausing if
+    /// the machine hit a watchpoint, an illegal opcode or the debugger command is done
+    fn step(&mut self) -> Result<(), Error> {
+        self.execute()?;
+        self.should_stop();
+        Ok(())
+    }
+
+    /// Execute one instruction on the machine and its twin
+    fn execute(&mut self) -> Result<(), Error> {
+        self.frame_steps += 1;
+        self.vm.step()?;
+        if let Some(twin) = &mut self.twin {
+            twin.step()?;
+        }
+        Ok(())
+    }
+
+    /// Pause if the machine hit a watchpoint, an illegal opcode, a `BRK`
+    /// or a breakpoint, or the debugger command is done, and return
+    /// whether it did
+    fn should_stop(&mut self) -> bool {
+        if self.check_hits() {
+            return true;
+        }
+        if let Some(debugger) = &mut self.debugger && debugger.should_stop(self.vm) {
+            println!("{}", self.location());
+            self.print_displays();
+            self.pause();
+            return true;
+        }
+        false
+    }
+
+    /// Pause and tell the frontend if the machine hit a watchpoint, an
+    /// illegal opcode or a `BRK`, and return whether it did. `PRINT` debug
+    /// traps are only printed
+    fn check_hits(&mut self) -> bool {
+        let trap_hit = self.vm.take_trap_hit();
+        if let Some(hit) = &trap_hit && hit.trap != DebugTrap::Break {
+            println!("{}", hit);
+            return false;
+        }
+        if let Some(illegal_opcode) = self.vm.take_illegal_opcode() {
+            println!("Illegal opcode: {}", illegal_opcode);
+        } else if let Some(hit) = self.vm.take_watchpoint_hit() {
+            println!("Watchpoint: {}", self.describe_hit(&hit));
+        } else if let Some(hit) = trap_hit {
+            println!("Break: {}", hit);
+        } else {
+            return false;
+        }
+        self.print_displays();
+        if let Some(debugger) = &mut self.debugger {
+            debugger.cancel();
+        }
+        self.pause();
+        true
+    }
+
+    /// Pause by itself, and tell the frontend
+    fn pause(&mut self) {
+        self.paused = true;
+        let snapshot = self.snapshot();
+        self.send(Update::PauseChanged(true, snapshot));
+    }
+
+    /// Execute one instruction on the machine and its twin, printing them
+    fn step_and_print(&mut self) -> Result<(), Error> {
+        self.frame_steps += 1;
+        match &mut self.twin {
+            Some(twin) => {
+                print!("A ");
+                step_and_print(self.vm)?;
+                print!("B ");
+                step_and_print(twin)
+            }
+            None => step_and_print(self.vm),
+        }
+    }
+
+    /// Tick the timers of the machine and its twin, ending a frame, change
+    /// the keys recorded during it and check whether the displays diverged
+    fn tick_timers(&mut self) {
+        self.vm.tick_timers();
+        self.frames += 1;
+        self.frame_steps = 0;
+        if let Some(recording) = &mut self.recording {
+            recording.end_frame();
+            let keys = std::mem::take(&mut self.pending_keys);
+            for &(key, pressed) in &keys {
+                recording.record(key, pressed);
+            }
+            for (key, pressed) in keys {
+                self.set_key(key, pressed);
+            }
+        }
+        self.apply_playback();
+        let Some(twin) = &mut self.twin else {
+            return;
+        };
+        twin.tick_timers();
+        if self.divergence.is_none() && self.vm.display() != twin.display() {
+            self.divergence = Some(self.frames);
+            println!(
+                "The displays diverged at frame {} (instruction {} of A, {} of B)",
+                self.frames, self.vm.cycles(), twin.cycles()
+            );
+        }
+    }
+
+    /// Check whether the display of the machine or its twin changed since
+    /// the last check
+    fn display_changed(&mut self) -> bool {
+        let twin_changed = self.twin.as_mut().is_some_and(|twin| twin.display_changed());
+        self.vm.display_changed() | twin_changed
+    }
+
+    fn snapshot(&self) -> Box<Snapshot> {
+        let mut snapshot = Snapshot::of(self.vm);
+        snapshot.twin_display = self.twin.as_ref().map(|twin| *twin.display());
+        snapshot.divergence = self.divergence;
+        Box::new(snapshot)
+    }
+
+    fn send_changed(&mut self) {
+        let snapshot = self.snapshot();
+        self.send(Update::Changed(snapshot));
+    }
+
+    /// Tick the timers at the end of a 1/60 s frame, and tell the frontend
+    fn end_frame(&mut self) -> Result<(), Error> {
+        for _ in 0..self.remaining_steps().unwrap_or(0) {
+            self.step()?;
+            // The rest of the frame runs once resumed
+            if self.paused {
+                return Ok(());
+            }
+        }
+        self.tick_timers();
+        if let Some(mirror) = &mut self.mirror && let Err(e) = mirror.update(self.vm) {
+            error(format!("failed to update {}, no longer mirroring: {}", mirror.path(), e));
+            self.mirror = None;
+        }
+        // The display changes are part of the frame
+        self.display_changed();
+        let snapshot = self.snapshot();
+        self.send(Update::Frame(snapshot));
+        Ok(())
+    }
+
+    fn restart_clock(&mut self) {
+        self.frame_start = Instant::now();
+        self.next_cycle = Instant::now();
+    }
+
+    /// Send an update, stopping if the frontend went away
+    fn send(&mut self, update: Update) {
+        if self.updates.send(update).is_err() {
+            self.quit = true;
+        }
+    }
+}
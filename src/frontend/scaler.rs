@@ -0,0 +1,190 @@
+//! Upscaling the 1-bit display into RGBA images
+//!
+//! Everything that turns the display into pixels goes through a [Scaler],
+//! so captures look exactly like the window. The scalers first compute how
+//! much every output pixel is covered by lit pixels, then blend the
+//! background and foreground colors accordingly:
+//! - `nearest`: every pixel becomes a square
+//! - `epx`: EPX (also known as Scale2x), which rounds the corners of
+//!   diagonal lines, applied repeatedly for factors of 4, 8...
+//! - `hqx-lite`: like EPX, but the filled-in corners are only half lit,
+//!   which smooths diagonals without thickening them
+
+use crate::interpreter::{Display, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::png::Image;
+
+/// The names accepted by [scaler_by_name]
+pub const SCALER_NAMES: [&str; 3] = ["nearest", "epx", "hqx-lite"];
+
+/// Converts the display into an image `factor` times larger
+pub trait Scaler {
+    /// How many times wider and taller than the display the images are
+    fn factor(&self) -> usize;
+
+    /// How much every pixel of the image is lit, from 0 to 255, row by row
+    fn coverage(&self, display: &Display) -> Vec<u8>;
+
+    /// Render the display with lit pixels in `foreground`
+    fn render(&self, display: &Display, background: [u8; 3], foreground: [u8; 3]) -> Image {
+        self.image(self.coverage(display), background, foreground)
+    }
+
+    /// Render a mix of two displays, `weight` (from 0 to 1) being how much
+    /// of `current` is shown over `previous`
+    fn render_blend(
+        &self,
+        previous: &Display,
+        current: &Display,
+        weight: f32,
+        background: [u8; 3],
+        foreground: [u8; 3]
+    ) -> Image {
+        let weight = (weight.clamp(0.0, 1.0) * 255.0).round() as u32;
+        let coverage = self.coverage(previous)
+            .into_iter()
+            .zip(self.coverage(current))
+            .map(|(from, to)| ((from as u32 * (255 - weight) + to as u32 * weight + 127) / 255) as u8)
+            .collect();
+        self.image(coverage, background, foreground)
+    }
+
+    /// Blend the colors of every pixel according to its coverage
+    fn image(&self, coverage: Vec<u8>, background: [u8; 3], foreground: [u8; 3]) -> Image {
+        let pixels = coverage
+            .into_iter()
+            .map(|coverage| {
+                let blend = |channel: usize| {
+                    let (from, to) = (background[channel] as u32, foreground[channel] as u32);
+                    ((from * (255 - coverage as u32) + to * coverage as u32 + 127) / 255) as u8
+                };
+                [blend(0), blend(1), blend(2), 0xFF]
+            })
+            .collect();
+        Image {
+            width: DISPLAY_WIDTH * self.factor(),
+            height: DISPLAY_HEIGHT * self.factor(),
+            pixels,
+        }
+    }
+}
+
+/// Every pixel becomes a `factor` x `factor` square
+pub struct Nearest {
+    pub factor: usize,
+}
+
+impl Scaler for Nearest {
+    fn factor(&self) -> usize {
+        self.factor
+    }
+
+    fn coverage(&self, display: &Display) -> Vec<u8> {
+        nearest(&lit_grid(display), DISPLAY_WIDTH, self.factor)
+    }
+}
+
+/// EPX, applied once per factor of 2 in `factor`. `factor` must be even
+pub struct Epx {
+    pub factor: usize,
+}
+
+impl Scaler for Epx {
+    fn factor(&self) -> usize {
+        self.factor
+    }
+
+    fn coverage(&self, display: &Display) -> Vec<u8> {
+        let mut grid = lit_grid(display);
+        let mut width = DISPLAY_WIDTH;
+        let mut remaining = self.factor;
+        while remaining.is_multiple_of(2) {
+            grid = scale2x(&grid, width, false);
+            width *= 2;
+            remaining /= 2;
+        }
+        nearest(&grid, width, remaining)
+    }
+}
+
+/// EPX with half-lit corners, followed by squares to reach `factor`.
+/// `factor` must be even
+pub struct HqxLite {
+    pub factor: usize,
+}
+
+impl Scaler for HqxLite {
+    fn factor(&self) -> usize {
+        self.factor
+    }
+
+    fn coverage(&self, display: &Display) -> Vec<u8> {
+        nearest(&scale2x(&lit_grid(display), DISPLAY_WIDTH, true), DISPLAY_WIDTH * 2, self.factor / 2)
+    }
+}
+
+/// Create a scaler from one of [SCALER_NAMES]
+pub fn scaler_by_name(name: &str, factor: usize) -> Result<Box<dyn Scaler>, String> {
+    if factor == 0 {
+        return Err("the scale must be at least 1".to_string());
+    }
+    if name != "nearest" && !factor.is_multiple_of(2) {
+        return Err(format!("the {} scaler needs an even scale", name));
+    }
+    match name {
+        "nearest" => Ok(Box::new(Nearest { factor })),
+        "epx" => Ok(Box::new(Epx { factor })),
+        "hqx-lite" => Ok(Box::new(HqxLite { factor })),
+        _ => Err(format!("unknown scaler {} (expected one of {})", name, SCALER_NAMES.join(", "))),
+    }
+}
+
+/// The coverage of the display itself: 255 for lit pixels, 0 otherwise
+fn lit_grid(display: &Display) -> Vec<u8> {
+    display.as_flattened().iter().map(|&pixel| if pixel { 255 } else { 0 }).collect()
+}
+
+/// Repeat every value of a grid as a `factor` x `factor` square
+fn nearest(grid: &[u8], width: usize, factor: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(grid.len() * factor * factor);
+    for row in grid.chunks(width) {
+        let scaled_row: Vec<u8> = row.iter().flat_map(|&value| std::iter::repeat_n(value, factor)).collect();
+        for _ in 0..factor {
+            out.extend_from_slice(&scaled_row);
+        }
+    }
+    out
+}
+
+/// Double the size of a grid with EPX. The corners it fills in are set to
+/// half of the neighboring value if `soften` is set
+fn scale2x(grid: &[u8], width: usize, soften: bool) -> Vec<u8> {
+    let height = grid.len() / width;
+    // Pixels outside the grid are considered equal to the nearest one
+    let at = |x: isize, y: isize| {
+        grid[y.clamp(0, height as isize - 1) as usize * width + x.clamp(0, width as isize - 1) as usize]
+    };
+
+    let mut out = vec![0; grid.len() * 4];
+    for y in 0..height as isize {
+        for x in 0..width as isize {
+            let p = at(x, y);
+            let (a, b, c, d) = (at(x, y - 1), at(x + 1, y), at(x - 1, y), at(x, y + 1));
+            let corner = |fill: bool, neighbor: u8| match (fill && neighbor != p, soften) {
+                (false, _) => p,
+                (true, false) => neighbor,
+                (true, true) => ((p as u16 + neighbor as u16) / 2) as u8,
+            };
+            let corners = [
+                corner(c == a && c != d && a != b, c),
+                corner(a == b && a != c && b != d, b),
+                corner(d == c && d != b && c != a, c),
+                corner(b == d && b != a && d != c, b),
+            ];
+            let (out_x, out_y) = (x as usize * 2, y as usize * 2);
+            for (i, value) in corners.into_iter().enumerate() {
+                out[(out_y + i / 2) * width * 2 + out_x + i % 2] = value;
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,1421 @@
+//! A desktop frontend using SDL2 for graphics, audio and keyboard input
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpec, AudioSpecDesired};
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::{FullscreenType, Window};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use sdl2::mouse::MouseButton;
+use sdl2::{AudioSubsystem, EventPump};
+use crate::debugger::Debugger;
+use crate::interpreter::replay::Replay;
+use crate::interpreter::{Display, Error, VM, DISPLAY_HEIGHT, DISPLAY_WIDTH, TIMER_FREQUENCY, VM_FREQUENCY};
+use crate::png::{self, Image};
+use crate::wav::Sound;
+use crate::logging::{error, timestamp};
+use super::control;
+use super::crt::CrtEffects;
+use super::font::{glyph, text_width, GLYPH_HEIGHT, GLYPH_WIDTH};
+use super::mirror::Mirror;
+use super::palette::DEFAULT_PALETTE;
+use super::recorder::Recorder;
+use super::runner::{Command, Runner, Snapshot, Update, UPDATE_QUEUE_SIZE};
+use super::scaler::Scaler;
+use super::waveform::{Oscillator, Waveform};
+
+const AUDIO_SAMPLE_RATE: i32 = 44100;
+/// The samples of audio buffers, unless set. Bigger buffers delay the
+/// buzzer, smaller ones crackle when the system is busy
+pub const DEFAULT_AUDIO_BUFFER: u16 = 512;
+/// The frequency of the tone of the buzzer in Hz, unless set
+pub const DEFAULT_BEEP_FREQUENCY: f32 = 440.0;
+/// The frequencies the tone can be set to
+pub const BEEP_FREQUENCY_RANGE: RangeInclusive<f32> = 50.0..=4000.0;
+/// The amplitude of the buzzer at full volume
+const MAX_AUDIO_AMPLITUDE: f32 = 0.2;
+/// The volume of the buzzer in percent, unless set
+const DEFAULT_VOLUME: u32 = 50;
+/// Assumed when SDL does not know the refresh rate of the monitor
+const DEFAULT_REFRESH_RATE: i32 = 60;
+/// The key starting the program again, see [Frontend::set_reload]
+const RELOAD_KEY: Scancode = Scancode::F1;
+/// The key turning the CRT effects on and off, see
+/// [Frontend::set_crt_effects]
+const CRT_TOGGLE: Scancode = Scancode::F3;
+/// The key showing and hiding lines between the pixels of the display
+const GRID_TOGGLE: Scancode = Scancode::F4;
+/// The key saving the display to a PNG file, see
+/// [Frontend::set_screenshot_dir]
+const SCREENSHOT_KEY: Scancode = Scancode::F12;
+/// The key showing and hiding the debug overlay
+const DEBUG_OVERLAY_TOGGLE: Scancode = Scancode::F10;
+/// How often the debug overlay is updated when nothing else is presented
+const DEBUG_OVERLAY_INTERVAL: Duration = Duration::from_millis(250);
+/// The key switching between windowed and fullscreen
+const FULLSCREEN_KEY: Scancode = Scancode::F11;
+/// The keys pausing and resuming the program, unless they press CHIP-8
+/// keys
+const PAUSE_KEYS: [Scancode; 2] = [Scancode::P, Scancode::Space];
+/// The keys running one frame and one instruction while paused, unless they
+/// press CHIP-8 keys
+const FRAME_STEP_KEY: Scancode = Scancode::Period;
+const INSTRUCTION_STEP_KEY: Scancode = Scancode::Comma;
+/// The key held to run the program as fast as possible, unless it presses
+/// a CHIP-8 key
+const FAST_FORWARD_KEY: Scancode = Scancode::Tab;
+/// The keys speeding up and slowing down the program. Every press changes
+/// the speed by [SPEED_STEP]
+const FASTER_KEYS: [Scancode; 2] = [Scancode::Equals, Scancode::KpPlus];
+const SLOWER_KEYS: [Scancode; 2] = [Scancode::Minus, Scancode::KpMinus];
+const SPEED_STEP: f64 = 1.25;
+/// The keys turning the volume of the buzzer up and down, by
+/// [VOLUME_STEP] percent
+const LOUDER_KEY: Scancode = Scancode::RightBracket;
+const QUIETER_KEY: Scancode = Scancode::LeftBracket;
+const VOLUME_STEP: u32 = 10;
+/// The title of the window, unless set, see [Frontend::set_title]
+pub const DEFAULT_TITLE: &str = "chip8vm — {rom}{status}";
+/// The color outlining the pixels that differ between a machine and its
+/// twin, see [Frontend::set_twin]
+const DIFFERENCE_COLOR: [u8; 3] = [0xFF, 0x30, 0x30];
+/// The key muting and unmuting the buzzer, see [Frontend::set_muted]
+const MUTE_KEY: Scancode = Scancode::M;
+/// The same keys with Shift change the frequency of the tone by a semitone
+const BEEP_FREQUENCY_STEP: f32 = 1.059_463_1;
+/// The instructions per second the speed can be set to
+pub const SPEED_RANGE: RangeInclusive<u32> = 100..=5000;
+/// How long messages are shown over the display
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+/// How long the window waits between checks for events and updates
+const WINDOW_POLL_INTERVAL: Duration = Duration::from_millis(1);
+/// How often the modification time of the watched file is checked
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+/// The key showing and hiding the on-screen keypad
+const KEYPAD_TOGGLE: Scancode = Scancode::F2;
+
+struct Beeper {
+    oscillator: Oscillator,
+    volume: f32,
+}
+
+impl AudioCallback for Beeper {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        if self.volume == 0.0 {
+            // Sounds start from their beginning every time the buzzer does
+            self.oscillator.restart();
+            out.fill(0.0);
+            return;
+        }
+        for sample in out.iter_mut() {
+            *sample = self.oscillator.next_sample() * self.volume;
+        }
+    }
+}
+
+/// The CHIP-8 keys, row by row as they are laid out on the keypad
+const KEYPAD: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+/// Which keyboard keys press which CHIP-8 keys. Keys are identified by
+/// their position on the keyboard (scancodes), so the default layout is the
+/// 4x4 block under `1`..`4` whatever the keyboard layout is:
+///
+/// ```text
+/// 1 2 3 C        1 2 3 4
+/// 4 5 6 D  <==>  Q W E R
+/// 7 8 9 E  <==>  A S D F
+/// A 0 B F        Z X C V
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keymap {
+    keys: [Vec<Scancode>; 16],
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let rows = [
+            [Scancode::Num1, Scancode::Num2, Scancode::Num3, Scancode::Num4],
+            [Scancode::Q, Scancode::W, Scancode::E, Scancode::R],
+            [Scancode::A, Scancode::S, Scancode::D, Scancode::F],
+            [Scancode::Z, Scancode::X, Scancode::C, Scancode::V],
+        ];
+        let mut keymap = Keymap { keys: Default::default() };
+        for (keypad_row, row) in KEYPAD.iter().zip(rows) {
+            for (&chip8_key, scancode) in keypad_row.iter().zip(row) {
+                keymap.keys[chip8_key as usize].push(scancode);
+            }
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    /// Make `scancodes` the only keys pressing a CHIP-8 key. They stop
+    /// pressing the CHIP-8 keys they were mapped to
+    pub fn set(&mut self, chip8_key: u8, scancodes: Vec<Scancode>) {
+        for keys in &mut self.keys {
+            keys.retain(|scancode| !scancodes.contains(scancode));
+        }
+        self.keys[(chip8_key & 0xF) as usize] = scancodes;
+    }
+
+    /// Get the CHIP-8 key a keyboard key presses
+    pub fn chip8_key(&self, scancode: Scancode) -> Option<u8> {
+        self.keys.iter().position(|keys| keys.contains(&scancode)).map(|key| key as u8)
+    }
+
+    /// Get the keyboard keys pressing a CHIP-8 key
+    pub fn keys(&self, chip8_key: u8) -> &[Scancode] {
+        &self.keys[(chip8_key & 0xF) as usize]
+    }
+}
+
+impl fmt::Display for Keymap {
+    /// List the keys pressing every CHIP-8 key, in keypad order
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chip8_key in KEYPAD.as_flattened() {
+            let names: Vec<&str> = self.keys(*chip8_key).iter().map(|scancode| scancode.name()).collect();
+            let names = if names.is_empty() { "(none)".to_string() } else { names.join(", ") };
+            writeln!(f, "{:X}  {}", chip8_key, names)?;
+        }
+        Ok(())
+    }
+}
+
+/// How keyboard events are matched against the keys of a [Keymap]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyMatching {
+    /// By the position of the key, named after the key at that position on
+    /// a US QWERTY keyboard
+    #[default]
+    Position,
+    /// By the character the key types with the current keyboard layout, so
+    /// `Q` is the key labeled Q on AZERTY and QWERTZ keyboards too. Keys
+    /// typing something that is not the name of a key (like `&` on the
+    /// AZERTY digit row) are matched by position
+    Layout,
+}
+
+impl KeyMatching {
+    /// Get the key of a keyboard event to look up in a keymap
+    fn scancode(self, scancode: Scancode, keycode: Option<Keycode>) -> Scancode {
+        match (self, keycode) {
+            (KeyMatching::Layout, Some(keycode)) => Scancode::from_name(&keycode.name()).unwrap_or(scancode),
+            _ => scancode,
+        }
+    }
+}
+
+/// How lit the pixels of the scaled display still are, and how much they
+/// fade every frame
+struct Phosphor {
+    fade: u8,
+    glow: Vec<u8>,
+}
+
+/// The rates shown on the debug overlay, measured over about a second
+struct DebugOverlay {
+    since: Instant,
+    presents: u32,
+    cycles: u64,
+    fps: f64,
+    ips: f64,
+    last_present: Instant,
+}
+
+/// A window presenting the display of a [VM], playing its buzzer and
+/// feeding it keyboard input
+pub struct Frontend<'a> {
+    event_pump: EventPump,
+    canvas: Canvas<Window>,
+    texture: Texture<'a>,
+    scaler: Box<dyn Scaler>,
+    audio_device: AudioDevice<Beeper>,
+    instructions_per_second: u32,
+    /// The volume of the buzzer in percent
+    volume: u32,
+    muted: bool,
+    beep_frequency: f32,
+    colors: ([u8; 3], [u8; 3]),
+    keymap: Keymap,
+    key_matching: KeyMatching,
+    /// The displays at the end of the last two frames, if frame blending is
+    /// enabled
+    frames: Option<[Display; 2]>,
+    grid_visible: bool,
+    /// The debug overlay and what it measures, if visible
+    debug_overlay: Option<DebugOverlay>,
+    screenshot_dir: PathBuf,
+    /// The scaler of screenshots, if not the one of the window
+    screenshot_scaler: Option<Box<dyn Scaler>>,
+    /// The CRT effects, applied while `crt_enabled`
+    crt: CrtEffects,
+    crt_enabled: bool,
+    /// The fading glow of the pixels, if phosphor persistence is enabled
+    phosphor: Option<Phosphor>,
+    mirror: Option<Mirror>,
+    recorder: Option<Recorder>,
+    /// The file the input is recorded to, and the recording, while the
+    /// runner does not have it
+    input_recording_path: Option<PathBuf>,
+    input_recording: Option<Replay>,
+    input_playback: Option<Replay>,
+    /// Where the control server listens, until the runner starts
+    control: Option<TcpListener>,
+    paused: bool,
+    /// Whether the window pauses the program while it is not focused, and
+    /// whether it is the reason the program is paused
+    pause_when_unfocused: bool,
+    paused_by_focus: bool,
+    fast_forward: bool,
+    /// The template of the title of the window and the name of the program,
+    /// and the status the title currently shows
+    title: Option<(String, String)>,
+    title_status: Option<&'static str>,
+    /// A message shown over the display until some time
+    message: Option<(String, Instant)>,
+    keypad_visible: bool,
+    /// The color around the display when its aspect ratio differs from the
+    /// window's, the background color if `None`
+    bar_color: Option<[u8; 3]>,
+    /// Whether the display is scaled by whole numbers only
+    integer_scaling: bool,
+    /// The key of the on-screen keypad held down with the mouse
+    clicked_key: Option<u8>,
+    /// The last state of the virtual machine sent by the runner
+    snapshot: Snapshot,
+    reload: Option<Box<dyn FnMut() -> Result<VM, String> + 'a>>,
+    /// The machine run side by side with the one given to [Frontend::run],
+    /// and how it is reloaded
+    twin: Option<VM>,
+    twin_reload: Option<Box<dyn FnMut() -> Result<VM, String> + 'a>>,
+    /// The file to reload when it changes, and its modification times when
+    /// it was loaded and when it was last checked
+    watched: Option<(PathBuf, Option<SystemTime>, Option<SystemTime>)>,
+    /// The debugger carrying out the commands read from the standard input
+    debugger: Option<Debugger>,
+    /// The commands the debugger carries out first
+    debug_script: Vec<String>,
+}
+
+impl<'a> Frontend<'a> {
+    /// Create a new frontend. The texture must be the size of the images
+    /// of the scaler (the CHIP-8 display times [Scaler::factor]), in the
+    /// RGBA32 format. Audio is played in buffers of `audio_buffer` samples
+    /// (a power of two, see [DEFAULT_AUDIO_BUFFER]) if the system allows,
+    /// see [Frontend::audio_spec]
+    pub fn new(
+        canvas: Canvas<Window>,
+        texture: Texture<'a>,
+        scaler: Box<dyn Scaler>,
+        event_pump: EventPump,
+        audio: AudioSubsystem,
+        audio_buffer: u16
+    ) -> Frontend<'a> {
+        let desired_spec = AudioSpecDesired {
+            freq: Some(AUDIO_SAMPLE_RATE),
+            channels: Some(1),
+            samples: Some(audio_buffer),
+        };
+        let audio_device = audio
+            .open_playback(None, &desired_spec, |spec| Beeper {
+                oscillator: Oscillator::new(Waveform::default(), DEFAULT_BEEP_FREQUENCY, spec.freq as f32),
+                volume: 0.0,
+            })
+            .unwrap();
+        audio_device.resume();
+
+        Frontend {
+            event_pump,
+            canvas,
+            texture,
+            scaler,
+            audio_device,
+            instructions_per_second: VM_FREQUENCY,
+            volume: DEFAULT_VOLUME,
+            muted: false,
+            beep_frequency: DEFAULT_BEEP_FREQUENCY,
+            colors: DEFAULT_PALETTE,
+            keymap: Keymap::default(),
+            key_matching: KeyMatching::default(),
+            frames: None,
+            grid_visible: false,
+            debug_overlay: None,
+            screenshot_dir: PathBuf::from("."),
+            screenshot_scaler: None,
+            crt: CrtEffects::ALL,
+            crt_enabled: false,
+            phosphor: None,
+            mirror: None,
+            recorder: None,
+            input_recording_path: None,
+            input_recording: None,
+            input_playback: None,
+            control: None,
+            paused: false,
+            pause_when_unfocused: true,
+            paused_by_focus: false,
+            fast_forward: false,
+            title: None,
+            title_status: None,
+            message: None,
+            keypad_visible: false,
+            bar_color: None,
+            integer_scaling: false,
+            clicked_key: None,
+            snapshot: Snapshot::of(&VM::new()),
+            reload: None,
+            twin: None,
+            debugger: None,
+            debug_script: Vec::new(),
+            twin_reload: None,
+            watched: None,
+        }
+    }
+
+    /// Present the window at the refresh rate of the monitor, fading
+    /// between the displays at the end of the last two 60 Hz frames instead
+    /// of showing every change. This hides the flicker of sprites erased and
+    /// redrawn with XOR, at the cost of one frame of latency. The virtual
+    /// machine itself is unaffected
+    pub fn set_frame_blending(&mut self, enabled: bool) {
+        self.frames = enabled.then_some([[[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT]; 2]);
+    }
+
+    /// Scale the display by the largest whole number fitting the window,
+    /// centered with bars around it, so that all pixels are the same size
+    pub fn set_integer_scaling(&mut self, enabled: bool) {
+        self.integer_scaling = enabled;
+    }
+
+    /// Cover the screen with the window (F11 switches back and forth)
+    pub fn set_fullscreen(&mut self, fullscreen: bool) -> Result<(), String> {
+        let fullscreen_type = if fullscreen { FullscreenType::Desktop } else { FullscreenType::Off };
+        self.canvas.window_mut().set_fullscreen(fullscreen_type)
+    }
+
+    /// Fade pixels out over `frames` 60 Hz frames once they are turned off,
+    /// like the phosphor of a CRT, instead of hiding them at once. This
+    /// hides the flicker of sprites erased and redrawn with XOR without
+    /// delaying anything, but leaves trails behind moving sprites
+    pub fn set_phosphor(&mut self, frames: u32) {
+        self.phosphor = (frames > 0).then(|| Phosphor {
+            fade: (255 / frames).max(1) as u8,
+            glow: vec![0; DISPLAY_WIDTH * DISPLAY_HEIGHT * self.scaler.factor().pow(2)],
+        });
+    }
+
+    /// Save screenshots taken with F12 into `dir` (by default the current
+    /// directory), named after the time they were taken
+    pub fn set_screenshot_dir(&mut self, dir: PathBuf) {
+        self.screenshot_dir = dir;
+    }
+
+    /// Upscale screenshots with `scaler` instead of the scaler of the
+    /// window
+    pub fn set_screenshot_scaler(&mut self, scaler: Box<dyn Scaler>) {
+        self.screenshot_scaler = Some(scaler);
+    }
+
+    /// Draw thin lines between the pixels of the display, to count the
+    /// pixels of sprites. F4 shows and hides them
+    pub fn set_grid(&mut self, visible: bool) {
+        self.grid_visible = visible;
+    }
+
+    /// Read commands of the [debugger](crate::debugger) from the standard
+    /// input while running
+    pub fn set_debugger(&mut self, debugger: Debugger) {
+        self.debugger = Some(debugger);
+    }
+
+    /// Carry out debugger commands at the start, pausing the program before
+    /// its first instruction, see [Runner::set_debug_script]
+    pub fn set_debug_script(&mut self, commands: Vec<String>) {
+        self.debug_script = commands;
+    }
+
+    /// Get the debugger, once [Frontend::run] returned
+    pub fn debugger(&self) -> Option<&Debugger> {
+        self.debugger.as_ref()
+    }
+
+    /// Show the achieved frames and instructions per second, the timers
+    /// and the registers in the top right corner of the display. F10 shows
+    /// and hides them
+    pub fn set_debug_overlay(&mut self, visible: bool) {
+        self.debug_overlay = visible.then(|| DebugOverlay {
+            since: Instant::now(),
+            presents: 0,
+            cycles: 0,
+            fps: 0.0,
+            ips: 0.0,
+            last_present: Instant::now(),
+        });
+    }
+
+    /// Apply CRT effects to the display. F3 turns them off and on again
+    /// (all of them if none were set)
+    pub fn set_crt_effects(&mut self, effects: CrtEffects) {
+        self.crt = effects;
+        self.crt_enabled = !effects.is_empty();
+    }
+
+    /// Pause the program and mute the buzzer while the window is not
+    /// focused (the default), or keep running
+    pub fn set_pause_when_unfocused(&mut self, enabled: bool) {
+        self.pause_when_unfocused = enabled;
+    }
+
+    /// Title the window from a template (see [DEFAULT_TITLE]), in which
+    /// `{rom}` is replaced by the name of the program and `{status}` by
+    /// ` (paused)` or ` (fast-forward)` while it is, and by nothing
+    /// otherwise. The title follows the status while running
+    pub fn set_title(&mut self, template: &str, rom_name: &str) {
+        self.title = Some((template.to_string(), rom_name.to_string()));
+        self.title_status = None;
+        self.update_title();
+    }
+
+    /// Set which keyboard keys press which CHIP-8 keys
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    /// Get which keyboard keys press which CHIP-8 keys
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    /// Copy the display and keypad into a file every frame
+    pub fn set_mirror(&mut self, mirror: Mirror) {
+        self.mirror = Some(mirror);
+    }
+
+    /// Record every frame of the display, with the scaler and colors of the
+    /// window, and the buzzer. The recording must be finished with
+    /// [Frontend::finish_recording]
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Finish writing the recording, if there is one
+    pub fn finish_recording(&mut self) -> Option<(PathBuf, io::Result<()>)> {
+        self.recorder.take().map(|recorder| (recorder.path().to_path_buf(), recorder.finish()))
+    }
+
+    /// Record the keys pressed, to be played back identically (see
+    /// [runner](super::runner)) by a machine whose random number generator
+    /// is seeded with `seed`. Speed changes are ignored while recording,
+    /// which must be saved with [Frontend::finish_input_recording]
+    pub fn set_input_recording(&mut self, path: PathBuf, seed: u64) {
+        self.input_recording_path = Some(path);
+        self.input_recording = Some(Replay::new(seed, self.instructions_per_second));
+    }
+
+    /// Play recorded keys back instead of the ones pressed, see
+    /// [Runner::set_input_playback]
+    pub fn set_input_playback(&mut self, replay: Replay) {
+        self.input_playback = Some(replay);
+    }
+
+    /// Take requests from other programs on `listener`, see
+    /// [control](super::control)
+    pub fn set_control_server(&mut self, listener: TcpListener) {
+        self.control = Some(listener);
+    }
+
+    /// Write the recorded input, if it is recorded
+    pub fn finish_input_recording(&mut self) -> Option<(PathBuf, io::Result<()>)> {
+        let path = self.input_recording_path.take()?;
+        let replay = self.input_recording.take()?;
+        let mut data = Vec::new();
+        replay.write_to(&mut data).expect("writing to a vector cannot fail");
+        let result = fs::write(&path, data);
+        Some((path, result))
+    }
+
+    /// Replace the virtual machine with a new one from `reload` when F1 is
+    /// pressed, typically running the ROM read again from disk. Errors are
+    /// printed and the current machine keeps running
+    pub fn set_reload(&mut self, reload: impl FnMut() -> Result<VM, String> + 'a) {
+        self.reload = Some(Box::new(reload));
+    }
+
+    /// Run another machine in lockstep with the one given to
+    /// [Frontend::run] (see [runner](super::runner)), shown to its right
+    /// with the pixels that differ outlined. The texture must then be twice
+    /// as wide. `reload` creates it again when the other one is reloaded
+    pub fn set_twin(&mut self, twin: VM, reload: impl FnMut() -> Result<VM, String> + 'a) {
+        self.twin = Some(twin);
+        self.twin_reload = Some(Box::new(reload));
+    }
+
+    /// Also reload (see [Frontend::set_reload]) when the file at `path` is
+    /// modified, polling its modification time every 250 ms. The
+    /// file is only read once its modification time stopped changing, so
+    /// half-written files are not loaded
+    pub fn set_watched_file(&mut self, path: PathBuf) {
+        let modified = modification_time(&path);
+        self.watched = Some((path, modified, modified));
+    }
+
+    /// Set how keyboard events are matched against the keymap
+    pub fn set_key_matching(&mut self, key_matching: KeyMatching) {
+        self.key_matching = key_matching;
+    }
+
+    /// Execute `instructions_per_frame` instructions per 60 Hz frame instead
+    /// of [VM_FREQUENCY] instructions per second
+    pub fn set_tickrate(&mut self, instructions_per_frame: u32) {
+        self.instructions_per_second = instructions_per_frame * TIMER_FREQUENCY;
+    }
+
+    /// Execute `instructions_per_second` instructions per second, within
+    /// [SPEED_RANGE]
+    pub fn set_speed(&mut self, instructions_per_second: u32) {
+        self.instructions_per_second = instructions_per_second.clamp(*SPEED_RANGE.start(), *SPEED_RANGE.end());
+    }
+
+    /// Get the number of instructions executed per second, which + and -
+    /// change while running
+    pub fn speed(&self) -> u32 {
+        self.instructions_per_second
+    }
+
+    /// Set the volume of the buzzer, from 0 to 100 percent (50 by default)
+    pub fn set_volume(&mut self, volume: u32) {
+        self.volume = volume.min(100);
+    }
+
+    /// Silence the buzzer, or let it sound again. The sound timer runs
+    /// either way, and a label shows while muted
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    /// Check whether the buzzer is silenced
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+
+    /// Set the waveform of the buzzer, and the part of every period square
+    /// waves are high, from 0 to 1
+    pub fn set_waveform(&mut self, waveform: Waveform, duty_cycle: f32) {
+        let mut oscillator = Oscillator::new(waveform, self.beep_frequency, self.audio_spec().freq as f32);
+        oscillator.set_duty_cycle(duty_cycle);
+        self.audio_device.lock().oscillator = oscillator;
+    }
+
+    /// Set the frequency of the tone of the buzzer in Hz, within
+    /// [BEEP_FREQUENCY_RANGE] (440 Hz by default). Videos being recorded
+    /// change too
+    pub fn set_beep_frequency(&mut self, frequency: f32) {
+        self.beep_frequency = frequency.clamp(*BEEP_FREQUENCY_RANGE.start(), *BEEP_FREQUENCY_RANGE.end());
+        self.audio_device.lock().oscillator.set_frequency(self.beep_frequency);
+        if let Some(recorder) = &mut self.recorder {
+            recorder.set_beep_frequency(self.beep_frequency);
+        }
+    }
+
+    /// Loop a sound while the buzzer sounds instead of a waveform
+    pub fn set_beep_sound(&mut self, sound: &Sound) {
+        let oscillator = Oscillator::from_sound(sound, self.audio_spec().freq as f32);
+        self.audio_device.lock().oscillator = oscillator;
+    }
+
+    /// Get the sample rate and buffer size audio is actually played with,
+    /// which may differ from the requested ones
+    pub fn audio_spec(&self) -> &AudioSpec {
+        self.audio_device.spec()
+    }
+
+    /// Set the RGB colors of unlit and lit pixels
+    pub fn set_colors(&mut self, background: [u8; 3], foreground: [u8; 3]) {
+        self.colors = (background, foreground);
+    }
+
+    /// Get the RGB colors of unlit and lit pixels
+    pub fn colors(&self) -> ([u8; 3], [u8; 3]) {
+        self.colors
+    }
+
+    /// Set the RGB color of the bars around the display, instead of the
+    /// color of unlit pixels
+    pub fn set_bar_color(&mut self, color: [u8; 3]) {
+        self.bar_color = Some(color);
+    }
+
+    /// Run the virtual machine until the user quits or the program causes
+    /// an error. The machine runs on another thread (see
+    /// [runner](super::runner)) while this one handles the window
+    pub fn run(&mut self, vm: &mut VM) -> Result<(), Error> {
+        let (command_sender, commands) = mpsc::channel();
+        let (update_sender, updates) = mpsc::sync_channel(UPDATE_QUEUE_SIZE);
+        let mut twin = self.twin.take();
+        self.snapshot = Snapshot::of(vm);
+        self.snapshot.twin_display = twin.as_ref().map(|twin| *twin.display());
+        let mut runner = Runner::new(vm, commands, update_sender, self.instructions_per_second);
+        if let Some(mirror) = self.mirror.take() {
+            runner.set_mirror(mirror);
+        }
+        if let Some(twin) = &mut twin {
+            runner.set_twin(twin);
+        }
+        if let Some(replay) = self.input_recording.take() {
+            runner.set_input_recording(replay);
+        }
+        if let Some(replay) = &self.input_playback {
+            runner.set_input_playback(replay.clone());
+        }
+        if let Some(debugger) = self.debugger.take() {
+            runner.set_debugger(debugger);
+            runner.set_debug_script(std::mem::take(&mut self.debug_script));
+            println!("Debugger commands are read from the terminal, type help for the list");
+            // Not joined, as reading the standard input cannot be
+            // interrupted: the thread ends with the process
+            let commands = command_sender.clone();
+            thread::spawn(move || {
+                for line in io::stdin().lines() {
+                    let Ok(line) = line else {
+                        break;
+                    };
+                    if commands.send(Command::Debug(line)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        if let Some(listener) = self.control.take() {
+            // Not joined either, waiting for connections until the
+            // process ends
+            let commands = command_sender.clone();
+            thread::spawn(move || control::serve(listener, commands));
+        }
+
+        thread::scope(|scope| {
+            let handle = scope.spawn(|| runner.run());
+            // Dropping the receiver of updates stops the runner if it is
+            // waiting for room in the channel
+            let result = self.run_window(&command_sender, updates);
+            let _ = command_sender.send(Command::Quit);
+            (self.input_recording, self.debugger) = handle.join().expect("the runner panicked");
+            result
+        })
+    }
+
+    fn run_window(&mut self, runner: &Sender<Command>, updates: Receiver<Update>) -> Result<(), Error> {
+        let frame_duration = Duration::from_secs_f64(1.0 / TIMER_FREQUENCY as f64);
+        let refresh_rate = match self.canvas.window().display_mode() {
+            Ok(mode) if mode.refresh_rate > 0 => mode.refresh_rate,
+            _ => DEFAULT_REFRESH_RATE,
+        };
+        let refresh_duration = Duration::from_secs_f64(1.0 / refresh_rate as f64);
+        // Sending commands only fails once the runner stopped, which the
+        // updates tell
+        let send = |command: Command| {
+            let _ = runner.send(command);
+        };
+
+        let mut last_frame = Instant::now();
+        let mut last_present = Instant::now();
+        let mut display_dirty = true;
+        let mut last_watch = Instant::now();
+
+        loop {
+            self.update_title();
+            // A bounded number at a time, so that a runner running as fast
+            // as possible does not keep the window from handling events
+            for _ in 0..UPDATE_QUEUE_SIZE {
+                let update = match updates.try_recv() {
+                    Ok(update) => update,
+                    Err(TryRecvError::Empty) => break,
+                    // The runner only stops on its own after an error
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                };
+                match update {
+                    Update::Frame(snapshot) => {
+                        self.snapshot = *snapshot;
+                        self.end_frame();
+                        last_frame = Instant::now();
+                    }
+                    Update::Changed(snapshot) => self.snapshot = *snapshot,
+                    Update::PauseChanged(paused, snapshot) => {
+                        self.snapshot = *snapshot;
+                        self.paused = paused;
+                        self.paused_by_focus = false;
+                        if paused {
+                            self.audio_device.lock().volume = 0.0;
+                        }
+                    }
+                    Update::Stopped(e) => return Err(e),
+                }
+                display_dirty = true;
+            }
+
+            if self.message.as_ref().is_some_and(|(_, until)| Instant::now() >= *until) {
+                self.message = None;
+                display_dirty = true;
+            }
+            if self.debug_overlay.as_ref().is_some_and(|overlay| overlay.last_present.elapsed() >= DEBUG_OVERLAY_INTERVAL) {
+                display_dirty = true;
+            }
+
+            if last_watch.elapsed() >= WATCH_INTERVAL {
+                last_watch = Instant::now();
+                if self.watched_file_changed() {
+                    self.reload(send);
+                }
+            }
+
+            // Handling some events needs the whole frontend
+            let events: Vec<Event> = self.event_pump.poll_iter().collect();
+            for event in events {
+                match event {
+                    Event::Quit { .. } | Event::KeyDown { scancode: Some(Scancode::Escape), .. } => {
+                        return Ok(());
+                    }
+                    Event::KeyDown { scancode: Some(RELOAD_KEY), repeat: false, .. } => self.reload(send),
+                    Event::KeyDown { scancode: Some(CRT_TOGGLE), repeat: false, .. } => {
+                        if self.crt.is_empty() {
+                            self.crt = CrtEffects::ALL;
+                        }
+                        self.crt_enabled = !self.crt_enabled;
+                        self.present_display();
+                    }
+                    Event::KeyDown { scancode: Some(GRID_TOGGLE), repeat: false, .. } => {
+                        self.grid_visible = !self.grid_visible;
+                        self.present_display();
+                    }
+                    Event::KeyDown { scancode: Some(SCREENSHOT_KEY), repeat: false, .. } => self.save_screenshot(),
+                    Event::KeyDown { scancode: Some(DEBUG_OVERLAY_TOGGLE), repeat: false, .. } => {
+                        self.set_debug_overlay(self.debug_overlay.is_none());
+                        self.present_display();
+                    }
+                    Event::KeyDown { scancode: Some(FULLSCREEN_KEY), repeat: false, .. } => {
+                        let fullscreen = self.canvas.window().fullscreen_state() == FullscreenType::Off;
+                        if let Err(e) = self.set_fullscreen(fullscreen) {
+                            error(format!("failed to switch to fullscreen: {}", e));
+                        }
+                    }
+                    // The window is redrawn when it resizes, entering or
+                    // leaving fullscreen notably
+                    Event::Window { win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed, .. } => {
+                        self.present_display();
+                    }
+                    Event::Window { win_event: WindowEvent::FocusLost, .. } => {
+                        // Tab will be released in another window
+                        if self.fast_forward {
+                            self.fast_forward = false;
+                            send(Command::SetFastForward(false));
+                        }
+                        if self.pause_when_unfocused && !self.paused {
+                            self.paused = true;
+                            self.paused_by_focus = true;
+                            send(Command::SetPaused(true));
+                            self.audio_device.lock().volume = 0.0;
+                        }
+                        self.present_display();
+                    }
+                    // Programs paused by hand stay paused
+                    Event::Window { win_event: WindowEvent::FocusGained, .. } if self.paused_by_focus => {
+                        self.paused = false;
+                        self.paused_by_focus = false;
+                        send(Command::SetPaused(false));
+                        self.present_display();
+                    }
+                    Event::KeyDown { scancode: Some(KEYPAD_TOGGLE), repeat: false, .. } => {
+                        self.keypad_visible = !self.keypad_visible;
+                        self.present_display();
+                    }
+                    Event::KeyDown { scancode: Some(scancode), keycode, repeat: false, .. }
+                        if PAUSE_KEYS.contains(&scancode) && self.is_hotkey(scancode, keycode) =>
+                    {
+                        self.paused = !self.paused;
+                        self.paused_by_focus = false;
+                        send(Command::SetPaused(self.paused));
+                        if self.paused {
+                            self.audio_device.lock().volume = 0.0;
+                        }
+                        self.present_display();
+                    }
+                    Event::KeyDown { scancode: Some(scancode @ (FRAME_STEP_KEY | INSTRUCTION_STEP_KEY)), keycode, .. }
+                        if self.paused && self.is_hotkey(scancode, keycode) =>
+                    {
+                        send(if scancode == FRAME_STEP_KEY { Command::StepFrame } else { Command::StepInstruction });
+                    }
+                    Event::KeyDown { scancode: Some(scancode), keycode, .. }
+                        if (FASTER_KEYS.contains(&scancode) || SLOWER_KEYS.contains(&scancode))
+                            && self.is_hotkey(scancode, keycode) =>
+                    {
+                        if self.input_recording_path.is_some() {
+                            self.show_message("SPEED FIXED WHILE RECORDING".to_string());
+                            continue;
+                        }
+                        let factor = if FASTER_KEYS.contains(&scancode) { SPEED_STEP } else { 1.0 / SPEED_STEP };
+                        // Rounded to tens, so that stepping back and forth
+                        // gives round numbers
+                        let speed = (self.instructions_per_second as f64 * factor / 10.0).round() as u32 * 10;
+                        self.set_speed(speed);
+                        send(Command::SetSpeed(self.instructions_per_second));
+                        self.show_message(format!("{} IPS", self.instructions_per_second));
+                    }
+                    Event::KeyDown { scancode: Some(scancode @ (LOUDER_KEY | QUIETER_KEY)), keycode, keymod, .. }
+                        if self.is_hotkey(scancode, keycode) && keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) =>
+                    {
+                        let factor = if scancode == LOUDER_KEY { BEEP_FREQUENCY_STEP } else { 1.0 / BEEP_FREQUENCY_STEP };
+                        self.set_beep_frequency(self.beep_frequency * factor);
+                        self.show_message(format!("BEEP {:.0} HZ", self.beep_frequency));
+                    }
+                    Event::KeyDown { scancode: Some(scancode @ (LOUDER_KEY | QUIETER_KEY)), keycode, .. }
+                        if self.is_hotkey(scancode, keycode) =>
+                    {
+                        let volume = if scancode == LOUDER_KEY {
+                            self.volume + VOLUME_STEP
+                        } else {
+                            self.volume.saturating_sub(VOLUME_STEP)
+                        };
+                        // Changing the volume is pointless without hearing it
+                        self.muted = false;
+                        self.set_volume(volume);
+                        self.show_message(format!("VOLUME {}%", self.volume));
+                    }
+                    Event::KeyDown { scancode: Some(MUTE_KEY), keycode, repeat: false, .. }
+                        if self.is_hotkey(MUTE_KEY, keycode) =>
+                    {
+                        self.muted = !self.muted;
+                        self.present_display();
+                    }
+                    Event::KeyDown { scancode: Some(FAST_FORWARD_KEY), keycode, repeat: false, .. }
+                        if self.is_hotkey(FAST_FORWARD_KEY, keycode) =>
+                    {
+                        self.fast_forward = true;
+                        send(Command::SetFastForward(true));
+                        self.audio_device.lock().volume = 0.0;
+                    }
+                    Event::KeyUp { scancode: Some(FAST_FORWARD_KEY), keycode, .. }
+                        if self.is_hotkey(FAST_FORWARD_KEY, keycode) =>
+                    {
+                        self.fast_forward = false;
+                        send(Command::SetFastForward(false));
+                        self.present_display();
+                    }
+                    Event::MouseButtonDown { mouse_btn: MouseButton::Left, x, y, .. } => {
+                        if let Some(chip8_key) = self.keypad_key_at(x, y) {
+                            send(Command::SetKey(chip8_key, true));
+                            self.clicked_key = Some(chip8_key);
+                        }
+                    }
+                    Event::MouseButtonUp { mouse_btn: MouseButton::Left, .. } => {
+                        if let Some(chip8_key) = self.clicked_key.take() {
+                            send(Command::SetKey(chip8_key, false));
+                        }
+                    }
+                    Event::KeyDown { scancode: Some(scancode), keycode, .. } => {
+                        if let Some(chip8_key) = self.keymap.chip8_key(self.key_matching.scancode(scancode, keycode)) {
+                            send(Command::SetKey(chip8_key, true));
+                        }
+                    }
+                    Event::KeyUp { scancode: Some(scancode), keycode, .. } => {
+                        if let Some(chip8_key) = self.keymap.chip8_key(self.key_matching.scancode(scancode, keycode)) {
+                            send(Command::SetKey(chip8_key, false));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            // Presented once per refresh at most, so that waiting for the
+            // monitor does not hold the window back
+            if last_present.elapsed() >= refresh_duration {
+                if let Some([previous, current]) = self.frames && !self.paused {
+                    let weight = last_frame.elapsed().as_secs_f32() / frame_duration.as_secs_f32();
+                    let (background, foreground) = self.colors;
+                    let image = self.scaler.render_blend(&previous, &current, weight, background, foreground);
+                    self.present(&image);
+                    last_present = Instant::now();
+                } else if display_dirty {
+                    self.present_display();
+                    last_present = Instant::now();
+                    display_dirty = false;
+                }
+            }
+            std::thread::sleep(WINDOW_POLL_INTERVAL);
+        }
+    }
+
+    /// Show the status of the program in the title of the window, if it
+    /// changed
+    fn update_title(&mut self) {
+        let status = if self.paused {
+            " (paused)"
+        } else if self.fast_forward {
+            " (fast-forward)"
+        } else {
+            ""
+        };
+        let Some((template, rom_name)) = &self.title else {
+            return;
+        };
+        if self.title_status == Some(status) {
+            return;
+        }
+        let title = template.replace("{rom}", rom_name).replace("{status}", status);
+        if let Err(e) = self.canvas.window_mut().set_title(&title) {
+            error(format!("failed to set the title of the window: {}", e));
+        }
+        self.title_status = Some(status);
+    }
+
+    /// Update what follows the frames of the runner
+    fn end_frame(&mut self) {
+        let sound = self.snapshot.sound_active && !self.fast_forward && !self.paused;
+        let amplitude = if self.muted { 0.0 } else { MAX_AUDIO_AMPLITUDE * self.volume as f32 / 100.0 };
+        let mut beeper = self.audio_device.lock();
+        beeper.volume = if sound { amplitude } else { 0.0 };
+        beeper.oscillator.set_pattern(self.snapshot.audio_pattern);
+        drop(beeper);
+        let display = self.snapshot.state.display;
+        if let Some(frames) = &mut self.frames {
+            *frames = [frames[1], display];
+        }
+        if let Some(phosphor) = &mut self.phosphor {
+            for (glow, coverage) in phosphor.glow.iter_mut().zip(self.scaler.coverage(&display)) {
+                *glow = coverage.max(glow.saturating_sub(phosphor.fade));
+            }
+        }
+        if let Some(recorder) = &mut self.recorder {
+            let (background, foreground) = self.colors;
+            let image = self.scaler.render(&display, background, foreground);
+            recorder.set_audio_pattern(self.snapshot.audio_pattern);
+            if let Err(e) = recorder.add_frame(&image, self.snapshot.sound_active) {
+                error(format!("failed to record to {}, no longer recording: {}", recorder.path().display(), e));
+                self.recorder = None;
+            }
+        }
+    }
+
+    /// Check whether a key controls the frontend, which it only does if it
+    /// is not mapped to a CHIP-8 key
+    fn is_hotkey(&self, scancode: Scancode, keycode: Option<Keycode>) -> bool {
+        self.keymap.chip8_key(self.key_matching.scancode(scancode, keycode)).is_none()
+    }
+
+    /// Check whether the watched file was modified since it was loaded,
+    /// and has not been modified since the last check
+    fn watched_file_changed(&mut self) -> bool {
+        let Some((path, loaded, last_checked)) = &mut self.watched else {
+            return false;
+        };
+        let modified = modification_time(path);
+        let settled = modified == *last_checked;
+        *last_checked = modified;
+        if settled && modified != *loaded {
+            *loaded = modified;
+            return true;
+        }
+        false
+    }
+
+    /// Replace the virtual machine with the one from the reload callback
+    fn reload(&mut self, send: impl Fn(Command)) {
+        let Some(reload) = &mut self.reload else {
+            return;
+        };
+        // Both machines are replaced together, or neither is
+        let machines = reload().and_then(|vm| match &mut self.twin_reload {
+            Some(twin_reload) => Ok((vm, Some(twin_reload()?))),
+            None => Ok((vm, None)),
+        });
+        match machines {
+            Ok((vm, twin)) => {
+                self.snapshot = Snapshot::of(&vm);
+                self.snapshot.twin_display = twin.as_ref().map(|twin| *twin.display());
+                send(Command::Replace(Box::new(vm), twin.map(Box::new)));
+                // The keys held down are released with the old machine
+                self.clicked_key = None;
+                if let Some(frames) = &mut self.frames {
+                    *frames = [self.snapshot.state.display; 2];
+                }
+                if let Some(phosphor) = &mut self.phosphor {
+                    phosphor.glow.fill(0);
+                }
+                self.present_display();
+            }
+            Err(e) => error(e),
+        }
+    }
+
+    /// Save the display as it is in RAM, in the current colors but without
+    /// any effect, to a new file of the screenshot directory
+    fn save_screenshot(&mut self) {
+        let (background, foreground) = self.colors;
+        let scaler = self.screenshot_scaler.as_ref().unwrap_or(&self.scaler);
+        let image = scaler.render(&self.snapshot.state.display, background, foreground);
+
+        let name = format!("chip8vm-{}", timestamp(SystemTime::now()));
+        let mut path = self.screenshot_dir.join(format!("{}.png", name));
+        // Several screenshots can be taken in the same second
+        let mut n = 2;
+        while path.exists() {
+            path = self.screenshot_dir.join(format!("{}-{}.png", name, n));
+            n += 1;
+        }
+        match fs::write(&path, png::encode(&image)) {
+            Ok(()) => {
+                println!("Saved a screenshot to {}", path.display());
+                self.show_message("SCREENSHOT SAVED".to_string());
+            }
+            Err(e) => error(format!("failed to save a screenshot to {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Present the current display, without blending but with the glow of
+    /// the pixels turned off recently
+    fn present_display(&mut self) {
+        let (background, foreground) = self.colors;
+        let display = &self.snapshot.state.display;
+        let image = match &self.phosphor {
+            Some(phosphor) => {
+                let coverage = self.scaler.coverage(display)
+                    .into_iter()
+                    .zip(&phosphor.glow)
+                    .map(|(coverage, &glow)| coverage.max(glow))
+                    .collect();
+                self.scaler.image(coverage, background, foreground)
+            }
+            None => self.scaler.render(display, background, foreground),
+        };
+        self.present(&image);
+    }
+
+    fn present(&mut self, image: &Image) {
+        let (background, foreground) = self.colors;
+        let [r, g, b] = self.bar_color.unwrap_or(background);
+        self.canvas.set_draw_color(Color::RGB(r, g, b));
+        self.canvas.clear();
+
+        let (canvas_width, canvas_height) = self.canvas.output_size().unwrap();
+        let displays = if self.snapshot.twin_display.is_some() { 2 } else { 1 };
+        let (display_rect, keypad_rect) = layout(canvas_width, canvas_height, displays, self.keypad_visible, self.integer_scaling);
+
+        // The twin to the right, rendered the same way
+        let pair_image;
+        let image = match &self.snapshot.twin_display {
+            Some(twin_display) => {
+                let twin_image = self.scaler.render(twin_display, background, foreground);
+                pair_image = side_by_side(image, &twin_image);
+                &pair_image
+            }
+            None => image,
+        };
+
+        let crt_image;
+        let image = if self.crt_enabled {
+            crt_image = self.crt.apply(image);
+            &crt_image
+        } else {
+            image
+        };
+        self.texture
+            .update(None, image.pixels.as_flattened(), image.width * 4)
+            .unwrap();
+        self.canvas
+            .copy(&self.texture, None, Some(display_rect))
+            .unwrap();
+        let display_rects = split(display_rect, displays);
+        if self.grid_visible {
+            for &rect in &display_rects {
+                self.draw_grid(rect, blend(background, foreground, 0.5));
+            }
+        }
+        if let Some(twin_display) = self.snapshot.twin_display {
+            let display = self.snapshot.state.display;
+            self.draw_differences(&display_rects, &display, &twin_display);
+        }
+        if let Some(keypad_rect) = keypad_rect {
+            let keys = self.snapshot.keys;
+            self.draw_keypad(keypad_rect, &keys, background, foreground);
+        }
+        let mut labels = Vec::new();
+        if self.paused {
+            labels.push("PAUSED".to_string());
+        } else if self.fast_forward {
+            labels.push(">> FAST".to_string());
+        }
+        if self.muted {
+            labels.push("MUTED".to_string());
+        }
+        if let Some(frame) = self.snapshot.divergence {
+            labels.push(format!("DIVERGED AT FRAME {}", frame));
+        }
+        if let Some((message, _)) = &self.message {
+            labels.push(message.clone());
+        }
+        self.draw_labels(&labels, display_rect, background, foreground);
+        if self.debug_overlay.is_some() {
+            self.draw_debug_overlay(display_rect, background, foreground);
+        }
+        self.canvas.present();
+    }
+
+    /// Draw lines between the pixels of the display, halfway between the
+    /// colors of unlit and lit pixels so that they show over both
+    fn draw_grid(&mut self, display_rect: Rect, color: [u8; 3]) {
+        self.canvas.set_draw_color(Color::RGB(color[0], color[1], color[2]));
+        for column in 1..DISPLAY_WIDTH as i32 {
+            let x = display_rect.x() + column * display_rect.width() as i32 / DISPLAY_WIDTH as i32;
+            self.canvas.draw_line((x, display_rect.top()), (x, display_rect.bottom() - 1)).unwrap();
+        }
+        for row in 1..DISPLAY_HEIGHT as i32 {
+            let y = display_rect.y() + row * display_rect.height() as i32 / DISPLAY_HEIGHT as i32;
+            self.canvas.draw_line((display_rect.left(), y), (display_rect.right() - 1, y)).unwrap();
+        }
+    }
+
+    /// Outline the pixels that differ between the displays of a machine and
+    /// its twin, on both
+    fn draw_differences(&mut self, display_rects: &[Rect], display: &Display, twin_display: &Display) {
+        self.canvas.set_draw_color(Color::RGB(DIFFERENCE_COLOR[0], DIFFERENCE_COLOR[1], DIFFERENCE_COLOR[2]));
+        for rect in display_rects {
+            let (pixel_width, pixel_height) = (rect.width() / DISPLAY_WIDTH as u32, rect.height() / DISPLAY_HEIGHT as u32);
+            for y in 0..DISPLAY_HEIGHT {
+                for x in (0..DISPLAY_WIDTH).filter(|&x| display[y][x] != twin_display[y][x]) {
+                    let left = rect.x() + x as i32 * rect.width() as i32 / DISPLAY_WIDTH as i32;
+                    let top = rect.y() + y as i32 * rect.height() as i32 / DISPLAY_HEIGHT as i32;
+                    let outline = Rect::new(left, top, pixel_width.max(1), pixel_height.max(1));
+                    self.canvas.draw_rect(outline).unwrap();
+                }
+            }
+        }
+    }
+
+    /// Draw the hexadecimal keypad, pressed keys in the foreground color and
+    /// the others dimmed, labeled with the font of the CHIP-8
+    fn draw_keypad(&mut self, rect: Rect, keys: &[bool; 16], background: [u8; 3], foreground: [u8; 3]) {
+        let cell = rect.width() as i32 / 4;
+        let margin = cell / 10;
+        let glyph_pixel = (cell / 10).max(1);
+        let dimmed = blend(background, foreground, 0.25);
+
+        for (row, keypad_row) in KEYPAD.iter().enumerate() {
+            for (column, &chip8_key) in keypad_row.iter().enumerate() {
+                let (key_color, label_color) = if keys[chip8_key as usize] {
+                    (foreground, background)
+                } else {
+                    (dimmed, foreground)
+                };
+                let x = rect.x() + column as i32 * cell;
+                let y = rect.y() + row as i32 * cell;
+                self.canvas.set_draw_color(Color::RGB(key_color[0], key_color[1], key_color[2]));
+                let size = (cell - 2 * margin).max(1) as u32;
+                self.canvas.fill_rect(Rect::new(x + margin, y + margin, size, size)).unwrap();
+
+                let glyph_x = x + (cell - GLYPH_WIDTH as i32 * glyph_pixel) / 2;
+                let glyph_y = y + (cell - GLYPH_HEIGHT as i32 * glyph_pixel) / 2;
+                let label = format!("{:X}", chip8_key);
+                self.draw_text(&label, glyph_x, glyph_y, glyph_pixel, label_color);
+            }
+        }
+    }
+
+    /// Draw lines of text in boxes in the top left corner of the display,
+    /// one under the other
+    fn draw_labels(&mut self, labels: &[String], display_rect: Rect, background: [u8; 3], foreground: [u8; 3]) {
+        let pixel_size = (display_rect.height() as i32 / 48).max(1);
+        let padding = 2 * pixel_size;
+        let height = GLYPH_HEIGHT as i32 * pixel_size + 2 * padding;
+        for (index, text) in labels.iter().enumerate() {
+            let width = text_width(text) as i32 * pixel_size + 2 * padding;
+            let x = display_rect.x() + padding;
+            let y = display_rect.y() + padding + index as i32 * (height + padding);
+
+            self.canvas.set_draw_color(Color::RGB(background[0], background[1], background[2]));
+            self.canvas.fill_rect(Rect::new(x, y, width as u32, height as u32)).unwrap();
+            self.draw_text(text, x + padding, y + padding, pixel_size, foreground);
+        }
+    }
+
+    /// Measure the rates of the debug overlay, and draw it in the top right
+    /// corner of the display
+    fn draw_debug_overlay(&mut self, display_rect: Rect, background: [u8; 3], foreground: [u8; 3]) {
+        let Some(overlay) = &mut self.debug_overlay else {
+            return;
+        };
+        overlay.presents += 1;
+        overlay.last_present = Instant::now();
+        let elapsed = overlay.since.elapsed().as_secs_f64();
+        if elapsed >= 1.0 {
+            overlay.fps = overlay.presents as f64 / elapsed;
+            // The count starts again when the program is reloaded
+            overlay.ips = self.snapshot.counters.instructions.saturating_sub(overlay.cycles) as f64 / elapsed;
+            overlay.since = Instant::now();
+            overlay.presents = 0;
+            overlay.cycles = self.snapshot.counters.instructions;
+        }
+
+        let state = &self.snapshot.state;
+        let hex_bytes = |bytes: &[u8]| bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ");
+        let mut lines = vec![
+            format!("FPS {:.0}  IPS {:.0}", overlay.fps, overlay.ips),
+            format!("PC {:03X}  I {:03X}", state.pc, state.reg_i),
+            format!("DT {:02X}  ST {:02X}", state.delay_timer, state.sound_timer),
+        ];
+        let counters = &self.snapshot.counters;
+        lines.push(format!("FRAMES {}", counters.frames));
+        lines.push(format!("DRW {}  HIT {}", counters.sprites_drawn, counters.collisions));
+        lines.push(format!("BEEPS {}", counters.sound_activations));
+        for (index, registers) in state.reg.chunks(4).enumerate() {
+            lines.push(format!("V{:X}-{:X} {}", index * 4, index * 4 + 3, hex_bytes(registers)));
+        }
+
+        // Smaller than the labels, so that the display stays visible
+        let pixel_size = (display_rect.height() as i32 / 96).max(1);
+        let padding = 2 * pixel_size;
+        let line_height = (GLYPH_HEIGHT as i32 + 1) * pixel_size;
+        let width = lines.iter().map(|line| text_width(line)).max().unwrap_or(0) as i32 * pixel_size + 2 * padding;
+        let height = lines.len() as i32 * line_height - pixel_size + 2 * padding;
+        let x = display_rect.right() - padding - width;
+        let y = display_rect.y() + padding;
+
+        self.canvas.set_draw_color(Color::RGB(background[0], background[1], background[2]));
+        self.canvas.fill_rect(Rect::new(x, y, width as u32, height as u32)).unwrap();
+        for (index, line) in lines.iter().enumerate() {
+            self.draw_text(line, x + padding, y + padding + index as i32 * line_height, pixel_size, foreground);
+        }
+    }
+
+    /// Show a message over the display for [MESSAGE_DURATION]
+    fn show_message(&mut self, message: String) {
+        self.message = Some((message, Instant::now() + MESSAGE_DURATION));
+        self.present_display();
+    }
+
+    /// Draw a line of text with the [font](super::font), every pixel of
+    /// the glyphs being a `pixel_size` square
+    fn draw_text(&mut self, text: &str, x: i32, y: i32, pixel_size: i32, color: [u8; 3]) {
+        draw_text(&mut self.canvas, text, x, y, pixel_size, color);
+    }
+
+    /// Get the key of the on-screen keypad at a position in the window
+    fn keypad_key_at(&self, x: i32, y: i32) -> Option<u8> {
+        if !self.keypad_visible {
+            return None;
+        }
+        // The canvas may have more pixels than the window on high DPI
+        // screens
+        let (canvas_width, canvas_height) = self.canvas.output_size().ok()?;
+        let (window_width, window_height) = self.canvas.window().size();
+        let x = (x as i64 * canvas_width as i64 / window_width.max(1) as i64) as i32;
+        let y = (y as i64 * canvas_height as i64 / window_height.max(1) as i64) as i32;
+
+        let displays = if self.snapshot.twin_display.is_some() { 2 } else { 1 };
+        let (_, keypad_rect) = layout(canvas_width, canvas_height, displays, true, self.integer_scaling);
+        let keypad_rect = keypad_rect?;
+        if !keypad_rect.contains_point((x, y)) {
+            return None;
+        }
+        let cell = (keypad_rect.width() as i32 / 4).max(1);
+        let column = ((x - keypad_rect.x()) / cell).min(3) as usize;
+        let row = ((y - keypad_rect.y()) / cell).min(3) as usize;
+        Some(KEYPAD[row][column])
+    }
+}
+
+/// Draw a line of text with the [font](super::font), its glyph pixels
+/// `pixel_size` screen pixels wide, its top left corner at (x, y)
+pub fn draw_text(canvas: &mut Canvas<Window>, text: &str, x: i32, y: i32, pixel_size: i32, color: [u8; 3]) {
+    canvas.set_draw_color(Color::RGB(color[0], color[1], color[2]));
+    for (index, c) in text.chars().enumerate() {
+        let glyph_x = x + index as i32 * (GLYPH_WIDTH as i32 + 1) * pixel_size;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH as i32 {
+                if bits & (0x80 >> column) != 0 {
+                    let pixel = Rect::new(
+                        glyph_x + column * pixel_size,
+                        y + row as i32 * pixel_size,
+                        pixel_size as u32,
+                        pixel_size as u32,
+                    );
+                    canvas.fill_rect(pixel).unwrap();
+                }
+            }
+        }
+    }
+}
+
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Split the canvas between `displays` displays side by side and, if
+/// visible, the keypad: a square on the right, next to the area left for
+/// the displays. The displays are then shrunk to keep their aspect ratio,
+/// or with integer scaling to a whole multiple of their size, and centered
+/// in their area
+fn layout(width: u32, height: u32, displays: u32, keypad_visible: bool, integer_scaling: bool) -> (Rect, Option<Rect>) {
+    let (display_area, keypad_rect) = if !keypad_visible || width <= height {
+        (Rect::new(0, 0, width, height), None)
+    } else {
+        let keypad_size = height.min(width / 2);
+        (
+            Rect::new(0, 0, width - keypad_size, height),
+            Some(Rect::new((width - keypad_size) as i32, ((height - keypad_size) / 2) as i32, keypad_size, keypad_size)),
+        )
+    };
+
+    let total_width = DISPLAY_WIDTH as u32 * displays;
+    let (display_width, display_height) = if integer_scaling {
+        // The display is never scaled below 1, even if the window is smaller
+        let scale = (display_area.width() / total_width)
+            .min(display_area.height() / DISPLAY_HEIGHT as u32)
+            .max(1);
+        (total_width * scale, DISPLAY_HEIGHT as u32 * scale)
+    } else {
+        let display_width = display_area.width().min(display_area.height() * total_width / DISPLAY_HEIGHT as u32);
+        (display_width.max(1), (display_width * DISPLAY_HEIGHT as u32 / total_width).max(1))
+    };
+    let mut display_rect = Rect::new(0, 0, display_width, display_height);
+    display_rect.center_on(display_area.center());
+    (display_rect, keypad_rect)
+}
+
+/// Split the area of displays side by side into the area of each
+fn split(rect: Rect, displays: u32) -> Vec<Rect> {
+    let width = rect.width() / displays;
+    (0..displays).map(|i| Rect::new(rect.x() + (i * width) as i32, rect.y(), width.max(1), rect.height())).collect()
+}
+
+/// Put two images of the same height side by side
+fn side_by_side(left: &Image, right: &Image) -> Image {
+    let pixels = left.pixels.chunks(left.width)
+        .zip(right.pixels.chunks(right.width))
+        .flat_map(|(left_row, right_row)| left_row.iter().chain(right_row))
+        .copied()
+        .collect();
+    Image { width: left.width + right.width, height: left.height, pixels }
+}
+
+/// Mix two colors, `amount` being the share of `to`
+fn blend(from: [u8; 3], to: [u8; 3], amount: f32) -> [u8; 3] {
+    let channel = |i: usize| (from[i] as f32 + (to[i] as f32 - from[i] as f32) * amount).round() as u8;
+    [channel(0), channel(1), channel(2)]
+}
@@ -0,0 +1,153 @@
+//! A C ABI for driving the VM from JavaScript when compiled to WebAssembly
+//!
+//! The browser frontend in `web/` uses it like this:
+//! 1. `chip8_new()` creates a machine, `chip8_seed()` seeds `RND`
+//! 2. `chip8_alloc(len)` reserves memory the ROM is copied into, and
+//!    `chip8_load()` loads it
+//! 3. every animation frame, key changes are passed to `chip8_set_key()`
+//!    and `chip8_run_frame()` executes one frame worth of instructions
+//! 4. `chip8_display()` points at [DISPLAY_WIDTH] * [DISPLAY_HEIGHT] bytes,
+//!    one per pixel (0 or 1), and `chip8_sound_active()` tells whether the
+//!    buzzer sounds
+//! 5. `chip8_counters()` points at the [Counters] of the machine, 5 64-bit
+//!    integers, to show statistics
+//!
+//! Nothing here is specific to WebAssembly, so the same functions can be
+//! called from any language with a C FFI.
+
+use std::ffi::CString;
+use crate::interpreter::{VM, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::interpreter::counters::Counters;
+
+/// A virtual machine, the last error it stopped with and the counters
+/// last returned by [chip8_counters]
+pub struct Machine {
+    vm: VM,
+    error: Option<CString>,
+    counters: Counters,
+}
+
+/// Create a new machine. Free it with [chip8_free]
+#[unsafe(no_mangle)]
+pub extern "C" fn chip8_new() -> *mut Machine {
+    Box::into_raw(Box::new(Machine { vm: VM::new(), error: None, counters: Counters::default() }))
+}
+
+/// Free a machine created with [chip8_new]
+///
+/// # Safety
+/// `machine` must come from [chip8_new] and must not be used afterwards
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_free(machine: *mut Machine) {
+    drop(unsafe { Box::from_raw(machine) });
+}
+
+/// Allocate `len` bytes to copy a ROM into. Free them with [chip8_dealloc]
+#[unsafe(no_mangle)]
+pub extern "C" fn chip8_alloc(len: usize) -> *mut u8 {
+    let mut buffer = vec![0u8; len].into_boxed_slice();
+    let ptr = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    ptr
+}
+
+/// Free memory allocated with [chip8_alloc]
+///
+/// # Safety
+/// `ptr` and `len` must come from a single [chip8_alloc] call
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_dealloc(ptr: *mut u8, len: usize) {
+    drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)) });
+}
+
+/// Seed the random number generator
+///
+/// # Safety
+/// `machine` must be a live machine from [chip8_new]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_seed(machine: *mut Machine, seed: u32) {
+    unsafe { &mut *machine }.vm.seed_rng(seed as u64);
+}
+
+/// Load a ROM of `len` bytes at `rom`
+///
+/// # Safety
+/// `machine` must be a live machine from [chip8_new] and `rom` must point
+/// at `len` readable bytes
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_load(machine: *mut Machine, rom: *const u8, len: usize) {
+    let rom = unsafe { std::slice::from_raw_parts(rom, len) };
+    unsafe { &mut *machine }.vm.load_program(rom);
+}
+
+/// Press (`pressed` = 1) or release (`pressed` = 0) a key
+///
+/// # Safety
+/// `machine` must be a live machine from [chip8_new]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_set_key(machine: *mut Machine, key: u8, pressed: u8) {
+    unsafe { &mut *machine }.vm.set_key(key, pressed != 0);
+}
+
+/// Run one 1/60 s frame: execute instructions and tick the timers once.
+/// Returns 0 on success and 1 if the program stopped with an error, see
+/// [chip8_error]
+///
+/// # Safety
+/// `machine` must be a live machine from [chip8_new]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_run_frame(machine: *mut Machine) -> u8 {
+    let machine = unsafe { &mut *machine };
+    if machine.error.is_some() {
+        return 1;
+    }
+    if let Err(e) = machine.vm.run_frames(1, |_| {}) {
+        machine.error = CString::new(e.to_string()).ok();
+        return 1;
+    }
+    0
+}
+
+/// Get a pointer to the display, one byte per pixel, row by row
+///
+/// # Safety
+/// `machine` must be a live machine from [chip8_new]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_display(machine: *mut Machine) -> *const u8 {
+    let display = unsafe { &*machine }.vm.display();
+    debug_assert_eq!(size_of_val(display), DISPLAY_WIDTH * DISPLAY_HEIGHT);
+    display.as_ptr().cast()
+}
+
+/// Returns 1 if the buzzer should sound, 0 otherwise
+///
+/// # Safety
+/// `machine` must be a live machine from [chip8_new]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_sound_active(machine: *mut Machine) -> u8 {
+    unsafe { &*machine }.vm.sound_active() as u8
+}
+
+/// Get a pointer to the counters of the machine, valid until the next call
+///
+/// # Safety
+/// `machine` must be a live machine from [chip8_new]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_counters(machine: *mut Machine) -> *const Counters {
+    let machine = unsafe { &mut *machine };
+    machine.counters = machine.vm.counters();
+    &machine.counters
+}
+
+/// Get the error the program stopped with as a NUL-terminated string, or
+/// null if there was none
+///
+/// # Safety
+/// `machine` must be a live machine from [chip8_new]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chip8_error(machine: *mut Machine) -> *const u8 {
+    match &unsafe { &*machine }.error {
+        Some(error) => error.as_ptr().cast(),
+        None => std::ptr::null(),
+    }
+}
@@ -0,0 +1,153 @@
+//! The waveforms the buzzer can play
+//!
+//! CHIP-8 only says when the buzzer sounds, not what it sounds like. A
+//! square wave is the traditional beep, but it is harsh at some
+//! frequencies, so softer ones can be picked:
+//! - `square`: high for part of every period (half of it by default, see
+//!   [Oscillator::set_duty_cycle]) and low for the rest
+//! - `triangle`: rising and falling linearly
+//! - `sine`: a pure tone
+//! - `noise`: random levels, changing 16 times per period, like the hiss
+//!   of old sound chips
+//!
+//! An [Oscillator] can also loop a recorded [Sound] instead, like the
+//! "bloop" of the COSMAC VIP, starting it over whenever the buzzer starts.
+//! Either way, the [AudioPattern] of an XO-CHIP program plays instead
+//! once it loaded one.
+
+use crate::interpreter::AudioPattern;
+use crate::wav::Sound;
+
+/// The names accepted by [waveform_by_name]
+pub const WAVEFORM_NAMES: [&str; 4] = ["square", "triangle", "sine", "noise"];
+
+/// The part of a period square waves are high, unless set
+pub const DEFAULT_DUTY_CYCLE: f32 = 0.5;
+/// How many random levels noise has per period
+const NOISE_STEPS: f32 = 16.0;
+
+/// The shape of the tone of the buzzer, see the module docs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Triangle,
+    Sine,
+    Noise,
+}
+
+/// Get one of [WAVEFORM_NAMES]
+pub fn waveform_by_name(name: &str) -> Result<Waveform, String> {
+    match name {
+        "square" => Ok(Waveform::Square),
+        "triangle" => Ok(Waveform::Triangle),
+        "sine" => Ok(Waveform::Sine),
+        "noise" => Ok(Waveform::Noise),
+        _ => Err(format!("unknown waveform {} (expected one of {})", name, WAVEFORM_NAMES.join(", "))),
+    }
+}
+
+/// Generates the samples of a tone of a waveform, of a looped sound or of
+/// an audio pattern
+#[derive(Debug, Clone)]
+pub struct Oscillator {
+    waveform: Waveform,
+    duty_cycle: f32,
+    /// How far into a period the next sample is, from 0 to 1
+    phase: f32,
+    /// How far the phase moves every sample
+    step: f32,
+    sample_rate: f32,
+    /// The state of the xorshift generator of noise, and its current level
+    noise: u32,
+    noise_level: f32,
+    /// The looped sound, at the sample rate of the oscillator, and the
+    /// next of its samples
+    sound: Option<Vec<f32>>,
+    position: usize,
+    /// The pattern played instead, and how far into it the next sample is,
+    /// in bits
+    pattern: Option<AudioPattern>,
+    pattern_position: f32,
+}
+
+impl Oscillator {
+    /// Create an oscillator playing a `frequency` Hz tone, sampled
+    /// `sample_rate` times per second
+    pub fn new(waveform: Waveform, frequency: f32, sample_rate: f32) -> Oscillator {
+        Oscillator {
+            waveform,
+            duty_cycle: DEFAULT_DUTY_CYCLE,
+            phase: 0.0,
+            step: frequency / sample_rate,
+            sample_rate,
+            noise: 0x2545_F491,
+            noise_level: 0.0,
+            sound: None,
+            position: 0,
+            pattern: None,
+            pattern_position: 0.0,
+        }
+    }
+
+    /// Create an oscillator looping a sound, sampled `sample_rate` times per
+    /// second
+    pub fn from_sound(sound: &Sound, sample_rate: f32) -> Oscillator {
+        let mut oscillator = Oscillator::new(Waveform::default(), 0.0, sample_rate);
+        oscillator.sound = Some(sound.resample(sample_rate as u32));
+        oscillator
+    }
+
+    /// Set the frequency of the tone in Hz, keeping its phase
+    pub fn set_frequency(&mut self, frequency: f32) {
+        self.step = frequency / self.sample_rate;
+    }
+
+    /// Set the part of every period square waves are high, from 0 to 1
+    pub fn set_duty_cycle(&mut self, duty_cycle: f32) {
+        self.duty_cycle = duty_cycle.clamp(0.0, 1.0);
+    }
+
+    /// Play an audio pattern instead of the tone or sound, or stop
+    pub fn set_pattern(&mut self, pattern: Option<AudioPattern>) {
+        self.pattern = pattern;
+    }
+
+    /// Start the tone, sound or pattern over from its beginning
+    pub fn restart(&mut self) {
+        self.phase = 0.0;
+        self.position = 0;
+        self.pattern_position = 0.0;
+    }
+
+    /// Generate the next sample, from -1 to 1. The phase carries over
+    /// between calls, so tones are continuous however they are split
+    pub fn next_sample(&mut self) -> f32 {
+        if let Some(pattern) = &self.pattern {
+            let bit = self.pattern_position as usize;
+            self.pattern_position = (self.pattern_position + pattern.sample_rate() / self.sample_rate) % 128.0;
+            return if pattern.samples[bit / 8] & (0x80 >> (bit % 8)) != 0 { 1.0 } else { -1.0 };
+        }
+        if let Some(sound) = &self.sound {
+            let Some(&sample) = sound.get(self.position) else { return 0.0 };
+            self.position = (self.position + 1) % sound.len();
+            return sample;
+        }
+        let previous = self.phase;
+        self.phase = (self.phase + self.step) % 1.0;
+        match self.waveform {
+            Waveform::Square => if self.phase < self.duty_cycle { 1.0 } else { -1.0 },
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Sine => (self.phase * std::f32::consts::TAU).sin(),
+            Waveform::Noise => {
+                if (self.phase * NOISE_STEPS).floor() != (previous * NOISE_STEPS).floor() {
+                    self.noise ^= self.noise << 13;
+                    self.noise ^= self.noise >> 17;
+                    self.noise ^= self.noise << 5;
+                    self.noise_level = self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0;
+                }
+                self.noise_level
+            }
+        }
+    }
+}
@@ -1,56 +1,191 @@
 //! A CHIP-8 interpreter
 //! 
 //! This module implements a CHIP-8 interpreter, which is a virtual machine
-//! that can run CHIP-8 programs. It uses the SDL2 library for graphics and
-//! audio handling.
-
-use std::time::{Duration, Instant};
-use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::{Canvas, Texture};
-use sdl2::video::Window;
-use sdl2::event::Event;
-use sdl2::keyboard::Scancode;
-use sdl2::{AudioSubsystem, EventPump};
+//! that can run CHIP-8 programs. The virtual machine is headless: it does not
+//! keep time, draw or play sound by itself. A frontend (see
+//! [crate::frontend]) calls [VM::step] and [VM::tick_timers] at the right
+//! rates, feeds key presses with [VM::set_key] and presents
+//! [VM::display] and [VM::sound_active].
+
+pub mod coredump;
+pub mod counters;
+pub mod coverage;
+pub mod diff;
+pub mod heatmap;
+pub mod history;
+pub mod lockstep;
+pub mod memory_dump;
+pub mod profile;
+pub mod replay;
+pub mod state;
+pub mod trace;
+pub mod watchpoint;
+
+use std::fmt;
+use std::ops::Range;
+use std::time::Instant;
+use rand::{Rng, SeedableRng};
+use rand::rngs::SmallRng;
+use crate::hash::{sha1, to_hex};
+use crate::isa::{DebugTrap, Instruction, Platform, DEBUG_TRAP_ADDRESSES};
+use crate::logging::{error, runtime_warning};
+use coredump::{Executed, Recent};
+use counters::Counters;
+use coverage::Coverage;
+use heatmap::Heatmap;
+use history::{Entry, History};
+use memory_dump::MemoryDump;
+use profile::Profile;
+use state::State;
+use trace::{Registers, Trace};
+use watchpoint::{Register, Watchpoint, WatchpointHit};
 
 /// The width of the display in pixels
 pub const DISPLAY_WIDTH: usize = 64;
 /// The height of the display in pixel
 pub const DISPLAY_HEIGHT: usize = 32;
 
-const VM_FREQUENCY: u32 = 500;
-const TIMER_FREQUENCY: u32 = 60;
+/// The size of RAM in bytes
+pub const RAM_SIZE: usize = 4096;
+
+/// The number of instructions executed per second
+pub const VM_FREQUENCY: u32 = 500;
+/// The number of times per second the timers are decremented
+pub const TIMER_FREQUENCY: u32 = 60;
+
+/// The hexadecimal digit sprites, 5 bytes each, loaded at address 0
+pub const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+/// The pitch of [AudioPattern]s until `LD PITCH, Vx` sets it, playing
+/// 4000 samples per second
+pub const DEFAULT_PITCH: u8 = 64;
 
-const AUDIO_SAMPLE_RATE: f32 = 44100.0;
-const AUDIO_TARGET_FREQUENCY: f32 = 440.0;
-const AUDIO_VOLUME: f32 = 0.1;
+/// The display, row by row. `true` pixels are lit
+pub type Display = [[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
 
-struct SquareWave {
-    phase: f32,
-    volume: f32,
+/// The sound XO-CHIP programs play while the sound timer is nonzero, once
+/// they loaded one with `LD AUDIO, [I]` (F002), instead of the buzzer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioPattern {
+    /// 128 1-bit samples, looped, the most significant bit of each byte
+    /// first
+    pub samples: [u8; 16],
+    /// The playback rate, set with `LD PITCH, Vx` (Fx3A), see
+    /// [AudioPattern::sample_rate]
+    pub pitch: u8,
 }
 
-impl AudioCallback for SquareWave {
-    type Channel = f32;
+impl AudioPattern {
+    /// Get how many samples are played per second: 4000 at the
+    /// [DEFAULT_PITCH], twice as many every 48 steps above it
+    pub fn sample_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - DEFAULT_PITCH as f32) / 48.0)
+    }
+}
 
-    fn callback(&mut self, out: &mut [f32]) {
-        for sample in out.iter_mut() {
-            self.phase = (self.phase + AUDIO_TARGET_FREQUENCY / AUDIO_SAMPLE_RATE) % 1.0;
+/// How writes into the memory the ROM was loaded to are treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RomProtection {
+    /// Writes are allowed and go unnoticed
+    #[default]
+    Off,
+    /// Writes are allowed, but the first write to every address is reported
+    Warn,
+    /// Writes stop the virtual machine with [Error::RomWrite]
+    Strict,
+}
+
+/// The bytes executed as code so far, to catch the program writing over
+/// them, see [VM::set_code_protection]
+struct CodeProtection {
+    executed: Coverage,
+    strict: bool,
+    reported: Vec<bool>,
+}
 
-            *sample = if self.phase < 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
+/// Behaviors that differ between CHIP-8 interpreters, named after the
+/// quirks of the [CHIP-8 database](https://github.com/chip-8/chip-8-database).
+/// The default follows the COSMAC VIP, except that drawing does not wait
+/// for the display refresh
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// `8xy6` and `8xyE` shift Vx in place instead of setting it to Vy
+    /// shifted
+    pub shift: bool,
+    /// `Fx55` and `Fx65` increment I by x instead of x + 1
+    pub memory_increment_by_x: bool,
+    /// `Fx55` and `Fx65` leave I unchanged
+    pub memory_leave_i_unchanged: bool,
+    /// Sprites wrap around the edges of the display instead of being clipped
+    pub wrap: bool,
+    /// `Bxnn` jumps to xnn + Vx instead of nnn + V0
+    pub jump: bool,
+    /// `DRW` waits for the next timer tick, limiting drawing to 60 sprites
+    /// per second
+    pub vblank: bool,
+    /// `8xy1`, `8xy2` and `8xy3` reset VF to 0
+    pub logic: bool,
+    /// `Fx0A` stores the key once it is released instead of as soon as it
+    /// is pressed
+    pub key_release: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            shift: false,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: false,
+            wrap: false,
+            jump: false,
+            vblank: false,
+            logic: true,
+            key_release: true,
+        }
+    }
+}
+
+impl Quirks {
+    /// The quirks platforms are usually emulated with
+    pub fn of_platform(platform: Platform) -> Quirks {
+        match platform {
+            Platform::Chip8 => Quirks::default(),
+            Platform::SuperChip => Quirks {
+                shift: true,
+                memory_leave_i_unchanged: true,
+                jump: true,
+                logic: false,
+                ..Quirks::default()
+            },
+            Platform::XoChip => Quirks {
+                wrap: true,
+                logic: false,
+                ..Quirks::default()
+            },
         }
     }
 }
 
 /// Stucture representing the state of the virtual machine
-pub struct VM<'a> {
-    running: bool,
-    ram: [u8; 4096],
+pub struct VM {
+    ram: [u8; RAM_SIZE],
     pc: usize,
     reg: [u8; 16],
     reg_i: u16,
@@ -59,37 +194,59 @@ pub struct VM<'a> {
     delay_timer: u8,
     sound_timer: u8,
     waiting_for_key: Option<usize>,
-    display: [[u8; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
-    event_pump: sdl2::EventPump,
-    canvas: Canvas<Window>,
-    texture: Texture<'a>,
-    audio_device: AudioDevice<SquareWave>,
+    waiting_for_vblank: bool,
+    keys: [bool; 16],
+    key_latching: bool,
+    /// The key whose press or release would have ended `LD Vx, K` in this
+    /// frame, see [VM::set_key_latching]
+    latched_key: Option<u8>,
+    cycles: u64,
+    /// The counters other than the instructions, which are the cycles
+    counters: Counters,
+    instructions_per_second: u32,
+    frames: u64,
+    ram_written: bool,
+    memory_dumps: Vec<MemoryDump>,
+    watchpoints: Vec<Watchpoint>,
+    /// The last watchpoint hit, until taken with [VM::take_watchpoint_hit]
+    watchpoint_hit: Option<WatchpointHit>,
+    break_on_illegal: bool,
+    /// The last illegal opcode executed, until taken with
+    /// [VM::take_illegal_opcode]
+    illegal_opcode: Option<IllegalOpcode>,
+    debug_traps: bool,
+    /// The last debug trap executed, until taken with [VM::take_trap_hit]
+    trap_hit: Option<TrapHit>,
+    history: Option<History>,
+    trace: Option<Trace>,
+    profile: Option<Profile>,
+    heatmap: Option<Heatmap>,
+    coverage: Option<Coverage>,
+    recent: Option<Recent>,
+    rom: Range<usize>,
+    rom_protection: RomProtection,
+    rom_write_reported: Vec<bool>,
+    code_protection: Option<CodeProtection>,
+    quirks: Quirks,
+    display: Display,
+    display_changed: bool,
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
+    rng: SmallRng,
 }
 
-impl<'a> VM<'a> {
-    /// Create a new virtual machine
-    pub fn new(
-        canvas: Canvas<Window>,
-        texture: Texture,
-        event_pump: EventPump,
-        audio: AudioSubsystem
-    ) -> VM {
-        let desired_spec = AudioSpecDesired {
-            freq: Some(AUDIO_SAMPLE_RATE as i32),
-            channels: Some(1),
-            samples: None,
-        };
-        let audio_device = audio
-            .open_playback(None, &desired_spec, |_spec| SquareWave {
-                phase: 0.0,
-                volume: 0.0,
-            })
-            .unwrap();
-        audio_device.resume();
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
+impl VM {
+    /// Create a new virtual machine with the font loaded. The random number
+    /// generator starts from a fixed seed, see [VM::seed_rng]
+    pub fn new() -> VM {
         let mut vm = VM {
-            running: true,
-            ram: [0; 4096],
+            ram: [0; RAM_SIZE],
             pc: 0x200,
             reg: [0; 16],
             reg_i: 0,
@@ -98,345 +255,1131 @@ impl<'a> VM<'a> {
             delay_timer: 0,
             sound_timer: 0,
             waiting_for_key: None,
-            display: [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
-            event_pump,
-            canvas,
-            texture,
-            audio_device
+            waiting_for_vblank: false,
+            keys: [false; 16],
+            key_latching: false,
+            latched_key: None,
+            cycles: 0,
+            counters: Counters::default(),
+            instructions_per_second: VM_FREQUENCY,
+            frames: 0,
+            ram_written: false,
+            memory_dumps: Vec::new(),
+            watchpoints: Vec::new(),
+            watchpoint_hit: None,
+            break_on_illegal: false,
+            illegal_opcode: None,
+            debug_traps: false,
+            trap_hit: None,
+            history: None,
+            trace: None,
+            profile: None,
+            heatmap: None,
+            coverage: None,
+            recent: None,
+            rom: 0x200..0x200,
+            rom_protection: RomProtection::Off,
+            rom_write_reported: vec![false; RAM_SIZE],
+            code_protection: None,
+            quirks: Quirks::default(),
+            display: [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+            display_changed: true,
+            audio_pattern: None,
+            pitch: DEFAULT_PITCH,
+            rng: SmallRng::seed_from_u64(0),
         };
 
-        let font_data: [u8; 80] = [
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-        ];
-        vm.ram[0x000..0x050].copy_from_slice(&font_data);
+        vm.ram[0x000..0x050].copy_from_slice(&FONT);
 
         vm
     }
 
-    /// Load a program into the virtual machine
+    /// Load a program into the virtual machine. A program with an odd size
+    /// is padded with a zero byte, so its last instruction is complete.
+    /// Panics if it is longer than the `RAM_SIZE - 0x200` bytes of RAM after
+    /// 0x200, see [check_rom]
     pub fn load_program(&mut self, program: &[u8]) {
+        let end = (0x200 + program.len().next_multiple_of(2)).min(RAM_SIZE);
         self.ram[0x200..0x200 + program.len()].copy_from_slice(program);
+        self.ram[0x200 + program.len()..end].fill(0);
+        self.rom = 0x200..end;
     }
 
-    /// Start the main loop of the virtual machine
-    pub fn mainloop(&mut self) {
-        let mut last_timer_update = Instant::now();
-        let cycle_duration = Duration::from_secs_f64(1.0 / (VM_FREQUENCY as f64));
+    /// Get the addresses of the program loaded
+    pub fn rom(&self) -> Range<usize> {
+        self.rom.clone()
+    }
 
-        while self.running {
-            let cycle_start = Instant::now();
+    /// Set how writes into the loaded ROM image are treated
+    pub fn set_rom_protection(&mut self, rom_protection: RomProtection) {
+        self.rom_protection = rom_protection;
+    }
 
-            if last_timer_update.elapsed() >= Duration::from_secs_f64(1.0 / TIMER_FREQUENCY as f64) {
-                if self.delay_timer > 0 {
-                    self.delay_timer -= 1;
-                }
-                if self.sound_timer >= 1 {
-                    self.sound_timer -= 1;
-                    self.audio_device.lock().volume = AUDIO_VOLUME;
-                } else {
-                    self.audio_device.lock().volume = 0.0;
-                }
-                last_timer_update = Instant::now();
+    /// Treat writes into bytes already executed as code like writes into
+    /// the ROM (see [RomProtection]), to catch code trampled by a store
+    /// through a bad `I`
+    pub fn set_code_protection(&mut self, protection: RomProtection) {
+        self.code_protection = (protection != RomProtection::Off).then(|| CodeProtection {
+            executed: Coverage::new(),
+            strict: protection == RomProtection::Strict,
+            reported: vec![false; RAM_SIZE],
+        });
+    }
+
+    /// Set the behaviors that differ between interpreters
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    /// Remember the key presses (or releases, see [Quirks::key_release])
+    /// happening while `LD Vx, K` is not waiting until the end of the frame,
+    /// so that one executed later in the frame still gets a tap that
+    /// started and ended between two batches of instructions
+    pub fn set_key_latching(&mut self, enabled: bool) {
+        self.key_latching = enabled;
+        self.latched_key = None;
+    }
+
+    /// Execute `instructions_per_frame` instructions per frame in
+    /// [VM::run_frames] instead of [VM_FREQUENCY] instructions per second
+    pub fn set_tickrate(&mut self, instructions_per_frame: u32) {
+        self.instructions_per_second = instructions_per_frame * TIMER_FREQUENCY;
+    }
+
+    /// Execute `instructions_per_second` instructions per second in
+    /// [VM::run_frames], see [VM::set_tickrate]
+    pub fn set_instructions_per_second(&mut self, instructions_per_second: u32) {
+        self.instructions_per_second = instructions_per_second;
+    }
+
+    /// Reseed the random number generator used by `RND`. Programs behave
+    /// identically for identical seeds and key presses
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = SmallRng::seed_from_u64(seed);
+    }
+
+    /// Take a snapshot of the current state
+    pub fn state(&self) -> State {
+        State {
+            ram: self.ram,
+            pc: self.pc as u16,
+            reg: self.reg,
+            reg_i: self.reg_i,
+            stack: self.stack,
+            sp: self.sp as u8,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            waiting_for_key: self.waiting_for_key.map(|x| x as u8),
+            cycles: self.cycles,
+            display: self.display,
+        }
+    }
+
+    /// Resume from a snapshot taken with [VM::state]
+    pub fn restore_state(&mut self, state: &State) {
+        self.ram = state.ram;
+        self.pc = state.pc as usize;
+        self.reg = state.reg;
+        self.reg_i = state.reg_i;
+        self.stack = state.stack;
+        self.sp = state.sp as usize;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.waiting_for_key = state.waiting_for_key.map(|x| x as usize);
+        self.cycles = state.cycles;
+        self.display = state.display;
+        self.display_changed = true;
+        self.waiting_for_vblank = false;
+        self.latched_key = None;
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+    }
+
+    /// Append a snapshot of a RAM region to a file every time it changes.
+    /// The current contents of the region are written right away
+    pub fn add_memory_dump(&mut self, mut dump: MemoryDump) {
+        match dump.update(&self.ram, self.cycles) {
+            Ok(()) => self.memory_dumps.push(dump),
+            Err(e) => error(format!("failed to write to {}: {}", dump.path(), e)),
+        }
+    }
+
+    /// Stop on a register changing, see [watchpoint]. Whether one was hit
+    /// is told by [VM::take_watchpoint_hit]
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Get the watchpoints, in the order they were added
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Delete the watchpoints on a register, or all of them
+    pub fn remove_watchpoints(&mut self, register: Option<Register>) {
+        self.watchpoints.retain(|watchpoint| register.is_some_and(|register| watchpoint.register != register));
+    }
+
+    /// Get the watchpoint hit by the instructions executed since the last
+    /// call, if any. The machine does not stop by itself: the caller is
+    /// expected to stop stepping it
+    pub fn take_watchpoint_hit(&mut self) -> Option<WatchpointHit> {
+        self.watchpoint_hit.take()
+    }
+
+    /// Report the opcodes executed that are not instructions of CHIP-8 (and
+    /// not the XO-CHIP audio instructions), which otherwise do nothing
+    /// silently, with [VM::take_illegal_opcode]
+    pub fn set_break_on_illegal(&mut self, enabled: bool) {
+        self.break_on_illegal = enabled;
+    }
+
+    /// Get the illegal opcode executed since the last call, if any and if
+    /// reporting them. Like with watchpoints, the machine does not stop by
+    /// itself, and the opcode was executed as doing nothing
+    pub fn take_illegal_opcode(&mut self) -> Option<IllegalOpcode> {
+        self.illegal_opcode.take()
+    }
+
+    /// Report the debug traps executed (see [DebugTrap]) with
+    /// [VM::take_trap_hit]. They otherwise do nothing, like the other
+    /// `SYS` instructions of [DEBUG_TRAP_ADDRESSES]
+    pub fn set_debug_traps(&mut self, enabled: bool) {
+        self.debug_traps = enabled;
+    }
+
+    /// Get the debug trap executed since the last call, if any and if
+    /// reporting them, with what it prints
+    pub fn take_trap_hit(&mut self) -> Option<TrapHit> {
+        self.trap_hit.take()
+    }
+
+    /// Remember the last `length` instructions executed, so that they can
+    /// be undone with [VM::step_back], see [history]
+    pub fn set_history_length(&mut self, length: usize) {
+        self.history = (length > 0).then(|| History::new(length));
+    }
+
+    /// Get the number of instructions [VM::step_back] can undo
+    pub fn history_length(&self) -> usize {
+        self.history.as_ref().map_or(0, History::len)
+    }
+
+    /// Undo the last instruction executed, returning whether there was one
+    /// in the history
+    pub fn step_back(&mut self) -> bool {
+        let Some(entry) = self.history.as_mut().and_then(History::pop) else {
+            return false;
+        };
+        self.pc = entry.pc as usize;
+        self.reg = entry.reg;
+        self.reg_i = entry.reg_i;
+        self.stack = entry.stack;
+        self.sp = entry.sp as usize;
+        self.delay_timer = entry.delay_timer;
+        self.sound_timer = entry.sound_timer;
+        self.waiting_for_key = entry.waiting_for_key;
+        self.waiting_for_vblank = entry.waiting_for_vblank;
+        self.latched_key = entry.latched_key;
+        self.cycles = entry.cycles;
+        self.audio_pattern = entry.audio_pattern;
+        self.pitch = entry.pitch;
+        let address = entry.ram_address as usize;
+        self.ram[address..address + entry.ram.len()].copy_from_slice(&entry.ram);
+        if let Some(display) = entry.display {
+            self.display = *display;
+            self.display_changed = true;
+        }
+        true
+    }
+
+    /// Undo instructions until one that hit a watchpoint, which is returned,
+    /// until `stop` tells the machine is back at a breakpoint, or until the
+    /// history is exhausted. Returns the number of instructions undone too
+    pub fn run_backwards(&mut self, stop: impl Fn(&VM) -> bool) -> (u64, Option<WatchpointHit>) {
+        let mut undone = 0;
+        while let Some(hit) = self.history.as_mut().and_then(History::last_mut).map(|entry| entry.watchpoint_hit) {
+            self.step_back();
+            undone += 1;
+            if hit.is_some() {
+                return (undone, hit);
             }
+            if stop(self) {
+                break;
+            }
+        }
+        (undone, None)
+    }
 
-            for event in self.event_pump.poll_iter() {
-                if let Event::KeyDown { scancode: Some(Scancode::Escape), .. } = event {
-                    self.running = false;
-                }
-                if let Event::KeyUp { scancode: Some(scancode), .. } = event {
-                    if let Some(register) = self.waiting_for_key {
-                        if let Some(chip8_key) = VM::scancode_to_chip8_key(scancode) {
-                            self.reg[register] = chip8_key;
-                            self.waiting_for_key = None;
-                        }
-                    }
-                }
+    /// Write every instruction executed to a [trace]
+    pub fn set_trace(&mut self, trace: Trace) {
+        self.trace = Some(trace);
+    }
+
+    /// Count the instructions executed and the time they take into a
+    /// [profile]
+    pub fn enable_profiling(&mut self) {
+        self.profile.get_or_insert_with(Profile::new);
+    }
+
+    /// Get the profile, if profiling
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Count how many times the instruction at every address is executed
+    /// into a [heatmap]
+    pub fn enable_heatmap(&mut self) {
+        self.heatmap.get_or_insert_with(Heatmap::new);
+    }
+
+    /// Get the heatmap, if counting
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.heatmap.as_ref()
+    }
+
+    /// Record which addresses are executed as code into a [coverage]
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(Coverage::new);
+    }
+
+    /// Get the coverage, if recording it
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Keep the last `length` instructions executed, for a
+    /// [core file](coredump)
+    pub fn enable_recent(&mut self, length: usize) {
+        self.recent.get_or_insert_with(|| Recent::new(length));
+    }
+
+    /// Get the last instructions executed, the oldest first, if keeping them
+    pub fn recent(&self) -> Option<&Recent> {
+        self.recent.as_ref()
+    }
+
+    /// Execute a single instruction. Does nothing while an `LD Vx, K`
+    /// instruction is waiting for a key, or while `DRW` is
+    /// waiting for the next timer tick (see [Quirks::vblank])
+    pub fn step(&mut self) -> Result<(), Error> {
+        if self.waiting_for_key.is_some() || self.waiting_for_vblank {
+            return Ok(());
+        }
+        let fetched = self.fetch()?;
+        if !self.is_debugging() {
+            self.execute(fetched)?;
+        } else {
+            self.execute_debugged(fetched)?;
+        }
+        self.cycles += 1;
+        if self.ram_written {
+            self.update_memory_dumps();
+        }
+        Ok(())
+    }
+
+    /// Decrement the delay and sound timers. Must be called
+    /// [TIMER_FREQUENCY] times per second
+    pub fn tick_timers(&mut self) {
+        self.waiting_for_vblank = false;
+        self.latched_key = None;
+        self.counters.frames += 1;
+        let ticking = self.delay_timer > 0 || self.sound_timer > 0;
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        if ticking {
+            self.write_trace_event(&format!("timers tick, DT={:02X} ST={:02X}", self.delay_timer, self.sound_timer));
+        }
+        if let Some(trace) = &mut self.trace && let Err(e) = trace.end_frame() {
+            error(format!("failed to write the trace, no longer tracing: {}", e));
+            self.trace = None;
+        }
+    }
+
+    /// Run `n` frames of 1/[TIMER_FREQUENCY] s: execute the instructions
+    /// of the frame, tick the timers and call `on_frame`, which may for
+    /// example present the display or press keys for the next frame. When
+    /// the instructions per second are not a multiple of the frame rate,
+    /// frames execute one more instruction now and then to keep the pace
+    pub fn run_frames(&mut self, n: u64, mut on_frame: impl FnMut(&mut VM)) -> Result<(), Error> {
+        let rate = self.instructions_per_second as u64;
+        let timer_frequency = TIMER_FREQUENCY as u64;
+        for _ in 0..n {
+            let start = self.frames * rate / timer_frequency;
+            let end = (self.frames + 1) * rate / timer_frequency;
+            for _ in start..end {
+                self.step()?;
             }
+            self.tick_timers();
+            self.frames += 1;
+            on_frame(self);
+        }
+        Ok(())
+    }
 
-            // Do not process opcodes while waiting for a key
-            // Is not inside the upper loop because there may be no events
-            if self.waiting_for_key.is_some() {
-                continue;
+    /// Get the number of frames run by [VM::run_frames] so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Press or release a key of the hexadecimal keypad. `LD Vx, K` gets
+    /// the key on release or on press, see [Quirks::key_release]
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        let key = key & 0xF;
+        let changed = self.keys[key as usize] != pressed;
+        self.keys[key as usize] = pressed;
+        let event = format!("key {:X} {}", key, if pressed { "down" } else { "up" });
+        if pressed == self.quirks.key_release {
+            if changed {
+                self.write_trace_event(&event);
             }
+            return;
+        }
+        match self.waiting_for_key.take() {
+            Some(register) => {
+                self.reg[register] = key;
+                self.write_trace_event(&format!("{}, stored in V{:X}", event, register));
+                return;
+            }
+            None if self.key_latching => self.latched_key = Some(key),
+            None => {}
+        }
+        if changed {
+            self.write_trace_event(&event);
+        }
+    }
 
-            let fetched = self.fetch();
-            self.execute(fetched);
+    /// Get which keys of the keypad are pressed
+    pub fn keys(&self) -> &[bool; 16] {
+        &self.keys
+    }
+
+    /// Check whether `LD Vx, K` is waiting for a key press
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.waiting_for_key.is_some()
+    }
+
+    /// Get the display
+    pub fn display(&self) -> &Display {
+        &self.display
+    }
 
-            let elapsed = cycle_start.elapsed();
-            if elapsed < cycle_duration {
-                std::thread::sleep(cycle_duration - elapsed);
+    /// Check whether the display changed since the last call
+    pub fn display_changed(&mut self) -> bool {
+        std::mem::take(&mut self.display_changed)
+    }
+
+    /// Check whether the buzzer should sound
+    pub fn sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    /// Get the sound the buzzer should play, if the program loaded one, see
+    /// [AudioPattern]
+    pub fn audio_pattern(&self) -> Option<AudioPattern> {
+        self.audio_pattern.map(|samples| AudioPattern { samples, pitch: self.pitch })
+    }
+
+    /// Get the registers an instruction may change, see [trace]
+    pub fn registers(&self) -> Registers {
+        Registers {
+            reg: self.reg,
+            reg_i: self.reg_i,
+            sp: self.sp as u8,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        }
+    }
+
+    /// Get the number of instructions executed so far
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Get what the program did since it started, see [counters]
+    pub fn counters(&self) -> Counters {
+        Counters { instructions: self.cycles, ..self.counters }
+    }
+
+    /// Get the address of the next instruction
+    pub fn pc(&self) -> u16 {
+        self.pc as u16
+    }
+
+    /// Get the return addresses of the subroutines being run, the
+    /// innermost last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp]
+    }
+
+    /// Get the RAM
+    pub fn ram(&self) -> &[u8; RAM_SIZE] {
+        &self.ram
+    }
+
+    /// Change a V register, I or a timer, truncating the value to the size
+    /// of the register
+    pub fn set_register(&mut self, register: Register, value: u16) {
+        match register {
+            Register::V(x) => self.reg[x as usize & 0xF] = value as u8,
+            Register::I => self.reg_i = value,
+            Register::DelayTimer => self.delay_timer = value as u8,
+            Register::SoundTimer => self.sound_timer = value as u8,
+        }
+    }
+
+    /// Change a byte of RAM, even in a protected ROM (see
+    /// [VM::set_rom_protection]). Addresses past the RAM are ignored
+    pub fn poke(&mut self, address: u16, value: u8) {
+        if let Some(byte) = self.ram.get_mut(address as usize) {
+            *byte = value;
+            self.update_memory_dumps();
+        }
+    }
+
+    fn read_ram(&self, address: usize) -> Result<u8, Error> {
+        self.ram.get(address).copied().ok_or(Error::MemoryOutOfBounds { address, pc: self.pc - 2 })
+    }
+
+    fn write_ram(&mut self, address: usize, value: u8) -> Result<(), Error> {
+        if address >= RAM_SIZE {
+            return Err(Error::MemoryOutOfBounds { address, pc: self.pc - 2 });
+        }
+        if self.rom.contains(&address) {
+            self.check_rom_write(address)?;
+        }
+        self.check_code_write(address)?;
+        self.ram[address] = value;
+        self.ram_written = true;
+        Ok(())
+    }
+
+    fn check_rom_write(&mut self, address: usize) -> Result<(), Error> {
+        let pc = self.pc - 2;
+        match self.rom_protection {
+            RomProtection::Off => {}
+            RomProtection::Warn => {
+                if !self.rom_write_reported[address] {
+                    self.rom_write_reported[address] = true;
+                    runtime_warning(format!("write to ROM address 0x{:03X}", address), pc);
+                }
             }
+            RomProtection::Strict => return Err(Error::RomWrite { address, pc }),
         }
+        Ok(())
     }
 
-    fn push(&mut self, value: u16) {
-        self.stack[self.sp] = value;
+    fn check_code_write(&mut self, address: usize) -> Result<(), Error> {
+        let pc = self.pc - 2;
+        let Some(protection) = &mut self.code_protection else {
+            return Ok(());
+        };
+        if !protection.executed.is_executed(address) {
+            return Ok(());
+        }
+        if protection.strict {
+            return Err(Error::CodeWrite { address, pc });
+        }
+        if !protection.reported[address] {
+            protection.reported[address] = true;
+            runtime_warning(format!("write to 0x{:03X}, which was executed as code", address), pc);
+        }
+        Ok(())
+    }
+
+    fn update_memory_dumps(&mut self) {
+        let (ram, cycles) = (&self.ram, self.cycles);
+        self.memory_dumps.retain_mut(|dump| match dump.update(ram, cycles) {
+            Ok(()) => true,
+            Err(e) => {
+                error(format!("failed to write to {}, no longer dumping: {}", dump.path(), e));
+                false
+            }
+        });
+        self.ram_written = false;
+    }
+
+    fn push(&mut self, value: u16) -> Result<(), Error> {
+        let slot = self.stack.get_mut(self.sp).ok_or(Error::StackOverflow { pc: self.pc - 2 })?;
+        *slot = value;
         self.sp += 1;
+        Ok(())
     }
 
-    fn pop(&mut self) -> u16 {
+    fn pop(&mut self) -> Result<u16, Error> {
+        let value = self.sp.checked_sub(1)
+            .and_then(|sp| self.stack.get(sp))
+            .copied()
+            .ok_or(Error::StackUnderflow { pc: self.pc - 2 })?;
         self.sp -= 1;
-        self.stack[self.sp]
+        Ok(value)
     }
 
-    fn fetch(&mut self) -> u16 {
+    fn fetch(&mut self) -> Result<u16, Error> {
+        if self.pc + 1 >= RAM_SIZE {
+            return Err(Error::PcOutOfBounds { pc: self.pc });
+        }
         let high = self.ram[self.pc] as u16;
         let low = self.ram[self.pc + 1] as u16;
         self.pc += 2;
-        (high << 8) | low
+        Ok((high << 8) | low)
+    }
+
+    /// Execute the XO-CHIP audio instructions, which are the only
+    /// instructions past CHIP-8 that are supported. Other opcodes do
+    /// nothing, see [VM::set_break_on_illegal]
+    fn execute_audio(&mut self, opcode: u16) -> Result<(), Error> {
+        if opcode == 0xF002 {
+            let mut samples = [0; 16];
+            for (i, sample) in samples.iter_mut().enumerate() {
+                *sample = self.read_ram(self.reg_i as usize + i)?;
+            }
+            self.audio_pattern = Some(samples);
+        } else if opcode & 0xF0FF == 0xF03A {
+            self.pitch = self.reg[(opcode as usize >> 8) & 0xF];
+        } else if self.break_on_illegal {
+            self.illegal_opcode = Some(IllegalOpcode { opcode, pc: (self.pc - 2) as u16 });
+        }
+        Ok(())
     }
 
     fn clear_screen(&mut self) {
-        self.display = [[0; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
-        self.render_display();
+        self.display = [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        self.display_changed = true;
     }
 
-    fn draw_sprite(&mut self, x: u8, y: u8, n: u8) {
+    fn draw_sprite(&mut self, x: u8, y: u8, n: u8) -> Result<(), Error> {
         self.reg[0xF] = 0;
         let x = x % DISPLAY_WIDTH as u8;
         let y = y % DISPLAY_HEIGHT as u8;
         for byte in 0..n {
-            let y_coord = y as usize + byte as usize;
+            let mut y_coord = y as usize + byte as usize;
             if y_coord >= DISPLAY_HEIGHT {
-                break;
+                if !self.quirks.wrap {
+                    break;
+                }
+                y_coord %= DISPLAY_HEIGHT;
             }
-            let sprite_byte = self.ram[self.reg_i as usize + byte as usize];
+            let sprite_byte = self.read_ram(self.reg_i as usize + byte as usize)?;
             for bit in 0..8 {
-                let x_coord = x as usize + bit;
+                let mut x_coord = x as usize + bit;
                 if x_coord >= DISPLAY_WIDTH {
-                    break;
+                    if !self.quirks.wrap {
+                        break;
+                    }
+                    x_coord %= DISPLAY_WIDTH;
                 }
-                let sprite_pixel = match (sprite_byte >> (7 - bit)) & 1 {
-                    0 => 0,
-                    1 => 0xFF,
-                    _ => unreachable!()
-                };
+                let sprite_pixel = (sprite_byte >> (7 - bit)) & 1 == 1;
                 let screen_pixel = &mut self.display[y_coord][x_coord];
-                if *screen_pixel == 1 && sprite_pixel == 1 {
+                if *screen_pixel && sprite_pixel {
                     self.reg[0xF] = 1;
                 }
                 *screen_pixel ^= sprite_pixel;
             }
         }
-        self.render_display();
-    }
-
-    fn render_display(&mut self) {
-        self.canvas.set_draw_color(Color::BLACK);
-        self.canvas.clear();
-
-        let (canvas_width, canvas_height) = self.canvas.output_size().unwrap();
-        let dest_rect = Rect::new(0, 0, canvas_width, canvas_height);
-
-        self.texture
-            .update(None, self.display.as_flattened(), DISPLAY_WIDTH)
-            .unwrap();
-        self.canvas
-            .copy(&self.texture, None, Some(dest_rect))
-            .unwrap();
-        self.canvas.present();
-    }
-
-    // 1 2 3 C        1 2 3 4
-    // 4 5 6 D  <==>  Q W E R
-    // 7 8 9 E  <==>  A S D F
-    // A 0 B F        Z X C V
-    fn scancode_to_chip8_key(scancode: Scancode) -> Option<u8> {
-        match scancode {
-            Scancode::Num1 => Some(0x1),
-            Scancode::Num2 => Some(0x2),
-            Scancode::Num3 => Some(0x3),
-            Scancode::Num4 => Some(0xC),
-            Scancode::Q => Some(0x4),
-            Scancode::W => Some(0x5),
-            Scancode::E => Some(0x6),
-            Scancode::R => Some(0xD),
-            Scancode::A => Some(0x7),
-            Scancode::S => Some(0x8),
-            Scancode::D => Some(0x9),
-            Scancode::F => Some(0xE),
-            Scancode::Z => Some(0xA),
-            Scancode::X => Some(0x0),
-            Scancode::C => Some(0xB),
-            Scancode::V => Some(0xF),
-            _ => None,
-        }
-    }
-
-    fn chip8_key_to_scancode(chip8_key: u8) -> Scancode {
-        match chip8_key {
-            0x1 => Scancode::Num1,
-            0x2 => Scancode::Num2,
-            0x3 => Scancode::Num3,
-            0xC => Scancode::Num4,
-            0x4 => Scancode::Q,
-            0x5 => Scancode::W,
-            0x6 => Scancode::E,
-            0xD => Scancode::R,
-            0x7 => Scancode::A,
-            0x8 => Scancode::S,
-            0x9 => Scancode::D,
-            0xE => Scancode::F,
-            0xA => Scancode::Z,
-            0x0 => Scancode::X,
-            0xB => Scancode::C,
-            0xF => Scancode::V,
-            _ => panic!("Invalid CHIP-8 key: {}", chip8_key),
-        }
+        self.display_changed = true;
+        self.waiting_for_vblank = self.quirks.vblank;
+        self.counters.sprites_drawn += 1;
+        self.counters.collisions += self.reg[0xF] as u64;
+        Ok(())
     }
 
     fn is_key_pressed(&self, chip8_key: u8) -> bool {
-        let keyboard_state = self.event_pump.keyboard_state();
-        keyboard_state.is_scancode_pressed(VM::chip8_key_to_scancode(chip8_key))
-    }
-
-    fn execute(&mut self, opcode: u16) {
-        let nnn = opcode & 0x0FFF;
-        let nn = (opcode & 0x00FF) as u8;
-        let n = (opcode & 0x000F) as u8;
-        let x = ((opcode & 0x0F00) >> 8) as usize;
-        let y = ((opcode & 0x00F0) >> 4) as usize;
-
-        match opcode & 0xF000 {
-            0x0000 => match opcode {
-                0x00E0 => self.clear_screen(), // 00E0
-                0x00EE => self.pc = self.pop() as usize, // 00EE
-                _ => panic!("0x0NNN (execute native subroutine) called!") // 0nnn
-            },
-            0x1000 => self.pc = nnn as usize, // 1nnn
-            0x2000 => { // 2nnn
-                self.push(self.pc as u16);
-                self.pc = nnn as usize;
+        self.keys[(chip8_key & 0xF) as usize]
+    }
+
+    /// Check whether instructions are saved into the history, watched,
+    /// traced, profiled, counted, covered, kept or protected, which
+    /// [VM::execute_debugged] does
+    fn is_debugging(&self) -> bool {
+        !self.watchpoints.is_empty() || self.history.is_some() || self.trace.is_some()
+            || self.profile.is_some() || self.heatmap.is_some() || self.coverage.is_some()
+            || self.recent.is_some() || self.code_protection.is_some()
+    }
+
+    /// Execute an instruction, saving what it changes into the history,
+    /// checking whether it hit a watchpoint, tracing, profiling, counting
+    /// it, recording its coverage, keeping it with the last ones and
+    /// protecting its bytes from being written over
+    fn execute_debugged(&mut self, opcode: u16) -> Result<(), Error> {
+        let pc = (self.pc - 2) as u16;
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.add(pc);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.add(pc);
+        }
+        if let Some(protection) = &mut self.code_protection {
+            protection.executed.add(pc);
+        }
+        let entry = self.history.is_some().then(|| self.history_entry(pc, opcode));
+        if let (Some(history), Some(entry)) = (&mut self.history, entry) {
+            history.push(entry);
+        }
+        let registers = self.trace.is_some().then(|| self.registers());
+        let before: Vec<u16> = self.watchpoints.iter().map(|watchpoint| self.register(watchpoint.register)).collect();
+        let start = self.profile.is_some().then(Instant::now);
+        let result = self.execute(opcode);
+        // Kept even if it failed, as the instruction a core file is about
+        if self.recent.is_some() {
+            let executed = Executed { cycles: self.cycles, pc, opcode, registers: self.registers() };
+            if let Some(recent) = &mut self.recent {
+                recent.push(executed);
             }
-            0x3000 => { // 3xnn
-                if self.reg[x] == nn {
-                    self.pc += 2;
-                }
+        }
+        result?;
+        if let (Some(profile), Some(start)) = (&mut self.profile, start) {
+            profile.add(pc, opcode, start.elapsed());
+        }
+        let hit = self.watchpoints.iter().zip(before).find_map(|(watchpoint, old)| {
+            let new = self.register(watchpoint.register);
+            let hit = new != old && watchpoint.value.is_none_or(|value| value == new);
+            hit.then_some(WatchpointHit { register: watchpoint.register, old, new, pc })
+        });
+        if hit.is_some() {
+            self.watchpoint_hit = hit;
+            if let Some(entry) = self.history.as_mut().and_then(History::last_mut) {
+                entry.watchpoint_hit = hit;
             }
-            0x4000 => {
-                if self.reg[x] != nn { // 4xnn
-                    self.pc += 2;
-                }
+        }
+        if let Some(registers) = registers {
+            self.write_trace(pc, opcode, &registers);
+        }
+        Ok(())
+    }
+
+    /// Write the line of trace of an instruction, given the registers
+    /// before it
+    fn write_trace(&mut self, pc: u16, opcode: u16, before: &Registers) {
+        let (after, cycles) = (self.registers(), self.cycles);
+        let Some(trace) = &mut self.trace else {
+            return;
+        };
+        if !trace.traces(pc, opcode) || !trace.next_line_written() {
+            return;
+        }
+        if let Err(e) = trace.write(cycles, pc, opcode, before, &after) {
+            error(format!("failed to write the trace, no longer tracing: {}", e));
+            self.trace = None;
+        }
+    }
+
+    /// Write a key or timer event between the lines of trace, see
+    /// [Trace::write_event]
+    fn write_trace_event(&mut self, event: &str) {
+        let Some(trace) = &mut self.trace else {
+            return;
+        };
+        if let Err(e) = trace.write_event(self.cycles, event) {
+            error(format!("failed to write the trace, no longer tracing: {}", e));
+            self.trace = None;
+        }
+    }
+
+    /// Report a debug trap just executed, with what it prints
+    fn trap_hit(&self, trap: DebugTrap) -> TrapHit {
+        let output = match trap {
+            DebugTrap::Break => String::new(),
+            DebugTrap::PrintRegisters => {
+                let registers: Vec<String> = self.reg.iter().enumerate()
+                    .map(|(x, value)| format!("V{:X}={:02X}", x, value))
+                    .collect();
+                format!("{} I=0x{:03X}", registers.join(" "), self.reg_i)
             }
-            0x5000 => {
-                if self.reg[x] == self.reg[y] { // 5xy0
-                    self.pc += 2;
-                }
+            DebugTrap::PrintMemory => {
+                let start = (self.reg_i as usize).min(RAM_SIZE);
+                let bytes: Vec<String> = self.ram[start..(start + 16).min(RAM_SIZE)].iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect();
+                format!("[0x{:03X}] {}", self.reg_i, bytes.join(" "))
             }
-            0x6000 => self.reg[x] = nn, // 6xnn
-            0x7000 => self.reg[x] = self.reg[x].wrapping_add(nn), // 7xnn
-            0x8000 => match opcode & 0x000F {
-                0x0 => self.reg[x] = self.reg[y], // 8xy0
-                0x1 => { // 8xy1
-                    self.reg[x] |= self.reg[y];
-                    self.reg[0xF] = 0;
-                }
-                0x2 => { // 8xy2
-                    self.reg[x] &= self.reg[y];
-                    self.reg[0xF] = 0;
-                }
-                0x3 => { // 8xy3
-                    self.reg[x] ^= self.reg[y];
-                    self.reg[0xF] = 0;
-                }
-                0x4 => { // 8xy4
-                    let (result, carry) = self.reg[x].overflowing_add(self.reg[y]);
-                    self.reg[x] = result;
-                    self.reg[0xF] = if carry { 1 } else { 0 };
-                }
-                0x5 => { // 8xy5
-                    let (result, borrow) = self.reg[x].overflowing_sub(self.reg[y]);
-                    self.reg[x] = result;
-                    self.reg[0xF] = if borrow { 0 } else { 1 };
-                }
-                0x6 => { // 8xy6
-                    self.reg[x] = self.reg[y];
-                    if x == 0xF {
-                        self.reg[x] &= 0x1;
-                    }
-                    else {
-                        self.reg[0xF] = self.reg[x] & 0x1;
-                        self.reg[x] >>= 1;
-                    }
-                }
-                0x7 => { // 8xy7
-                    let (result, borrow) = self.reg[y].overflowing_sub(self.reg[x]);
-                    self.reg[x] = result;
-                    self.reg[0xF] = if borrow { 0 } else { 1 };
-                }
-                0xE => { // 8xyE
-                    self.reg[x] = self.reg[y];
-                    self.reg[0xF] = (self.reg[x] & 0x80) >> 7;
-                    if x != 0xF {
-                        self.reg[x] <<= 1;
-                    }
-                }
-                _ => {}
-            },
-            0x9000 => { // 9xy0
-                if self.reg[x] != self.reg[y] {
-                    self.pc += 2;
+        };
+        TrapHit { trap, pc: (self.pc - 2) as u16, output }
+    }
+
+    /// Save what the instruction at `pc` is about to change
+    fn history_entry(&self, pc: u16, opcode: u16) -> Entry {
+        let instruction = Instruction::decode(opcode);
+        let written = match instruction {
+            Some(Instruction::LdBVx(_)) => 3,
+            Some(Instruction::LdMemVx(x)) => x as usize + 1,
+            _ => 0,
+        };
+        let ram_address = (self.reg_i as usize).min(RAM_SIZE);
+        let ram_end = (ram_address + written).min(RAM_SIZE);
+        let draws = matches!(instruction, Some(Instruction::Cls | Instruction::Drw(..)));
+        Entry {
+            pc,
+            reg: self.reg,
+            reg_i: self.reg_i,
+            stack: self.stack,
+            sp: self.sp as u8,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            waiting_for_key: self.waiting_for_key,
+            waiting_for_vblank: self.waiting_for_vblank,
+            latched_key: self.latched_key,
+            cycles: self.cycles,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            ram_address: ram_address as u16,
+            ram: self.ram[ram_address..ram_end].to_vec(),
+            display: draws.then(|| Box::new(self.display)),
+            watchpoint_hit: None,
+        }
+    }
+
+    /// Get the value of a V register, I or a timer
+    pub fn register(&self, register: Register) -> u16 {
+        match register {
+            Register::V(x) => self.reg[x as usize & 0xF] as u16,
+            Register::I => self.reg_i,
+            Register::DelayTimer => self.delay_timer as u16,
+            Register::SoundTimer => self.sound_timer as u16,
+        }
+    }
+
+    fn execute(&mut self, opcode: u16) -> Result<(), Error> {
+        let Some(instruction) = Instruction::decode(opcode) else {
+            return self.execute_audio(opcode);
+        };
+
+        match instruction {
+            Instruction::Cls => self.clear_screen(),
+            Instruction::Ret => self.pc = self.pop()? as usize,
+            Instruction::Sys(address) if DEBUG_TRAP_ADDRESSES.contains(&address) => {
+                if self.debug_traps && let Some(trap) = DebugTrap::of_address(address) {
+                    self.trap_hit = Some(self.trap_hit(trap));
                 }
             }
-            0xA000 => self.reg_i = nnn, // Annn
-            0xB000 => self.pc = (nnn + self.reg[0] as u16) as usize, // Bnnn
-            0xC000 => self.reg[x] = rand::random::<u8>() & nn, // Cxnn
-            0xD000 => self.draw_sprite(self.reg[x], self.reg[y], n), // Dxyn
-            0xE000 => match opcode & 0x00FF {
-                0x9E => { // Ex9E
-                    if self.is_key_pressed(self.reg[x]) {
-                        self.pc += 2;
-                    }
-                }
-                0xA1 => { // ExA1
-                    if !self.is_key_pressed(self.reg[x]) {
-                        self.pc += 2;
-                    }
-                }
-                _ => {}
+            Instruction::Sys(address) => {
+                return Err(Error::NativeSubroutine { address, pc: self.pc - 2 });
+            }
+            Instruction::Jp(nnn) => self.pc = nnn as usize,
+            Instruction::Call(nnn) => {
+                self.push(self.pc as u16)?;
+                self.pc = nnn as usize;
+            }
+            Instruction::SeByte(x, kk) => self.skip_if(self.reg[x as usize] == kk),
+            Instruction::SneByte(x, kk) => self.skip_if(self.reg[x as usize] != kk),
+            Instruction::SeReg(x, y) => self.skip_if(self.reg[x as usize] == self.reg[y as usize]),
+            Instruction::LdByte(x, kk) => self.reg[x as usize] = kk,
+            Instruction::AddByte(x, kk) => {
+                self.reg[x as usize] = self.reg[x as usize].wrapping_add(kk);
+            }
+            Instruction::LdReg(x, y) => self.reg[x as usize] = self.reg[y as usize],
+            Instruction::Or(x, y) => {
+                self.reg[x as usize] |= self.reg[y as usize];
+                self.reset_flag_after_logic();
+            }
+            Instruction::And(x, y) => {
+                self.reg[x as usize] &= self.reg[y as usize];
+                self.reset_flag_after_logic();
+            }
+            Instruction::Xor(x, y) => {
+                self.reg[x as usize] ^= self.reg[y as usize];
+                self.reset_flag_after_logic();
+            }
+            Instruction::AddReg(x, y) => {
+                let (result, carry) = self.reg[x as usize].overflowing_add(self.reg[y as usize]);
+                self.reg[x as usize] = result;
+                self.reg[0xF] = if carry { 1 } else { 0 };
+            }
+            Instruction::Sub(x, y) => {
+                let (result, borrow) = self.reg[x as usize].overflowing_sub(self.reg[y as usize]);
+                self.reg[x as usize] = result;
+                self.reg[0xF] = if borrow { 0 } else { 1 };
+            }
+            Instruction::Shr(x, y) => {
+                let value = self.shift_source(x, y);
+                self.reg[x as usize] = value >> 1;
+                self.reg[0xF] = value & 0x1;
+            }
+            Instruction::Subn(x, y) => {
+                let (result, borrow) = self.reg[y as usize].overflowing_sub(self.reg[x as usize]);
+                self.reg[x as usize] = result;
+                self.reg[0xF] = if borrow { 0 } else { 1 };
+            }
+            Instruction::Shl(x, y) => {
+                let value = self.shift_source(x, y);
+                self.reg[x as usize] = value << 1;
+                self.reg[0xF] = (value & 0x80) >> 7;
+            }
+            Instruction::SneReg(x, y) => self.skip_if(self.reg[x as usize] != self.reg[y as usize]),
+            Instruction::LdI(nnn) => self.reg_i = nnn,
+            Instruction::JpV0(nnn) => {
+                let offset_register = if self.quirks.jump { (nnn >> 8) as usize } else { 0 };
+                self.pc = (nnn + self.reg[offset_register] as u16) as usize;
+            }
+            Instruction::Rnd(x, kk) => self.reg[x as usize] = self.rng.random::<u8>() & kk,
+            Instruction::Drw(x, y, n) => self.draw_sprite(self.reg[x as usize], self.reg[y as usize], n)?,
+            Instruction::Skp(x) => self.skip_if(self.is_key_pressed(self.reg[x as usize])),
+            Instruction::Sknp(x) => self.skip_if(!self.is_key_pressed(self.reg[x as usize])),
+            Instruction::LdVxDt(x) => self.reg[x as usize] = self.delay_timer,
+            Instruction::LdVxK(x) => match self.latched_key.take() {
+                Some(key) => self.reg[x as usize] = key,
+                None => self.waiting_for_key = Some(x as usize),
             },
-            0xF000 => match opcode & 0x00FF {
-                0x07 => self.reg[x] = self.delay_timer, // Fx07
-                0x0A => self.waiting_for_key = Some(x), // Fx0A
-                0x15 => self.delay_timer = self.reg[x], // Fx15
-                0x18 => self.sound_timer = self.reg[x], // Fx18
-                0x1E => self.reg_i = self.reg_i.wrapping_add(self.reg[x] as u16), // Fx1E
-                0x29 => self.reg_i = (self.reg[x] as u16) * 5, // Fx29
-                0x33 => { // Fx33
-                    self.ram[self.reg_i as usize] = self.reg[x] / 100;
-                    self.ram[self.reg_i as usize + 1] = (self.reg[x] / 10) % 10;
-                    self.ram[self.reg_i as usize + 2] = self.reg[x] % 10;
+            Instruction::LdDtVx(x) => self.delay_timer = self.reg[x as usize],
+            Instruction::LdStVx(x) => {
+                if self.sound_timer == 0 && self.reg[x as usize] > 0 {
+                    self.counters.sound_activations += 1;
                 }
-                0x55 => { // Fx55
-                    for i in 0..=x {
-                        self.ram[self.reg_i as usize] = self.reg[i];
-                        self.reg_i = self.reg_i.wrapping_add(1);
-                    }
+                self.sound_timer = self.reg[x as usize];
+            }
+            Instruction::AddIVx(x) => self.reg_i = self.reg_i.wrapping_add(self.reg[x as usize] as u16),
+            Instruction::LdFVx(x) => self.reg_i = (self.reg[x as usize] as u16) * 5,
+            Instruction::LdBVx(x) => {
+                let value = self.reg[x as usize];
+                let address = self.reg_i as usize;
+                self.write_ram(address, value / 100)?;
+                self.write_ram(address + 1, (value / 10) % 10)?;
+                self.write_ram(address + 2, value % 10)?;
+            }
+            Instruction::LdMemVx(x) => {
+                for i in 0..=x as usize {
+                    self.write_ram(self.reg_i as usize + i, self.reg[i])?;
                 }
-                0x65 => { // Fx65
-                    for i in 0..=x {
-                        self.reg[i] = self.ram[self.reg_i as usize];
-                        self.reg_i = self.reg_i.wrapping_add(1);
-                    }
+                self.increment_i_after_memory(x);
+            }
+            Instruction::LdVxMem(x) => {
+                for i in 0..=x as usize {
+                    self.reg[i] = self.read_ram(self.reg_i as usize + i)?;
                 }
-                _ => {}
-            },
-            _ => {}
+                self.increment_i_after_memory(x);
+            }
+        }
+        Ok(())
+    }
+
+    fn reset_flag_after_logic(&mut self) {
+        if self.quirks.logic {
+            self.reg[0xF] = 0;
+        }
+    }
+
+    /// The value `8xy6` and `8xyE` shift (see [Quirks::shift])
+    fn shift_source(&self, x: u8, y: u8) -> u8 {
+        if self.quirks.shift { self.reg[x as usize] } else { self.reg[y as usize] }
+    }
+
+    fn increment_i_after_memory(&mut self, x: u8) {
+        if self.quirks.memory_leave_i_unchanged {
+            return;
+        }
+        let increment = if self.quirks.memory_increment_by_x { x } else { x + 1 };
+        self.reg_i = self.reg_i.wrapping_add(increment as u16);
+    }
+
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            self.pc += 2;
+        }
+    }
+}
+
+/// File signatures of common formats that are not CHIP-8 programs. The
+/// shortest ones are 3 bytes long, as 2 bytes are a valid instruction
+const FOREIGN_SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x7FELF", "an ELF executable"),
+    (b"\x89PNG\r\n\x1A\n", "a PNG image"),
+    (b"GIF87a", "a GIF image"),
+    (b"GIF89a", "a GIF image"),
+    (b"\xFF\xD8\xFF", "a JPEG image"),
+    (b"PK\x03\x04", "a ZIP archive"),
+    (b"\x1F\x8B\x08", "a gzip archive"),
+    (b"%PDF-", "a PDF document"),
+];
+
+/// Draw a display as text, one line per row, lit pixels as `█` and unlit
+/// ones as spaces
+pub fn display_to_text(display: &Display) -> String {
+    display.iter()
+        .map(|row| row.iter().map(|&pixel| if pixel { '█' } else { ' ' }).collect::<String>() + "\n")
+        .collect()
+}
+
+/// Hash a display, to check it against a known one: the SHA-1, in
+/// lowercase hexadecimal, of its rows packed top to bottom, 8 pixels per
+/// byte with the leftmost one in the highest bit, lit pixels being 1
+pub fn display_hash(display: &Display) -> String {
+    let packed: Vec<u8> = display.iter()
+        .flat_map(|row| row.chunks(8))
+        .map(|pixels| pixels.iter().fold(0, |byte, &pixel| byte << 1 | pixel as u8))
+        .collect();
+    to_hex(&sha1(&packed))
+}
+
+/// Parse a decimal or `0x`-prefixed hexadecimal number, as taken by
+/// command line options and debugger commands
+pub fn parse_number(s: &str) -> Option<u16> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Parse an address of RAM, see [parse_number]
+pub fn parse_address(s: &str) -> Option<u16> {
+    parse_number(s).filter(|&address| (address as usize) < RAM_SIZE)
+}
+
+/// Check that a file can be loaded with [VM::load_program] and looks like a
+/// CHIP-8 program. Files with an odd size are accepted, but probably
+/// truncated
+pub fn check_rom(rom: &[u8]) -> Result<(), RomError> {
+    if rom.is_empty() {
+        return Err(RomError::Empty);
+    }
+    if let Some((_, format)) = FOREIGN_SIGNATURES.iter().find(|(signature, _)| rom.starts_with(signature)) {
+        return Err(RomError::Foreign { format });
+    }
+    if rom.len() > RAM_SIZE - 0x200 {
+        return Err(RomError::TooLarge { size: rom.len() });
+    }
+    // Programs contain zeros and sprites, so they are never plain text
+    let is_text = rom.iter().all(|&c| c.is_ascii_graphic() || c.is_ascii_whitespace());
+    if is_text && rom.contains(&b'\n') {
+        return Err(RomError::Foreign { format: "a text file (maybe assembly that was not assembled?)" });
+    }
+    Ok(())
+}
+
+/// An error that prevents a file from being loaded as a program
+#[derive(Debug)]
+pub enum RomError {
+    /// The file is empty
+    Empty,
+    /// The file does not fit in RAM after 0x200
+    TooLarge {
+        size: usize
+    },
+    /// The file is in another, well-known format
+    Foreign {
+        format: &'static str
+    },
+}
+
+impl std::error::Error for RomError {}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::Empty => write!(f, "the file is empty"),
+            RomError::TooLarge { size } => write!(
+                f, "the file is {} bytes long, but at most {} bytes fit in RAM", size, RAM_SIZE - 0x200
+            ),
+            RomError::Foreign { format } => write!(f, "this is {}, not a CHIP-8 program", format),
+        }
+    }
+}
+
+/// An opcode executed as doing nothing, see [VM::set_break_on_illegal]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IllegalOpcode {
+    pub opcode: u16,
+    pub pc: u16,
+}
+
+impl fmt::Display for IllegalOpcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [high, low] = self.opcode.to_be_bytes();
+        write!(f, "PC 0x{:03X}: opcode {:02X} {:02X} ", self.pc, high, low)?;
+        match Platform::of_opcode(self.opcode) {
+            Some(platform) => write!(f, "is a {} instruction, which is not implemented", platform),
+            None => write!(f, "is not an instruction"),
+        }
+    }
+}
+
+/// A debug trap executed, see [VM::set_debug_traps]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrapHit {
+    pub trap: DebugTrap,
+    pub pc: u16,
+    /// The registers or memory printed, empty for `BRK`
+    pub output: String,
+}
+
+impl fmt::Display for TrapHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "PC 0x{:03X}: {}", self.pc, self.trap)?;
+        if !self.output.is_empty() {
+            write!(f, "  {}", self.output)?;
+        }
+        Ok(())
+    }
+}
+
+/// An error that stops the virtual machine
+#[derive(Debug)]
+pub enum Error {
+    /// A `SYS addr` (0nnn) instruction was executed. Native machine code
+    /// routines cannot be emulated
+    NativeSubroutine {
+        address: u16,
+        pc: usize
+    },
+    /// The program wrote into its own ROM image while
+    /// [RomProtection::Strict] was enabled
+    RomWrite {
+        address: usize,
+        pc: usize
+    },
+    /// The program wrote over code it executed while code protection was
+    /// strict, see [VM::set_code_protection]
+    CodeWrite {
+        address: usize,
+        pc: usize
+    },
+    /// The program read or wrote memory past the end of RAM
+    MemoryOutOfBounds {
+        address: usize,
+        pc: usize
+    },
+    /// A subroutine was called with 16 subroutines already running
+    StackOverflow {
+        pc: usize
+    },
+    /// `RET` was executed outside of a subroutine
+    StackUnderflow {
+        pc: usize
+    },
+    /// The program counter went past the end of RAM
+    PcOutOfBounds {
+        pc: usize
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NativeSubroutine { address, pc } => write!(
+                f, "PC 0x{:03X}: native subroutine 0x{:03X} called (SYS is not supported)",
+                pc, address
+            ),
+            Error::RomWrite { address, pc } => write!(
+                f, "PC 0x{:03X}: write to ROM address 0x{:03X}", pc, address
+            ),
+            Error::CodeWrite { address, pc } => write!(
+                f, "PC 0x{:03X}: write to 0x{:03X}, which was executed as code", pc, address
+            ),
+            Error::MemoryOutOfBounds { address, pc } => write!(
+                f, "PC 0x{:03X}: access to address 0x{:X}, past the end of RAM", pc, address
+            ),
+            Error::StackOverflow { pc } => write!(
+                f, "PC 0x{:03X}: stack overflow (more than 16 nested calls)", pc
+            ),
+            Error::StackUnderflow { pc } => write!(
+                f, "PC 0x{:03X}: RET outside of a subroutine", pc
+            ),
+            Error::PcOutOfBounds { pc } => write!(
+                f, "PC 0x{:X}: program counter past the end of RAM", pc
+            ),
         }
     }
 }
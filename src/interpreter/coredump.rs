@@ -0,0 +1,198 @@
+//! Saving the state of a program that crashed, to inspect it later
+//!
+//! When a program stops with an error, the interpreter writes a core file,
+//! `crash-<timestamp>.c8core`, with the state of the machine and the last
+//! instructions executed, which `chip8 inspect-core` prints. A core file
+//! is, in order (multi-byte values big-endian):
+//! - 4 bytes: magic `C8CR`
+//! - 1 byte: format version, currently 1
+//! - 8 bytes: when it was written, in seconds since the Unix epoch
+//! - 2 bytes and as many of UTF-8: why it was written, the error
+//! - 2 bytes and as many of UTF-8: the path of the ROM
+//! - the [state](super::state) in its binary format
+//! - 4 bytes: the number of instructions recorded, followed by them, the
+//!   oldest first, as records of the binary [trace](super::trace) format
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::time::{Duration, UNIX_EPOCH};
+use crate::disassembler::decode_instruction;
+use crate::logging::timestamp;
+use super::display_to_text;
+use super::state::State;
+use super::trace::{format_line, Registers};
+
+/// How many of the last instructions executed a core file has
+pub const RECENT_LENGTH: usize = 256;
+
+const MAGIC: &[u8; 4] = b"C8CR";
+const VERSION: u8 = 1;
+const RAM_ROW_SIZE: usize = 16;
+
+/// An instruction executed after `cycles` others, and the registers after
+/// it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Executed {
+    pub cycles: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub registers: Registers,
+}
+
+/// The last `capacity` instructions executed, the oldest first
+pub struct Recent {
+    instructions: VecDeque<Executed>,
+    capacity: usize,
+}
+
+impl Recent {
+    pub fn new(capacity: usize) -> Recent {
+        Recent { instructions: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    /// Add an instruction, forgetting the oldest one if full
+    pub fn push(&mut self, executed: Executed) {
+        if self.instructions.len() == self.capacity {
+            self.instructions.pop_front();
+        }
+        self.instructions.push_back(executed);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Executed> {
+        self.instructions.iter()
+    }
+}
+
+/// The contents of a core file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Core {
+    /// Seconds since the Unix epoch
+    pub time: u64,
+    pub reason: String,
+    pub rom_path: String,
+    pub state: State,
+    pub recent: Vec<Executed>,
+}
+
+impl Core {
+    /// Write the core in the format described in the module docs
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&self.time.to_be_bytes())?;
+        write_text(&mut writer, &self.reason)?;
+        write_text(&mut writer, &self.rom_path)?;
+        self.state.write_to(&mut writer)?;
+        writer.write_all(&(self.recent.len() as u32).to_be_bytes())?;
+        for executed in &self.recent {
+            let registers = &executed.registers;
+            writer.write_all(&executed.cycles.to_be_bytes())?;
+            writer.write_all(&executed.pc.to_be_bytes())?;
+            writer.write_all(&executed.opcode.to_be_bytes())?;
+            writer.write_all(&registers.reg)?;
+            writer.write_all(&registers.reg_i.to_be_bytes())?;
+            writer.write_all(&[registers.sp, registers.delay_timer, registers.sound_timer])?;
+        }
+        Ok(())
+    }
+
+    /// Read a core written by [Core::write_to]
+    pub fn read_from(mut reader: impl Read) -> io::Result<Core> {
+        let mut header = [0u8; 5];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a CHIP-8 core file"));
+        }
+        if header[4] != VERSION {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported core file version"));
+        }
+        let mut time = [0u8; 8];
+        reader.read_exact(&mut time)?;
+        let reason = read_text(&mut reader)?;
+        let rom_path = read_text(&mut reader)?;
+        let state = State::read_from(&mut reader)?;
+        let mut count = [0u8; 4];
+        reader.read_exact(&mut count)?;
+        let mut recent = Vec::new();
+        for _ in 0..u32::from_be_bytes(count) {
+            let mut record = [0u8; 33];
+            reader.read_exact(&mut record)?;
+            let u16_at = |i: usize| u16::from_be_bytes([record[i], record[i + 1]]);
+            recent.push(Executed {
+                cycles: u64::from_be_bytes(record[..8].try_into().unwrap()),
+                pc: u16_at(8),
+                opcode: u16_at(10),
+                registers: Registers {
+                    reg: record[12..28].try_into().unwrap(),
+                    reg_i: u16_at(28),
+                    sp: record[30],
+                    delay_timer: record[31],
+                    sound_timer: record[32],
+                },
+            });
+        }
+        Ok(Core { time: u64::from_be_bytes(time), reason, rom_path, state, recent })
+    }
+
+    /// Describe the crash: the program, when and why it stopped, its
+    /// registers and stack, the instructions recorded with the registers
+    /// they changed, the display, and with `ram` a hexdump of the RAM
+    pub fn report(&self, ram: bool) -> String {
+        let state = &self.state;
+        let time = timestamp(UNIX_EPOCH + Duration::from_secs(self.time));
+        let mut report = format!("{}, {} UTC: {}\n\n", self.rom_path, time, self.reason);
+        for row in state.reg.chunks(8).enumerate() {
+            let registers: Vec<String> = row.1.iter().enumerate()
+                .map(|(x, value)| format!("V{:X}={:02X}", row.0 * 8 + x, value))
+                .collect();
+            report += &format!("{}\n", registers.join("  "));
+        }
+        report += &format!(
+            "I=0x{:03X}  DT={:02X}  ST={:02X}  PC=0x{:03X}  SP={}\n",
+            state.reg_i, state.delay_timer, state.sound_timer, state.pc, state.sp
+        );
+        let pc = state.pc as usize;
+        if let Some(bytes) = state.ram.get(pc..pc + 2) {
+            let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+            report += &format!("Next instruction: {:04X}  {}\n", opcode, decode_instruction(opcode));
+        }
+        let stack: Vec<String> = state.stack[..(state.sp as usize).min(state.stack.len())].iter()
+            .rev()
+            .map(|address| format!("0x{:03X}", address))
+            .collect();
+        report += &format!("Stack, innermost first: {}\n", if stack.is_empty() { "empty".to_string() } else { stack.join(" ") });
+        report += &format!("{} instructions executed\n", state.cycles);
+
+        report += &format!("\nThe last {} instructions, the oldest first:\n", self.recent.len());
+        let mut before: Option<&Registers> = None;
+        for executed in &self.recent {
+            let changes = before.map_or(String::new(), |before| before.changes(&executed.registers));
+            report += &format!("{}\n", format_line(executed.pc, executed.opcode, &changes));
+            before = Some(&executed.registers);
+        }
+
+        report += &format!("\nDisplay:\n{}", display_to_text(&state.display));
+        if ram {
+            report += "\nRAM:\n";
+            for (row, bytes) in state.ram.chunks(RAM_ROW_SIZE).enumerate() {
+                let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                report += &format!("{:04X}: {}\n", row * RAM_ROW_SIZE, bytes.join(" "));
+            }
+        }
+        report
+    }
+}
+
+fn write_text(writer: &mut impl Write, text: &str) -> io::Result<()> {
+    let bytes = &text.as_bytes()[..text.len().min(u16::MAX as usize)];
+    writer.write_all(&(bytes.len() as u16).to_be_bytes())?;
+    writer.write_all(bytes)
+}
+
+fn read_text(reader: &mut impl Read) -> io::Result<String> {
+    let mut length = [0u8; 2];
+    reader.read_exact(&mut length)?;
+    let mut bytes = vec![0; u16::from_be_bytes(length) as usize];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid text in core file"))
+}
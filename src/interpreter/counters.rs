@@ -0,0 +1,65 @@
+//! Counting what a program does, for embedders and tools to show
+//!
+//! The virtual machine always keeps [Counters], at the cost of an addition
+//! here and there, so the debug overlay, the profile reports and programs
+//! embedding the machine all read the same numbers from [VM::counters].
+//! They count from the start of the program, and are not undone by
+//! [VM::step_back]. The report is a line per counter:
+//!
+//! ```text
+//! instructions          123456
+//! frames                   247
+//! sprites drawn            980
+//! collisions                12
+//! sound activations          3
+//! ```
+//!
+//! [VM::counters]: super::VM::counters
+//! [VM::step_back]: super::VM::step_back
+
+use crate::json::Value;
+
+/// What the program did since it started. The layout is the one of 5
+/// consecutive 64-bit integers, for the C ABI of the `wasm` frontend
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    /// Instructions executed
+    pub instructions: u64,
+    /// Frames ended, the timers ticking once per frame
+    pub frames: u64,
+    /// `DRW` instructions executed
+    pub sprites_drawn: u64,
+    /// Sprites that turned pixels off, setting VF to 1
+    pub collisions: u64,
+    /// Times the buzzer started, the sound timer being set while zero
+    pub sound_activations: u64,
+}
+
+impl Counters {
+    /// The names of the counters and their values
+    fn fields(&self) -> [(&'static str, u64); 5] {
+        [
+            ("instructions", self.instructions),
+            ("frames", self.frames),
+            ("sprites drawn", self.sprites_drawn),
+            ("collisions", self.collisions),
+            ("sound activations", self.sound_activations),
+        ]
+    }
+
+    /// Format the report described in the module docs
+    pub fn report(&self) -> String {
+        self.fields().iter().map(|(name, value)| format!("{:<17} {:>10}\n", name, value)).collect()
+    }
+
+    /// The counters as a JSON object, with the names of the report in
+    /// snake case
+    pub fn to_json(&self) -> Value {
+        Value::Object(
+            self.fields().iter()
+                .map(|(name, value)| (name.replace(' ', "_"), Value::Number(*value as f64)))
+                .collect()
+        )
+    }
+}
@@ -0,0 +1,113 @@
+//! Recording which bytes of the program were executed as code
+//!
+//! [Coverage] marks the addresses instructions were fetched from. The
+//! report tells how much of the program was executed, lists the regions
+//! that never were, and disassembles the whole program with the
+//! instructions never executed marked with `-`:
+//!
+//! ```text
+//! 0x0200..0x020A: 6 of 10 bytes executed (60.0%)
+//!
+//! Never executed:
+//! 0x0204..0x0207  3 bytes
+//! 0x0209..0x020A  1 byte
+//!
+//! Disassembly:
+//!   0x0200  6001  LD V0, 0x01
+//!   0x0202  1207  JP 0x207
+//! - 0x0204  F090  .word 0xF090
+//! - 0x0206  AA    .byte 0xAA
+//!   0x0207  1207  JP 0x207
+//! - 0x0209  FF    .byte 0xFF
+//! ```
+//!
+//! Regions never executed are either code the run did not reach, which is
+//! worth testing, or data, like sprites.
+
+use std::ops::Range;
+use crate::disassembler::decode_instruction;
+use super::RAM_SIZE;
+
+/// The addresses instructions were fetched from
+pub struct Coverage {
+    starts: Vec<bool>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage { starts: vec![false; RAM_SIZE] }
+    }
+
+    /// Mark the instruction at `pc` as executed
+    pub fn add(&mut self, pc: u16) {
+        if let Some(start) = self.starts.get_mut(pc as usize) {
+            *start = true;
+        }
+    }
+
+    /// Check whether an instruction was fetched from `address`
+    pub fn is_instruction(&self, address: usize) -> bool {
+        self.starts.get(address).copied().unwrap_or(false)
+    }
+
+    /// Check whether `address` is a byte of an instruction executed
+    pub fn is_executed(&self, address: usize) -> bool {
+        self.is_instruction(address) || address.checked_sub(1).is_some_and(|previous| self.is_instruction(previous))
+    }
+
+    /// Get the regions of `range` never executed
+    pub fn unexecuted(&self, range: Range<usize>) -> Vec<Range<usize>> {
+        let mut regions: Vec<Range<usize>> = Vec::new();
+        for address in range.filter(|&address| !self.is_executed(address)) {
+            match regions.last_mut() {
+                Some(region) if region.end == address => region.end += 1,
+                _ => regions.push(address..address + 1),
+            }
+        }
+        regions
+    }
+
+    /// Format the report described in the module docs for the program in
+    /// `range` of `ram`
+    pub fn report(&self, ram: &[u8], range: Range<usize>) -> String {
+        let size = range.len();
+        let executed = range.clone().filter(|&address| self.is_executed(address)).count();
+        let percent = if size > 0 { 100.0 * executed as f64 / size as f64 } else { 0.0 };
+        let mut report = format!(
+            "0x{:04X}..0x{:04X}: {} of {} bytes executed ({:.1}%)\n",
+            range.start, range.end, executed, size, percent
+        );
+
+        let unexecuted = self.unexecuted(range.clone());
+        if !unexecuted.is_empty() {
+            report += "\nNever executed:\n";
+            for region in unexecuted {
+                let plural = if region.len() == 1 { "" } else { "s" };
+                report += &format!("0x{:04X}..0x{:04X}  {} byte{}\n", region.start, region.end, region.len(), plural);
+            }
+        }
+
+        report += "\nDisassembly:\n";
+        let mut address = range.start;
+        while address < range.end {
+            let marker = if self.is_instruction(address) { ' ' } else { '-' };
+            // A byte never executed before an instruction is shown alone,
+            // so that the instruction is aligned
+            if address + 1 >= range.end || (!self.is_instruction(address) && self.is_instruction(address + 1)) {
+                report += &format!("{} 0x{:04X}  {:02X}    .byte 0x{:02X}\n", marker, address, ram[address], ram[address]);
+                address += 1;
+            } else {
+                let opcode = u16::from_be_bytes([ram[address], ram[address + 1]]);
+                report += &format!("{} 0x{:04X}  {:04X}  {}\n", marker, address, opcode, decode_instruction(opcode));
+                address += 2;
+            }
+        }
+        report
+    }
+}
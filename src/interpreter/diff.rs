@@ -0,0 +1,197 @@
+//! Side-by-side, colored comparison of two virtual machine states
+//!
+//! Used to make divergences between two runs readable at a glance: only
+//! the registers, RAM rows and display rows that differ are highlighted.
+//! [format_changes] lists only what changed from one state to another,
+//! like a level transition:
+//!
+//! ```text
+//! cycles  1200 -> 5460
+//! PC      0x2F4 -> 0x318
+//! V3      0x00 -> 0x05
+//! S0      0x204 -> 0x210
+//!
+//! RAM, 3 bytes changed:
+//! 0x0300  00 00 -> 01 02  <score>
+//! 0x0320  01 -> 02        <level>
+//!
+//! Display: 120 pixels changed
+//! ```
+
+use std::fmt::Write;
+use std::ops::Range;
+use colored::Colorize;
+use super::state::State;
+use super::{Display, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+const RAM_ROW_SIZE: usize = 16;
+/// Number of bytes changed shown per line by [format_changes]
+const RAM_RUN_SIZE: usize = 8;
+/// Number of RAM rows shown around the first difference
+const RAM_CONTEXT_ROWS: usize = 2;
+
+/// Format the differences between two states as a side-by-side table of
+/// registers, a RAM window around the first differing byte (or around the
+/// program counter of `a` if RAM is identical) and both displays
+pub fn format_diff(a: &State, b: &State, name_a: &str, name_b: &str) -> String {
+    let mut out = String::new();
+    writeln!(out, "{:<6}{:<10}{}", "", name_a.bold(), name_b.bold()).unwrap();
+
+    let mut registers: Vec<(String, String, String)> = vec![
+        ("PC".to_string(), format!("0x{:03X}", a.pc), format!("0x{:03X}", b.pc)),
+        ("I".to_string(), format!("0x{:03X}", a.reg_i), format!("0x{:03X}", b.reg_i)),
+        ("SP".to_string(), a.sp.to_string(), b.sp.to_string()),
+        ("DT".to_string(), a.delay_timer.to_string(), b.delay_timer.to_string()),
+        ("ST".to_string(), a.sound_timer.to_string(), b.sound_timer.to_string()),
+    ];
+    for i in 0..16 {
+        registers.push((format!("V{:X}", i), format!("0x{:02X}", a.reg[i]), format!("0x{:02X}", b.reg[i])));
+    }
+    for i in 0..a.sp.max(b.sp).min(16) as usize {
+        registers.push((
+            format!("S{:X}", i),
+            format!("0x{:03X}", a.stack[i]),
+            format!("0x{:03X}", b.stack[i]),
+        ));
+    }
+    for (name, value_a, value_b) in registers {
+        if value_a == value_b {
+            writeln!(out, "{:<6}{:<10}{}", name, value_a, value_b).unwrap();
+        } else {
+            writeln!(out, "{:<6}{:<10}{}", name.bold(), value_a.red(), value_b.green()).unwrap();
+        }
+    }
+
+    writeln!(out).unwrap();
+    let first_difference = a.ram.iter().zip(&b.ram).position(|(x, y)| x != y);
+    match first_difference {
+        Some(address) => writeln!(out, "RAM (first difference at 0x{:03X})", address).unwrap(),
+        None => writeln!(out, "RAM (identical, around PC)").unwrap(),
+    }
+    let center_row = first_difference.unwrap_or(a.pc as usize) / RAM_ROW_SIZE;
+    let n_rows = a.ram.len() / RAM_ROW_SIZE;
+    let rows = center_row.saturating_sub(RAM_CONTEXT_ROWS)..(center_row + RAM_CONTEXT_ROWS + 1).min(n_rows);
+    for row in rows {
+        let range = row * RAM_ROW_SIZE..(row + 1) * RAM_ROW_SIZE;
+        writeln!(
+            out, "{:03X}  {}  {}",
+            range.start,
+            format_ram_row(&a.ram[range.clone()], &b.ram[range.clone()], |byte| byte.red()),
+            format_ram_row(&b.ram[range.clone()], &a.ram[range.clone()], |byte| byte.green()),
+        ).unwrap();
+    }
+
+    writeln!(out).unwrap();
+    if a.display == b.display {
+        writeln!(out, "Displays are identical").unwrap();
+    } else {
+        out.push_str(&format_displays(&a.display, &b.display, name_a, name_b));
+    }
+    out
+}
+
+/// Format the registers, stack slots and runs of RAM bytes that differ
+/// from `a` to `b`, annotating addresses with the label `label_of` gives,
+/// and how many pixels of the display changed
+pub fn format_changes(a: &State, b: &State, label_of: impl Fn(u16) -> Option<String>) -> String {
+    let mut changes: Vec<(String, String, String)> = vec![
+        ("PC".to_string(), format!("0x{:03X}", a.pc), format!("0x{:03X}", b.pc)),
+        ("I".to_string(), format!("0x{:03X}", a.reg_i), format!("0x{:03X}", b.reg_i)),
+        ("SP".to_string(), a.sp.to_string(), b.sp.to_string()),
+        ("DT".to_string(), a.delay_timer.to_string(), b.delay_timer.to_string()),
+        ("ST".to_string(), a.sound_timer.to_string(), b.sound_timer.to_string()),
+    ];
+    for i in 0..16 {
+        changes.push((format!("V{:X}", i), format!("0x{:02X}", a.reg[i]), format!("0x{:02X}", b.reg[i])));
+    }
+    for i in 0..a.sp.max(b.sp).min(16) as usize {
+        changes.push((format!("S{:X}", i), format!("0x{:03X}", a.stack[i]), format!("0x{:03X}", b.stack[i])));
+    }
+    // K is the register LD Vx, K is waiting to store a key press in
+    let key = |waiting_for_key: Option<u8>| waiting_for_key.map_or("none".to_string(), |x| format!("V{:X}", x));
+    changes.push(("K".to_string(), key(a.waiting_for_key), key(b.waiting_for_key)));
+    changes.retain(|(_, value_a, value_b)| value_a != value_b);
+
+    let mut runs: Vec<Range<usize>> = Vec::new();
+    for address in (0..a.ram.len()).filter(|&address| a.ram[address] != b.ram[address]) {
+        match runs.last_mut() {
+            Some(run) if run.end == address && run.len() < RAM_RUN_SIZE => run.end += 1,
+            _ => runs.push(address..address + 1),
+        }
+    }
+    let pixels = a.display.iter().flatten().zip(b.display.iter().flatten()).filter(|(x, y)| x != y).count();
+    if a.cycles == b.cycles && changes.is_empty() && runs.is_empty() && pixels == 0 {
+        return "The states are identical\n".to_string();
+    }
+
+    let mut out = String::new();
+    writeln!(out, "{:<8}{} -> {}", "cycles", a.cycles, b.cycles).unwrap();
+    for (name, value_a, value_b) in changes {
+        writeln!(out, "{:<8}{} -> {}", name, value_a, value_b).unwrap();
+    }
+    if !runs.is_empty() {
+        let count: usize = runs.iter().map(|run| run.len()).sum();
+        writeln!(out, "\nRAM, {} byte{} changed:", count, if count == 1 { "" } else { "s" }).unwrap();
+        let lines: Vec<(usize, String)> = runs.into_iter()
+            .map(|run| {
+                let hex = |ram: &[u8]| ram[run.clone()].iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ");
+                (run.start, format!("{} -> {}", hex(&a.ram), hex(&b.ram)))
+            })
+            .collect();
+        let width = lines.iter().map(|(_, bytes)| bytes.len()).max().unwrap_or(0);
+        for (address, bytes) in lines {
+            match label_of(address as u16) {
+                Some(label) => writeln!(out, "0x{:04X}  {:<width$}  <{}>", address, bytes, label).unwrap(),
+                None => writeln!(out, "0x{:04X}  {}", address, bytes).unwrap(),
+            }
+        }
+    }
+    if pixels > 0 {
+        writeln!(out, "\nDisplay: {} pixel{} changed", pixels, if pixels == 1 { "" } else { "s" }).unwrap();
+    }
+    out
+}
+
+/// Format two displays side by side as ASCII art, highlighting the pixels
+/// that differ
+pub fn format_displays(a: &Display, b: &Display, name_a: &str, name_b: &str) -> String {
+    let mut out = String::new();
+    writeln!(out, "{:<width$}  {}", name_a.bold(), name_b.bold(), width = DISPLAY_WIDTH).unwrap();
+    for y in 0..DISPLAY_HEIGHT {
+        writeln!(
+            out, "{}  {}",
+            format_display_row(&a[y], &b[y], |pixel| pixel.red()),
+            format_display_row(&b[y], &a[y], |pixel| pixel.green()),
+        ).unwrap();
+    }
+    out
+}
+
+fn format_ram_row(
+    row: &[u8],
+    other: &[u8],
+    highlight: impl Fn(&str) -> colored::ColoredString
+) -> String {
+    row.iter()
+        .zip(other)
+        .map(|(byte, other_byte)| {
+            let hex = format!("{:02X}", byte);
+            if byte == other_byte { hex } else { highlight(&hex).bold().to_string() }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn format_display_row(
+    row: &[bool],
+    other: &[bool],
+    highlight: impl Fn(&str) -> colored::ColoredString
+) -> String {
+    row.iter()
+        .zip(other)
+        .map(|(&pixel, &other_pixel)| {
+            let c = if pixel { "#" } else { "." };
+            if pixel == other_pixel { c.to_string() } else { highlight(c).bold().to_string() }
+        })
+        .collect()
+}
@@ -0,0 +1,86 @@
+//! Counting how many times the instruction at every address was executed
+//!
+//! A [Heatmap] exports as CSV, a line `address,count` for every address
+//! executed, or as an image of the whole RAM, 64 addresses per row from
+//! 0x000 at the top left, where addresses never executed are dark and the
+//! others go from blue to yellow as they are executed more often. The
+//! colors follow the logarithm of the counts, so that code run once stands
+//! out next to a loop run millions of times.
+
+use crate::png::Image;
+use super::RAM_SIZE;
+
+/// How many addresses a row of the image shows
+const ADDRESSES_PER_ROW: usize = 64;
+/// The width and height in pixels of an address in the image
+const CELL_SIZE: usize = 8;
+/// The color of the addresses never executed
+const UNEXECUTED_COLOR: [u8; 3] = [0x20, 0x20, 0x20];
+/// The colors of the addresses executed least to most often, blended
+/// between them
+const HEAT_COLORS: [[u8; 3]; 4] = [[0x20, 0x30, 0xC0], [0xC0, 0x20, 0x80], [0xF0, 0x60, 0x20], [0xFF, 0xF0, 0x60]];
+
+/// The execution count of every address
+pub struct Heatmap {
+    counts: Vec<u64>,
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heatmap {
+    pub fn new() -> Heatmap {
+        Heatmap { counts: vec![0; RAM_SIZE] }
+    }
+
+    /// Count an execution of the instruction at `pc`
+    pub fn add(&mut self, pc: u16) {
+        if let Some(count) = self.counts.get_mut(pc as usize) {
+            *count += 1;
+        }
+    }
+
+    /// Get how many times the instruction at `address` was executed
+    pub fn count(&self, address: u16) -> u64 {
+        self.counts.get(address as usize).copied().unwrap_or(0)
+    }
+
+    /// Format the CSV described in the module docs, with a header line
+    pub fn to_csv(&self) -> String {
+        let mut csv = "address,count\n".to_string();
+        for (address, count) in self.counts.iter().enumerate().filter(|(_, count)| **count > 0) {
+            csv += &format!("0x{:03X},{}\n", address, count);
+        }
+        csv
+    }
+
+    /// Render the image described in the module docs
+    pub fn to_image(&self) -> Image {
+        let width = ADDRESSES_PER_ROW * CELL_SIZE;
+        let height = RAM_SIZE.div_ceil(ADDRESSES_PER_ROW) * CELL_SIZE;
+        let max = (self.counts.iter().copied().max().unwrap_or(0) as f64).ln_1p();
+        let colors: Vec<[u8; 3]> = self.counts.iter()
+            .map(|&count| if count == 0 { UNEXECUTED_COLOR } else { heat_color((count as f64).ln_1p() / max) })
+            .collect();
+        let pixels = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width / CELL_SIZE, i / width / CELL_SIZE);
+                let [r, g, b] = colors[y * ADDRESSES_PER_ROW + x];
+                [r, g, b, 0xFF]
+            })
+            .collect();
+        Image { width, height, pixels }
+    }
+}
+
+/// Blend the [HEAT_COLORS] for a heat between 0 and 1
+fn heat_color(heat: f64) -> [u8; 3] {
+    let position = heat.clamp(0.0, 1.0) * (HEAT_COLORS.len() - 1) as f64;
+    let index = (position as usize).min(HEAT_COLORS.len() - 2);
+    let t = position - index as f64;
+    let (from, to) = (HEAT_COLORS[index], HEAT_COLORS[index + 1]);
+    [0, 1, 2].map(|c| (from[c] as f64 + (to[c] as f64 - from[c] as f64) * t).round() as u8)
+}
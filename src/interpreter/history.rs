@@ -0,0 +1,81 @@
+//! The last instructions executed, to undo them
+//!
+//! Before every instruction, the registers, timers and stack are saved,
+//! with the audio pattern, pitch and register `LD Vx, K` waits to store a
+//! key in, along with the bytes of RAM it overwrites and the display if it
+//! draws, which is all [VM::step_back] needs to undo it. The random numbers and
+//! keys are not saved: running forward again executes the instructions
+//! anew, with the current keys.
+//!
+//! [VM::step_back]: super::VM::step_back
+
+use std::collections::VecDeque;
+use super::Display;
+use super::watchpoint::WatchpointHit;
+
+/// What an instruction changed, and the watchpoint it hit
+pub struct Entry {
+    pub pc: u16,
+    pub reg: [u8; 16],
+    pub reg_i: u16,
+    pub stack: [u16; 16],
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub waiting_for_key: Option<usize>,
+    pub waiting_for_vblank: bool,
+    pub latched_key: Option<u8>,
+    pub cycles: u64,
+    pub audio_pattern: Option<[u8; 16]>,
+    pub pitch: u8,
+    /// The address and former contents of the RAM the instruction writes
+    pub ram_address: u16,
+    pub ram: Vec<u8>,
+    /// The display before a `CLS` or `DRW`
+    pub display: Option<Box<Display>>,
+    pub watchpoint_hit: Option<WatchpointHit>,
+}
+
+/// The entries of the last `capacity` instructions, the oldest first
+pub struct History {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> History {
+        History { entries: VecDeque::new(), capacity }
+    }
+
+    /// Add the entry of an instruction, forgetting the oldest one if the
+    /// history is full
+    pub fn push(&mut self, entry: Entry) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Take the entry of the last instruction
+    pub fn pop(&mut self) -> Option<Entry> {
+        self.entries.pop_back()
+    }
+
+    /// Get the entry of the last instruction
+    pub fn last_mut(&mut self) -> Option<&mut Entry> {
+        self.entries.back_mut()
+    }
+
+    /// Get the number of instructions that can be undone
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
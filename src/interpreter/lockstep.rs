@@ -0,0 +1,107 @@
+//! Running machines one instruction at a time, side by side
+//!
+//! [Frames] ticks the timers of the machines and presses the keys of a
+//! replay at the same instructions as [VM::run_frames] would, so that
+//! machines stepped one instruction at a time can be checked after every
+//! one. [compare] runs two machines in lockstep, usually two builds of a
+//! program, and finds the first instruction after which their states
+//! differ.
+
+use super::replay::Replay;
+use super::state::State;
+use super::{VM, VM_FREQUENCY, TIMER_FREQUENCY};
+
+/// Ticks the timers of machines stepped one instruction at a time, and
+/// presses the keys of a replay
+pub struct Frames {
+    replay: Option<Replay>,
+    instructions_per_second: u32,
+    /// The current frame, counting from 0
+    pub frame: u64,
+}
+
+impl Frames {
+    /// Start the first frame, at the speed of the replay if there is one,
+    /// ticking the timers every [VM_FREQUENCY] / [TIMER_FREQUENCY]
+    /// instructions otherwise
+    pub fn new(replay: Option<Replay>, vms: &mut [VM]) -> Frames {
+        let instructions_per_second = replay.as_ref()
+            .map_or(VM_FREQUENCY / TIMER_FREQUENCY * TIMER_FREQUENCY, |replay| replay.instructions_per_second);
+        let frames = Frames { replay, instructions_per_second, frame: 0 };
+        frames.press_keys(vms);
+        frames
+    }
+
+    /// Tick the timers once `cycles` instructions were executed, if a
+    /// frame ended. Frames end after as many instructions as in
+    /// [VM::run_frames], so that replays play back as recorded
+    pub fn update(&mut self, cycles: u64, vms: &mut [VM]) {
+        let frame_start = |frame: u64| frame * self.instructions_per_second as u64 / TIMER_FREQUENCY as u64;
+        while frame_start(self.frame + 1) <= cycles {
+            vms.iter_mut().for_each(VM::tick_timers);
+            self.frame += 1;
+            self.press_keys(vms);
+        }
+    }
+
+    fn press_keys(&self, vms: &mut [VM]) {
+        if let Some(replay) = &self.replay {
+            vms.iter_mut().for_each(|vm| replay.apply(self.frame as u32, vm));
+        }
+    }
+}
+
+/// How two machines run by [compare] ended
+#[derive(Debug)]
+pub enum Comparison {
+    /// They went through the same states for all the instructions
+    Identical,
+    /// They stopped with the same error after `cycles` instructions,
+    /// having gone through the same states
+    Stopped { cycles: u64, error: String },
+    Diverged(Box<Divergence>),
+}
+
+/// The first instruction after which two machines differ
+#[derive(Debug)]
+pub struct Divergence {
+    /// The number of instructions executed, that one included
+    pub cycles: u64,
+    /// The frame the instruction ran in, counting from 1
+    pub frame: u64,
+    /// The states after the instruction
+    pub states: [State; 2],
+    /// The errors the machines stopped with at the instruction
+    pub errors: [Option<String>; 2],
+}
+
+/// Step two machines together for up to `cycles` instructions, until their
+/// states or errors differ, or both stop. With `ignore_ram`, only the
+/// registers, stack, timers and display are compared
+pub fn compare(vms: &mut [VM; 2], frames: &mut Frames, cycles: u64, ignore_ram: bool) -> Comparison {
+    for cycle in 0..cycles {
+        let results = vms.each_mut().map(|vm| vm.step().map_err(|e| e.to_string()));
+        let frame = frames.frame + 1;
+        frames.update(cycle + 1, vms);
+
+        let [state_a, mut state_b] = vms.each_ref().map(VM::state);
+        let ram_b = state_b.ram;
+        if ignore_ram {
+            state_b.ram = state_a.ram;
+        }
+        let differ = state_a != state_b;
+        state_b.ram = ram_b;
+        if differ || results[0] != results[1] {
+            return Comparison::Diverged(Box::new(Divergence {
+                cycles: cycle + 1,
+                frame,
+                states: [state_a, state_b],
+                errors: results.map(Result::err),
+            }));
+        }
+        if let Err(error) = &results[0] {
+            return Comparison::Stopped { cycles: cycle + 1, error: error.clone() };
+        }
+    }
+    Comparison::Identical
+}
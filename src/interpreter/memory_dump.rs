@@ -0,0 +1,62 @@
+//! Snapshots of a RAM region, appended to a file whenever the region changes
+//!
+//! Every record in the output file has the same layout, so the file can be
+//! read back as a flat array of records:
+//! - 8 bytes: Unix timestamp in milliseconds, big-endian
+//! - 8 bytes: number of instructions executed so far, big-endian
+//! - N bytes: contents of the region, where N is the length of the region
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A watched RAM region and the file its snapshots are written to
+pub struct MemoryDump {
+    range: Range<usize>,
+    path: String,
+    writer: BufWriter<File>,
+    last: Option<Vec<u8>>,
+}
+
+impl MemoryDump {
+    /// Create a dump of `range`, truncating the file at `path`
+    pub fn new(range: Range<usize>, path: &str) -> io::Result<MemoryDump> {
+        Ok(MemoryDump {
+            range,
+            path: path.to_string(),
+            writer: BufWriter::new(File::create(path)?),
+            last: None,
+        })
+    }
+
+    /// Get the path of the file the snapshots are written to
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Get the watched region
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// Append a snapshot if the region differs from the last written one
+    pub fn update(&mut self, ram: &[u8], cycle: u64) -> io::Result<()> {
+        let region = &ram[self.range.clone()];
+        if self.last.as_deref() == Some(region) {
+            return Ok(());
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or(0);
+        self.writer.write_all(&timestamp.to_be_bytes())?;
+        self.writer.write_all(&cycle.to_be_bytes())?;
+        self.writer.write_all(region)?;
+        self.writer.flush()?;
+
+        self.last = Some(region.to_vec());
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+//! Counting the instructions executed and the host time they took
+//!
+//! A [Profile] counts the executions and sums the time spent executing
+//! every kind of opcode (`Dxyn`, `8xy4`...) and every address. The report
+//! lists the opcodes and the busiest addresses, the ones that took the most
+//! time first, then the [counters](super::counters) of the machine:
+//!
+//! ```text
+//! 1000 instructions in 438.4 µs
+//!
+//! OPCODE       COUNT   SHARE       TIME   SHARE
+//! Dxyn           250   25.0%   339.4 µs   77.4%
+//! 1nnn           249   24.9%    33.1 µs    7.6%
+//!
+//! ADDRESS  INSTRUCTION                COUNT   SHARE       TIME   SHARE
+//! 0x0206   DRW V0, V1, 5                250   25.0%   339.4 µs   77.4%
+//! 0x0208   JP 0x202                     249   24.9%    33.1 µs    7.6%
+//!
+//! instructions            1000
+//! frames                    17
+//! ...
+//! ```
+//!
+//! [Profile::to_json] has the same counts, with the time in nanoseconds,
+//! for every address executed, and the counters.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+use crate::disassembler::decode_instruction;
+use crate::isa::opcode_pattern;
+use crate::json::Value;
+use super::RAM_SIZE;
+use super::counters::Counters;
+
+/// How many addresses [Profile::report] lists
+const ADDRESSES_REPORTED: usize = 20;
+
+/// The executions of an opcode or of the instructions at an address
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counter {
+    pub count: u64,
+    pub time: Duration,
+}
+
+impl Counter {
+    fn add(&mut self, time: Duration) {
+        self.count += 1;
+        self.time += time;
+    }
+}
+
+/// The counters of every opcode pattern and address
+pub struct Profile {
+    /// Keyed by [opcode_pattern], or `????` for opcodes of no platform
+    opcodes: BTreeMap<&'static str, Counter>,
+    /// The counter and the last opcode executed of every address
+    addresses: Vec<(Counter, u16)>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile { opcodes: BTreeMap::new(), addresses: vec![(Counter::default(), 0); RAM_SIZE] }
+    }
+
+    /// Count the instruction `opcode` at `pc`, which took `time`
+    pub fn add(&mut self, pc: u16, opcode: u16, time: Duration) {
+        self.opcodes.entry(opcode_pattern(opcode).unwrap_or("????")).or_default().add(time);
+        if let Some((counter, last_opcode)) = self.addresses.get_mut(pc as usize) {
+            counter.add(time);
+            *last_opcode = opcode;
+        }
+    }
+
+    /// Get the counters of the opcode patterns, by pattern
+    pub fn opcodes(&self) -> &BTreeMap<&'static str, Counter> {
+        &self.opcodes
+    }
+
+    /// Get the counter and last opcode of the addresses executed, by
+    /// address
+    pub fn addresses(&self) -> impl Iterator<Item = (u16, Counter, u16)> + '_ {
+        self.addresses.iter().enumerate()
+            .filter(|(_, (counter, _))| counter.count > 0)
+            .map(|(address, &(counter, opcode))| (address as u16, counter, opcode))
+    }
+
+    /// Get the number of instructions executed and the time they took
+    pub fn total(&self) -> Counter {
+        self.opcodes.values().fold(Counter::default(), |total, counter| Counter {
+            count: total.count + counter.count,
+            time: total.time + counter.time,
+        })
+    }
+
+    /// Format the report described in the module docs, ending with the
+    /// counters of the machine
+    pub fn report(&self, counters: &Counters) -> String {
+        let total = self.total();
+        let share = |counter: &Counter| {
+            let part = |a: f64, b: f64| if b > 0.0 { 100.0 * a / b } else { 0.0 };
+            format!(
+                "{:>10}  {:>5.1}%  {:>9}  {:>5.1}%",
+                counter.count, part(counter.count as f64, total.count as f64),
+                format_duration(counter.time), part(counter.time.as_secs_f64(), total.time.as_secs_f64())
+            )
+        };
+        let mut report = format!("{} instructions in {}\n\n", total.count, format_duration(total.time));
+
+        let mut opcodes: Vec<_> = self.opcodes.iter().collect();
+        opcodes.sort_by_key(|(_, counter)| std::cmp::Reverse(counter.time));
+        report += &format!("{:<6}  {:>10}  {:>6}  {:>9}  {:>6}\n", "OPCODE", "COUNT", "SHARE", "TIME", "SHARE");
+        for (pattern, counter) in opcodes {
+            report += &format!("{:<6}  {}\n", pattern, share(counter));
+        }
+
+        let mut addresses: Vec<_> = self.addresses().collect();
+        addresses.sort_by_key(|(_, counter, _)| std::cmp::Reverse(counter.time));
+        report += &format!(
+            "\n{:<7}  {:<20}  {:>10}  {:>6}  {:>9}  {:>6}\n",
+            "ADDRESS", "INSTRUCTION", "COUNT", "SHARE", "TIME", "SHARE"
+        );
+        for (address, counter, opcode) in addresses.into_iter().take(ADDRESSES_REPORTED) {
+            let instruction = decode_instruction(opcode);
+            report += &format!("0x{:04X}   {:<20}  {}\n", address, instruction, share(&counter));
+        }
+        report + "\n" + &counters.report()
+    }
+
+    /// Convert the profile to a JSON object of the total, the arrays
+    /// `opcodes` and `addresses` of counters, the time in nanoseconds, and
+    /// the `counters` of the machine
+    pub fn to_json(&self, counters: &Counters) -> Value {
+        let counter = |counter: &Counter| vec![
+            ("count".to_string(), Value::Number(counter.count as f64)),
+            ("nanoseconds".to_string(), Value::Number(counter.time.as_nanos() as f64)),
+        ];
+        let opcodes = self.opcodes.iter()
+            .map(|(pattern, c)| {
+                Value::Object([vec![("opcode".to_string(), Value::String(pattern.to_string()))], counter(c)].concat())
+            })
+            .collect();
+        let addresses = self.addresses()
+            .map(|(address, c, opcode)| {
+                Value::Object([vec![
+                    ("address".to_string(), Value::Number(address as f64)),
+                    ("instruction".to_string(), Value::String(decode_instruction(opcode))),
+                ], counter(&c)].concat())
+            })
+            .collect();
+        Value::Object([counter(&self.total()), vec![
+            ("opcodes".to_string(), Value::Array(opcodes)),
+            ("addresses".to_string(), Value::Array(addresses)),
+            ("counters".to_string(), counters.to_json()),
+        ]].concat())
+    }
+}
+
+/// Format a duration in µs, ms or s, whichever is readable
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64();
+    if seconds < 1e-3 {
+        format!("{:.1} µs", seconds * 1e6)
+    } else if seconds < 1.0 {
+        format!("{:.1} ms", seconds * 1e3)
+    } else {
+        format!("{:.2} s", seconds)
+    }
+}
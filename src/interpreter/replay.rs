@@ -0,0 +1,132 @@
+//! Recorded key presses, to run a program again with the same input
+//!
+//! A [Replay] lists the CHIP-8 keys pressed and released at the start of
+//! every 60 Hz frame. Played back with the same seed and number of
+//! instructions per second (see [VM::run_frames]), a program goes through
+//! exactly the same states every time, so replays can drive regression
+//! tests or compare two builds of a program. The binary format is, in
+//! order (multi-byte values big-endian):
+//! - 4 bytes: magic `C8RP`
+//! - 1 byte: format version, currently 1
+//! - 8 bytes: seed of the random number generator
+//! - 4 bytes: instructions executed per second
+//! - 4 bytes: number of frames recorded
+//! - 4 bytes: number of events, followed by the events in frame order:
+//!   - 4 bytes: frame the key changes at
+//!   - 1 byte: the key in the low nibble, the high bit set if it is
+//!     pressed and clear if it is released
+
+use std::io::{self, Read, Write};
+use super::VM;
+
+const MAGIC: &[u8; 4] = b"C8RP";
+const VERSION: u8 = 1;
+const PRESSED: u8 = 0x80;
+
+/// A key pressed or released
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    /// The frame the key changes at, before its instructions run
+    pub frame: u32,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+/// A recording of the input of a program, see the module docs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Replay {
+    pub seed: u64,
+    pub instructions_per_second: u32,
+    /// How many frames were recorded, including the last ones without
+    /// events
+    pub frames: u32,
+    pub events: Vec<KeyEvent>,
+}
+
+impl Replay {
+    /// Start a recording without any event
+    pub fn new(seed: u64, instructions_per_second: u32) -> Replay {
+        Replay { seed, instructions_per_second, frames: 0, events: Vec::new() }
+    }
+
+    /// Record a key change at the start of the current frame
+    pub fn record(&mut self, key: u8, pressed: bool) {
+        self.events.push(KeyEvent { frame: self.frames, key: key & 0xF, pressed });
+    }
+
+    /// Start recording the next frame
+    pub fn end_frame(&mut self) {
+        self.frames += 1;
+    }
+
+    /// Prepare a virtual machine to play the replay back: seed its random
+    /// number generator and set its speed
+    pub fn set_up(&self, vm: &mut VM) {
+        vm.seed_rng(self.seed);
+        vm.set_instructions_per_second(self.instructions_per_second);
+    }
+
+    /// Press and release the keys of the start of a frame
+    pub fn apply(&self, frame: u32, vm: &mut VM) {
+        let start = self.events.partition_point(|event| event.frame < frame);
+        for event in self.events[start..].iter().take_while(|event| event.frame == frame) {
+            vm.set_key(event.key, event.pressed);
+        }
+    }
+
+    /// Write the replay in the binary format described in the module docs
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&self.seed.to_be_bytes())?;
+        writer.write_all(&self.instructions_per_second.to_be_bytes())?;
+        writer.write_all(&self.frames.to_be_bytes())?;
+        writer.write_all(&(self.events.len() as u32).to_be_bytes())?;
+        for event in &self.events {
+            writer.write_all(&event.frame.to_be_bytes())?;
+            writer.write_all(&[event.key | if event.pressed { PRESSED } else { 0 }])?;
+        }
+        Ok(())
+    }
+
+    /// Read a replay written by [Replay::write_to]
+    pub fn read_from(mut reader: impl Read) -> io::Result<Replay> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut header = [0u8; 5];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid("not a CHIP-8 replay file"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid("unsupported replay file version"));
+        }
+
+        let mut seed = [0u8; 8];
+        reader.read_exact(&mut seed)?;
+        let instructions_per_second = read_u32(&mut reader)?;
+        if instructions_per_second == 0 {
+            return Err(invalid("no instructions per second"));
+        }
+        let frames = read_u32(&mut reader)?;
+        let n_events = read_u32(&mut reader)?;
+        let mut events = Vec::new();
+        for _ in 0..n_events {
+            let frame = read_u32(&mut reader)?;
+            let mut byte = [0u8; 1];
+            reader.read_exact(&mut byte)?;
+            if events.last().is_some_and(|last: &KeyEvent| last.frame > frame) || frame > frames {
+                return Err(invalid("events out of order"));
+            }
+            events.push(KeyEvent { frame, key: byte[0] & 0xF, pressed: byte[0] & PRESSED != 0 });
+        }
+
+        Ok(Replay { seed: u64::from_be_bytes(seed), instructions_per_second, frames, events })
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
@@ -0,0 +1,254 @@
+//! A plain-data snapshot of the virtual machine and its binary format
+//!
+//! A [State] holds everything needed to resume a program exactly where it
+//! was, so it can be persisted (save states), exchanged between tools and
+//! compared. The binary format is, in order (multi-byte values big-endian):
+//! - 4 bytes: magic `C8ST`
+//! - 1 byte: format version, currently 1
+//! - 4096 bytes: RAM
+//! - 2 bytes: PC
+//! - 16 bytes: V0..VF
+//! - 2 bytes: I
+//! - 32 bytes: stack, 16 entries of 2 bytes
+//! - 1 byte each: SP, delay timer, sound timer
+//! - 1 byte: register waiting for a key press, `0xFF` if none
+//! - 8 bytes: number of instructions executed
+//! - 2048 bytes: display, one byte per pixel (0 or 1), row by row
+//!
+//! States can also be written as JSON with [State::to_json], to be read by
+//! people and other tools: an object with the numbers `pc`, `i`, `sp`,
+//! `delay_timer`, `sound_timer`, `waiting_for_key` (`null` if none) and
+//! `cycles`, the arrays of numbers `v` and `stack`, the display as an array
+//! of rows drawn with `#` and `.`, and the RAM as an array of hexdump lines
+//! like `"0200: 00 E0 A2 2A ..."`, 16 bytes each, and read back with
+//! [State::from_json]
+
+use std::io::{self, Read, Write};
+use crate::json::Value;
+use super::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+const RAM_ROW_SIZE: usize = 16;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u8 = 1;
+
+/// A snapshot of the virtual machine state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct State {
+    /// Contents of the memory
+    pub ram: [u8; 4096],
+    /// Program counter
+    pub pc: u16,
+    /// General purpose registers V0..VF
+    pub reg: [u8; 16],
+    /// Address register I
+    pub reg_i: u16,
+    /// Return address stack
+    pub stack: [u16; 16],
+    /// Stack pointer, the number of addresses on the stack
+    pub sp: u8,
+    /// Delay timer
+    pub delay_timer: u8,
+    /// Sound timer
+    pub sound_timer: u8,
+    /// The register `LD Vx, K` is waiting to store a key press in
+    pub waiting_for_key: Option<u8>,
+    /// Number of instructions executed since the program was started
+    pub cycles: u64,
+    /// Display pixels, `true` if lit
+    pub display: [[bool; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
+}
+
+impl State {
+    /// Write the state in the binary format described in the module docs
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&self.ram)?;
+        writer.write_all(&self.pc.to_be_bytes())?;
+        writer.write_all(&self.reg)?;
+        writer.write_all(&self.reg_i.to_be_bytes())?;
+        for address in self.stack {
+            writer.write_all(&address.to_be_bytes())?;
+        }
+        writer.write_all(&[
+            self.sp,
+            self.delay_timer,
+            self.sound_timer,
+            self.waiting_for_key.unwrap_or(0xFF),
+        ])?;
+        writer.write_all(&self.cycles.to_be_bytes())?;
+        for row in &self.display {
+            let bytes: Vec<u8> = row.iter().map(|&pixel| pixel as u8).collect();
+            writer.write_all(&bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Convert the state to the JSON object described in the module docs
+    pub fn to_json(&self) -> Value {
+        let number = |n: u64| Value::Number(n as f64);
+        let numbers = |values: &mut dyn Iterator<Item = u64>| Value::Array(values.map(number).collect());
+        let display = self.display.iter()
+            .map(|row| Value::String(row.iter().map(|&pixel| if pixel { '#' } else { '.' }).collect()))
+            .collect();
+        let ram = self.ram.chunks(RAM_ROW_SIZE).enumerate()
+            .map(|(row, bytes)| {
+                let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                Value::String(format!("{:04X}: {}", row * RAM_ROW_SIZE, bytes.join(" ")))
+            })
+            .collect();
+
+        Value::Object(vec![
+            ("pc".to_string(), number(self.pc as u64)),
+            ("i".to_string(), number(self.reg_i as u64)),
+            ("v".to_string(), numbers(&mut self.reg.iter().map(|&x| x as u64))),
+            ("sp".to_string(), number(self.sp as u64)),
+            ("stack".to_string(), numbers(&mut self.stack.iter().map(|&x| x as u64))),
+            ("delay_timer".to_string(), number(self.delay_timer as u64)),
+            ("sound_timer".to_string(), number(self.sound_timer as u64)),
+            ("waiting_for_key".to_string(), self.waiting_for_key.map_or(Value::Null, |x| number(x as u64))),
+            ("cycles".to_string(), number(self.cycles)),
+            ("display".to_string(), Value::Array(display)),
+            ("ram".to_string(), Value::Array(ram)),
+        ])
+    }
+
+    /// Read a state converted to JSON by [State::to_json]
+    pub fn from_json(json: &Value) -> Result<State, String> {
+        let number = |key: &str, max: u64| {
+            json.get(key).and_then(Value::as_f64)
+                .filter(|&n| n >= 0.0 && n.fract() == 0.0 && n <= max as f64)
+                .map(|n| n as u64)
+                .ok_or_else(|| format!("invalid or missing \"{}\"", key))
+        };
+        let numbers = |key: &str, max: u64, out: &mut [u64]| {
+            let items = json.get(key).and_then(Value::as_array)
+                .filter(|items| items.len() == out.len())
+                .ok_or_else(|| format!("invalid or missing \"{}\"", key))?;
+            for (n, item) in out.iter_mut().zip(items) {
+                *n = item.as_f64()
+                    .filter(|&n| n >= 0.0 && n.fract() == 0.0 && n <= max as f64)
+                    .ok_or_else(|| format!("invalid number in \"{}\"", key))? as u64;
+            }
+            Ok::<(), String>(())
+        };
+
+        let mut reg = [0; 16];
+        numbers("v", u8::MAX as u64, &mut reg)?;
+        let mut stack = [0; 16];
+        numbers("stack", u16::MAX as u64, &mut stack)?;
+        let waiting_for_key = match json.get("waiting_for_key") {
+            Some(Value::Null) => None,
+            _ => Some(number("waiting_for_key", 15)? as u8),
+        };
+
+        let mut display = [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        let rows = json.get("display").and_then(Value::as_array)
+            .filter(|rows| rows.len() == DISPLAY_HEIGHT)
+            .ok_or("invalid or missing \"display\"")?;
+        for (row, line) in display.iter_mut().zip(rows) {
+            let line = line.as_str().filter(|line| line.len() == DISPLAY_WIDTH).ok_or("invalid display row")?;
+            for (pixel, c) in row.iter_mut().zip(line.chars()) {
+                *pixel = c == '#';
+            }
+        }
+
+        let mut ram = [0u8; 4096];
+        let lines = json.get("ram").and_then(Value::as_array)
+            .filter(|lines| lines.len() == ram.len() / RAM_ROW_SIZE)
+            .ok_or("invalid or missing \"ram\"")?;
+        for (row, line) in ram.chunks_mut(RAM_ROW_SIZE).zip(lines) {
+            let (_, bytes) = line.as_str().and_then(|line| line.split_once(": ")).ok_or("invalid RAM line")?;
+            let bytes: Vec<u8> = bytes.split(' ')
+                .map(|byte| u8::from_str_radix(byte, 16))
+                .collect::<Result<_, _>>()
+                .map_err(|_| "invalid RAM line")?;
+            if bytes.len() != RAM_ROW_SIZE {
+                return Err("invalid RAM line".to_string());
+            }
+            row.copy_from_slice(&bytes);
+        }
+
+        Ok(State {
+            ram,
+            pc: number("pc", u16::MAX as u64)? as u16,
+            reg: reg.map(|x| x as u8),
+            reg_i: number("i", u16::MAX as u64)? as u16,
+            stack: stack.map(|x| x as u16),
+            sp: number("sp", stack.len() as u64)? as u8,
+            delay_timer: number("delay_timer", u8::MAX as u64)? as u8,
+            sound_timer: number("sound_timer", u8::MAX as u64)? as u8,
+            waiting_for_key,
+            cycles: number("cycles", u64::MAX)?,
+            display,
+        })
+    }
+
+    /// Read a state written by [State::write_to]
+    pub fn read_from(mut reader: impl Read) -> io::Result<State> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+
+        let mut header = [0u8; 5];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid("not a CHIP-8 state file"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid("unsupported state file version"));
+        }
+
+        let mut ram = [0u8; 4096];
+        reader.read_exact(&mut ram)?;
+        let pc = read_u16(&mut reader)?;
+        let mut reg = [0u8; 16];
+        reader.read_exact(&mut reg)?;
+        let reg_i = read_u16(&mut reader)?;
+        let mut stack = [0u16; 16];
+        for address in stack.iter_mut() {
+            *address = read_u16(&mut reader)?;
+        }
+        let mut bytes = [0u8; 4];
+        reader.read_exact(&mut bytes)?;
+        let [sp, delay_timer, sound_timer, waiting_for_key] = bytes;
+        if sp as usize > stack.len() {
+            return Err(invalid("stack pointer out of range"));
+        }
+        let waiting_for_key = match waiting_for_key {
+            0xFF => None,
+            x if x < 16 => Some(x),
+            _ => return Err(invalid("invalid key register")),
+        };
+        let mut cycles = [0u8; 8];
+        reader.read_exact(&mut cycles)?;
+
+        let mut display = [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        for row in display.iter_mut() {
+            let mut bytes = [0u8; DISPLAY_WIDTH];
+            reader.read_exact(&mut bytes)?;
+            for (pixel, byte) in row.iter_mut().zip(bytes) {
+                *pixel = byte != 0;
+            }
+        }
+
+        Ok(State {
+            ram,
+            pc,
+            reg,
+            reg_i,
+            stack,
+            sp,
+            delay_timer,
+            sound_timer,
+            waiting_for_key,
+            cycles: u64::from_be_bytes(cycles),
+            display,
+        })
+    }
+}
+
+fn read_u16(reader: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0u8; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+}
@@ -0,0 +1,310 @@
+//! Printing every instruction executed, with the registers it changed
+//!
+//! A line of trace is the address, opcode and disassembly of an
+//! instruction, followed by the new values of the registers it changed:
+//!
+//! ```text
+//! 0x0214  D015  DRW V0, V1, 5             VF=01
+//! 0x0216  7001  ADD V0, 0x01              V0=09
+//! ```
+//!
+//! A [Trace] may be limited to a number of lines per frame, the lines past
+//! the limit being counted instead, so that tracing a fast program does not
+//! flood the terminal. A [TraceFilter] only lets the instructions at some
+//! addresses or of some kinds through.
+//!
+//! Traces of millions of instructions are smaller and faster to write in
+//! the binary format, where every instruction is a record of 33 bytes,
+//! big-endian, the registers being their values after the instruction:
+//! - 8 bytes: number of instructions executed before it
+//! - 2 bytes: address
+//! - 2 bytes: opcode
+//! - 16 bytes: V0 to VF
+//! - 2 bytes: I
+//! - 1 byte each: stack pointer, delay timer, sound timer
+//!
+//! The canonical format is text made to compare traces of different
+//! emulators (see [canonical_differences]), easy to write from any of
+//! them: a line per instruction of space-separated fields, the number of
+//! instructions executed before it in decimal, then in hexadecimal the
+//! address, opcode, V0 to VF, I, stack pointer, delay and sound timers
+//! after the instruction:
+//!
+//! ```text
+//! 12 0214 D015 08 05 00 00 00 00 00 00 00 00 00 00 00 00 00 01 0300 1 00 00
+//! ```
+//!
+//! Empty lines and lines starting with `#` are ignored.
+//!
+//! Key presses and releases, and the ticks of the timers while one is
+//! running, are written between the instructions, with the number of
+//! instructions executed before them, to tell which instructions saw a
+//! key pressed. In text, `@1234 key 5 down`, and in the canonical format,
+//! as a comment: `# 1234 key 5 down`. A key stored by `LD Vx, K` names the
+//! register (`key 5 down, stored in V3`), and a tick the timers after it
+//! (`timers tick, DT=03 ST=00`). The binary format leaves them out.
+
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use crate::disassembler::decode_instruction;
+use crate::isa::opcode_pattern;
+
+/// The size of the buffer of traces, large to make writing millions of
+/// lines cheap
+const BUFFER_SIZE: usize = 1 << 16;
+
+/// The registers an instruction may change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub reg: [u8; 16],
+    pub reg_i: u16,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+impl Registers {
+    /// The new values of the registers that differ in `after`, like
+    /// `V0=09 I=0x302`
+    pub fn changes(&self, after: &Registers) -> String {
+        let mut changes = Vec::new();
+        for (x, (before, after)) in self.reg.iter().zip(after.reg).enumerate() {
+            if *before != after {
+                changes.push(format!("V{:X}={:02X}", x, after));
+            }
+        }
+        if self.reg_i != after.reg_i {
+            changes.push(format!("I=0x{:03X}", after.reg_i));
+        }
+        if self.sp != after.sp {
+            changes.push(format!("SP={}", after.sp));
+        }
+        if self.delay_timer != after.delay_timer {
+            changes.push(format!("DT={:02X}", after.delay_timer));
+        }
+        if self.sound_timer != after.sound_timer {
+            changes.push(format!("ST={:02X}", after.sound_timer));
+        }
+        changes.join(" ")
+    }
+}
+
+/// The names of the fields of the canonical format
+pub const CANONICAL_FIELDS: [&str; 23] = [
+    "cycle", "PC", "opcode", "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7",
+    "V8", "V9", "VA", "VB", "VC", "VD", "VE", "VF", "I", "SP", "DT", "ST",
+];
+
+/// Format a line of trace, see the module docs
+pub fn format_line(pc: u16, opcode: u16, changes: &str) -> String {
+    let instruction = format!("0x{:04X}  {:04X}  {}", pc, opcode, decode_instruction(opcode));
+    if changes.is_empty() {
+        instruction
+    } else {
+        format!("{:<38}  {}", instruction, changes)
+    }
+}
+
+/// How instructions are written, see the module docs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    Binary,
+    Canonical,
+}
+
+/// Which instructions are traced: the ones in one of `addresses` and
+/// matching one of `opcodes`, each of them only filtering if not empty
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    pub addresses: Vec<Range<u16>>,
+    /// Opcode patterns as written by [opcode_pattern] (`Dxyn`, `2nnn`...),
+    /// or the first hexadecimal digit of opcodes (`D`, `F`...)
+    pub opcodes: Vec<String>,
+}
+
+impl TraceFilter {
+    /// Check whether the instruction `opcode` at `pc` is traced
+    pub fn matches(&self, pc: u16, opcode: u16) -> bool {
+        let address_matches = self.addresses.is_empty() || self.addresses.iter().any(|range| range.contains(&pc));
+        address_matches && (self.opcodes.is_empty() || self.opcodes.iter().any(|class| {
+            let pattern = opcode_pattern(opcode).unwrap_or("");
+            let digit = u16::from_str_radix(class, 16).ok().filter(|_| class.len() == 1);
+            class.eq_ignore_ascii_case(pattern) || digit == Some(opcode >> 12)
+        }))
+    }
+}
+
+/// Where the lines of trace go, and how many more can be written during
+/// the current frame
+pub struct Trace {
+    writer: BufWriter<Box<dyn Write + Send>>,
+    format: TraceFormat,
+    filter: TraceFilter,
+    lines_per_frame: Option<u32>,
+    lines: u32,
+    skipped: u64,
+}
+
+impl Trace {
+    /// Trace into `writer` as text, at most `lines_per_frame` lines per
+    /// frame if set
+    pub fn new(writer: Box<dyn Write + Send>, lines_per_frame: Option<u32>) -> Trace {
+        Trace {
+            writer: BufWriter::with_capacity(BUFFER_SIZE, writer),
+            format: TraceFormat::Text,
+            filter: TraceFilter::default(),
+            lines_per_frame,
+            lines: 0,
+            skipped: 0,
+        }
+    }
+
+    pub fn set_format(&mut self, format: TraceFormat) {
+        self.format = format;
+    }
+
+    /// Only trace the instructions the filter matches
+    pub fn set_filter(&mut self, filter: TraceFilter) {
+        self.filter = filter;
+    }
+
+    /// Check whether the instruction `opcode` at `pc` is traced, see
+    /// [TraceFilter]
+    pub fn traces(&self, pc: u16, opcode: u16) -> bool {
+        self.filter.matches(pc, opcode)
+    }
+
+    /// Check whether the next line would be written rather than skipped,
+    /// counting it. Lines are only formatted when they are written
+    pub fn next_line_written(&mut self) -> bool {
+        let written = self.lines_per_frame.is_none_or(|limit| self.lines < limit);
+        if written {
+            self.lines += 1;
+        } else {
+            self.skipped += 1;
+        }
+        written
+    }
+
+    /// Write an instruction executed after `cycles` others, given the
+    /// registers before and after it
+    pub fn write(&mut self, cycles: u64, pc: u16, opcode: u16, before: &Registers, after: &Registers) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(self.writer, "{}", format_line(pc, opcode, &before.changes(after))),
+            TraceFormat::Binary => {
+                self.writer.write_all(&cycles.to_be_bytes())?;
+                self.writer.write_all(&pc.to_be_bytes())?;
+                self.writer.write_all(&opcode.to_be_bytes())?;
+                self.writer.write_all(&after.reg)?;
+                self.writer.write_all(&after.reg_i.to_be_bytes())?;
+                self.writer.write_all(&[after.sp, after.delay_timer, after.sound_timer])
+            }
+            TraceFormat::Canonical => {
+                write!(self.writer, "{} {:04X} {:04X}", cycles, pc, opcode)?;
+                for value in after.reg {
+                    write!(self.writer, " {:02X}", value)?;
+                }
+                writeln!(
+                    self.writer, " {:04X} {:X} {:02X} {:02X}",
+                    after.reg_i, after.sp, after.delay_timer, after.sound_timer
+                )
+            }
+        }
+    }
+
+    /// Write a key or timer event that happened after `cycles`
+    /// instructions, see the module docs. Events are not filtered nor
+    /// limited like instructions
+    pub fn write_event(&mut self, cycles: u64, event: &str) -> io::Result<()> {
+        match self.format {
+            TraceFormat::Text => writeln!(self.writer, "@{} {}", cycles, event),
+            TraceFormat::Binary => Ok(()),
+            TraceFormat::Canonical => writeln!(self.writer, "# {} {}", cycles, event),
+        }
+    }
+
+    /// Tell how many lines were skipped during the frame, and write the
+    /// lines out
+    pub fn end_frame(&mut self) -> io::Result<()> {
+        if self.skipped > 0 && self.format == TraceFormat::Text {
+            writeln!(self.writer, "... {} instructions not traced", self.skipped)?;
+        }
+        self.lines = 0;
+        self.skipped = 0;
+        self.writer.flush()
+    }
+}
+
+/// Parse a line of the canonical format into its fields, or `None` if it
+/// is not one
+pub fn parse_canonical(line: &str) -> Option<[u64; CANONICAL_FIELDS.len()]> {
+    let mut fields = [0; CANONICAL_FIELDS.len()];
+    let mut words = line.split_whitespace();
+    for (i, field) in fields.iter_mut().enumerate() {
+        let word = words.next()?;
+        *field = if i == 0 { word.parse().ok()? } else { u64::from_str_radix(word, 16).ok()? };
+    }
+    words.next().is_none().then_some(fields)
+}
+
+/// Get the names of the fields that differ between two lines of the
+/// canonical format
+pub fn canonical_differences(a: &[u64; CANONICAL_FIELDS.len()], b: &[u64; CANONICAL_FIELDS.len()]) -> Vec<&'static str> {
+    CANONICAL_FIELDS.iter().zip(a.iter().zip(b)).filter(|(_, (a, b))| a != b).map(|(&name, _)| name).collect()
+}
+
+/// How two traces in the canonical format compare, see [diff_canonical]
+#[derive(Debug, PartialEq, Eq)]
+pub enum CanonicalDiff {
+    /// Both traces have the same `instructions` lines
+    Identical { instructions: u64 },
+    /// The instruction numbered `instruction`, counting from 1, differs in
+    /// `fields`. `previous` is the line of the instruction before it
+    Differ { instruction: u64, fields: Vec<&'static str>, lines: [String; 2], previous: Option<String> },
+    /// The trace `shorter` (0 or 1) ends after `instructions` lines, the
+    /// other going on
+    Ends { shorter: usize, instructions: u64 },
+}
+
+/// A line of a trace that is not in the canonical format
+#[derive(Debug, PartialEq, Eq)]
+pub struct CanonicalError {
+    /// Which of the traces it is in, 0 or 1
+    pub trace: usize,
+    pub line_number: usize,
+    pub line: String,
+}
+
+/// Compare two traces in the canonical format line by line, skipping
+/// empty lines and comments, up to the first instruction that differs
+pub fn diff_canonical<I: Iterator<Item = String>>(traces: [I; 2]) -> Result<CanonicalDiff, CanonicalError> {
+    let mut traces = traces.map(|lines| {
+        lines.enumerate().filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+    });
+    let mut previous = None;
+    let mut instructions = 0;
+    loop {
+        let mut lines = [None, None];
+        for (trace, (lines, next)) in traces.iter_mut().zip(&mut lines).enumerate() {
+            if let Some((index, line)) = lines.next() {
+                let Some(fields) = parse_canonical(&line) else {
+                    return Err(CanonicalError { trace, line_number: index + 1, line });
+                };
+                *next = Some((line, fields));
+            }
+        }
+        let [a, b] = match lines {
+            [None, None] => return Ok(CanonicalDiff::Identical { instructions }),
+            [None, Some(_)] => return Ok(CanonicalDiff::Ends { shorter: 0, instructions }),
+            [Some(_), None] => return Ok(CanonicalDiff::Ends { shorter: 1, instructions }),
+            [Some(a), Some(b)] => [a, b],
+        };
+        instructions += 1;
+        let fields = canonical_differences(&a.1, &b.1);
+        if !fields.is_empty() {
+            return Ok(CanonicalDiff::Differ { instruction: instructions, fields, lines: [a.0, b.0], previous });
+        }
+        previous = Some(a.0);
+    }
+}
@@ -0,0 +1,132 @@
+//! Stopping the program when a register changes
+//!
+//! A [Watchpoint] watches a V register, I or one of the timers, and is hit
+//! when an instruction changes it, or changes it to a given value. The
+//! timers counting down do not hit watchpoints. Watchpoints are only
+//! checked while the machine has some (see [VM::add_watchpoint]), so that
+//! normal runs pay nothing for them.
+//!
+//! [VM::add_watchpoint]: super::VM::add_watchpoint
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A register a [Watchpoint] can watch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    DelayTimer,
+    SoundTimer,
+}
+
+impl Register {
+    /// Format a value of the register in hexadecimal, with 3 digits for I
+    /// and 2 for the others
+    pub fn format_value(self, value: u16) -> String {
+        match self {
+            Register::I => format!("0x{:03X}", value),
+            _ => format!("0x{:02X}", value),
+        }
+    }
+
+    /// The largest value the register holds
+    pub fn max_value(self) -> u16 {
+        match self {
+            Register::I => u16::MAX,
+            _ => u8::MAX as u16,
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    /// Parse `V0` to `VF`, `I`, `DT` or `ST`, in any case
+    fn from_str(s: &str) -> Result<Register, String> {
+        match s.to_ascii_uppercase().as_str() {
+            "I" => Ok(Register::I),
+            "DT" => Ok(Register::DelayTimer),
+            "ST" => Ok(Register::SoundTimer),
+            name => name.strip_prefix('V')
+                .filter(|x| x.len() == 1)
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+                .map(Register::V)
+                .ok_or_else(|| format!("unknown register \"{}\", expected V0..VF, I, DT or ST", s)),
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "V{:X}", x),
+            Register::I => write!(f, "I"),
+            Register::DelayTimer => write!(f, "DT"),
+            Register::SoundTimer => write!(f, "ST"),
+        }
+    }
+}
+
+/// A register to stop on when it changes, or only when it changes to
+/// `value`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub register: Register,
+    pub value: Option<u16>,
+}
+
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    /// Parse `<register>` or `<register>=<value>`, the value being decimal
+    /// or `0x`-prefixed hexadecimal
+    fn from_str(s: &str) -> Result<Watchpoint, String> {
+        let (register, value) = match s.split_once('=') {
+            Some((register, value)) => (register, Some(value.trim())),
+            None => (s, None),
+        };
+        let register: Register = register.trim().parse()?;
+        let value = match value {
+            Some(value) => {
+                let parsed = match value.strip_prefix("0x") {
+                    Some(hex) => u16::from_str_radix(hex, 16).ok(),
+                    None => value.parse().ok(),
+                };
+                let value = parsed
+                    .filter(|&value| value <= register.max_value())
+                    .ok_or_else(|| format!("invalid value \"{}\" for {}", value, register))?;
+                Some(value)
+            }
+            None => None,
+        };
+        Ok(Watchpoint { register, value })
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.value {
+            Some(value) => write!(f, "{}={}", self.register, self.register.format_value(value)),
+            None => write!(f, "{}", self.register),
+        }
+    }
+}
+
+/// A watched register changed, by the instruction at `pc`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchpointHit {
+    pub register: Register,
+    pub old: u16,
+    pub new: u16,
+    pub pc: u16,
+}
+
+impl fmt::Display for WatchpointHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f, "{} changed from {} to {} at 0x{:03X}",
+            self.register, self.register.format_value(self.old), self.register.format_value(self.new), self.pc
+        )
+    }
+}
@@ -0,0 +1,482 @@
+//! The CHIP-8 instruction set
+//!
+//! [Instruction] is the single description of every opcode: the assembler
+//! encodes it, the disassembler prints it and the interpreter decodes and
+//! executes it. Keeping the encoding in one place guarantees the three agree.
+//!
+//! Mnemonics and operand order follow Cowgod's technical reference:
+//! <http://devernay.free.fr/hacks/chip8/C8TECH10.HTM>
+
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+/// Index of a general purpose register (`V0`..`VF`)
+pub type Register = u8;
+
+/// A 12-bit memory address
+pub type Address = u16;
+
+/// A CHIP-8 variant. Each one extends the instruction set of the previous
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Platform {
+    /// The original COSMAC VIP CHIP-8
+    Chip8,
+    /// SUPER-CHIP 1.1
+    SuperChip,
+    /// XO-CHIP
+    XoChip,
+}
+
+impl Platform {
+    /// Get a platform from its name in databases and on the command line:
+    /// `chip8`, `superchip` or `xochip`
+    pub fn from_name(name: &str) -> Option<Platform> {
+        match name {
+            "chip8" => Some(Platform::Chip8),
+            "superchip" => Some(Platform::SuperChip),
+            "xochip" => Some(Platform::XoChip),
+            _ => None,
+        }
+    }
+
+    /// Get the name of a platform, see [Platform::from_name]
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::SuperChip => "superchip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    /// Get the earliest platform an opcode is valid on, or `None` if no
+    /// platform defines it. Only [Platform::Chip8] opcodes can be decoded
+    /// into an [Instruction]
+    pub fn of_opcode(opcode: u16) -> Option<Platform> {
+        let n = opcode & 0x000F;
+        let kk = opcode & 0x00FF;
+        let platform = match opcode & 0xF000 {
+            0x0000 if opcode & 0xFFF0 == 0x00C0 => Platform::SuperChip,  // 00Cn
+            0x0000 if (0x00FB..=0x00FF).contains(&opcode) => Platform::SuperChip,
+            0x0000 if opcode & 0xFFF0 == 0x00D0 => Platform::XoChip,  // 00Dn
+            0x5000 if n == 0x2 || n == 0x3 => Platform::XoChip,  // 5xy2, 5xy3
+            0xD000 if n == 0x0 => Platform::SuperChip,  // Dxy0
+            0xF000 if opcode == 0xF000 || opcode == 0xF002 => Platform::XoChip,
+            0xF000 => match kk {
+                0x01 | 0x3A => Platform::XoChip,  // Fn01, Fx3A
+                0x30 | 0x75 | 0x85 => Platform::SuperChip,
+                _ => return Instruction::decode(opcode).map(|_| Platform::Chip8),
+            },
+            _ => return Instruction::decode(opcode).map(|_| Platform::Chip8),
+        };
+        Some(platform)
+    }
+}
+
+/// Get the pattern of an opcode as written in references, with operands as
+/// lowercase letters (`00E0`, `Dxyn`, `Fx65`...), or `None` if no platform
+/// defines it
+pub fn opcode_pattern(opcode: u16) -> Option<&'static str> {
+    Platform::of_opcode(opcode)?;
+    let n = opcode & 0x000F;
+    let kk = opcode & 0x00FF;
+    let pattern = match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => "00E0",
+            0x00EE => "00EE",
+            0x00FB => "00FB",
+            0x00FC => "00FC",
+            0x00FD => "00FD",
+            0x00FE => "00FE",
+            0x00FF => "00FF",
+            _ if opcode & 0xFFF0 == 0x00C0 => "00Cn",
+            _ if opcode & 0xFFF0 == 0x00D0 => "00Dn",
+            _ => "0nnn",
+        },
+        0x1000 => "1nnn",
+        0x2000 => "2nnn",
+        0x3000 => "3xkk",
+        0x4000 => "4xkk",
+        0x5000 => match n {
+            0x0 => "5xy0",
+            0x2 => "5xy2",
+            _ => "5xy3",
+        },
+        0x6000 => "6xkk",
+        0x7000 => "7xkk",
+        0x8000 => match n {
+            0x0 => "8xy0",
+            0x1 => "8xy1",
+            0x2 => "8xy2",
+            0x3 => "8xy3",
+            0x4 => "8xy4",
+            0x5 => "8xy5",
+            0x6 => "8xy6",
+            0x7 => "8xy7",
+            _ => "8xyE",
+        },
+        0x9000 => "9xy0",
+        0xA000 => "Annn",
+        0xB000 => "Bnnn",
+        0xC000 => "Cxkk",
+        0xD000 if n == 0x0 => "Dxy0",
+        0xD000 => "Dxyn",
+        0xE000 if kk == 0x9E => "Ex9E",
+        0xE000 => "ExA1",
+        _ => match (opcode, kk) {
+            (0xF000, _) => "F000",
+            (0xF002, _) => "F002",
+            (_, 0x01) => "Fn01",
+            (_, 0x07) => "Fx07",
+            (_, 0x0A) => "Fx0A",
+            (_, 0x15) => "Fx15",
+            (_, 0x18) => "Fx18",
+            (_, 0x1E) => "Fx1E",
+            (_, 0x29) => "Fx29",
+            (_, 0x30) => "Fx30",
+            (_, 0x33) => "Fx33",
+            (_, 0x3A) => "Fx3A",
+            (_, 0x55) => "Fx55",
+            (_, 0x65) => "Fx65",
+            (_, 0x75) => "Fx75",
+            _ => "Fx85",
+        },
+    };
+    Some(pattern)
+}
+
+/// A class of opcodes: the ones whose bits set in `mask` are those of
+/// `value`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OpcodeMask {
+    pub mask: u16,
+    pub value: u16,
+}
+
+impl OpcodeMask {
+    /// Check whether an opcode is of the class
+    pub fn matches(self, opcode: u16) -> bool {
+        opcode & self.mask == self.value
+    }
+}
+
+impl FromStr for OpcodeMask {
+    type Err = String;
+
+    /// Parse a pattern like the ones of [opcode_pattern], where hexadecimal
+    /// digits must match and `x`, `y`, `n` and `k` match any digit (`Fx18`,
+    /// `Dxyn`, `D01n`), or `<value>/<mask>` in hexadecimal
+    /// (`0xF018/0xF0FF`)
+    fn from_str(s: &str) -> Result<OpcodeMask, String> {
+        let invalid = || format!("invalid opcode pattern \"{}\", expected one like Fx18 or 0xF018/0xF0FF", s);
+        if let Some((value, mask)) = s.split_once('/') {
+            let parse = |hex: &str| u16::from_str_radix(hex.trim().trim_start_matches("0x"), 16).map_err(|_| invalid());
+            let (value, mask) = (parse(value)?, parse(mask)?);
+            return Ok(OpcodeMask { mask, value: value & mask });
+        }
+        if s.chars().count() != 4 {
+            return Err(invalid());
+        }
+        let mut opcode_mask = OpcodeMask { mask: 0, value: 0 };
+        for c in s.chars() {
+            opcode_mask.mask <<= 4;
+            opcode_mask.value <<= 4;
+            match c {
+                'x' | 'y' | 'n' | 'k' | 'X' | 'Y' | 'N' | 'K' => {}
+                _ => {
+                    opcode_mask.mask |= 0xF;
+                    opcode_mask.value |= c.to_digit(16).ok_or_else(invalid)? as u16;
+                }
+            }
+        }
+        Ok(opcode_mask)
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Platform::Chip8 => write!(f, "CHIP-8"),
+            Platform::SuperChip => write!(f, "SUPER-CHIP"),
+            Platform::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
+
+/// The `SYS` addresses reserved for debug traps, see [DebugTrap]
+pub const DEBUG_TRAP_ADDRESSES: RangeInclusive<Address> = 0xFF0..=0xFFF;
+
+/// A debug trap: a `SYS` instruction of [DEBUG_TRAP_ADDRESSES], with its
+/// own mnemonic, which interpreters ignore unless told to stop or print
+/// at it. The addresses after 0FF2 are reserved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DebugTrap {
+    /// `BRK` (0FF0): pause the program
+    Break,
+    /// `PRINT` (0FF1): print the V registers and I
+    PrintRegisters,
+    /// `PRINT [I]` (0FF2): print the 16 bytes of memory at I
+    PrintMemory,
+}
+
+impl DebugTrap {
+    /// Get the trap of a `SYS` address, if it is one
+    pub fn of_address(address: Address) -> Option<DebugTrap> {
+        match address {
+            0xFF0 => Some(DebugTrap::Break),
+            0xFF1 => Some(DebugTrap::PrintRegisters),
+            0xFF2 => Some(DebugTrap::PrintMemory),
+            _ => None,
+        }
+    }
+
+    /// The `SYS` address of the trap
+    pub fn address(self) -> Address {
+        match self {
+            DebugTrap::Break => 0xFF0,
+            DebugTrap::PrintRegisters => 0xFF1,
+            DebugTrap::PrintMemory => 0xFF2,
+        }
+    }
+}
+
+impl fmt::Display for DebugTrap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DebugTrap::Break => write!(f, "BRK"),
+            DebugTrap::PrintRegisters => write!(f, "PRINT"),
+            DebugTrap::PrintMemory => write!(f, "PRINT [I]"),
+        }
+    }
+}
+
+/// A single decoded CHIP-8 instruction
+///
+/// Registers are 4-bit and addresses are 12-bit; [Instruction::encode] masks
+/// out anything wider.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    /// `CLS` (00E0): clear the display
+    Cls,
+    /// `RET` (00EE): return from a subroutine
+    Ret,
+    /// `SYS addr` (0nnn): jump to a machine code routine
+    Sys(Address),
+    /// `JP addr` (1nnn): jump to an address
+    Jp(Address),
+    /// `CALL addr` (2nnn): call a subroutine
+    Call(Address),
+    /// `SE Vx, byte` (3xkk): skip next instruction if Vx == byte
+    SeByte(Register, u8),
+    /// `SNE Vx, byte` (4xkk): skip next instruction if Vx != byte
+    SneByte(Register, u8),
+    /// `SE Vx, Vy` (5xy0): skip next instruction if Vx == Vy
+    SeReg(Register, Register),
+    /// `LD Vx, byte` (6xkk): set Vx = byte
+    LdByte(Register, u8),
+    /// `ADD Vx, byte` (7xkk): set Vx = Vx + byte
+    AddByte(Register, u8),
+    /// `LD Vx, Vy` (8xy0): set Vx = Vy
+    LdReg(Register, Register),
+    /// `OR Vx, Vy` (8xy1): set Vx = Vx | Vy
+    Or(Register, Register),
+    /// `AND Vx, Vy` (8xy2): set Vx = Vx & Vy
+    And(Register, Register),
+    /// `XOR Vx, Vy` (8xy3): set Vx = Vx ^ Vy
+    Xor(Register, Register),
+    /// `ADD Vx, Vy` (8xy4): set Vx = Vx + Vy, VF = carry
+    AddReg(Register, Register),
+    /// `SUB Vx, Vy` (8xy5): set Vx = Vx - Vy, VF = NOT borrow
+    Sub(Register, Register),
+    /// `SHR Vx, Vy` (8xy6): set Vx = Vy >> 1, VF = shifted out bit
+    Shr(Register, Register),
+    /// `SUBN Vx, Vy` (8xy7): set Vx = Vy - Vx, VF = NOT borrow
+    Subn(Register, Register),
+    /// `SHL Vx, Vy` (8xyE): set Vx = Vy << 1, VF = shifted out bit
+    Shl(Register, Register),
+    /// `SNE Vx, Vy` (9xy0): skip next instruction if Vx != Vy
+    SneReg(Register, Register),
+    /// `LD I, addr` (Annn): set I = addr
+    LdI(Address),
+    /// `JP V0, addr` (Bnnn): jump to addr + V0
+    JpV0(Address),
+    /// `RND Vx, byte` (Cxkk): set Vx = random byte & byte
+    Rnd(Register, u8),
+    /// `DRW Vx, Vy, nibble` (Dxyn): draw an n-byte sprite at (Vx, Vy)
+    Drw(Register, Register, u8),
+    /// `SKP Vx` (Ex9E): skip next instruction if key Vx is pressed
+    Skp(Register),
+    /// `SKNP Vx` (ExA1): skip next instruction if key Vx is not pressed
+    Sknp(Register),
+    /// `LD Vx, DT` (Fx07): set Vx = delay timer
+    LdVxDt(Register),
+    /// `LD Vx, K` (Fx0A): wait for a key press, store it in Vx
+    LdVxK(Register),
+    /// `LD DT, Vx` (Fx15): set delay timer = Vx
+    LdDtVx(Register),
+    /// `LD ST, Vx` (Fx18): set sound timer = Vx
+    LdStVx(Register),
+    /// `ADD I, Vx` (Fx1E): set I = I + Vx
+    AddIVx(Register),
+    /// `LD F, Vx` (Fx29): set I = location of the font sprite for digit Vx
+    LdFVx(Register),
+    /// `LD B, Vx` (Fx33): store BCD of Vx at I, I+1 and I+2
+    LdBVx(Register),
+    /// `LD [I], Vx` (Fx55): store V0..Vx in memory starting at I
+    LdMemVx(Register),
+    /// `LD Vx, [I]` (Fx65): read V0..Vx from memory starting at I
+    LdVxMem(Register),
+}
+
+impl Instruction {
+    /// Decode an opcode, returning `None` if it is not a valid instruction
+    pub fn decode(opcode: u16) -> Option<Instruction> {
+        let nnn = opcode & 0x0FFF;
+        let kk = (opcode & 0x00FF) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+
+        let instruction = match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00E0 => Instruction::Cls,
+                0x00EE => Instruction::Ret,
+                _ => Instruction::Sys(nnn),
+            },
+            0x1000 => Instruction::Jp(nnn),
+            0x2000 => Instruction::Call(nnn),
+            0x3000 => Instruction::SeByte(x, kk),
+            0x4000 => Instruction::SneByte(x, kk),
+            0x5000 if n == 0x0 => Instruction::SeReg(x, y),
+            0x6000 => Instruction::LdByte(x, kk),
+            0x7000 => Instruction::AddByte(x, kk),
+            0x8000 => match n {
+                0x0 => Instruction::LdReg(x, y),
+                0x1 => Instruction::Or(x, y),
+                0x2 => Instruction::And(x, y),
+                0x3 => Instruction::Xor(x, y),
+                0x4 => Instruction::AddReg(x, y),
+                0x5 => Instruction::Sub(x, y),
+                0x6 => Instruction::Shr(x, y),
+                0x7 => Instruction::Subn(x, y),
+                0xE => Instruction::Shl(x, y),
+                _ => return None,
+            },
+            0x9000 if n == 0x0 => Instruction::SneReg(x, y),
+            0xA000 => Instruction::LdI(nnn),
+            0xB000 => Instruction::JpV0(nnn),
+            0xC000 => Instruction::Rnd(x, kk),
+            0xD000 => Instruction::Drw(x, y, n),
+            0xE000 => match kk {
+                0x9E => Instruction::Skp(x),
+                0xA1 => Instruction::Sknp(x),
+                _ => return None,
+            },
+            0xF000 => match kk {
+                0x07 => Instruction::LdVxDt(x),
+                0x0A => Instruction::LdVxK(x),
+                0x15 => Instruction::LdDtVx(x),
+                0x18 => Instruction::LdStVx(x),
+                0x1E => Instruction::AddIVx(x),
+                0x29 => Instruction::LdFVx(x),
+                0x33 => Instruction::LdBVx(x),
+                0x55 => Instruction::LdMemVx(x),
+                0x65 => Instruction::LdVxMem(x),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(instruction)
+    }
+
+    /// Encode the instruction into its 16-bit opcode
+    pub fn encode(&self) -> u16 {
+        let addr = |nnn: Address| nnn & 0x0FFF;
+        let x = |x: Register| ((x & 0xF) as u16) << 8;
+        let y = |y: Register| ((y & 0xF) as u16) << 4;
+
+        match *self {
+            Instruction::Cls             => 0x00E0,
+            Instruction::Ret             => 0x00EE,
+            Instruction::Sys(nnn)        => addr(nnn),
+            Instruction::Jp(nnn)         => 0x1000 | addr(nnn),
+            Instruction::Call(nnn)       => 0x2000 | addr(nnn),
+            Instruction::SeByte(vx, kk)  => 0x3000 | x(vx) | kk as u16,
+            Instruction::SneByte(vx, kk) => 0x4000 | x(vx) | kk as u16,
+            Instruction::SeReg(vx, vy)   => 0x5000 | x(vx) | y(vy),
+            Instruction::LdByte(vx, kk)  => 0x6000 | x(vx) | kk as u16,
+            Instruction::AddByte(vx, kk) => 0x7000 | x(vx) | kk as u16,
+            Instruction::LdReg(vx, vy)   => 0x8000 | x(vx) | y(vy),
+            Instruction::Or(vx, vy)      => 0x8001 | x(vx) | y(vy),
+            Instruction::And(vx, vy)     => 0x8002 | x(vx) | y(vy),
+            Instruction::Xor(vx, vy)     => 0x8003 | x(vx) | y(vy),
+            Instruction::AddReg(vx, vy)  => 0x8004 | x(vx) | y(vy),
+            Instruction::Sub(vx, vy)     => 0x8005 | x(vx) | y(vy),
+            Instruction::Shr(vx, vy)     => 0x8006 | x(vx) | y(vy),
+            Instruction::Subn(vx, vy)    => 0x8007 | x(vx) | y(vy),
+            Instruction::Shl(vx, vy)     => 0x800E | x(vx) | y(vy),
+            Instruction::SneReg(vx, vy)  => 0x9000 | x(vx) | y(vy),
+            Instruction::LdI(nnn)        => 0xA000 | addr(nnn),
+            Instruction::JpV0(nnn)       => 0xB000 | addr(nnn),
+            Instruction::Rnd(vx, kk)     => 0xC000 | x(vx) | kk as u16,
+            Instruction::Drw(vx, vy, n)  => 0xD000 | x(vx) | y(vy) | (n & 0xF) as u16,
+            Instruction::Skp(vx)         => 0xE09E | x(vx),
+            Instruction::Sknp(vx)        => 0xE0A1 | x(vx),
+            Instruction::LdVxDt(vx)      => 0xF007 | x(vx),
+            Instruction::LdVxK(vx)       => 0xF00A | x(vx),
+            Instruction::LdDtVx(vx)      => 0xF015 | x(vx),
+            Instruction::LdStVx(vx)      => 0xF018 | x(vx),
+            Instruction::AddIVx(vx)      => 0xF01E | x(vx),
+            Instruction::LdFVx(vx)       => 0xF029 | x(vx),
+            Instruction::LdBVx(vx)       => 0xF033 | x(vx),
+            Instruction::LdMemVx(vx)     => 0xF055 | x(vx),
+            Instruction::LdVxMem(vx)     => 0xF065 | x(vx),
+        }
+    }
+}
+
+/// Formats the instruction as assembly accepted by the assembler
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Cls             => write!(f, "CLS"),
+            Instruction::Ret             => write!(f, "RET"),
+            Instruction::Sys(nnn)        => match DebugTrap::of_address(nnn) {
+                Some(trap) => write!(f, "{}", trap),
+                None => write!(f, "SYS 0x{:03X}", nnn),
+            },
+            Instruction::Jp(nnn)         => write!(f, "JP 0x{:03X}",          nnn),
+            Instruction::Call(nnn)       => write!(f, "CALL 0x{:03X}",        nnn),
+            Instruction::SeByte(x, kk)   => write!(f, "SE V{:X}, 0x{:02X}",   x, kk),
+            Instruction::SneByte(x, kk)  => write!(f, "SNE V{:X}, 0x{:02X}",  x, kk),
+            Instruction::SeReg(x, y)     => write!(f, "SE V{:X}, V{:X}",      x, y),
+            Instruction::LdByte(x, kk)   => write!(f, "LD V{:X}, 0x{:02X}",   x, kk),
+            Instruction::AddByte(x, kk)  => write!(f, "ADD V{:X}, 0x{:02X}",  x, kk),
+            Instruction::LdReg(x, y)     => write!(f, "LD V{:X}, V{:X}",      x, y),
+            Instruction::Or(x, y)        => write!(f, "OR V{:X}, V{:X}",      x, y),
+            Instruction::And(x, y)       => write!(f, "AND V{:X}, V{:X}",     x, y),
+            Instruction::Xor(x, y)       => write!(f, "XOR V{:X}, V{:X}",     x, y),
+            Instruction::AddReg(x, y)    => write!(f, "ADD V{:X}, V{:X}",     x, y),
+            Instruction::Sub(x, y)       => write!(f, "SUB V{:X}, V{:X}",     x, y),
+            Instruction::Shr(x, y)       => write!(f, "SHR V{:X}, V{:X}",     x, y),
+            Instruction::Subn(x, y)      => write!(f, "SUBN V{:X}, V{:X}",    x, y),
+            Instruction::Shl(x, y)       => write!(f, "SHL V{:X}, V{:X}",     x, y),
+            Instruction::SneReg(x, y)    => write!(f, "SNE V{:X}, V{:X}",     x, y),
+            Instruction::LdI(nnn)        => write!(f, "LD I, 0x{:03X}",       nnn),
+            Instruction::JpV0(nnn)       => write!(f, "JP V0, 0x{:03X}",      nnn),
+            Instruction::Rnd(x, kk)      => write!(f, "RND V{:X}, 0x{:02X}",  x, kk),
+            Instruction::Drw(x, y, n)    => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp(x)          => write!(f, "SKP V{:X}",            x),
+            Instruction::Sknp(x)         => write!(f, "SKNP V{:X}",           x),
+            Instruction::LdVxDt(x)       => write!(f, "LD V{:X}, DT",         x),
+            Instruction::LdVxK(x)        => write!(f, "LD V{:X}, K",          x),
+            Instruction::LdDtVx(x)       => write!(f, "LD DT, V{:X}",         x),
+            Instruction::LdStVx(x)       => write!(f, "LD ST, V{:X}",         x),
+            Instruction::AddIVx(x)       => write!(f, "ADD I, V{:X}",         x),
+            Instruction::LdFVx(x)        => write!(f, "LD F, V{:X}",          x),
+            Instruction::LdBVx(x)        => write!(f, "LD B, V{:X}",          x),
+            Instruction::LdMemVx(x)      => write!(f, "LD [I], V{:X}",        x),
+            Instruction::LdVxMem(x)      => write!(f, "LD V{:X}, [I]",        x),
+        }
+    }
+}
@@ -2,7 +2,19 @@
 //! - disassembler
 //! - assembler
 //! - interpreter
+//! - debugger
 
 pub mod assembler;
+pub mod base64;
+pub mod database;
+pub mod debugger;
+pub mod disassembler;
+pub mod frontend;
+pub mod hash;
 pub mod interpreter;
-pub mod logging;
\ No newline at end of file
+pub mod isa;
+pub mod json;
+pub mod logging;
+pub mod png;
+pub mod settings;
+pub mod wav;
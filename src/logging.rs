@@ -1,5 +1,6 @@
 //! Pretty-printing messages to the console
 
+use std::time::{SystemTime, UNIX_EPOCH};
 use colored::Colorize;
 
 /// Pretty-print an error message to the console
@@ -13,3 +14,35 @@ pub fn warning(message: String, line_number: usize) {
     let warning = "warning:".yellow().bold();
     eprintln!("{} line {}: {}", warning, line_number, message);
 }
+
+/// Pretty-print a warning caused by the instruction at `pc` to the console
+pub fn runtime_warning(message: String, pc: usize) {
+    let warning = "warning:".yellow().bold();
+    eprintln!("{} PC 0x{:03X}: {}", warning, pc, message);
+}
+
+/// Pretty-print a warning about a file to the console
+pub fn file_warning(message: String, path: &str) {
+    let warning = "warning:".yellow().bold();
+    eprintln!("{} {}: {}", warning, path, message);
+}
+
+/// Format a time as `YYYYMMDD-HHMMSS`, in UTC
+pub fn timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    let (days, seconds_of_day) = (seconds / 86400, seconds % 86400);
+    // Civil from days, by Howard Hinnant: years start in March, so that
+    // leap days are at their end
+    let era_days = days + 719468;
+    let day_of_era = era_days % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = era_days / 146097 * 400 + year_of_era + (month <= 2) as u64;
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year, month, day, seconds_of_day / 3600, seconds_of_day / 60 % 60, seconds_of_day % 60
+    )
+}
@@ -0,0 +1,482 @@
+//! A minimal PNG decoder and encoder, enough to read and take screenshots
+//!
+//! Every color type and bit depth is supported, but interlaced images are
+//! not. Checksums are not verified. Images are decoded to 8-bit RGBA, and
+//! encoded as 8-bit RGBA without compression.
+
+use std::fmt;
+use std::ops::Range;
+use crate::hash::crc32;
+use crate::interpreter::{Display, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+const SIGNATURE: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+const TRUNCATED_CHUNK: Error = Error::Malformed { message: "truncated chunk" };
+
+/// A decoded image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// RGBA pixels, row by row
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl Image {
+    /// The pixel at (x, y)
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Reduce a capture of the whole display, at any scale and in any
+    /// colors, to CHIP-8 pixels. Every pixel is lit if the average brightness
+    /// of its area is closer to the brightest area of the image than to the
+    /// darkest one
+    pub fn to_display(&self) -> Display {
+        if self.width == 0 || self.height == 0 {
+            return [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        }
+        let mut brightness = [[0.0f64; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        for (y, row) in brightness.iter_mut().enumerate() {
+            let ys = cell_area(y, DISPLAY_HEIGHT, self.height);
+            for (x, cell) in row.iter_mut().enumerate() {
+                let xs = cell_area(x, DISPLAY_WIDTH, self.width);
+                let mut sum = 0.0;
+                for y in ys.clone() {
+                    for x in xs.clone() {
+                        let [r, g, b, _] = self.pixel(x, y);
+                        sum += 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64;
+                    }
+                }
+                *cell = sum / (ys.len() * xs.len()) as f64;
+            }
+        }
+
+        let cells = brightness.iter().flatten();
+        let darkest = cells.clone().copied().fold(f64::INFINITY, f64::min);
+        let brightest = cells.copied().fold(f64::NEG_INFINITY, f64::max);
+        // A uniform image is either all lit or all off
+        let threshold = if brightest - darkest < 1.0 { 127.5 } else { (darkest + brightest) / 2.0 };
+        brightness.map(|row| row.map(|cell| cell > threshold))
+    }
+}
+
+/// The image pixels covered by one of `n_cells` cells, at least one pixel
+/// wide even if the image is smaller than the grid
+fn cell_area(cell: usize, n_cells: usize, size: usize) -> Range<usize> {
+    let start = (cell * size / n_cells).min(size - 1);
+    start..((cell + 1) * size / n_cells).max(start + 1)
+}
+
+/// Decode a PNG file
+pub fn decode(data: &[u8]) -> Result<Image, Error> {
+    let mut chunks = data.strip_prefix(SIGNATURE).ok_or(Error::NotPng)?;
+    let mut header = None;
+    let mut palette: Vec<[u8; 4]> = Vec::new();
+    let mut compressed = Vec::new();
+
+    while !chunks.is_empty() {
+        let length = u32::from_be_bytes(chunks.get(0..4).ok_or(TRUNCATED_CHUNK)?.try_into().unwrap()) as usize;
+        let kind = chunks.get(4..8).ok_or(TRUNCATED_CHUNK)?;
+        let body = chunks.get(8..8 + length).ok_or(TRUNCATED_CHUNK)?;
+        match kind {
+            b"IHDR" => header = Some(Header::parse(body)?),
+            b"PLTE" => palette = body.chunks_exact(3).map(|rgb| [rgb[0], rgb[1], rgb[2], 0xFF]).collect(),
+            b"tRNS" => {
+                for (entry, &alpha) in palette.iter_mut().zip(body) {
+                    entry[3] = alpha;
+                }
+            }
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+        // Skip the chunk and its CRC
+        chunks = chunks.get(12 + length..).ok_or(TRUNCATED_CHUNK)?;
+    }
+
+    let header = header.ok_or(Error::Malformed { message: "missing IHDR chunk" })?;
+    // Skip the 2-byte zlib header, the Adler-32 checksum is ignored
+    let filtered = inflate(compressed.get(2..).ok_or(Error::Malformed { message: "missing image data" })?)?;
+    let raw = unfilter(&header, &filtered)?;
+    Ok(header.to_rgba(&raw, &palette))
+}
+
+/// Encode an image as a PNG file
+pub fn encode(image: &Image) -> Vec<u8> {
+    // Every row starts with its filter type, none
+    let mut raw = Vec::with_capacity(image.height * (1 + image.width * 4));
+    for row in image.pixels.chunks(image.width.max(1)) {
+        raw.push(0);
+        raw.extend_from_slice(row.as_flattened());
+    }
+
+    // A zlib stream of stored deflate blocks
+    let mut compressed = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = if raw.is_empty() { vec![&[]] } else { raw.chunks(0xFFFF).collect() };
+    for (index, block) in blocks.iter().enumerate() {
+        compressed.push((index == blocks.len() - 1) as u8);
+        compressed.extend_from_slice(&(block.len() as u16).to_le_bytes());
+        compressed.extend_from_slice(&(!(block.len() as u16)).to_le_bytes());
+        compressed.extend_from_slice(block);
+    }
+    compressed.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(image.width as u32).to_be_bytes());
+    header.extend_from_slice(&(image.height as u32).to_be_bytes());
+    // 8 bits per channel, RGBA, deflate, adaptive filtering, not interlaced
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    for (kind, body) in [(b"IHDR", &header[..]), (b"IDAT", &compressed[..]), (b"IEND", &[])] {
+        png.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(kind);
+        png.extend_from_slice(body);
+        let crc = crc32(&png[start..]);
+        png.extend_from_slice(&crc.to_be_bytes());
+    }
+    png
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+}
+
+impl Header {
+    fn parse(body: &[u8]) -> Result<Header, Error> {
+        if body.len() != 13 {
+            return Err(Error::Malformed { message: "invalid IHDR chunk" });
+        }
+        let header = Header {
+            width: u32::from_be_bytes(body[0..4].try_into().unwrap()) as usize,
+            height: u32::from_be_bytes(body[4..8].try_into().unwrap()) as usize,
+            bit_depth: body[8],
+            color_type: body[9],
+        };
+        if body[12] != 0 {
+            return Err(Error::Unsupported { feature: "interlaced images" });
+        }
+        let valid_depths: &[u8] = match header.color_type {
+            0 => &[1, 2, 4, 8, 16],
+            3 => &[1, 2, 4, 8],
+            2 | 4 | 6 => &[8, 16],
+            _ => return Err(Error::Malformed { message: "invalid color type" }),
+        };
+        if !valid_depths.contains(&header.bit_depth) {
+            return Err(Error::Malformed { message: "invalid bit depth" });
+        }
+        if header.width == 0 || header.height == 0 {
+            return Err(Error::Malformed { message: "empty image" });
+        }
+        // The sizes of the filtered rows and of the decoded pixels, which
+        // crafted files make overflow
+        let filtered_size = header.width.checked_mul(header.bits_per_pixel())
+            .and_then(|row_bits| (row_bits.div_ceil(8) + 1).checked_mul(header.height));
+        let decoded_size = header.width.checked_mul(header.height).and_then(|pixels| pixels.checked_mul(4));
+        if filtered_size.is_none() || decoded_size.is_none() {
+            return Err(Error::Malformed { message: "image too large" });
+        }
+        Ok(header)
+    }
+
+    fn channels(&self) -> usize {
+        match self.color_type {
+            2 => 3,
+            4 => 2,
+            6 => 4,
+            _ => 1,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    fn row_bytes(&self) -> usize {
+        (self.width * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    fn to_rgba(&self, raw: &[u8], palette: &[[u8; 4]]) -> Image {
+        let row_bytes = self.row_bytes();
+        let mut pixels = Vec::with_capacity(self.width * self.height);
+        for row in raw.chunks_exact(row_bytes) {
+            for x in 0..self.width {
+                // Samples are reduced to their 8 most significant bits
+                let sample = |channel: usize| -> u8 {
+                    let bit_depth = self.bit_depth as usize;
+                    let bit = (x * self.channels() + channel) * bit_depth;
+                    match bit_depth {
+                        8 | 16 => row[bit / 8],
+                        _ => {
+                            let value = (row[bit / 8] >> (8 - bit_depth - bit % 8)) & ((1 << bit_depth) - 1);
+                            if self.color_type == 3 { value } else { value * (255 / ((1 << bit_depth) - 1)) }
+                        }
+                    }
+                };
+                pixels.push(match self.color_type {
+                    0 => [sample(0), sample(0), sample(0), 0xFF],
+                    2 => [sample(0), sample(1), sample(2), 0xFF],
+                    3 => palette.get(sample(0) as usize).copied().unwrap_or([0, 0, 0, 0xFF]),
+                    4 => [sample(0), sample(0), sample(0), sample(1)],
+                    _ => [sample(0), sample(1), sample(2), sample(3)],
+                });
+            }
+        }
+        Image { width: self.width, height: self.height, pixels }
+    }
+}
+
+fn unfilter(header: &Header, filtered: &[u8]) -> Result<Vec<u8>, Error> {
+    let row_bytes = header.row_bytes();
+    // Filters work on bytes, comparing with the same channel of the pixel to
+    // the left, or the previous byte for pixels smaller than a byte
+    let bpp = header.bits_per_pixel().div_ceil(8);
+    if filtered.len() < (row_bytes + 1) * header.height {
+        return Err(Error::Malformed { message: "not enough image data" });
+    }
+
+    let mut raw = vec![0u8; row_bytes * header.height];
+    for y in 0..header.height {
+        let line = &filtered[y * (row_bytes + 1)..(y + 1) * (row_bytes + 1)];
+        let (filter, line) = (line[0], &line[1..]);
+        let (previous_rows, rows) = raw.split_at_mut(y * row_bytes);
+        let up_row = previous_rows.get(previous_rows.len().saturating_sub(row_bytes)..).filter(|_| y > 0);
+        let row = &mut rows[..row_bytes];
+        for i in 0..row_bytes {
+            let left = if i >= bpp { row[i - bpp] } else { 0 };
+            let up = up_row.map_or(0, |up_row| up_row[i]);
+            let up_left = if i >= bpp { up_row.map_or(0, |up_row| up_row[i - bpp]) } else { 0 };
+            let predictor = match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(Error::Malformed { message: "invalid filter type" }),
+            };
+            row[i] = line[i].wrapping_add(predictor);
+        }
+    }
+    Ok(raw)
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, n: usize) -> Result<u32, Error> {
+        let mut value = 0;
+        for i in 0..n {
+            let byte = self.data.get(self.position / 8).ok_or(Error::Malformed { message: "truncated image data" })?;
+            value |= (((byte >> (self.position % 8)) & 1) as u32) << i;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    fn align_to_byte(&mut self) {
+        self.position = self.position.div_ceil(8) * 8;
+    }
+}
+
+/// A canonical Huffman code, as the number of codes of every length and the
+/// symbols sorted by code
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16).filter(|&s| lengths[s as usize] != 0).collect();
+        symbols.sort_by_key(|&s| lengths[s as usize]);
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, Error> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(Error::Malformed { message: "invalid Huffman code" })
+    }
+}
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+/// The order code length code lengths are stored in
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Decompress a raw DEFLATE stream
+fn inflate(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut reader = BitReader { data, position: 0 };
+    let mut out = Vec::new();
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                reader.align_to_byte();
+                let length = reader.bits(16)? as usize;
+                reader.bits(16)?;
+                let start = reader.position / 8;
+                let stored = data.get(start..start + length).ok_or(Error::Malformed { message: "truncated image data" })?;
+                out.extend_from_slice(stored);
+                reader.position += length * 8;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                inflate_block(&mut reader, &mut out, &Huffman::new(&lengths), &Huffman::new(&[5; 30]))?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &literals, &distances)?;
+            }
+            _ => return Err(Error::Malformed { message: "invalid block type" }),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn read_dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), Error> {
+    let n_literals = reader.bits(5)? as usize + 257;
+    let n_distances = reader.bits(5)? as usize + 1;
+    let n_code_lengths = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..n_code_lengths] {
+        code_lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(n_literals + n_distances);
+    while lengths.len() < n_literals + n_distances {
+        let (value, repeat) = match code_length_code.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths.last().ok_or(Error::Malformed { message: "invalid code lengths" })?;
+                (previous, 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat));
+    }
+    if lengths.len() != n_literals + n_distances {
+        return Err(Error::Malformed { message: "invalid code lengths" });
+    }
+    Ok((Huffman::new(&lengths[..n_literals]), Huffman::new(&lengths[n_literals..])))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman
+) -> Result<(), Error> {
+    loop {
+        let symbol = literals.decode(reader)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                let base = *LENGTH_BASES.get(index).ok_or(Error::Malformed { message: "invalid length" })? as usize;
+                let length = base + reader.bits(LENGTH_EXTRA_BITS[index] as usize)? as usize;
+                let index = distances.decode(reader)? as usize;
+                let base = *DISTANCE_BASES.get(index).ok_or(Error::Malformed { message: "invalid distance" })? as usize;
+                let distance = base + reader.bits(DISTANCE_EXTRA_BITS[index] as usize)? as usize;
+                if distance > out.len() {
+                    return Err(Error::Malformed { message: "invalid distance" });
+                }
+                // The copy may overlap the bytes it produces
+                for _ in 0..length {
+                    out.push(out[out.len() - distance]);
+                }
+            }
+        }
+    }
+}
+
+/// An error that can occur while decoding a PNG file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The file does not start with the PNG signature
+    NotPng,
+    /// The file is corrupted
+    Malformed {
+        message: &'static str
+    },
+    /// The file uses a feature that is not supported
+    Unsupported {
+        feature: &'static str
+    },
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotPng => write!(f, "not a PNG file"),
+            Error::Malformed { message } => write!(f, "malformed PNG file: {}", message),
+            Error::Unsupported { feature } => write!(f, "unsupported PNG file: {} are not supported", feature),
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Settings the user changed for particular ROMs, keyed by their SHA-1
+//!
+//! The interpreter remembers the speed, colors, muting and keys a ROM was
+//! last played with when they differ from what it would get anyway, and
+//! applies them the next time it is loaded. They are kept in
+//! `games.txt` in [config_dir], in the format of database files (see
+//! [database](crate::database)):
+//!
+//! ```text
+//! [1ba58656810b67fd131eb9af3e3987863bf26c90]   ; SHA-1 of the ROM
+//! speed = 700                 ; instructions per second
+//! colors = #000000 #FFB000    ; background and foreground
+//! muted = yes                 ; yes or no
+//! key 8 = Up,Keypad 8         ; keyboard keys pressing a CHIP-8 key
+//! ```
+//!
+//! Keys are named as in SDL, like for the `--key` option.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use crate::database::{config_dir, format_color, parse_color, DatabaseError};
+use crate::hash::{sha1, to_hex};
+
+/// The settings of a ROM, `None` (or no keys) where it has the usual ones
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GameSettings {
+    /// Number of instructions to execute per second
+    pub speed: Option<u32>,
+    /// Background and foreground colors, as RGB
+    pub colors: Option<([u8; 3], [u8; 3])>,
+    pub muted: Option<bool>,
+    /// The names of the keyboard keys pressing CHIP-8 keys
+    pub keys: BTreeMap<u8, Vec<String>>,
+}
+
+impl GameSettings {
+    /// Check whether the ROM has the usual settings
+    pub fn is_empty(&self) -> bool {
+        *self == GameSettings::default()
+    }
+}
+
+/// Game settings, keyed by the SHA-1 of the ROM in lowercase hexadecimal
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Settings {
+    games: BTreeMap<String, GameSettings>,
+}
+
+impl Settings {
+    /// Parse the contents of a settings file
+    pub fn parse(source: &str) -> Result<Settings, DatabaseError> {
+        let mut games = BTreeMap::new();
+        let mut current: Option<(String, GameSettings)> = None;
+
+        for (line_index, line) in source.lines().enumerate() {
+            let line_number = line_index + 1;
+            let parse_error = |message: String| DatabaseError::Parse { line_number, message };
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(hash) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+                let hash = hash.trim().to_ascii_lowercase();
+                if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(parse_error(format!("\"{}\" is not a SHA-1", hash)));
+                }
+                if let Some((hash, game)) = current.replace((hash, GameSettings::default())) {
+                    games.insert(hash, game);
+                }
+                continue;
+            }
+
+            let Some((_, game)) = &mut current else {
+                return Err(parse_error("expected a [sha1] section".to_string()));
+            };
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(parse_error("expected `key = value`".to_string()));
+            };
+            let value = value.trim();
+            match key.trim() {
+                "speed" => {
+                    game.speed = Some(value.parse().ok().filter(|speed| *speed > 0)
+                        .ok_or_else(|| parse_error("the speed must be a positive integer".to_string()))?);
+                }
+                "colors" => {
+                    let colors: Option<Vec<[u8; 3]>> = value.split_whitespace().map(parse_color).collect();
+                    let Some(&[background, foreground]) = colors.as_deref() else {
+                        return Err(parse_error("expected two colors such as #000000 #FFFFFF".to_string()));
+                    };
+                    game.colors = Some((background, foreground));
+                }
+                "muted" => {
+                    game.muted = Some(match value {
+                        "yes" => true,
+                        "no" => false,
+                        _ => return Err(parse_error("muted must be yes or no".to_string())),
+                    });
+                }
+                key => {
+                    let chip8_key = key.strip_prefix("key ")
+                        .and_then(|chip8_key| u8::from_str_radix(chip8_key.trim(), 16).ok())
+                        .filter(|chip8_key| *chip8_key < 16)
+                        .ok_or_else(|| parse_error(format!("unknown setting \"{}\"", key)))?;
+                    let names = value.split(',').map(str::trim).filter(|name| !name.is_empty()).map(str::to_string);
+                    game.keys.insert(chip8_key, names.collect());
+                }
+            }
+        }
+
+        if let Some((hash, game)) = current {
+            games.insert(hash, game);
+        }
+        Ok(Settings { games })
+    }
+
+    /// Get the settings of a ROM
+    pub fn get(&self, rom: &[u8]) -> Option<&GameSettings> {
+        self.games.get(&to_hex(&sha1(rom)))
+    }
+
+    /// Replace the settings of a ROM, forgetting them if they are the usual
+    /// ones
+    pub fn set(&mut self, rom: &[u8], game: GameSettings) {
+        let hash = to_hex(&sha1(rom));
+        if game.is_empty() {
+            self.games.remove(&hash);
+        } else {
+            self.games.insert(hash, game);
+        }
+    }
+}
+
+impl fmt::Display for Settings {
+    /// Write the settings in the format read by [Settings::parse]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (hash, game)) in self.games.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "[{}]", hash)?;
+            if let Some(speed) = game.speed {
+                writeln!(f, "speed = {}", speed)?;
+            }
+            if let Some((background, foreground)) = game.colors {
+                writeln!(f, "colors = {} {}", format_color(background), format_color(foreground))?;
+            }
+            if let Some(muted) = game.muted {
+                writeln!(f, "muted = {}", if muted { "yes" } else { "no" })?;
+            }
+            for (chip8_key, names) in &game.keys {
+                writeln!(f, "key {:X} = {}", chip8_key, names.join(","))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The path of the file game settings are kept in, `games.txt` in
+/// [config_dir]
+pub fn settings_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("games.txt"))
+}
@@ -0,0 +1,151 @@
+//! A minimal WAV decoder, enough to read the sounds the buzzer can play
+//!
+//! Integer PCM samples of 8, 16, 24 or 32 bits and 32-bit float samples
+//! are supported, in any number of channels, which are mixed to mono.
+//! Compressed formats are not.
+
+use std::fmt;
+
+const PCM: u16 = 1;
+const FLOAT: u16 = 3;
+/// The format is given by the first two bytes of a GUID after the header
+const EXTENSIBLE: u16 = 0xFFFE;
+const TRUNCATED_CHUNK: Error = Error::Malformed { message: "truncated chunk" };
+
+/// A decoded sound
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sound {
+    /// How many samples there are per second
+    pub sample_rate: u32,
+    /// Mono samples, from -1 to 1
+    pub samples: Vec<f32>,
+}
+
+impl Sound {
+    /// Get the sound at another sample rate, interpolating between samples
+    pub fn resample(&self, sample_rate: u32) -> Vec<f32> {
+        if self.samples.is_empty() || sample_rate == self.sample_rate {
+            return self.samples.clone();
+        }
+        let ratio = self.sample_rate as f64 / sample_rate as f64;
+        let length = (self.samples.len() as f64 / ratio).round().max(1.0) as usize;
+        (0..length).map(|i| {
+            let position = i as f64 * ratio;
+            let index = position as usize;
+            let next = self.samples.get(index + 1).unwrap_or(&self.samples[self.samples.len() - 1]);
+            let current = self.samples[index.min(self.samples.len() - 1)];
+            current + (next - current) * position.fract() as f32
+        }).collect()
+    }
+}
+
+/// Decode a WAV file
+pub fn decode(data: &[u8]) -> Result<Sound, Error> {
+    if data.get(0..4) != Some(b"RIFF") || data.get(8..12) != Some(b"WAVE") {
+        return Err(Error::NotWav);
+    }
+    let mut chunks = &data[12..];
+    let mut format = None;
+
+    while !chunks.is_empty() {
+        let kind = chunks.get(0..4).ok_or(TRUNCATED_CHUNK)?;
+        let length = u32::from_le_bytes(chunks.get(4..8).ok_or(TRUNCATED_CHUNK)?.try_into().unwrap()) as usize;
+        // Some files end with a data chunk longer than the file
+        let body = if kind == b"data" {
+            &chunks[8..chunks.len().min(8 + length)]
+        } else {
+            chunks.get(8..8 + length).ok_or(TRUNCATED_CHUNK)?
+        };
+        match kind {
+            b"fmt " => format = Some(Format::parse(body)?),
+            b"data" => {
+                let format = format.ok_or(Error::Malformed { message: "samples before the format" })?;
+                return Ok(Sound { sample_rate: format.sample_rate, samples: format.decode(body) });
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        chunks = chunks.get(8 + length + length % 2..).unwrap_or_default();
+    }
+    Err(Error::Malformed { message: "no samples" })
+}
+
+/// The contents of the `fmt ` chunk
+#[derive(Debug, Clone, Copy)]
+struct Format {
+    tag: u16,
+    channels: usize,
+    sample_rate: u32,
+    bits_per_sample: usize,
+}
+
+impl Format {
+    fn parse(body: &[u8]) -> Result<Format, Error> {
+        if body.len() < 16 {
+            return Err(Error::Malformed { message: "truncated format" });
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([body[i], body[i + 1]]);
+        let mut tag = u16_at(0);
+        if tag == EXTENSIBLE {
+            tag = if body.len() >= 26 { u16_at(24) } else { 0 };
+        }
+        let format = Format {
+            tag,
+            channels: u16_at(2) as usize,
+            sample_rate: u32::from_le_bytes(body[4..8].try_into().unwrap()),
+            bits_per_sample: u16_at(14) as usize,
+        };
+        match (format.tag, format.bits_per_sample) {
+            (PCM, 8 | 16 | 24 | 32) | (FLOAT, 32) => {}
+            (PCM | FLOAT, _) => return Err(Error::Unsupported { feature: "sample sizes other than 8, 16, 24 and 32 bits" }),
+            _ => return Err(Error::Unsupported { feature: "compressed formats" }),
+        }
+        if format.channels == 0 || format.sample_rate == 0 {
+            return Err(Error::Malformed { message: "no channels or sample rate" });
+        }
+        Ok(format)
+    }
+
+    /// Decode the samples of the `data` chunk, mixing the channels
+    fn decode(&self, body: &[u8]) -> Vec<f32> {
+        let sample_size = self.bits_per_sample / 8;
+        body.chunks_exact(sample_size * self.channels).map(|frame| {
+            let sum: f32 = frame.chunks_exact(sample_size).map(|bytes| match (self.tag, sample_size) {
+                // 8-bit samples are unsigned, the others signed
+                (PCM, 1) => (bytes[0] as f32 - 128.0) / 128.0,
+                (PCM, 2) => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+                (PCM, 3) => i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) as f32 / 2147483648.0,
+                (PCM, _) => i32::from_le_bytes(bytes.try_into().unwrap()) as f32 / 2147483648.0,
+                _ => f32::from_le_bytes(bytes.try_into().unwrap()).clamp(-1.0, 1.0),
+            }).sum();
+            sum / self.channels as f32
+        }).collect()
+    }
+}
+
+/// An error that can occur while decoding a WAV file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The file does not start with the RIFF and WAVE signatures
+    NotWav,
+    /// The file is corrupted
+    Malformed {
+        message: &'static str
+    },
+    /// The file uses a feature that is not supported
+    Unsupported {
+        feature: &'static str
+    },
+}
+
+impl std::error::Error for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotWav => write!(f, "not a WAV file"),
+            Error::Malformed { message } => write!(f, "malformed WAV file: {}", message),
+            Error::Unsupported { feature } => write!(f, "unsupported WAV file: {} are not supported", feature),
+        }
+    }
+}
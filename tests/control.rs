@@ -0,0 +1,278 @@
+//! Parses control requests, and drives a runner through the control
+//! server, over TCP and WebSocket
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use chip8vm::{assembler, base64, png};
+use chip8vm::frontend::control::{serve, Request, MAX_MESSAGE_SIZE, MAX_SCREENSHOT_SCALE, MAX_STEP_COUNT};
+use chip8vm::frontend::runner::{Command, Runner, UPDATE_QUEUE_SIZE};
+use chip8vm::interpreter::{VM, VM_FREQUENCY};
+use chip8vm::json::{self, Value};
+
+fn parse(request: &str) -> Result<Request, String> {
+    Request::parse(&json::parse(request).unwrap())
+}
+
+#[test]
+fn parses_requests() {
+    let cases = [
+        (r#"{"command": "pause"}"#, Request::Pause),
+        (r#"{"command": "resume", "id": 3}"#, Request::Resume),
+        (r#"{"command": "step"}"#, Request::Step(1)),
+        (r#"{"command": "step", "count": 100000}"#, Request::Step(MAX_STEP_COUNT)),
+        (r#"{"command": "state"}"#, Request::State),
+        (r#"{"command": "counters"}"#, Request::Counters),
+        (r#"{"command": "read", "address": 768}"#, Request::Read { address: 768, length: 1 }),
+        (r#"{"command": "read", "address": 4080, "length": 16}"#, Request::Read { address: 4080, length: 16 }),
+        (r#"{"command": "write", "address": 4094, "bytes": [1, 255]}"#, Request::Write { address: 4094, bytes: vec![1, 255] }),
+        (r#"{"command": "screenshot"}"#, Request::Screenshot { scale: 4 }),
+        (r#"{"command": "screenshot", "scale": 16}"#, Request::Screenshot { scale: MAX_SCREENSHOT_SCALE }),
+        (r#"{"command": "key", "key": 15, "pressed": true}"#, Request::Key { key: 0xF, pressed: true }),
+    ];
+    for (request, expected) in cases {
+        assert_eq!(parse(request), Ok(expected), "{}", request);
+    }
+}
+
+#[test]
+fn rejects_invalid_requests() {
+    let invalid = [
+        r#"{}"#,
+        r#"{"command": 1}"#,
+        r#"{"command": "jump"}"#,
+        r#"{"command": "step", "count": 100001}"#,
+        r#"{"command": "step", "count": -1}"#,
+        r#"{"command": "step", "count": 1.5}"#,
+        r#"{"command": "step", "count": "10"}"#,
+        r#"{"command": "read"}"#,
+        r#"{"command": "read", "address": 4096}"#,
+        r#"{"command": "read", "address": 4080, "length": 17}"#,
+        r#"{"command": "write", "address": 768}"#,
+        r#"{"command": "write", "address": 4095, "bytes": [1, 2]}"#,
+        r#"{"command": "write", "address": 768, "bytes": [256]}"#,
+        r#"{"command": "write", "address": 768, "bytes": [-1]}"#,
+        r#"{"command": "screenshot", "scale": 0}"#,
+        r#"{"command": "screenshot", "scale": 17}"#,
+        r#"{"command": "key", "key": 16, "pressed": true}"#,
+        r#"{"command": "key", "key": 1}"#,
+    ];
+    for request in invalid {
+        assert!(parse(request).is_err(), "{}", request);
+    }
+}
+
+/// Quits the runner when the test ends, even failing
+struct Quit(Sender<Command>);
+
+impl Drop for Quit {
+    fn drop(&mut self) {
+        let _ = self.0.send(Command::Quit);
+    }
+}
+
+/// Run a program looping forever with a control server, and a test
+/// connecting to it
+fn with_server(test: impl FnOnce(SocketAddr)) {
+    let mut vm = VM::new();
+    vm.load_program(&assembler::assemble("loop:\nADD V0, 1\nJP loop").unwrap());
+    let (commands, receiver) = mpsc::channel();
+    let (updates, updates_receiver) = mpsc::sync_channel(UPDATE_QUEUE_SIZE);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server_commands = commands.clone();
+    thread::spawn(move || serve(listener, server_commands));
+    thread::spawn(move || updates_receiver.into_iter().for_each(drop));
+    thread::scope(|scope| {
+        let runner = Runner::new(&mut vm, receiver, updates, VM_FREQUENCY);
+        scope.spawn(move || runner.run());
+        let _quit = Quit(commands);
+        test(address);
+    });
+}
+
+/// Send a request on a line, and read the answer
+fn request(stream: &mut BufReader<TcpStream>, request: &str) -> Value {
+    writeln!(stream.get_mut(), "{}", request).unwrap();
+    let mut line = String::new();
+    stream.read_line(&mut line).unwrap();
+    json::parse(&line).unwrap()
+}
+
+fn ok(answer: &Value) -> bool {
+    answer.get("ok").and_then(Value::as_bool).unwrap()
+}
+
+#[test]
+fn answers_requests_on_lines() {
+    with_server(|address| {
+        let mut stream = BufReader::new(TcpStream::connect(address).unwrap());
+        let answer = request(&mut stream, r#"{"id": "a", "command": "pause"}"#);
+        assert_eq!(answer.to_string(), r#"{"id": "a", "ok": true}"#);
+
+        assert!(ok(&request(&mut stream, r#"{"command": "write", "address": 768, "bytes": [1, 2, 3]}"#)));
+        let answer = request(&mut stream, r#"{"command": "read", "address": 768, "length": 4}"#);
+        assert_eq!(answer.get("bytes").unwrap().to_string(), "[1, 2, 3, 0]");
+
+        let cycles = |answer: &Value| answer.get("state").and_then(|state| state.get("cycles")).and_then(Value::as_f64);
+        let before = cycles(&request(&mut stream, r#"{"command": "state"}"#)).unwrap();
+        let answer = request(&mut stream, r#"{"command": "step", "count": 3}"#);
+        assert_eq!(answer.get("steps").and_then(Value::as_f64), Some(3.0));
+        assert_eq!(answer.get("cycles").and_then(Value::as_f64), Some(before + 3.0));
+
+        let answer = request(&mut stream, r#"{"command": "screenshot", "scale": 2}"#);
+        let data = base64::decode(answer.get("png").and_then(Value::as_str).unwrap()).unwrap();
+        let image = png::decode(&data).unwrap();
+        assert_eq!((image.width, image.height), (128, 64));
+
+        for invalid in [r#"{"id": 7, "command": "read", "address": 4096}"#, r#"{"id": 7, "command": "jump"}"#] {
+            let answer = request(&mut stream, invalid);
+            assert!(!ok(&answer));
+            assert_eq!(answer.get("id").and_then(Value::as_f64), Some(7.0));
+        }
+        assert!(!ok(&request(&mut stream, "{not json")));
+        assert!(ok(&request(&mut stream, r#"{"command": "resume"}"#)));
+    });
+}
+
+/// Open a WebSocket connection, with the example key of RFC 6455
+fn websocket(address: SocketAddr) -> BufReader<TcpStream> {
+    let mut stream = BufReader::new(TcpStream::connect(address).unwrap());
+    write!(
+        stream.get_mut(),
+        "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n"
+    ).unwrap();
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        stream.read_line(&mut line).unwrap();
+        if line.trim().is_empty() {
+            break;
+        }
+        headers.push(line.trim().to_string());
+    }
+    assert_eq!(headers[0], "HTTP/1.1 101 Switching Protocols");
+    assert!(headers.contains(&"Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=".to_string()));
+    stream
+}
+
+/// Write a frame, whole, masked if `mask` is given
+fn write_frame(stream: &mut TcpStream, opcode: u8, payload: &[u8], mask: Option<[u8; 4]>) {
+    let mut frame = vec![0x80 | opcode];
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        length @ 0..=125 => frame.push(mask_bit | length as u8),
+        length => {
+            frame.push(mask_bit | 127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    if let Some(mask) = mask {
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+    } else {
+        frame.extend_from_slice(payload);
+    }
+    stream.write_all(&frame).unwrap();
+}
+
+/// Read an unmasked frame of the server, returning its opcode and payload
+fn read_frame(stream: &mut impl Read) -> (u8, Vec<u8>) {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).unwrap();
+    assert_eq!(header[0] & 0x80, 0x80, "fragmented frame");
+    assert_eq!(header[1] & 0x80, 0, "masked frame");
+    let length = match header[1] {
+        126 => {
+            let mut length = [0u8; 2];
+            stream.read_exact(&mut length).unwrap();
+            u16::from_be_bytes(length) as usize
+        }
+        length => length as usize,
+    };
+    let mut payload = vec![0; length];
+    stream.read_exact(&mut payload).unwrap();
+    (header[0] & 0x0F, payload)
+}
+
+/// Check that the server closes the connection after a protocol error
+fn assert_closed(stream: &mut BufReader<TcpStream>) {
+    let (opcode, payload) = read_frame(stream);
+    assert_eq!(opcode, 0x8);
+    assert_eq!(payload[..2], 1002u16.to_be_bytes());
+    assert_eq!(stream.read(&mut [0]).unwrap(), 0);
+}
+
+#[test]
+fn answers_websocket_messages() {
+    with_server(|address| {
+        let mut stream = websocket(address);
+        let mask = Some([0x12, 0x34, 0x56, 0x78]);
+        write_frame(stream.get_mut(), 0x1, br#"{"id": 1, "command": "read", "address": 0, "length": 5}"#, mask);
+        let (opcode, payload) = read_frame(&mut stream);
+        assert_eq!(opcode, 0x1);
+        assert_eq!(String::from_utf8(payload).unwrap(), r#"{"id": 1, "ok": true, "bytes": [240, 144, 144, 144, 240]}"#);
+
+        // A message in two frames
+        let request = br#"{"id": 2, "command": "counters"}"#;
+        let mut first = vec![0x01, 0x80 | 10];
+        first.extend_from_slice(&[0; 4]);
+        first.extend_from_slice(&request[..10]);
+        stream.get_mut().write_all(&first).unwrap();
+        let mut last = vec![0x80, 0x80 | (request.len() - 10) as u8];
+        last.extend_from_slice(&[0; 4]);
+        last.extend_from_slice(&request[10..]);
+        stream.get_mut().write_all(&last).unwrap();
+        let (_, payload) = read_frame(&mut stream);
+        assert!(String::from_utf8(payload).unwrap().starts_with(r#"{"id": 2, "ok": true"#));
+
+        write_frame(stream.get_mut(), 0x9, b"ping", mask);
+        assert_eq!(read_frame(&mut stream), (0xA, b"ping".to_vec()));
+
+        write_frame(stream.get_mut(), 0x8, &1000u16.to_be_bytes(), mask);
+        assert_eq!(read_frame(&mut stream), (0x8, 1000u16.to_be_bytes().to_vec()));
+    });
+}
+
+#[test]
+fn closes_on_unmasked_frames() {
+    with_server(|address| {
+        let mut stream = websocket(address);
+        write_frame(stream.get_mut(), 0x1, br#"{"command": "state"}"#, None);
+        assert_closed(&mut stream);
+    });
+}
+
+#[test]
+fn closes_on_oversized_messages() {
+    with_server(|address| {
+        let mut stream = websocket(address);
+        let mut header = vec![0x81, 0x80 | 127];
+        header.extend_from_slice(&(MAX_MESSAGE_SIZE as u64 + 1).to_be_bytes());
+        stream.get_mut().write_all(&header).unwrap();
+        assert_closed(&mut stream);
+
+        let mut stream = websocket(address);
+        let half = vec![b' '; MAX_MESSAGE_SIZE / 2 + 1];
+        let mut first = vec![0x01, 0x80 | 127];
+        first.extend_from_slice(&(half.len() as u64).to_be_bytes());
+        first.extend_from_slice(&[0; 4]);
+        first.extend_from_slice(&half);
+        stream.get_mut().write_all(&first).unwrap();
+        write_frame(stream.get_mut(), 0x0, &half, Some([0; 4]));
+        assert_closed(&mut stream);
+    });
+}
+
+#[test]
+fn rejects_handshakes_without_a_key() {
+    with_server(|address| {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut answer = String::new();
+        stream.read_to_string(&mut answer).unwrap();
+        assert!(answer.starts_with("HTTP/1.1 400 Bad Request"), "{}", answer);
+    });
+}
@@ -0,0 +1,9 @@
+#!/bin/sh
+# Download the Timendus CHIP-8 test suite used by tests/timendus.rs
+set -e
+cd "$(dirname "$0")"
+mkdir -p roms
+for rom in 1-chip8-logo 2-ibm-logo 3-corax+ 4-flags 5-quirks; do
+    curl -fsSL -o "roms/$rom.ch8" \
+        "https://github.com/Timendus/chip8-test-suite/raw/v4.1/bin/$rom.ch8"
+done
@@ -0,0 +1,134 @@
+//! Runs programs forward, then undoes their instructions and checks that
+//! the machine is back in the states it went through
+
+use chip8vm::assembler::{self, PROGRAM_START};
+use chip8vm::interpreter::state::State;
+use chip8vm::interpreter::watchpoint::{Register, Watchpoint};
+use chip8vm::interpreter::VM;
+
+/// Changes every register, I, both timers, the stack, RAM and the display
+const PROGRAM: &str = "
+        LD V0, 0x12
+        LD V1, 5
+        ADD V0, V1
+        LD DT, V0
+        LD ST, V1
+        LD I, buffer
+        LD B, V0
+        LD [I], V1
+        CALL draw
+        RND V2, 0xFF
+        LD I, 0x300
+    loop:
+        JP loop
+    draw:
+        LD F, V1
+        DRW V1, V1, 5
+        CLS
+        DRW V0, V1, 5
+        RET
+    buffer:
+        .SPACE 4
+";
+
+const STEPS: usize = 16;
+
+fn vm_with_history(source: &str, history_length: usize) -> VM {
+    let rom = assembler::assemble(source).unwrap();
+    let mut vm = VM::new();
+    vm.load_program(&rom);
+    vm.set_history_length(history_length);
+    vm
+}
+
+/// Step `n` instructions, returning the states before each one and after
+/// the last one
+fn run_forward(vm: &mut VM, n: usize) -> Vec<State> {
+    let mut states = vec![vm.state()];
+    for _ in 0..n {
+        vm.step().unwrap();
+        states.push(vm.state());
+    }
+    states
+}
+
+#[test]
+fn steps_back_through_every_state() {
+    let mut vm = vm_with_history(PROGRAM, 100);
+    let states = run_forward(&mut vm, STEPS);
+    assert_ne!(states[0].display, states[STEPS].display);
+    assert_eq!(vm.history_length(), STEPS);
+
+    for expected in states.iter().rev().skip(1) {
+        assert!(vm.step_back());
+        assert_eq!(&vm.state(), expected, "at cycle {}", expected.cycles);
+    }
+    assert_eq!(vm.history_length(), 0);
+    assert!(!vm.step_back());
+    assert_eq!(vm.state(), states[0]);
+}
+
+#[test]
+fn runs_forward_again_after_stepping_back() {
+    let mut vm = vm_with_history(PROGRAM, 100);
+    let states = run_forward(&mut vm, STEPS);
+    for _ in 0..5 {
+        vm.step_back();
+    }
+    assert_eq!(vm.state(), states[STEPS - 5]);
+    // The random number of RND, the 15th instruction, is drawn anew, so
+    // stop before it
+    for expected in &states[STEPS - 5..14] {
+        assert_eq!(&vm.state(), expected);
+        vm.step().unwrap();
+    }
+}
+
+#[test]
+fn forgets_the_oldest_instructions() {
+    let mut vm = vm_with_history(PROGRAM, 4);
+    let states = run_forward(&mut vm, STEPS);
+    assert_eq!(vm.history_length(), 4);
+    assert_eq!(vm.run_backwards(|_| false), (4, None));
+    assert_eq!(vm.state(), states[STEPS - 4]);
+}
+
+#[test]
+fn runs_backwards_to_a_breakpoint() {
+    let mut vm = vm_with_history(PROGRAM, 100);
+    let states = run_forward(&mut vm, STEPS);
+    // The instruction at 0x206, LD DT, V0, is the 4th one
+    let breakpoint = PROGRAM_START + 6;
+    let (undone, hit) = vm.run_backwards(|vm| vm.pc() == breakpoint);
+    assert_eq!((undone, hit), (STEPS as u64 - 3, None));
+    assert_eq!(vm.state(), states[3]);
+}
+
+#[test]
+fn runs_backwards_to_a_watchpoint_hit() {
+    let mut vm = vm_with_history(PROGRAM, 100);
+    vm.add_watchpoint(Watchpoint { register: Register::V(1), value: None });
+    let states = run_forward(&mut vm, STEPS);
+    assert!(vm.take_watchpoint_hit().is_some());
+
+    // Only LD V1, 5 changes V1
+    let (undone, hit) = vm.run_backwards(|_| false);
+    assert_eq!(undone, STEPS as u64 - 1);
+    let hit = hit.unwrap();
+    assert_eq!((hit.register, hit.old, hit.new, hit.pc), (Register::V(1), 0, 5, PROGRAM_START + 2));
+    assert_eq!(vm.state(), states[1]);
+}
+
+#[test]
+fn runs_backwards_until_the_history_is_empty() {
+    let mut vm = vm_with_history(PROGRAM, 100);
+    let states = run_forward(&mut vm, STEPS);
+    assert_eq!(vm.run_backwards(|_| false), (STEPS as u64, None));
+    assert_eq!(vm.state(), states[0]);
+    assert_eq!(vm.run_backwards(|_| false), (0, None));
+
+    let mut vm = vm_with_history(PROGRAM, 0);
+    run_forward(&mut vm, STEPS);
+    assert_eq!(vm.run_backwards(|_| false), (0, None));
+    assert!(!vm.step_back());
+}
@@ -0,0 +1,50 @@
+//! Parses and writes JSON documents, including ones made to break the
+//! parser
+
+use chip8vm::json::{parse, Value, MAX_DEPTH};
+
+#[test]
+fn limits_nesting() {
+    let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+    assert!(parse(&nested(MAX_DEPTH)).is_ok());
+    assert_eq!(parse(&nested(MAX_DEPTH + 1)).unwrap_err().message, "too deeply nested");
+    assert!(parse(&"[".repeat(200_000)).is_err());
+    assert!(parse(&"{\"a\":".repeat(200_000)).is_err());
+}
+
+#[test]
+fn parses_escapes() {
+    let value = parse(r#""\"\\\/\b\f\n\r\té😀""#).unwrap();
+    assert_eq!(value, Value::String("\"\\/\u{8}\u{c}\n\r\té😀".to_string()));
+    assert_eq!(value.to_string(), "\"\\\"\\\\/\\u0008\\u000c\\n\\r\\té😀\"");
+    assert_eq!(parse(&value.to_string()).unwrap(), value);
+
+    for invalid in [r#""\ud83d""#, r#""\ud83dA""#, r#""\ude00""#, r#""\x""#, r#""\u12""#, r#""\u+123""#, r#""abc"#] {
+        assert!(parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn rejects_trailing_data() {
+    assert!(parse(" [1, 2] \n").is_ok());
+    for invalid in ["[1, 2] 3", "{} {}", "null,", "truex", "[1, 2,]", "{\"a\": 1,}"] {
+        assert!(parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn parses_numbers_strictly() {
+    for (number, expected) in [("0", 0.0), ("-0", -0.0), ("12", 12.0), ("-1.5", -1.5), ("1e3", 1000.0), ("2.5E-1", 0.25), ("1e+2", 100.0)] {
+        assert_eq!(parse(number).unwrap(), Value::Number(expected), "{}", number);
+    }
+    for invalid in ["01", "-01", "1.", ".5", "+1", "-", "1e", "1e+", "0x10", "1.5.2", "--1", "1e400", "-1e400"] {
+        assert!(parse(invalid).is_err(), "{}", invalid);
+    }
+}
+
+#[test]
+fn writes_non_finite_numbers_as_null() {
+    let value = Value::Array(vec![Value::Number(f64::NAN), Value::Number(f64::INFINITY), Value::Number(-f64::INFINITY)]);
+    assert_eq!(value.to_string(), "[null, null, null]");
+    assert_eq!(value.to_pretty_string(), "[null, null, null]");
+}
@@ -0,0 +1,98 @@
+//! Presses and releases keys around `LD Vx, K`, with and without the
+//! key_release quirk and key latching
+
+use chip8vm::assembler;
+use chip8vm::interpreter::{Quirks, VM};
+
+/// Executes one instruction before waiting for a key into V5
+const PROGRAM: &str = "
+        LD V0, 1
+        LD V5, K
+    loop:
+        JP loop
+";
+
+fn new_vm(key_release: bool, key_latching: bool) -> VM {
+    let mut vm = VM::new();
+    vm.load_program(&assembler::assemble(PROGRAM).unwrap());
+    vm.set_quirks(Quirks { key_release, ..Quirks::default() });
+    vm.set_key_latching(key_latching);
+    vm
+}
+
+fn tap(vm: &mut VM, key: u8) {
+    vm.set_key(key, true);
+    vm.set_key(key, false);
+}
+
+fn v5(vm: &VM) -> u8 {
+    vm.state().reg[5]
+}
+
+#[test]
+fn latching_catches_taps_between_instructions() {
+    for key_release in [true, false] {
+        let mut vm = new_vm(key_release, true);
+        vm.step().unwrap();
+        tap(&mut vm, 7);
+        vm.step().unwrap();
+        assert!(!vm.is_waiting_for_key());
+        assert_eq!(v5(&vm), 7);
+    }
+}
+
+#[test]
+fn latched_keys_last_until_the_end_of_the_frame() {
+    let mut vm = new_vm(true, true);
+    vm.step().unwrap();
+    tap(&mut vm, 7);
+    vm.tick_timers();
+    vm.step().unwrap();
+    assert!(vm.is_waiting_for_key());
+}
+
+#[test]
+fn waits_for_a_new_key_without_latching() {
+    for key_release in [true, false] {
+        let mut vm = new_vm(key_release, false);
+        vm.step().unwrap();
+        tap(&mut vm, 7);
+        vm.step().unwrap();
+        assert!(vm.is_waiting_for_key());
+        assert_eq!(v5(&vm), 0);
+
+        tap(&mut vm, 9);
+        assert!(!vm.is_waiting_for_key());
+        assert_eq!(v5(&vm), 9);
+    }
+}
+
+#[test]
+fn key_release_quirk_stores_the_key_on_release() {
+    let mut vm = new_vm(true, false);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    vm.set_key(0xA, true);
+    assert!(vm.is_waiting_for_key());
+    vm.set_key(0xA, false);
+    assert!(!vm.is_waiting_for_key());
+    assert_eq!(v5(&vm), 0xA);
+
+    let mut vm = new_vm(false, false);
+    vm.step().unwrap();
+    vm.step().unwrap();
+    vm.set_key(0xA, true);
+    assert!(!vm.is_waiting_for_key());
+    assert_eq!(v5(&vm), 0xA);
+}
+
+#[test]
+fn latching_with_key_release_ignores_presses_held_down() {
+    let mut vm = new_vm(true, true);
+    vm.step().unwrap();
+    vm.set_key(3, true);
+    vm.step().unwrap();
+    assert!(vm.is_waiting_for_key());
+    vm.set_key(3, false);
+    assert_eq!(v5(&vm), 3);
+}
@@ -0,0 +1,123 @@
+//! Runs two builds of a program side by side with the same random numbers
+//! and keys, and checks where they are found to differ
+
+use chip8vm::assembler::{self, PROGRAM_START};
+use chip8vm::interpreter::lockstep::{compare, Comparison, Frames};
+use chip8vm::interpreter::replay::Replay;
+use chip8vm::interpreter::VM;
+
+/// Draws sprites at random places, and counts in V3 the loops run while
+/// key 5 is held down
+const PROGRAM: &str = "
+    start:
+        RND V0, 0x3F
+        RND V1, 0x1F
+        LD I, sprite
+        DRW V0, V1, 4
+        LD V2, 5
+        SKNP V2
+    counter:
+        ADD V3, 1
+        LD DT, V2
+        JP start
+    sprite:
+        .BYTE 0x90
+        .BYTE 0x60
+        .BYTE 0x60
+        .BYTE 0x90
+";
+
+const CYCLES: u64 = 5000;
+const SEED: u64 = 42;
+
+/// Key 5 is held down from frame 20 to 30
+fn replay() -> Replay {
+    let mut replay = Replay::new(SEED, 480);
+    for frame in 0..40 {
+        match frame {
+            20 => replay.record(5, true),
+            30 => replay.record(5, false),
+            _ => {}
+        }
+        replay.end_frame();
+    }
+    replay
+}
+
+fn vm(rom: &[u8]) -> VM {
+    let mut vm = VM::new();
+    vm.seed_rng(SEED);
+    vm.load_program(rom);
+    vm
+}
+
+fn run(roms: [&[u8]; 2], ignore_ram: bool) -> Comparison {
+    let mut vms = roms.map(vm);
+    let mut frames = Frames::new(Some(replay()), &mut vms);
+    compare(&mut vms, &mut frames, CYCLES, ignore_ram)
+}
+
+#[test]
+fn identical_roms_do_not_diverge() {
+    let rom = assembler::assemble(PROGRAM).unwrap();
+    assert!(matches!(run([&rom, &rom], false), Comparison::Identical));
+}
+
+#[test]
+fn reports_the_first_instruction_that_differs() {
+    let (rom, symbols) = assembler::assemble_with_symbols(PROGRAM).unwrap();
+    let counter = symbols["counter"];
+    let mut changed = rom.clone();
+    // ADD V3, 1 becomes ADD V3, 2
+    changed[counter as usize + 1] = 2;
+
+    // The instruction first runs once key 5 is pressed
+    let mut vms = [vm(&rom)];
+    let mut frames = Frames::new(Some(replay()), &mut vms);
+    let mut expected = 0;
+    while vms[0].pc() != PROGRAM_START + counter {
+        vms[0].step().unwrap();
+        expected += 1;
+        frames.update(expected, &mut vms);
+    }
+    expected += 1;
+    assert!(frames.frame >= 20);
+
+    let Comparison::Diverged(divergence) = run([&rom, &changed], true) else {
+        panic!("the ROMs did not diverge");
+    };
+    assert_eq!(divergence.cycles, expected);
+    assert_eq!(divergence.frame, frames.frame + 1);
+    assert_eq!(divergence.errors, [None, None]);
+    let [a, b] = &divergence.states;
+    assert_eq!((a.reg[3], b.reg[3]), (1, 2));
+    assert_eq!(a.pc, b.pc);
+}
+
+#[test]
+fn compares_ram_unless_ignored() {
+    let rom = assembler::assemble(PROGRAM).unwrap();
+    let mut changed = rom.clone();
+    changed.push(0xFF);
+    assert!(matches!(run([&rom, &changed], true), Comparison::Identical));
+    let Comparison::Diverged(divergence) = run([&rom, &changed], false) else {
+        panic!("the ROMs did not diverge");
+    };
+    assert_eq!(divergence.cycles, 1);
+}
+
+#[test]
+fn reports_roms_stopping_identically() {
+    let rom = assembler::assemble("LD V0, 1\nSYS 0x300").unwrap();
+    let Comparison::Stopped { cycles, .. } = run([&rom, &rom], false) else {
+        panic!("the ROMs did not stop");
+    };
+    assert_eq!(cycles, 2);
+
+    let other = assembler::assemble("LD V0, 1\nLD V0, 2").unwrap();
+    let Comparison::Diverged(divergence) = run([&rom, &other], true) else {
+        panic!("the ROMs did not diverge");
+    };
+    assert_eq!(divergence.cycles, 2);
+    assert!(divergence.errors[0].is_some() && divergence.errors[1].is_none());
+}
@@ -0,0 +1,91 @@
+//! Runs packed ROMs until their stub jumps to the unpacked program, and
+//! checks that it was restored exactly
+
+use chip8vm::assembler::{self, PROGRAM_START};
+use chip8vm::assembler::pack::{pack, rle_compress, rle_decompress};
+use chip8vm::interpreter::VM;
+
+const MAX_UNPACK_CYCLES: u64 = 1_000_000;
+
+fn unpack_in_vm(rom: &[u8]) {
+    let packed = pack(rom).unwrap();
+    let mut vm = VM::new();
+    vm.load_program(&packed);
+    while vm.state().pc != PROGRAM_START || vm.cycles() == 0 {
+        assert!(vm.cycles() < MAX_UNPACK_CYCLES, "the stub did not finish");
+        vm.step().unwrap();
+    }
+
+    let state = vm.state();
+    let start = PROGRAM_START as usize;
+    assert_eq!(&state.ram[start..start + rom.len()], rom);
+    assert_eq!(state.reg, [0; 16]);
+    assert_eq!(state.reg_i, 0);
+    assert_eq!(state.sp, 0);
+    assert!(state.display.iter().flatten().all(|&pixel| !pixel));
+}
+
+#[test]
+fn unpacks_sprite_heavy_rom() {
+    let rom = assembler::assemble("
+            LD I, sprite
+            LD V0, 0
+            LD V1, 0
+            DRW V0, V1, 15
+        loop:
+            JP loop
+        sprite:
+            .FILL 15, 0xFF
+            .SPACE 400
+            .TEXT \"end\"
+            .FILL 200, 0x81
+    ").unwrap();
+    assert!(pack(&rom).unwrap().len() < rom.len());
+    unpack_in_vm(&rom);
+}
+
+#[test]
+fn unpacks_bundled_roms() {
+    for path in ["roms/timer.asm", "roms/random.asm"] {
+        let rom = assembler::assemble_from_file(path).unwrap();
+        unpack_in_vm(&rom);
+    }
+    unpack_in_vm(&std::fs::read("roms/ibmlogo.ch8").unwrap());
+}
+
+#[test]
+fn unpacks_incompressible_rom() {
+    let rom: Vec<u8> = (0..=255).collect();
+    unpack_in_vm(&rom);
+}
+
+#[test]
+fn packed_rom_behaves_like_original() {
+    let rom = assembler::assemble_from_file("roms/timer.asm").unwrap();
+    let mut original = VM::new();
+    original.load_program(&rom);
+    let mut packed = VM::new();
+    packed.load_program(&pack(&rom).unwrap());
+    while packed.state().pc != PROGRAM_START || packed.cycles() == 0 {
+        packed.step().unwrap();
+    }
+
+    for _ in 0..1000 {
+        original.step().unwrap();
+        packed.step().unwrap();
+        assert_eq!(original.state().pc, packed.state().pc);
+        assert_eq!(original.display(), packed.display());
+    }
+}
+
+#[test]
+fn rle_round_trip() {
+    let data = [vec![1, 2, 3], vec![7; 300], vec![4, 4], (0..200).map(|i| i as u8).collect()].concat();
+    assert_eq!(rle_decompress(&rle_compress(&data)).unwrap(), data);
+    assert!(rle_decompress(&[0x05, 1, 2]).is_none());
+}
+
+#[test]
+fn rom_too_large() {
+    assert!(pack(&vec![0xAA; 0xE00]).is_err());
+}
@@ -0,0 +1,63 @@
+//! Decodes the images the encoder writes, and rejects truncated and
+//! crafted files without panicking
+
+use chip8vm::hash::crc32;
+use chip8vm::png::{decode, encode, Error, Image};
+
+fn image() -> Image {
+    let pixels = (0..12 * 5).map(|i| [i as u8, 255 - i as u8, 0x80, if i % 2 == 0 { 0xFF } else { 0x40 }]).collect();
+    Image { width: 12, height: 5, pixels }
+}
+
+/// Replace the IHDR chunk of an encoded image
+fn with_header(png: &[u8], width: u32, height: u32, bit_depth: u8, color_type: u8) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&width.to_be_bytes());
+    body.extend_from_slice(&height.to_be_bytes());
+    body.extend_from_slice(&[bit_depth, color_type, 0, 0, 0]);
+    let mut chunk = b"IHDR".to_vec();
+    chunk.extend_from_slice(&body);
+    let crc = crc32(&chunk);
+    // The signature, then the length of the IHDR chunk
+    let mut crafted = png[..12].to_vec();
+    crafted.extend_from_slice(&chunk);
+    crafted.extend_from_slice(&crc.to_be_bytes());
+    crafted.extend_from_slice(&png[33..]);
+    crafted
+}
+
+#[test]
+fn decodes_encoded_images() {
+    assert_eq!(decode(&encode(&image())), Ok(image()));
+}
+
+#[test]
+fn rejects_truncated_files() {
+    let png = encode(&image());
+    for length in 0..png.len() - 12 {
+        assert!(decode(&png[..length]).is_err(), "decoded the first {} bytes", length);
+    }
+}
+
+#[test]
+fn rejects_malformed_headers() {
+    let png = encode(&image());
+    assert_eq!(decode(b"GIF89a"), Err(Error::NotPng));
+    let malformed = |message| Err(Error::Malformed { message });
+    assert_eq!(decode(&with_header(&png, u32::MAX, u32::MAX, 16, 6)), malformed("image too large"));
+    assert_eq!(decode(&with_header(&png, 0, 5, 8, 6)), malformed("empty image"));
+    assert_eq!(decode(&with_header(&png, 12, 5, 3, 6)), malformed("invalid bit depth"));
+    assert_eq!(decode(&with_header(&png, 12, 5, 8, 7)), malformed("invalid color type"));
+    // More rows than the image data holds
+    assert_eq!(decode(&with_header(&png, 12, 500, 8, 6)), malformed("not enough image data"));
+}
+
+#[test]
+fn rejects_malformed_image_data() {
+    let mut png = encode(&image());
+    // The filter type of the first row, after the zlib header and the
+    // header of the stored block
+    let first_row = 33 + 8 + 2 + 5;
+    png[first_row] = 9;
+    assert_eq!(decode(&png), Err(Error::Malformed { message: "invalid filter type" }));
+}
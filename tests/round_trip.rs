@@ -0,0 +1,126 @@
+//! Round-trip properties of the instruction encoder, decoder, assembler and
+//! disassembler. Opcodes are only 16 bits wide, so most properties are
+//! checked exhaustively rather than on random samples
+
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use chip8vm::assembler;
+use chip8vm::disassembler::{decode_instruction, disassemble};
+use chip8vm::isa::{Address, Instruction, Register};
+
+const N_RANDOM_CASES: usize = 100_000;
+
+fn all_opcodes() -> impl Iterator<Item = u16> {
+    0..=u16::MAX
+}
+
+fn random_instruction(rng: &mut SmallRng) -> Instruction {
+    let x: Register = rng.random_range(0..16);
+    let y: Register = rng.random_range(0..16);
+    let kk: u8 = rng.random();
+    let n: u8 = rng.random_range(0..16);
+    let nnn: Address = rng.random_range(0..0x1000);
+    match rng.random_range(0..35) {
+        0 => Instruction::Cls,
+        1 => Instruction::Ret,
+        // 00E0 and 00EE decode to CLS and RET
+        2 if nnn != 0x0E0 && nnn != 0x0EE => Instruction::Sys(nnn),
+        2 => Instruction::Sys(0),
+        3 => Instruction::Jp(nnn),
+        4 => Instruction::Call(nnn),
+        5 => Instruction::SeByte(x, kk),
+        6 => Instruction::SneByte(x, kk),
+        7 => Instruction::SeReg(x, y),
+        8 => Instruction::LdByte(x, kk),
+        9 => Instruction::AddByte(x, kk),
+        10 => Instruction::LdReg(x, y),
+        11 => Instruction::Or(x, y),
+        12 => Instruction::And(x, y),
+        13 => Instruction::Xor(x, y),
+        14 => Instruction::AddReg(x, y),
+        15 => Instruction::Sub(x, y),
+        16 => Instruction::Shr(x, y),
+        17 => Instruction::Subn(x, y),
+        18 => Instruction::Shl(x, y),
+        19 => Instruction::SneReg(x, y),
+        20 => Instruction::LdI(nnn),
+        21 => Instruction::JpV0(nnn),
+        22 => Instruction::Rnd(x, kk),
+        23 => Instruction::Drw(x, y, n),
+        24 => Instruction::Skp(x),
+        25 => Instruction::Sknp(x),
+        26 => Instruction::LdVxDt(x),
+        27 => Instruction::LdVxK(x),
+        28 => Instruction::LdDtVx(x),
+        29 => Instruction::LdStVx(x),
+        30 => Instruction::AddIVx(x),
+        31 => Instruction::LdFVx(x),
+        32 => Instruction::LdBVx(x),
+        33 => Instruction::LdMemVx(x),
+        _ => Instruction::LdVxMem(x),
+    }
+}
+
+#[test]
+fn decode_encode_is_identity() {
+    let mut rng = SmallRng::seed_from_u64(0);
+    for _ in 0..N_RANDOM_CASES {
+        let instruction = random_instruction(&mut rng);
+        assert_eq!(Instruction::decode(instruction.encode()), Some(instruction));
+    }
+}
+
+#[test]
+fn encode_decode_is_identity() {
+    for opcode in all_opcodes() {
+        if let Some(instruction) = Instruction::decode(opcode) {
+            assert_eq!(instruction.encode(), opcode, "{}", instruction);
+        }
+    }
+}
+
+// The assembler is much faster on one long program than on many short ones,
+// so instructions are assembled in batches small enough to fit in RAM
+const BATCH_SIZE: usize = 1024;
+
+#[test]
+fn every_opcode_assembles_back() {
+    let opcodes: Vec<u16> = all_opcodes().collect();
+    for batch in opcodes.chunks(BATCH_SIZE) {
+        let lines: Vec<String> = batch.iter().map(|&opcode| decode_instruction(opcode)).collect();
+        let bytecode = assembler::assemble(&lines.join("\n")).unwrap();
+        assert_eq!(bytecode.len(), batch.len() * 2);
+        for ((line, &opcode), assembled) in lines.iter().zip(batch).zip(bytecode.chunks(2)) {
+            assert_eq!(assembled, opcode.to_be_bytes(), "`{}`", line);
+        }
+    }
+}
+
+#[test]
+fn random_instructions_assemble_back() {
+    let mut rng = SmallRng::seed_from_u64(1);
+    for _ in 0..N_RANDOM_CASES / BATCH_SIZE {
+        let instructions: Vec<Instruction> =
+            (0..BATCH_SIZE).map(|_| random_instruction(&mut rng)).collect();
+        let lines: Vec<String> = instructions.iter().map(Instruction::to_string).collect();
+        let bytecode = assembler::assemble(&lines.join("\n")).unwrap();
+        for (instruction, assembled) in instructions.iter().zip(bytecode.chunks(2)) {
+            assert_eq!(assembled, instruction.encode().to_be_bytes(), "`{}`", instruction);
+        }
+    }
+}
+
+#[test]
+fn disassembly_is_stable() {
+    let mut rng = SmallRng::seed_from_u64(2);
+    for _ in 0..100 {
+        // Odd lengths end with a lone `.byte`
+        let length = rng.random_range(0..1024);
+        let rom: Vec<u8> = (0..length).map(|_| rng.random()).collect();
+
+        let source = disassemble(&rom);
+        let reassembled = assembler::assemble(&source).unwrap();
+        assert_eq!(reassembled, rom);
+        assert_eq!(disassemble(&reassembled), source);
+    }
+}
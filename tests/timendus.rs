@@ -0,0 +1,96 @@
+//! Runs the Timendus CHIP-8 test suite headlessly and compares the final
+//! displays with known-good snapshots in `tests/snapshots`
+//!
+//! The suite is GPL-licensed, so it is not vendored: run
+//! `tests/fetch-timendus.sh` to download it into `tests/roms`. ROMs that
+//! have not been downloaded are skipped, except for the IBM logo, which is
+//! bundled in `roms/`. To record the snapshot of a ROM after checking its
+//! output by eye, run the tests with `CHIP8_BLESS=1`. A downloaded ROM
+//! without a snapshot fails the tests, as it would check nothing.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use chip8vm::interpreter::{Display, VM, VM_FREQUENCY, TIMER_FREQUENCY};
+
+struct SuiteRom {
+    /// Path of the ROM, relative to the crate root
+    path: &'static str,
+    /// Name of the snapshot in `tests/snapshots`
+    snapshot: &'static str,
+    /// Number of 60 Hz frames to run for
+    frames: u32,
+    /// Value written to 0x1FF before starting, which the suite reads to
+    /// skip its menus (1 selects CHIP-8)
+    preselect: Option<u8>,
+}
+
+const SUITE: &[SuiteRom] = &[
+    SuiteRom { path: "tests/roms/1-chip8-logo.ch8", snapshot: "chip8-logo", frames: 60, preselect: None },
+    SuiteRom { path: "tests/roms/2-ibm-logo.ch8", snapshot: "ibm-logo", frames: 60, preselect: None },
+    SuiteRom { path: "tests/roms/3-corax+.ch8", snapshot: "corax+", frames: 120, preselect: None },
+    SuiteRom { path: "tests/roms/4-flags.ch8", snapshot: "flags", frames: 120, preselect: None },
+    SuiteRom { path: "tests/roms/5-quirks.ch8", snapshot: "quirks", frames: 600, preselect: Some(1) },
+];
+
+const CYCLES_PER_FRAME: u32 = VM_FREQUENCY / TIMER_FREQUENCY;
+
+/// Run a ROM for a number of frames, with the random number generator
+/// seeded with 0, and return the display
+fn run(rom: &[u8], frames: u32, preselect: Option<u8>) -> Display {
+    let mut vm = VM::new();
+    vm.load_program(rom);
+    if let Some(value) = preselect {
+        let mut state = vm.state();
+        state.ram[0x1FF] = value;
+        vm.restore_state(&state);
+    }
+    vm.set_tickrate(CYCLES_PER_FRAME);
+    vm.run_frames(frames as u64, |_| {}).unwrap();
+    *vm.display()
+}
+
+fn render(display: &Display) -> String {
+    display
+        .iter()
+        .map(|row| row.iter().map(|&pixel| if pixel { '#' } else { '.' }).collect::<String>() + "\n")
+        .collect()
+}
+
+fn snapshot_path(snapshot: &str) -> PathBuf {
+    Path::new("tests/snapshots").join(format!("{}.txt", snapshot))
+}
+
+fn check_snapshot(rom_path: &str, snapshot: &str, frames: u32, preselect: Option<u8>) {
+    let rom = fs::read(rom_path).unwrap();
+    let actual = render(&run(&rom, frames, preselect));
+    let snapshot_path = snapshot_path(snapshot);
+
+    if std::env::var_os("CHIP8_BLESS").is_some() {
+        fs::write(&snapshot_path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&snapshot_path).unwrap_or_else(|_| {
+        panic!("no snapshot for {}; check its output and record it with CHIP8_BLESS=1", rom_path)
+    });
+    assert!(
+        actual == expected,
+        "display of {} after {} frames differs from {}:\n{}",
+        rom_path, frames, snapshot_path.display(), actual
+    );
+}
+
+#[test]
+fn bundled_ibm_logo() {
+    check_snapshot("roms/ibmlogo.ch8", "ibm-logo", 60, None);
+}
+
+#[test]
+fn timendus_suite() {
+    for rom in SUITE {
+        if !Path::new(rom.path).exists() {
+            eprintln!("skipping {}: run tests/fetch-timendus.sh to download it", rom.path);
+            continue;
+        }
+        check_snapshot(rom.path, rom.snapshot, rom.frames, rom.preselect);
+    }
+}
@@ -0,0 +1,125 @@
+//! Writes traces in the canonical format, reads them back and compares
+//! them
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use chip8vm::assembler;
+use chip8vm::interpreter::trace::{
+    diff_canonical, parse_canonical, CanonicalDiff, CanonicalError, Trace, TraceFormat, CANONICAL_FIELDS,
+};
+use chip8vm::interpreter::VM;
+
+/// Collects what a [Trace] writes, which takes ownership of its writer
+#[derive(Clone, Default)]
+struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Buffer {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+const PROGRAM: &str = "
+        LD V0, 0xAB
+        LD I, 0x345
+        CALL sub
+        LD DT, V0
+        LD ST, V0
+        ADD V0, 0x60
+    loop:
+        JP loop
+    sub:
+        LD VE, 7
+        RET
+";
+
+/// A canonical trace of `lines` instructions, all the same but for V3
+/// being 1 at the one numbered `differing`, if any
+fn trace(lines: u64, differing: Option<u64>) -> Vec<String> {
+    let mut trace = vec!["# written by hand".to_string()];
+    for cycle in 0..lines {
+        let v3 = if differing == Some(cycle + 1) { 1 } else { 0 };
+        trace.push(format!("{} {:04X} 1200 00 00 00 {:02X}{} 0000 0 00 00", cycle, 0x200, v3, " 00".repeat(12)));
+        if cycle % 3 == 0 {
+            trace.push(String::new());
+        }
+    }
+    trace
+}
+
+#[test]
+fn round_trips_canonical_lines() {
+    let buffer = Buffer::default();
+    let mut trace = Trace::new(Box::new(buffer.clone()), None);
+    trace.set_format(TraceFormat::Canonical);
+    let mut vm = VM::new();
+    vm.load_program(&assembler::assemble(PROGRAM).unwrap());
+    vm.set_trace(trace);
+
+    let mut expected = Vec::new();
+    for _ in 0..8 {
+        let (cycles, pc) = (vm.cycles(), vm.pc());
+        vm.step().unwrap();
+        let state = vm.state();
+        let opcode = u16::from_be_bytes([state.ram[pc as usize], state.ram[pc as usize + 1]]);
+        let mut fields = vec![cycles, pc as u64, opcode as u64];
+        fields.extend(state.reg.iter().map(|&x| x as u64));
+        fields.extend([state.reg_i as u64, state.sp as u64, state.delay_timer as u64, state.sound_timer as u64]);
+        expected.push(fields);
+    }
+    vm.tick_timers();
+
+    let written = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<&str> = written.lines().filter(|line| !line.starts_with('#')).collect();
+    assert_eq!(lines.len(), expected.len());
+    for (line, expected) in lines.iter().zip(&expected) {
+        let fields = parse_canonical(line).unwrap();
+        assert_eq!(fields.len(), CANONICAL_FIELDS.len());
+        assert_eq!(&fields[..], &expected[..], "{}", line);
+    }
+    assert_eq!(lines[0], "0 0200 60AB AB 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 0000 0 00 00");
+}
+
+#[test]
+fn rejects_malformed_lines() {
+    for invalid in ["", "0 0200 60AB", "x 0200 60AB 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 0000 0 00 00"] {
+        assert_eq!(parse_canonical(invalid), None, "{}", invalid);
+    }
+    let line = trace(1, None).remove(1);
+    assert!(parse_canonical(&line).is_some());
+    assert_eq!(parse_canonical(&format!("{} 00", line)), None);
+}
+
+#[test]
+fn finds_the_first_difference() {
+    let diff = diff_canonical([trace(20, None).into_iter(), trace(20, Some(13)).into_iter()]).unwrap();
+    let CanonicalDiff::Differ { instruction, fields, lines, previous } = diff else {
+        panic!("no difference found: {:?}", diff);
+    };
+    assert_eq!(instruction, 13);
+    assert_eq!(fields, ["V3"]);
+    assert!(lines[0].starts_with("12 0200 1200 00 00 00 00"));
+    assert!(lines[1].starts_with("12 0200 1200 00 00 00 01"));
+    assert!(previous.unwrap().starts_with("11 "));
+}
+
+#[test]
+fn compares_lengths() {
+    let diff = |a, b| diff_canonical([trace(a, None).into_iter(), trace(b, None).into_iter()]).unwrap();
+    assert_eq!(diff(20, 20), CanonicalDiff::Identical { instructions: 20 });
+    assert_eq!(diff(12, 20), CanonicalDiff::Ends { shorter: 0, instructions: 12 });
+    assert_eq!(diff(20, 5), CanonicalDiff::Ends { shorter: 1, instructions: 5 });
+}
+
+#[test]
+fn reports_lines_not_in_the_format() {
+    let mut invalid = trace(5, None);
+    invalid.insert(3, "0x0200  6001  LD V0, 0x01".to_string());
+    let error = diff_canonical([trace(5, None).into_iter(), invalid.into_iter()]).unwrap_err();
+    assert_eq!(error, CanonicalError { trace: 1, line_number: 4, line: "0x0200  6001  LD V0, 0x01".to_string() });
+}
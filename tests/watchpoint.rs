@@ -0,0 +1,109 @@
+//! Runs programs with watchpoints on the registers, I and the timers, and
+//! checks which instructions hit them
+
+use chip8vm::assembler::{self, PROGRAM_START};
+use chip8vm::interpreter::watchpoint::{Register, Watchpoint, WatchpointHit};
+use chip8vm::interpreter::VM;
+
+fn vm_with_watchpoint(source: &str, watchpoint: &str) -> VM {
+    let rom = assembler::assemble(source).unwrap();
+    let mut vm = VM::new();
+    vm.load_program(&rom);
+    vm.add_watchpoint(watchpoint.parse().unwrap());
+    vm
+}
+
+/// Step until a watchpoint is hit, returning the hit and the number of
+/// instructions executed
+fn run_to_hit(vm: &mut VM, max_steps: usize) -> Option<(WatchpointHit, usize)> {
+    for steps in 1..=max_steps {
+        vm.step().unwrap();
+        if let Some(hit) = vm.take_watchpoint_hit() {
+            return Some((hit, steps));
+        }
+    }
+    None
+}
+
+fn hit(register: Register, old: u16, new: u16, pc: u16) -> WatchpointHit {
+    WatchpointHit { register, old, new, pc: PROGRAM_START + pc }
+}
+
+#[test]
+fn watches_each_register() {
+    let program = "
+        LD V3, 7
+        LD I, 0x345
+        LD DT, V3
+        ADD V3, 1
+        LD ST, V3
+    ";
+    let cases = [
+        ("V3", hit(Register::V(3), 0, 7, 0), 1),
+        ("I", hit(Register::I, 0, 0x345, 2), 2),
+        ("DT", hit(Register::DelayTimer, 0, 7, 4), 3),
+        ("ST", hit(Register::SoundTimer, 0, 8, 8), 5),
+    ];
+    for (watchpoint, expected, steps) in cases {
+        let mut vm = vm_with_watchpoint(program, watchpoint);
+        assert_eq!(run_to_hit(&mut vm, 5), Some((expected, steps)), "{}", watchpoint);
+    }
+}
+
+#[test]
+fn watches_for_a_value() {
+    let program = "
+        LD V3, 7
+        ADD V3, 1
+        LD V3, 9
+        LD V3, 8
+    ";
+    let mut vm = vm_with_watchpoint(program, "v3=0x08");
+    assert_eq!(run_to_hit(&mut vm, 4), Some((hit(Register::V(3), 7, 8, 2), 2)));
+    assert_eq!(run_to_hit(&mut vm, 2), Some((hit(Register::V(3), 9, 8, 6), 2)));
+
+    let mut vm = vm_with_watchpoint(program, "V3=10");
+    assert_eq!(run_to_hit(&mut vm, 4), None);
+}
+
+#[test]
+fn ignores_unchanged_writes() {
+    let program = "
+        LD V3, 0
+        LD I, 0
+        LD DT, V3
+        LD ST, V3
+        ADD V3, 0
+        LD V3, V3
+        OR V3, V3
+    ";
+    for watchpoint in ["V3", "I", "DT", "ST", "V3=0"] {
+        let mut vm = vm_with_watchpoint(program, watchpoint);
+        assert_eq!(run_to_hit(&mut vm, 7), None, "{}", watchpoint);
+    }
+}
+
+#[test]
+fn ignores_timers_counting_down() {
+    let program = "
+        LD V0, 3
+        LD DT, V0
+    loop:
+        JP loop
+    ";
+    let mut vm = vm_with_watchpoint(program, "DT");
+    assert!(run_to_hit(&mut vm, 2).is_some());
+    vm.tick_timers();
+    vm.tick_timers();
+    assert_eq!(run_to_hit(&mut vm, 2), None);
+}
+
+#[test]
+fn parses_watchpoints() {
+    assert_eq!("vf".parse(), Ok(Watchpoint { register: Register::V(0xF), value: None }));
+    assert_eq!("I=0x123".parse(), Ok(Watchpoint { register: Register::I, value: Some(0x123) }));
+    assert_eq!("dt = 60".parse(), Ok(Watchpoint { register: Register::DelayTimer, value: Some(60) }));
+    for invalid in ["V10", "X", "V0=256", "ST=-1", "I=0x10000"] {
+        assert!(invalid.parse::<Watchpoint>().is_err(), "{}", invalid);
+    }
+}
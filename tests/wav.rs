@@ -0,0 +1,164 @@
+//! Decodes WAV files built by hand in every supported sample format, and
+//! broken ones
+
+use chip8vm::wav::{decode, Error, Sound};
+
+const PCM: u16 = 1;
+const FLOAT: u16 = 3;
+
+/// The body of a `fmt ` chunk, extensible if `extensible`
+fn format(tag: u16, channels: u16, sample_rate: u32, bits: u16, extensible: bool) -> Vec<u8> {
+    let block_align = channels * bits / 8;
+    let mut body = Vec::new();
+    body.extend_from_slice(&(if extensible { 0xFFFE } else { tag }).to_le_bytes());
+    body.extend_from_slice(&channels.to_le_bytes());
+    body.extend_from_slice(&sample_rate.to_le_bytes());
+    body.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    body.extend_from_slice(&block_align.to_le_bytes());
+    body.extend_from_slice(&bits.to_le_bytes());
+    if extensible {
+        body.extend_from_slice(&22u16.to_le_bytes());
+        body.extend_from_slice(&bits.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        // The GUID of the format, starting with its tag
+        body.extend_from_slice(&tag.to_le_bytes());
+        body.extend_from_slice(&[0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71]);
+    }
+    body
+}
+
+fn chunk(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut chunk = kind.to_vec();
+    chunk.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    chunk.extend_from_slice(body);
+    if body.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+    let body = chunks.concat();
+    let mut file = b"RIFF".to_vec();
+    file.extend_from_slice(&(body.len() as u32 + 4).to_le_bytes());
+    file.extend_from_slice(b"WAVE");
+    file.extend_from_slice(&body);
+    file
+}
+
+fn wav(tag: u16, channels: u16, bits: u16, samples: &[u8]) -> Vec<u8> {
+    riff(&[chunk(b"fmt ", &format(tag, channels, 8000, bits, false)), chunk(b"data", samples)])
+}
+
+fn samples(data: &[u8]) -> Vec<f32> {
+    let sound = decode(data).unwrap();
+    assert_eq!(sound.sample_rate, 8000);
+    sound.samples
+}
+
+#[test]
+fn decodes_integer_samples() {
+    assert_eq!(samples(&wav(PCM, 1, 8, &[0, 128, 255, 64])), [-1.0, 0.0, 127.0 / 128.0, -0.5]);
+
+    let data: Vec<u8> = [i16::MIN, 0, 16384, -8192].iter().flat_map(|x| x.to_le_bytes()).collect();
+    assert_eq!(samples(&wav(PCM, 1, 16, &data)), [-1.0, 0.0, 0.5, -0.25]);
+
+    let data = [0x00, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0xFF, 0xFF, 0xFF];
+    assert_eq!(samples(&wav(PCM, 1, 24, &data)), [-1.0, 0.0, 0.5, -1.0 / 8388608.0]);
+
+    let data: Vec<u8> = [i32::MIN, 0, 1 << 30].iter().flat_map(|x| x.to_le_bytes()).collect();
+    assert_eq!(samples(&wav(PCM, 1, 32, &data)), [-1.0, 0.0, 0.5]);
+}
+
+#[test]
+fn decodes_float_samples() {
+    let data: Vec<u8> = [0.25f32, -0.75, 2.0, -3.0].iter().flat_map(|x| x.to_le_bytes()).collect();
+    assert_eq!(samples(&wav(FLOAT, 1, 32, &data)), [0.25, -0.75, 1.0, -1.0]);
+}
+
+#[test]
+fn mixes_channels_down() {
+    let data: Vec<u8> = [16384i16, -16384, 16384, 16384, 0, -32768].iter().flat_map(|x| x.to_le_bytes()).collect();
+    assert_eq!(samples(&wav(PCM, 2, 16, &data)), [0.0, 0.5, -0.5]);
+    // A frame cut short is dropped
+    assert_eq!(samples(&wav(PCM, 2, 16, &data[..10])), [0.0, 0.5]);
+}
+
+#[test]
+fn decodes_the_extensible_format() {
+    let data: Vec<u8> = [16384i16, -16384].iter().flat_map(|x| x.to_le_bytes()).collect();
+    let file = riff(&[chunk(b"fmt ", &format(PCM, 1, 8000, 16, true)), chunk(b"data", &data)]);
+    assert_eq!(samples(&file), [0.5, -0.5]);
+
+    let data: Vec<u8> = [0.5f32].iter().flat_map(|x| x.to_le_bytes()).collect();
+    let file = riff(&[chunk(b"fmt ", &format(FLOAT, 1, 8000, 32, true)), chunk(b"data", &data)]);
+    assert_eq!(samples(&file), [0.5]);
+
+    let file = riff(&[chunk(b"fmt ", &format(2, 1, 8000, 4, true)), chunk(b"data", &data)]);
+    assert_eq!(decode(&file), Err(Error::Unsupported { feature: "compressed formats" }));
+}
+
+#[test]
+fn skips_other_chunks() {
+    let file = riff(&[
+        chunk(b"LIST", b"odd"),
+        chunk(b"fmt ", &format(PCM, 1, 8000, 8, false)),
+        chunk(b"fact", &[0; 4]),
+        chunk(b"data", &[128, 255]),
+    ]);
+    assert_eq!(samples(&file), [0.0, 127.0 / 128.0]);
+}
+
+#[test]
+fn reads_truncated_data_chunks() {
+    let data: Vec<u8> = [16384i16, -16384, 8192].iter().flat_map(|x| x.to_le_bytes()).collect();
+    let mut file = wav(PCM, 1, 16, &data);
+    // The data chunk claims 100 bytes, and the last sample is cut short
+    let length = file.len() - data.len() - 4;
+    file[length..length + 4].copy_from_slice(&100u32.to_le_bytes());
+    file.pop();
+    assert_eq!(samples(&file), [0.5, -0.5]);
+}
+
+#[test]
+fn rejects_malformed_files() {
+    let data = chunk(b"data", &[128, 128]);
+    let fmt = chunk(b"fmt ", &format(PCM, 1, 8000, 8, false));
+    assert_eq!(decode(b""), Err(Error::NotWav));
+    assert_eq!(decode(b"RIFF\0\0\0\0AVI LIST"), Err(Error::NotWav));
+    assert_eq!(decode(&riff(std::slice::from_ref(&data))), Err(Error::Malformed { message: "samples before the format" }));
+    assert_eq!(decode(&riff(std::slice::from_ref(&fmt))), Err(Error::Malformed { message: "no samples" }));
+    assert_eq!(decode(&riff(&[])), Err(Error::Malformed { message: "no samples" }));
+    assert_eq!(decode(&riff(&[fmt[..fmt.len() - 4].to_vec()])), Err(Error::Malformed { message: "truncated chunk" }));
+    assert_eq!(decode(&riff(&[fmt.clone(), b"da".to_vec()])), Err(Error::Malformed { message: "truncated chunk" }));
+    assert_eq!(decode(&riff(&[chunk(b"fmt ", &[1, 0, 1, 0]), data.clone()])), Err(Error::Malformed { message: "truncated format" }));
+    assert_eq!(
+        decode(&riff(&[chunk(b"fmt ", &format(PCM, 0, 8000, 8, false)), data.clone()])),
+        Err(Error::Malformed { message: "no channels or sample rate" })
+    );
+    assert!(matches!(decode(&wav(PCM, 1, 12, &[0; 4])), Err(Error::Unsupported { .. })));
+    assert!(matches!(decode(&wav(FLOAT, 1, 64, &[0; 8])), Err(Error::Unsupported { .. })));
+    assert!(matches!(decode(&wav(2, 1, 4, &[0; 4])), Err(Error::Unsupported { .. })));
+}
+
+#[test]
+fn resamples() {
+    let sound = Sound { sample_rate: 8000, samples: vec![0.0, 1.0, 0.0, -1.0] };
+    assert_eq!(sound.resample(8000), sound.samples);
+
+    let up = sound.resample(16000);
+    assert_eq!(up, [0.0, 0.5, 1.0, 0.5, 0.0, -0.5, -1.0, -1.0]);
+
+    let down = sound.resample(4000);
+    assert_eq!(down, [0.0, 0.0]);
+
+    let odd = Sound { sample_rate: 44100, samples: (0..441).map(|i| i as f32 / 440.0).collect() };
+    let resampled = odd.resample(48000);
+    assert_eq!(resampled.len(), 480);
+    assert_eq!(resampled[0], 0.0);
+    assert!((resampled[479] - 1.0).abs() < 0.01);
+    assert!(resampled.windows(2).all(|pair| pair[0] <= pair[1]));
+
+    assert_eq!(Sound { sample_rate: 8000, samples: vec![0.5] }.resample(100).len(), 1);
+    assert!(Sound { sample_rate: 8000, samples: Vec::new() }.resample(16000).is_empty());
+}
@@ -0,0 +1,114 @@
+// Browser frontend for chip8vm.wasm, see src/frontend/wasm.rs for the API
+
+const DISPLAY_WIDTH = 64;
+const DISPLAY_HEIGHT = 32;
+
+// 1 2 3 C        1 2 3 4
+// 4 5 6 D  <==>  Q W E R
+// 7 8 9 E  <==>  A S D F
+// A 0 B F        Z X C V
+const KEYMAP = {
+    Digit1: 0x1, Digit2: 0x2, Digit3: 0x3, Digit4: 0xC,
+    KeyQ: 0x4, KeyW: 0x5, KeyE: 0x6, KeyR: 0xD,
+    KeyA: 0x7, KeyS: 0x8, KeyD: 0x9, KeyF: 0xE,
+    KeyZ: 0xA, KeyX: 0x0, KeyC: 0xB, KeyV: 0xF,
+};
+
+const canvas = document.getElementById("display");
+const context = canvas.getContext("2d");
+const image = context.createImageData(DISPLAY_WIDTH, DISPLAY_HEIGHT);
+const status = document.getElementById("status");
+
+let wasm = null;
+let machine = null;
+let audio = null;
+
+class Beeper {
+    constructor() {
+        this.context = new AudioContext();
+        this.gain = this.context.createGain();
+        this.gain.gain.value = 0;
+        this.gain.connect(this.context.destination);
+        const oscillator = this.context.createOscillator();
+        oscillator.type = "square";
+        oscillator.frequency.value = 440;
+        oscillator.connect(this.gain);
+        oscillator.start();
+    }
+
+    set(active) {
+        this.gain.gain.value = active ? 0.1 : 0;
+    }
+}
+
+function readCString(ptr) {
+    const bytes = new Uint8Array(wasm.memory.buffer, ptr);
+    const end = bytes.indexOf(0);
+    return new TextDecoder().decode(bytes.subarray(0, end));
+}
+
+function start(rom) {
+    if (machine !== null) {
+        wasm.chip8_free(machine);
+    }
+    machine = wasm.chip8_new();
+    wasm.chip8_seed(machine, Math.floor(Math.random() * 0xFFFFFFFF));
+
+    const ptr = wasm.chip8_alloc(rom.length);
+    new Uint8Array(wasm.memory.buffer, ptr, rom.length).set(rom);
+    wasm.chip8_load(machine, ptr, rom.length);
+    wasm.chip8_dealloc(ptr, rom.length);
+    status.textContent = "Running";
+}
+
+function frame() {
+    if (machine !== null) {
+        if (wasm.chip8_run_frame(machine) !== 0) {
+            status.textContent = "Error: " + readCString(wasm.chip8_error(machine));
+            wasm.chip8_free(machine);
+            machine = null;
+        } else {
+            const display = new Uint8Array(
+                wasm.memory.buffer, wasm.chip8_display(machine), DISPLAY_WIDTH * DISPLAY_HEIGHT
+            );
+            for (let i = 0; i < display.length; i++) {
+                const value = display[i] ? 0xFF : 0x00;
+                image.data.set([value, value, value, 0xFF], i * 4);
+            }
+            context.putImageData(image, 0, 0);
+            if (audio !== null) {
+                audio.set(wasm.chip8_sound_active(machine) !== 0);
+            }
+        }
+    }
+    requestAnimationFrame(frame);
+}
+
+function onKey(event, pressed) {
+    const key = KEYMAP[event.code];
+    if (key !== undefined && machine !== null) {
+        wasm.chip8_set_key(machine, key, pressed ? 1 : 0);
+        event.preventDefault();
+    }
+}
+
+document.addEventListener("keydown", (event) => onKey(event, true));
+document.addEventListener("keyup", (event) => onKey(event, false));
+// Browsers only allow audio after a user interaction
+document.addEventListener("click", () => {
+    if (audio === null) {
+        audio = new Beeper();
+    }
+});
+
+document.getElementById("rom").addEventListener("change", async (event) => {
+    const file = event.target.files[0];
+    if (file !== undefined) {
+        start(new Uint8Array(await file.arrayBuffer()));
+    }
+});
+
+WebAssembly.instantiateStreaming(fetch("chip8vm.wasm"), {}).then((result) => {
+    wasm = result.instance.exports;
+    requestAnimationFrame(frame);
+});